@@ -0,0 +1,205 @@
+// backfill.rs
+
+use bson::{doc, Document};
+use debot_db::{PriceLog, PricePoint};
+use mongodb::{options::ReplaceOptions, Database};
+use rust_decimal::Decimal;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackfillMode {
+    Interpolate,
+    ForwardFill,
+}
+
+impl FromStr for BackfillMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "interpolate" => Ok(BackfillMode::Interpolate),
+            "forward_fill" => Ok(BackfillMode::ForwardFill),
+            _ => Err(format!("Invalid backfill mode: {}", s)),
+        }
+    }
+}
+
+// PricePoint has no room for the flag, so a filled point is stored without volume nor trades
+// and the flag is written next to it on the price document
+#[derive(Debug, Clone)]
+pub struct BackfilledPoint {
+    pub price_point: PricePoint,
+    pub interpolated: bool,
+}
+
+// Fills the gaps longer than the interval with points every interval. A gap is counted in
+// rounded intervals, so that a late tick is not taken for a missing one.
+pub fn backfill_prices(
+    price_points: &[PricePoint],
+    interval_secs: i64,
+    mode: BackfillMode,
+) -> Vec<BackfilledPoint> {
+    let mut sorted: Vec<&PricePoint> = price_points.iter().collect();
+    sorted.sort_by_key(|price_point| price_point.timestamp);
+
+    let mut backfilled = vec![];
+    let mut prev: Option<&PricePoint> = None;
+    for price_point in sorted {
+        if let Some(prev) = prev.filter(|_| interval_secs > 0) {
+            let gap = price_point.timestamp - prev.timestamp;
+            let missing = (gap + interval_secs / 2) / interval_secs - 1;
+            for k in 1..=missing {
+                let elapsed = k * interval_secs;
+                let price = match mode {
+                    BackfillMode::Interpolate => {
+                        prev.price
+                            + (price_point.price - prev.price) * Decimal::from(elapsed)
+                                / Decimal::from(gap)
+                    }
+                    BackfillMode::ForwardFill => prev.price,
+                };
+                let mut filled = prev.clone();
+                filled.timestamp = prev.timestamp + elapsed;
+                filled.price = price;
+                filled.volume = Some(Decimal::ZERO);
+                filled.num_trades = Some(0);
+                backfilled.push(BackfilledPoint {
+                    price_point: filled,
+                    interpolated: true,
+                });
+            }
+        }
+        backfilled.push(BackfilledPoint {
+            price_point: price_point.clone(),
+            interpolated: false,
+        });
+        prev = Some(price_point);
+    }
+    backfilled
+}
+
+// The price document of a point, with the flag that PriceLog does not carry. Readers that
+// deserialize a PriceLog ignore the extra field.
+pub fn price_document(
+    id: u32,
+    name: &str,
+    token_name: &str,
+    point: &BackfilledPoint,
+) -> Result<Document, bson::ser::Error> {
+    let item = PriceLog {
+        id: Some(id),
+        name: name.to_owned(),
+        token_name: token_name.to_owned(),
+        price_point: point.price_point.clone(),
+    };
+    let mut document = bson::to_document(&item)?;
+    document.insert("interpolated", point.interpolated);
+    Ok(document)
+}
+
+pub async fn write_backfilled_point(
+    db: &Database,
+    id: u32,
+    name: &str,
+    token_name: &str,
+    point: &BackfilledPoint,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let document = price_document(id, name, token_name, point)?;
+    let options = ReplaceOptions::builder().upsert(true).build();
+    db.collection::<Document>("price")
+        .replace_one(doc! { "id": document.get("id") }, document, options)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_interpolated(document: &Document) -> bool {
+        document.get_bool("interpolated").unwrap_or(false)
+    }
+
+    fn price_point(timestamp: i64, price: i64) -> PricePoint {
+        PricePoint {
+            timestamp,
+            price: Decimal::new(price, 0),
+            volume: Some(Decimal::ONE),
+            num_trades: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backfill_prices() {
+        // Every 60 seconds, with 4 points missing between 120 and 420, and a late tick at 485
+        let price_points = vec![
+            price_point(0, 100),
+            price_point(60, 100),
+            price_point(120, 100),
+            price_point(420, 150),
+            price_point(485, 150),
+        ];
+
+        let backfilled = backfill_prices(&price_points, 60, BackfillMode::Interpolate);
+        let interpolated: Vec<&BackfilledPoint> = backfilled
+            .iter()
+            .filter(|point| point.interpolated)
+            .collect();
+        assert_eq!(interpolated.len(), 4);
+        assert_eq!(backfilled.len(), price_points.len() + 4);
+        assert_eq!(
+            interpolated
+                .iter()
+                .map(|point| (point.price_point.timestamp, point.price_point.price))
+                .collect::<Vec<_>>(),
+            vec![
+                (180, Decimal::new(110, 0)),
+                (240, Decimal::new(120, 0)),
+                (300, Decimal::new(130, 0)),
+                (360, Decimal::new(140, 0)),
+            ]
+        );
+        assert!(interpolated
+            .iter()
+            .all(|point| point.price_point.volume == Some(Decimal::ZERO)));
+
+        let backfilled = backfill_prices(&price_points, 60, BackfillMode::ForwardFill);
+        assert!(backfilled
+            .iter()
+            .filter(|point| point.interpolated)
+            .all(|point| point.price_point.price == Decimal::new(100, 0)));
+    }
+
+    #[test]
+    fn test_interpolated_flag_is_kept_on_the_price_document() {
+        let price_points = vec![price_point(0, 100), price_point(120, 120)];
+        let backfilled = backfill_prices(&price_points, 60, BackfillMode::Interpolate);
+
+        let documents: Vec<Document> = backfilled
+            .iter()
+            .enumerate()
+            .map(|(i, point)| price_document(i as u32 + 1, "fund", "BTC-USD", point).unwrap())
+            .collect();
+        let raw: Vec<u8> = documents
+            .iter()
+            .flat_map(|document| bson::to_vec(document).unwrap())
+            .collect();
+
+        // Read back from the wire format, as the price and as the flag
+        let mut reader = raw.as_slice();
+        let mut read_back = vec![];
+        while !reader.is_empty() {
+            read_back.push(Document::from_reader(&mut reader).unwrap());
+        }
+        assert_eq!(
+            read_back.iter().map(is_interpolated).collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
+        let filled: PriceLog = bson::from_document(read_back[1].clone()).unwrap();
+        assert_eq!(filled.id, Some(2));
+        assert_eq!(filled.price_point.timestamp, 60);
+        assert_eq!(filled.price_point.price, Decimal::new(110, 0));
+        assert!(!is_interpolated(&doc! { "id": 1 }));
+    }
+}
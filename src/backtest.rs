@@ -2,12 +2,94 @@ use debot_db::TransactionLog;
 use debot_market_analyzer::TradingStrategy;
 use rust_decimal::{prelude::ToPrimitive, Decimal};
 use smartcore::linalg::basic::matrix::DenseMatrix;
+use std::ops::Range;
+
+pub type TrainingData = (DenseMatrix<f64>, Vec<i32>, Vec<f64>, Vec<f64>);
+
+struct Sample {
+    timestamp: i64,
+    input: Vec<f64>,
+    output_classifier: i32,
+    output_regressor_1: f64,
+    output_regressor_2: f64,
+}
+
+// Samples up to the validation window are for training, so nothing is learned from the future
+pub struct WalkForwardFold {
+    pub train: TrainingData,
+    pub validation: TrainingData,
+}
+
+// The folds choose the hyperparameters, and the final models are fit on all the samples,
+// the most recent ones included
+pub struct WalkForwardData {
+    pub folds: Vec<WalkForwardFold>,
+    pub all: TrainingData,
+}
 
 pub async fn download_data(
     transaction_logs: &Vec<TransactionLog>,
     key: &str,
     strategy: &TradingStrategy,
-) -> (DenseMatrix<f64>, Vec<i32>, Vec<f64>, Vec<f64>) {
+) -> TrainingData {
+    let samples = collect_samples(transaction_logs, key, strategy).await;
+    to_training_data(&samples)
+}
+
+// The samples are ordered by the open time, and each fold validates on the window
+// that follows its training data
+pub async fn download_walk_forward_data(
+    transaction_logs: &Vec<TransactionLog>,
+    key: &str,
+    strategy: &TradingStrategy,
+    n_folds: usize,
+) -> Option<WalkForwardData> {
+    let samples = collect_samples(transaction_logs, key, strategy).await;
+    walk_forward_data(samples, n_folds)
+}
+
+fn walk_forward_data(mut samples: Vec<Sample>, n_folds: usize) -> Option<WalkForwardData> {
+    samples.sort_by_key(|sample| sample.timestamp);
+
+    let folds: Vec<WalkForwardFold> = walk_forward_folds(samples.len(), n_folds)
+        .into_iter()
+        .map(|(train, validation)| WalkForwardFold {
+            train: to_training_data(&samples[train]),
+            validation: to_training_data(&samples[validation]),
+        })
+        .collect();
+    if folds.is_empty() {
+        return None;
+    }
+
+    Some(WalkForwardData {
+        folds,
+        all: to_training_data(&samples),
+    })
+}
+
+// Expanding windows: the data is cut into n_folds + 1 chunks, and the fold k
+// trains on the chunks 0..=k and validates on the chunk k + 1
+fn walk_forward_folds(len: usize, n_folds: usize) -> Vec<(Range<usize>, Range<usize>)> {
+    let chunk = len / (n_folds + 1);
+    if chunk == 0 {
+        log::warn!("not enough samples for {} folds: {}", n_folds, len);
+        return vec![];
+    }
+
+    (1..=n_folds)
+        .map(|k| {
+            let validation_end = if k == n_folds { len } else { (k + 1) * chunk };
+            (0..k * chunk, k * chunk..validation_end)
+        })
+        .collect()
+}
+
+async fn collect_samples(
+    transaction_logs: &Vec<TransactionLog>,
+    key: &str,
+    strategy: &TradingStrategy,
+) -> Vec<Sample> {
     log::info!("Key passed to download_data: {}", key);
 
     let parts: Vec<&str> = key.split('_').collect();
@@ -21,14 +103,11 @@ pub async fn download_data(
     let position_type = parts[1];
 
     // Collect inputs and outputs from positions
-    let mut inputs: Vec<Vec<f64>> = Vec::new();
-    let mut output_classifier: Vec<i32> = Vec::new();
-    let mut output_regressor_1: Vec<f64> = Vec::new();
-    let mut output_regressor_2: Vec<f64> = Vec::new();
+    let mut samples: Vec<Sample> = Vec::new();
 
     for transaction_log in transaction_logs {
         let db = transaction_log.get_r_db().await.expect("db is none");
-        let positions = TransactionLog::get_all_open_positions(&db).await;
+        let positions = TransactionLog::get_all_positions(&db).await;
         let positions_len = positions.len();
 
         for position in positions {
@@ -88,26 +167,44 @@ pub async fn download_data(
                 for pattern in candle_patterns {
                     input_vector.extend(pattern.iter().map(|&d| d.to_f64().unwrap()));
                 }
-                inputs.push(input_vector);
-
-                output_classifier.push(debug_log.output_1.to_i32().expect("conversion failed"));
-                output_regressor_1.push(debug_log.output_2.to_f64().expect("conversion failed"));
-                output_regressor_2.push(
-                    debug_log
+                samples.push(Sample {
+                    timestamp: position.open_timestamp,
+                    input: input_vector,
+                    output_classifier: debug_log.output_1.to_i32().expect("conversion failed"),
+                    output_regressor_1: debug_log.output_2.to_f64().expect("conversion failed"),
+                    output_regressor_2: debug_log
                         .output_3
                         .unwrap_or(Decimal::new(-1, 0))
                         .to_f64()
                         .expect("conversion failed"),
-                );
+                });
             }
         }
         log::info!(
             "num of inputs/positions = {}/{}",
-            inputs.len(),
+            samples.len(),
             positions_len
         );
     }
 
+    samples
+}
+
+fn to_training_data(samples: &[Sample]) -> TrainingData {
+    let inputs: Vec<&Vec<f64>> = samples.iter().map(|sample| &sample.input).collect();
+    let output_classifier: Vec<i32> = samples
+        .iter()
+        .map(|sample| sample.output_classifier)
+        .collect();
+    let output_regressor_1: Vec<f64> = samples
+        .iter()
+        .map(|sample| sample.output_regressor_1)
+        .collect();
+    let output_regressor_2: Vec<f64> = samples
+        .iter()
+        .map(|sample| sample.output_regressor_2)
+        .collect();
+
     let count_class_0 = output_classifier.iter().filter(|&&x| x == 0).count();
     let count_class_1 = output_classifier.iter().filter(|&&x| x == 1).count();
 
@@ -121,3 +218,51 @@ pub async fn download_data(
 
     (x, output_classifier, output_regressor_1, output_regressor_2)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk_forward_folds() {
+        let folds = walk_forward_folds(10, 3);
+        assert_eq!(folds, vec![(0..2, 2..4), (0..4, 4..6), (0..6, 6..10)]);
+        for (train, validation) in &folds {
+            // Validation follows the training data without overlapping it
+            assert_eq!(train.end, validation.start);
+            assert!(!validation.is_empty());
+        }
+        for window in folds.windows(2) {
+            assert!(window[0].1.end <= window[1].1.start);
+        }
+
+        assert!(walk_forward_folds(3, 3).is_empty());
+    }
+
+    #[test]
+    fn test_walk_forward_data() {
+        // Saved out of order, the sample of each timestamp has its timestamp as the input
+        let samples = [5, 1, 4, 0, 3, 2]
+            .into_iter()
+            .map(|timestamp| Sample {
+                timestamp,
+                input: vec![timestamp as f64],
+                output_classifier: (timestamp % 2) as i32,
+                output_regressor_1: timestamp as f64,
+                output_regressor_2: -1.0,
+            })
+            .collect();
+        let data = walk_forward_data(samples, 2).unwrap();
+
+        let regressor_1 = |data: &TrainingData| data.2.clone();
+        assert_eq!(data.folds.len(), 2);
+        assert_eq!(regressor_1(&data.folds[0].train), vec![0.0, 1.0]);
+        assert_eq!(regressor_1(&data.folds[0].validation), vec![2.0, 3.0]);
+        assert_eq!(regressor_1(&data.folds[1].train), vec![0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(regressor_1(&data.folds[1].validation), vec![4.0, 5.0]);
+        // The final fit takes the last validation window too
+        assert_eq!(regressor_1(&data.all), vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+
+        assert!(walk_forward_data(vec![], 2).is_none());
+    }
+}
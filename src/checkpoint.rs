@@ -0,0 +1,140 @@
+// checkpoint.rs
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// The completed stages of a training, one per line, and the scores of the hyperparameter
+// combos evaluated so far, as "stage\tcombo\tscore" lines. Each grid search saves its best
+// model to the DB when it finishes, so a completed stage needs not run again on a restart,
+// and a combo scored before the restart needs not be evaluated again.
+pub struct TrainingCheckpoint {
+    path: PathBuf,
+    completed: HashSet<String>,
+    scores: HashMap<(String, String), f64>,
+}
+
+impl TrainingCheckpoint {
+    // Without resume, the checkpoint of a previous run is discarded
+    pub fn load(dir: &Path, file_key: &str, resume: bool) -> Self {
+        let path = dir.join(format!("{}.checkpoint", file_key));
+        let mut completed = HashSet::new();
+        let mut scores = HashMap::new();
+        if resume {
+            let content = fs::read_to_string(&path).unwrap_or_default();
+            for line in content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+            {
+                match line.split('\t').collect::<Vec<_>>()[..] {
+                    [stage] => {
+                        completed.insert(stage.to_owned());
+                    }
+                    [stage, combo, score] => match score.parse::<f64>() {
+                        Ok(score) => {
+                            scores.insert((stage.to_owned(), combo.to_owned()), score);
+                        }
+                        Err(_) => log::warn!("a broken score in the checkpoint: {}", line),
+                    },
+                    _ => log::warn!("a broken line in the checkpoint: {}", line),
+                }
+            }
+        } else {
+            let _ = fs::remove_file(&path);
+        }
+        if !completed.is_empty() || !scores.is_empty() {
+            log::info!(
+                "resume the training of {}: {:?} completed, {} combo(s) scored",
+                file_key,
+                completed,
+                scores.len()
+            );
+        }
+        Self {
+            path,
+            completed,
+            scores,
+        }
+    }
+
+    pub fn is_completed(&self, stage: &str) -> bool {
+        self.completed.contains(stage)
+    }
+
+    pub fn complete(&mut self, stage: &str) -> io::Result<()> {
+        self.append(stage)?;
+        self.completed.insert(stage.to_owned());
+        Ok(())
+    }
+
+    pub fn score(&self, stage: &str, combo: &str) -> Option<f64> {
+        self.scores
+            .get(&(stage.to_owned(), combo.to_owned()))
+            .copied()
+    }
+
+    pub fn record_score(&mut self, stage: &str, combo: &str, score: f64) -> io::Result<()> {
+        self.append(&format!("{}\t{}\t{}", stage, combo, score))?;
+        self.scores
+            .insert((stage.to_owned(), combo.to_owned()), score);
+        Ok(())
+    }
+
+    fn append(&self, line: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)
+    }
+
+    // Once the whole training has finished
+    pub fn clear(self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_resume_skips_completed_stages() {
+        let dir = env::temp_dir();
+        let file_key = format!("debot_checkpoint_{}", std::process::id());
+        let stages = ["classifier", "regressor_1", "regressor_2"];
+
+        // Interrupted after the first stage
+        let mut checkpoint = TrainingCheckpoint::load(&dir, &file_key, false);
+        checkpoint.complete(stages[0]).unwrap();
+        drop(checkpoint);
+
+        let mut checkpoint = TrainingCheckpoint::load(&dir, &file_key, true);
+        let mut trained = vec![];
+        for stage in stages {
+            if !checkpoint.is_completed(stage) {
+                trained.push(stage);
+                checkpoint.complete(stage).unwrap();
+            }
+        }
+        assert_eq!(trained, vec!["regressor_1", "regressor_2"]);
+        assert!(stages.iter().all(|stage| checkpoint.is_completed(stage)));
+
+        // The scores survive a restart
+        checkpoint.record_score("classifier", "a", 0.75).unwrap();
+        let mut checkpoint = TrainingCheckpoint::load(&dir, &file_key, true);
+        assert_eq!(checkpoint.score("classifier", "a"), Some(0.75));
+        assert_eq!(checkpoint.score("regressor_1", "a"), None);
+        checkpoint.complete("regressor_1").unwrap();
+        assert!(checkpoint.is_completed("regressor_1"));
+
+        // A fresh run trains everything again
+        let checkpoint = TrainingCheckpoint::load(&dir, &file_key, false);
+        assert!(!checkpoint.is_completed(stages[0]));
+        checkpoint.clear();
+        assert!(!dir.join(format!("{}.checkpoint", file_key)).exists());
+    }
+}
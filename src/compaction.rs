@@ -0,0 +1,160 @@
+// compaction.rs
+
+use debot_db::PricePoint;
+use rust_decimal::Decimal;
+use std::collections::BTreeMap;
+
+// 1-minute bars for the last day, 1-hour bars beyond
+pub const DEFAULT_RETENTION_POLICY: &str = "0:60,86400:3600";
+
+// A bar of `interval_secs` is formed once its whole interval is at least `min_age_secs` old.
+// The tier with the largest age that applies wins, so the intervals are expected to nest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    tiers: Vec<(i64, i64)>,
+}
+
+impl RetentionPolicy {
+    // e.g. "0:60,86400:3600" as <min_age_secs>:<interval_secs>
+    pub fn parse(policy: &str) -> Result<Self, String> {
+        let mut tiers = vec![];
+        for tier in policy.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (min_age, interval) = tier
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid retention tier: {}", tier))?;
+            let min_age = min_age
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| format!("Invalid retention age: {}", tier))?;
+            let interval = interval
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| format!("Invalid retention interval: {}", tier))?;
+            if min_age < 0 || interval <= 0 {
+                return Err(format!("Invalid retention tier: {}", tier));
+            }
+            tiers.push((min_age, interval));
+        }
+        tiers.sort_by_key(|(min_age, _)| *min_age);
+        Ok(Self { tiers })
+    }
+
+    // The start and the interval of the bar the timestamp falls in
+    fn bar_of(&self, timestamp: i64, now: i64) -> Option<(i64, i64)> {
+        self.tiers.iter().rev().find_map(|(min_age, interval)| {
+            let bar_start = timestamp - timestamp.rem_euclid(*interval);
+            (now - (bar_start + interval) >= *min_age).then_some((bar_start, *interval))
+        })
+    }
+}
+
+// Each bar keeps the last point of its interval, stamped with the start of the interval,
+// and the sums of the volumes and the trades. The points no tier applies to are kept as they are.
+pub fn compact_prices(
+    price_points: &[PricePoint],
+    now: i64,
+    policy: &RetentionPolicy,
+) -> Vec<PricePoint> {
+    let mut bars: BTreeMap<(i64, i64), PricePoint> = BTreeMap::new();
+    let mut sorted: Vec<&PricePoint> = price_points.iter().collect();
+    sorted.sort_by_key(|price_point| price_point.timestamp);
+
+    for price_point in sorted {
+        let (bar_start, interval) = match policy.bar_of(price_point.timestamp, now) {
+            Some(bar) => bar,
+            None => {
+                bars.insert((price_point.timestamp, 0), price_point.clone());
+                continue;
+            }
+        };
+
+        let mut bar = price_point.clone();
+        bar.timestamp = bar_start;
+        if let Some(previous) = bars.get(&(bar_start, interval)) {
+            bar.volume = sum(previous.volume, price_point.volume);
+            bar.num_trades = sum(previous.num_trades, price_point.num_trades);
+        }
+        bars.insert((bar_start, interval), bar);
+    }
+
+    bars.into_values().collect()
+}
+
+fn sum<T: std::ops::Add<Output = T>>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a + b),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+pub fn total_volume(price_points: &[PricePoint]) -> Decimal {
+    price_points
+        .iter()
+        .filter_map(|price_point| price_point.volume)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price_point(timestamp: i64, price: i64, volume: i64) -> PricePoint {
+        PricePoint {
+            timestamp,
+            price: Decimal::new(price, 0),
+            volume: Some(Decimal::new(volume, 0)),
+            num_trades: Some(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compact_prices() {
+        let policy = RetentionPolicy::parse(DEFAULT_RETENTION_POLICY).unwrap();
+        let now = 10 * 86400;
+
+        // A point every 10 seconds over the last 2 days
+        let price_points: Vec<PricePoint> = (0..2 * 8640)
+            .map(|i| {
+                let timestamp = now - 2 * 86400 + i * 10;
+                price_point(timestamp, 100 + i % 7, 1 + i % 3)
+            })
+            .collect();
+
+        let bars = compact_prices(&price_points, now, &policy);
+
+        assert_eq!(total_volume(&bars), total_volume(&price_points));
+        assert_eq!(
+            bars.iter().map(|bar| bar.num_trades.unwrap()).sum::<u64>(),
+            price_points.len() as u64
+        );
+
+        // Hours beyond the last day, and minutes within it
+        let (old, recent): (Vec<_>, Vec<_>) =
+            bars.iter().partition(|bar| bar.timestamp < now - 86400);
+        assert!(old.iter().all(|bar| bar.timestamp % 3600 == 0));
+        assert!(recent.iter().all(|bar| bar.timestamp % 60 == 0));
+        assert_eq!(old.len(), 24);
+        assert_eq!(recent.len(), 1440);
+
+        // A bar closes at the last price of its interval
+        assert_eq!(bars[0].price, price_points[359].price);
+        assert!(bars.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+    }
+
+    #[test]
+    fn test_retention_policy() {
+        assert!(RetentionPolicy::parse("0:60,86400:3600").is_ok());
+        assert!(RetentionPolicy::parse("0:0").is_err());
+        assert!(RetentionPolicy::parse("60").is_err());
+
+        // Nothing younger than an hour is touched
+        let policy = RetentionPolicy::parse("3600:300").unwrap();
+        let price_points = vec![price_point(1000, 1, 1), price_point(9990, 2, 1)];
+        let bars = compact_prices(&price_points, 10000, &policy);
+        assert_eq!(bars.len(), 2);
+        assert_eq!(bars[0].timestamp, 900);
+        assert_eq!(bars[1].timestamp, 9990);
+    }
+}
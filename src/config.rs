@@ -1,3 +1,4 @@
+use chrono::FixedOffset;
 use debot_market_analyzer::TradingStrategy;
 use debot_market_analyzer::TrendType;
 use debot_utils::decrypt_data_with_kms;
@@ -5,6 +6,7 @@ use rust_decimal::Decimal;
 use rust_decimal::Error as DecimalParseError;
 use std::env;
 use std::fmt;
+use std::net::IpAddr;
 use std::num::{ParseFloatError, ParseIntError};
 
 #[derive(Debug)]
@@ -14,6 +16,38 @@ pub struct HyperliquidConfig {
     pub vault_address: Option<String>,
 }
 
+// Basis of the drawdown check.
+// Equity includes unrealized PnL, so a position held through a temporary swing can trip the check.
+// Realized only counts closed positions, so losses of open positions are not seen until they are closed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawdownBasis {
+    Equity,
+    Realized,
+}
+
+// What a trader does on each tick.
+// ComputeNoTrade runs the models and logs their signals, but never places an order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TradingMode {
+    Trade,
+    OnlyReadPrice,
+    ComputeNoTrade,
+}
+
+// How a limit price is rounded to the tick.
+// Favorable rounds buys down and sells up, so that a rounded order does not cross the spread.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundingMode {
+    Nearest,
+    Favorable,
+}
+
+#[derive(Debug)]
+pub struct BybitConfig {
+    pub api_key: String,
+    pub api_secret: String,
+}
+
 #[derive(Debug)]
 pub struct EnvConfig {
     pub mongodb_uri: String,
@@ -34,9 +68,47 @@ pub struct EnvConfig {
     pub web_socket_endpoint: String,
     pub leverage: u32,
     pub strategy: TradingStrategy,
-    pub only_read_price: bool,
+    pub trading_mode: TradingMode,
     pub back_test: bool,
     pub path_to_models: Option<String>,
+    pub dd_basis: DrawdownBasis,
+    pub sort_filled_orders: bool,
+    pub max_token_notional: Option<Decimal>,
+    pub log_fund_equity: bool,
+    pub equity_sample_ticks: u32,
+    pub balance_retry_attempts: u32,
+    pub balance_retry_backoff_millis: u64,
+    pub circuit_break_cooldown_secs: u64,
+    pub metrics_port: Option<u16>,
+    pub slippage_bps: Decimal,
+    pub taker_fee_bps: Decimal,
+    pub maker_fee_bps: Decimal,
+    // Timezone of the day boundary for the daily PnL
+    pub reporting_timezone: FixedOffset,
+    pub elapsed_ema_alpha: f64,
+    pub param_reload_secs: Option<u64>,
+    pub max_trades_per_token_per_hour: Option<u32>,
+    pub dry_run_price_source: Option<String>,
+    pub liquidate_retry_max: u32,
+    pub random_seed: Option<u64>,
+    pub trace_decisions: bool,
+    pub kill_switch_file: Option<String>,
+    pub max_price_age_secs: Option<i64>,
+    pub stale_price_restart_ticks: Option<u32>,
+    pub backtest_report_path: Option<String>,
+    pub rounding_mode: RoundingMode,
+    pub open_resubmit_attempts: u32,
+    pub control_port: Option<u16>,
+    pub control_token: Option<String>,
+    pub control_bind: IpAddr,
+    pub min_margin_ratio: Option<Decimal>,
+    pub skip_precompute_in_backtest: Option<u32>,
+    pub max_concurrent_open_tokens: Option<usize>,
+    pub token_blacklist: Vec<String>,
+    pub token_whitelist: Option<Vec<String>>,
+    pub order_verify_ticks: Option<u64>,
+    // Set by the replay command, not by the environment
+    pub replay: bool,
 }
 
 #[derive(Debug)]
@@ -84,7 +156,7 @@ fn get_env_var<T: std::str::FromStr>(
     var_str.parse::<T>()
 }
 
-fn get_bool_env_var(var: &str, default: bool) -> bool {
+pub fn get_bool_env_var(var: &str, default: bool) -> bool {
     match env::var(var) {
         Ok(val) => {
             let lower_val = val.to_lowercase();
@@ -104,6 +176,25 @@ fn get_optional_env_var<T: std::str::FromStr>(var: &str) -> Option<T> {
     }
 }
 
+// A blank token would let in any request with an empty bearer, so it counts as unset
+fn usable_control_token(token: Option<String>) -> Option<String> {
+    match token {
+        Some(token) if token.trim().is_empty() => {
+            log::error!("CONTROL_TOKEN is blank and is ignored");
+            None
+        }
+        token => token,
+    }
+}
+
+fn parse_token_names(val: &str) -> Vec<String> {
+    val.split(',')
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
 pub fn get_config_from_env() -> Result<EnvConfig, ConfigError> {
     let mongodb_uri = env::var("MONGODB_URI").expect("MONGODB_URI must be set");
     let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
@@ -137,11 +228,149 @@ pub fn get_config_from_env() -> Result<EnvConfig, ConfigError> {
         "trendfollow" => TradingStrategy::TrendFollow(TrendType::Unknown),
         &_ => panic!("Unknown strategy"),
     };
-    let only_read_price = get_bool_env_var("ONLY_READ_PRICE", false);
+    // ONLY_READ_PRICE is kept for the existing deployments
+    let trading_mode = match env::var("TRADING_MODE").unwrap_or_default().as_str() {
+        "" if get_bool_env_var("ONLY_READ_PRICE", false) => TradingMode::OnlyReadPrice,
+        "" | "trade" => TradingMode::Trade,
+        "only_read_price" => TradingMode::OnlyReadPrice,
+        "compute_no_trade" => TradingMode::ComputeNoTrade,
+        other => {
+            return Err(ConfigError::OtherError(format!(
+                "Unknown TRADING_MODE: {}",
+                other
+            )))
+        }
+    };
     let back_test = get_bool_env_var("BACK_TEST", false);
 
     let path_to_models = env::var("PATH_TO_MODELS").ok();
 
+    let dd_basis = match env::var("DD_BASIS").unwrap_or_default().as_str() {
+        "" | "equity" => DrawdownBasis::Equity,
+        "realized" => DrawdownBasis::Realized,
+        other => {
+            return Err(ConfigError::OtherError(format!(
+                "Unknown DD_BASIS: {}",
+                other
+            )))
+        }
+    };
+
+    let sort_filled_orders = get_bool_env_var("SORT_FILLED_ORDERS", true);
+
+    let max_token_notional: Option<Decimal> = get_optional_env_var("MAX_TOKEN_NOTIONAL");
+
+    let log_fund_equity = get_bool_env_var("LOG_FUND_EQUITY", false);
+
+    let equity_sample_ticks: u32 = get_env_var("EQUITY_SAMPLE_TICKS", "60")?;
+
+    let balance_retry_attempts: u32 = get_env_var("BALANCE_RETRY_ATTEMPTS", "3")?;
+
+    let balance_retry_backoff_millis: u64 = get_env_var("BALANCE_RETRY_BACKOFF_MILLIS", "500")?;
+
+    let circuit_break_cooldown_secs: u64 = get_env_var("CIRCUIT_BREAK_COOLDOWN_SECS", "300")?;
+
+    let metrics_port: Option<u16> = get_optional_env_var("METRICS_PORT");
+
+    let slippage_bps: Decimal = get_env_var("SLIPPAGE_BPS", "50")?;
+
+    let taker_fee_bps: Decimal = get_env_var("TAKER_FEE_BPS", "2")?;
+
+    let maker_fee_bps: Decimal = get_env_var("MAKER_FEE_BPS", "2")?;
+
+    let reporting_timezone_offset: i32 = get_env_var("REPORTING_TIMEZONE_OFFSET", "0")?;
+    let reporting_timezone = FixedOffset::east_opt(reporting_timezone_offset).ok_or_else(|| {
+        ConfigError::OtherError(format!(
+            "Invalid REPORTING_TIMEZONE_OFFSET: {}",
+            reporting_timezone_offset
+        ))
+    })?;
+
+    let elapsed_ema_alpha: f64 = get_env_var("ELAPSED_EMA_ALPHA", "0.1")?;
+    if !(elapsed_ema_alpha > 0.0 && elapsed_ema_alpha <= 1.0) {
+        return Err(ConfigError::OtherError(format!(
+            "ELAPSED_EMA_ALPHA must be in (0, 1]: {}",
+            elapsed_ema_alpha
+        )));
+    }
+
+    let param_reload_secs: Option<u64> = get_optional_env_var("PARAM_RELOAD_SECS");
+
+    let max_trades_per_token_per_hour: Option<u32> =
+        get_optional_env_var("MAX_TRADES_PER_TOKEN_PER_HOUR");
+
+    // A price file saved by the "save" command, replayed in dry runs instead of live prices
+    let dry_run_price_source = env::var("DRY_RUN_PRICE_SOURCE").ok();
+
+    let liquidate_retry_max = get_env_var("LIQUIDATE_RETRY_MAX", "3")?;
+
+    // Seeds the emulated fills and the RandomWalk opens so that a backtest is reproducible
+    let random_seed = env::var("RANDOM_SEED")
+        .ok()
+        .map(|val| val.parse::<u64>())
+        .transpose()?;
+
+    let trace_decisions = get_bool_env_var("TRACE_DECISIONS", false);
+
+    // Everything is liquidated and the process exits once this file exists
+    let kill_switch_file = env::var("KILL_SWITCH_FILE").ok();
+
+    // A token whose price has not changed for this long is skipped, as its feed may be stalled.
+    // This is a price-unchanged heuristic, not a check of the ticker timestamp, so a quiet but
+    // live market is skipped too
+    let max_price_age_secs: Option<i64> = get_optional_env_var("MAX_PRICE_AGE_SECS");
+
+    // The dex connector is restarted once prices have been stale for this many ticks in a row.
+    // Unset by default, as a flat price alone does not mean the feed is dead
+    let stale_price_restart_ticks: Option<u32> = get_optional_env_var("STALE_PRICE_RESTART_TICKS");
+
+    // A CSV of every closed position written when a back test finishes
+    let backtest_report_path = env::var("BACKTEST_REPORT_PATH").ok();
+
+    // How the limit prices are rounded to the tick
+    let rounding_mode = match env::var("ROUNDING_MODE").unwrap_or_default().as_str() {
+        "" | "nearest" => RoundingMode::Nearest,
+        "favorable" => RoundingMode::Favorable,
+        other => {
+            return Err(ConfigError::OtherError(format!(
+                "Unknown ROUNDING_MODE: {}",
+                other
+            )))
+        }
+    };
+
+    // Times a maker open rejected as it would cross is resubmitted at the touch price
+    let open_resubmit_attempts: u32 = get_env_var("OPEN_RESUBMIT_ATTEMPTS", "0")?;
+
+    // The control API is served only with a token, as it can liquidate the account
+    let control_port: Option<u16> = get_optional_env_var("CONTROL_PORT");
+    let control_token = usable_control_token(env::var("CONTROL_TOKEN").ok());
+    // Only local clients reach it unless another address is given
+    let control_bind: IpAddr = get_env_var("CONTROL_BIND", "127.0.0.1")
+        .map_err(|e| ConfigError::OtherError(format!("Invalid CONTROL_BIND: {}", e)))?;
+
+    // Liquidates when equity / maintenance margin falls below this
+    let min_margin_ratio: Option<Decimal> = get_optional_env_var("MIN_MARGIN_RATIO");
+
+    // In back test, the open signal model is evaluated only every this many ticks while flat
+    let skip_precompute_in_backtest: Option<u32> =
+        get_optional_env_var("SKIP_PRECOMPUTE_IN_BACKTEST");
+
+    // Tokens without a position cannot open once this many tokens have one
+    let max_concurrent_open_tokens: Option<usize> =
+        get_optional_env_var("MAX_CONCURRENT_OPEN_TOKENS");
+
+    // Comma-separated tokens dropped from every fund, and if set, the only tokens traded
+    let token_blacklist = parse_token_names(&env::var("TOKEN_BLACKLIST").unwrap_or_default());
+    let token_whitelist = env::var("TOKEN_WHITELIST")
+        .ok()
+        .map(|val| parse_token_names(&val))
+        .filter(|tokens| !tokens.is_empty());
+
+    // Every this many ticks, the pending orders are checked against the open orders of the venue
+    let order_verify_ticks: Option<u64> =
+        get_optional_env_var("ORDER_VERIFY_TICKS").filter(|ticks| *ticks > 0);
+
     let env_config = EnvConfig {
         mongodb_uri,
         db_r_name,
@@ -161,9 +390,45 @@ pub fn get_config_from_env() -> Result<EnvConfig, ConfigError> {
         web_socket_endpoint,
         leverage,
         strategy,
-        only_read_price,
+        trading_mode,
         back_test,
         path_to_models,
+        dd_basis,
+        sort_filled_orders,
+        max_token_notional,
+        log_fund_equity,
+        equity_sample_ticks,
+        balance_retry_attempts,
+        balance_retry_backoff_millis,
+        circuit_break_cooldown_secs,
+        metrics_port,
+        slippage_bps,
+        taker_fee_bps,
+        maker_fee_bps,
+        reporting_timezone,
+        elapsed_ema_alpha,
+        param_reload_secs,
+        max_trades_per_token_per_hour,
+        dry_run_price_source,
+        liquidate_retry_max,
+        random_seed,
+        trace_decisions,
+        kill_switch_file,
+        max_price_age_secs,
+        stale_price_restart_ticks,
+        backtest_report_path,
+        rounding_mode,
+        open_resubmit_attempts,
+        control_port,
+        control_token,
+        control_bind,
+        min_margin_ratio,
+        skip_precompute_in_backtest,
+        max_concurrent_open_tokens,
+        token_blacklist,
+        token_whitelist,
+        order_verify_ticks,
+        replay: false,
     };
 
     Ok(env_config)
@@ -191,3 +456,38 @@ pub async fn get_hyperliquid_config_from_env() -> Result<HyperliquidConfig, Conf
         vault_address,
     })
 }
+
+pub async fn get_bybit_config_from_env() -> Result<BybitConfig, ConfigError> {
+    let api_key = env::var("BYBIT_API_KEY").expect("BYBIT_API_KEY must be set");
+    let api_secret = env::var("BYBIT_API_SECRET").expect("BYBIT_API_SECRET must be set");
+
+    let encrypted_data_key = env::var("ENCRYPTED_DATA_KEY")
+        .expect("ENCRYPTED_DATA_KEY must be set")
+        .replace(" ", ""); // Remove whitespace characters
+
+    let api_secret_vec = decrypt_data_with_kms(&encrypted_data_key, api_secret, true)
+        .await
+        .map_err(|_| ConfigError::OtherError("decrypt api_secret".to_owned()))?;
+    let api_secret = String::from_utf8(api_secret_vec).unwrap();
+
+    Ok(BybitConfig {
+        api_key,
+        api_secret,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_control_token_is_unset() {
+        assert_eq!(usable_control_token(None), None);
+        assert_eq!(usable_control_token(Some(String::new())), None);
+        assert_eq!(usable_control_token(Some(" \t".to_owned())), None);
+        assert_eq!(
+            usable_control_token(Some("secret".to_owned())),
+            Some("secret".to_owned())
+        );
+    }
+}
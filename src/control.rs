@@ -0,0 +1,196 @@
+// control.rs
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+// Overrides of a running bot, read by main_loop on every tick
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct ControlState {
+    // No new position is opened while paused. Closes go on.
+    pub paused: bool,
+    // Taken by main_loop, which liquidates all the traders once
+    pub liquidate_requested: bool,
+}
+
+pub type SharedControl = Arc<Mutex<ControlState>>;
+
+fn response(status: StatusCode, body: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain")
+        .body(Body::from(body.to_owned()))
+        .unwrap()
+}
+
+fn is_authorized(req: &Request<Body>, token: &str) -> bool {
+    if token.trim().is_empty() {
+        return false;
+    }
+    req.headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|bearer| constant_time_eq(bearer.as_bytes(), token.as_bytes()))
+}
+
+// Takes as long wherever the first difference is, so that the token cannot be guessed
+// byte by byte from the response times
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+async fn handle(req: Request<Body>, token: &str, control: &SharedControl) -> Response<Body> {
+    if !is_authorized(&req, token) {
+        return response(StatusCode::UNAUTHORIZED, "unauthorized\n");
+    }
+
+    let mut control = control.lock().await;
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/state") => {}
+        (&Method::POST, "/pause") => control.paused = true,
+        (&Method::POST, "/resume") => control.paused = false,
+        // Stays paused afterwards, so that the positions are not opened again right away
+        (&Method::POST, "/liquidate") => {
+            control.paused = true;
+            control.liquidate_requested = true;
+        }
+        _ => return response(StatusCode::NOT_FOUND, "not found\n"),
+    }
+    log::info!(
+        "control: {} {}: {:?}",
+        req.method(),
+        req.uri().path(),
+        *control
+    );
+
+    response(
+        StatusCode::OK,
+        &format!(
+            "paused={}\nliquidate_requested={}\n",
+            control.paused, control.liquidate_requested
+        ),
+    )
+}
+
+// Serves the control API authenticated by a bearer token, and returns the bound address
+pub fn start_server(
+    bind: IpAddr,
+    port: u16,
+    token: String,
+    control: SharedControl,
+) -> Result<SocketAddr, hyper::Error> {
+    let addr = SocketAddr::new(bind, port);
+    let token = Arc::new(token);
+
+    let make_service = make_service_fn(move |_conn| {
+        let token = token.clone();
+        let control = control.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let token = token.clone();
+                let control = control.clone();
+                async move { Ok::<_, Infallible>(handle(req, &token, &control).await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    log::info!("control server is listening on {}", local_addr);
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("control server: {:?}", e);
+        }
+    });
+
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn post(port: u16, path: &str, token: &str) -> reqwest::StatusCode {
+        reqwest::Client::new()
+            .post(format!("http://127.0.0.1:{}{}", port, path))
+            .bearer_auth(token)
+            .send()
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn test_control_endpoints() {
+        let control: SharedControl = Arc::new(Mutex::new(ControlState::default()));
+        let addr = start_server(
+            IpAddr::from([127, 0, 0, 1]),
+            0,
+            "secret".to_owned(),
+            control.clone(),
+        )
+        .unwrap();
+        assert!(addr.ip().is_loopback());
+        let port = addr.port();
+
+        assert_eq!(
+            post(port, "/pause", "wrong").await,
+            reqwest::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            post(port, "/pause", "secreT").await,
+            reqwest::StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(*control.lock().await, ControlState::default());
+
+        assert_eq!(
+            post(port, "/pause", "secret").await,
+            reqwest::StatusCode::OK
+        );
+        assert!(control.lock().await.paused);
+
+        assert_eq!(
+            post(port, "/resume", "secret").await,
+            reqwest::StatusCode::OK
+        );
+        assert!(!control.lock().await.paused);
+
+        assert_eq!(
+            post(port, "/liquidate", "secret").await,
+            reqwest::StatusCode::OK
+        );
+        assert_eq!(
+            *control.lock().await,
+            ControlState {
+                paused: true,
+                liquidate_requested: true,
+            }
+        );
+
+        assert_eq!(
+            post(port, "/unknown", "secret").await,
+            reqwest::StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn test_blank_token_lets_no_one_in() {
+        let request = |authorization: &str| {
+            Request::builder()
+                .header(hyper::header::AUTHORIZATION, authorization)
+                .body(Body::empty())
+                .unwrap()
+        };
+        assert!(!is_authorized(&request("Bearer "), ""));
+        assert!(!is_authorized(&request("Bearer  "), " "));
+        assert!(is_authorized(&request("Bearer secret"), "secret"));
+    }
+}
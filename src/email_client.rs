@@ -3,6 +3,7 @@ use lettre::transport::smtp::authentication::Credentials;
 use lettre::{SmtpTransport, Transport};
 use std::env;
 
+#[derive(Clone)]
 pub struct EmailClient {
     mailer: Option<SmtpTransport>,
     from_address: Option<String>,
@@ -39,7 +40,7 @@ impl EmailClient {
         }
     }
 
-    pub fn send(&self, subject: &str, body: &str) {
+    pub fn try_send(&self, subject: &str, body: &str) -> Result<(), ()> {
         if let Some(mailer) = &self.mailer {
             let from_address = self.from_address.as_ref().expect("from_address is missing");
             let to_address = self.to_address.as_ref().expect("to_address is missing");
@@ -52,9 +53,12 @@ impl EmailClient {
 
             if let Err(e) = mailer.send(&email) {
                 log::warn!("Failed to send an e-mail: {:?}", e);
+                return Err(());
             }
+            Ok(())
         } else {
             log::warn!("No mailer available to send the email");
+            Err(())
         }
     }
 }
@@ -1,21 +1,193 @@
-use crate::email_client::EmailClient;
+use crate::notifier::{self, Notifier};
+use chrono::Utc;
+use futures::future::join_all;
+use std::collections::VecDeque;
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+// Alerts waiting beyond this are dropped from the oldest
+const ALERT_BACKLOG: usize = 32;
+// Deliveries failed on every notifier are retried this many times before the alert file
+const ALERT_RETRY_MAX: u32 = 3;
+const ALERT_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+// Alerts waiting for the background sender, the oldest first
+struct AlertQueue {
+    alerts: Mutex<VecDeque<(String, String)>>,
+    backlog: usize,
+    notify: Notify,
+    delivering: AtomicBool,
+}
+
+impl AlertQueue {
+    fn new(backlog: usize) -> Self {
+        AlertQueue {
+            alerts: Mutex::new(VecDeque::new()),
+            backlog: backlog.max(1),
+            notify: Notify::new(),
+            delivering: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, subject: &str, body: &str) {
+        let mut alerts = self.alerts.lock().unwrap();
+        if alerts.len() >= self.backlog {
+            if let Some((dropped, _)) = alerts.pop_front() {
+                log::warn!("The alert backlog is full, drop the oldest: {}", dropped);
+            }
+        }
+        alerts.push_back((subject.to_owned(), body.to_owned()));
+        drop(alerts);
+        self.notify.notify_one();
+    }
+
+    fn pop(&self) -> Option<(String, String)> {
+        let mut alerts = self.alerts.lock().unwrap();
+        let alert = alerts.pop_front();
+        // Set under the lock, so that is_idle never sees an alert taken but not yet delivering
+        self.delivering.store(alert.is_some(), Ordering::SeqCst);
+        alert
+    }
+
+    fn is_idle(&self) -> bool {
+        self.alerts.lock().unwrap().is_empty() && !self.delivering.load(Ordering::SeqCst)
+    }
+}
+
+struct AlertSender {
+    notifiers: Vec<Box<dyn Notifier>>,
+    alert_file_path: String,
+    retry_interval: Duration,
+}
+
+impl AlertSender {
+    async fn run(self, queue: Arc<AlertQueue>) {
+        loop {
+            while let Some((subject, body)) = queue.pop() {
+                self.deliver(&subject, &body).await;
+            }
+            queue.notify.notified().await;
+        }
+    }
+
+    // Fans out to every configured notifier, and falls back to the local alert file
+    // when none of them succeeded even after the retries
+    async fn deliver(&self, subject: &str, body: &str) {
+        for attempt in 0..=ALERT_RETRY_MAX {
+            if attempt > 0 {
+                tokio::time::sleep(self.retry_interval).await;
+            }
+            let results = join_all(
+                self.notifiers
+                    .iter()
+                    .map(|notifier| notifier.send(subject, body)),
+            )
+            .await;
+            if results.iter().any(|result| result.is_ok()) {
+                return;
+            }
+        }
+        if self.write_alert_file(subject, body).is_ok() {
+            return;
+        }
+        log::error!(
+            "Failed to deliver the alert on every channel: {} {}",
+            subject,
+            body
+        );
+    }
+
+    fn write_alert_file(&self, subject: &str, body: &str) -> Result<(), ()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.alert_file_path)
+            .map_err(|e| log::warn!("Failed to open {}: {:?}", self.alert_file_path, e))?;
+        writeln!(file, "{} {} {}", Utc::now().to_rfc3339(), subject, body)
+            .map_err(|e| log::warn!("Failed to write {}: {:?}", self.alert_file_path, e))
+    }
+}
+
+// Queues alerts for the background sender of an ErrorManager, from wherever they are raised
+#[derive(Clone)]
+pub(crate) struct AlertHandle {
+    alerts: Arc<AlertQueue>,
+}
+
+impl AlertHandle {
+    pub fn send(&self, subject: &str, body: &str) {
+        self.alerts.push(subject, body);
+    }
+}
+
+#[cfg(test)]
+impl AlertHandle {
+    // Nothing delivers these, so the tests can look at what was queued
+    pub fn detached() -> Self {
+        AlertHandle {
+            alerts: Arc::new(AlertQueue::new(ALERT_BACKLOG)),
+        }
+    }
+
+    pub fn queued(&self) -> Vec<(String, String)> {
+        self.alerts.alerts.lock().unwrap().iter().cloned().collect()
+    }
+}
 
 pub(crate) struct ErrorManager {
     first_error_time: Option<Instant>,
-    email_client: EmailClient,
+    alerts: Arc<AlertQueue>,
 }
 
 impl ErrorManager {
     pub fn new() -> Self {
+        Self::with_sender(
+            AlertSender {
+                notifiers: notifier::from_env(),
+                alert_file_path: env::var("ALERT_FILE_PATH")
+                    .unwrap_or_else(|_| "debot_alerts.log".to_owned()),
+                retry_interval: ALERT_RETRY_INTERVAL,
+            },
+            ALERT_BACKLOG,
+        )
+    }
+
+    // The alerts are delivered by a background task, so that a slow notifier never
+    // stalls the trading loop
+    fn with_sender(sender: AlertSender, backlog: usize) -> Self {
+        let alerts = Arc::new(AlertQueue::new(backlog));
+        tokio::spawn(sender.run(alerts.clone()));
         ErrorManager {
             first_error_time: None,
-            email_client: EmailClient::new(),
+            alerts,
         }
     }
 
     pub fn send(&self, subject: &str, body: &str) {
-        self.email_client.send(subject, body);
+        self.alerts.push(subject, body);
+    }
+
+    pub fn alert_handle(&self) -> AlertHandle {
+        AlertHandle {
+            alerts: self.alerts.clone(),
+        }
+    }
+
+    // Waits for the queued alerts before the process exits
+    pub async fn flush(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while !self.alerts.is_idle() {
+            if Instant::now() >= deadline {
+                log::warn!("Some alerts were not delivered before the exit");
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
     }
 
     pub fn save_first_error_time(&mut self) {
@@ -36,3 +208,137 @@ impl ErrorManager {
         }
     }
 }
+
+// Returns how long to wait before the circuit break is checked again, or None to resume trading
+pub(crate) fn circuit_break_cooldown(circuit_break: bool, cooldown_secs: u64) -> Option<Duration> {
+    if circuit_break {
+        Some(Duration::from_secs(cooldown_secs.max(1)))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    struct MockNotifier {
+        succeeds: bool,
+        sent: Arc<Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Notifier for MockNotifier {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        async fn send(&self, subject: &str, _body: &str) -> Result<(), ()> {
+            self.sent.lock().unwrap().push(subject.to_owned());
+            if self.succeeds {
+                Ok(())
+            } else {
+                Err(())
+            }
+        }
+    }
+
+    fn alert_sender(
+        succeeds: &[bool],
+        alert_file_path: &str,
+    ) -> (AlertSender, Vec<Arc<Mutex<Vec<String>>>>) {
+        let sents: Vec<_> = succeeds
+            .iter()
+            .map(|_| Arc::new(Mutex::new(vec![])))
+            .collect();
+        let notifiers = succeeds
+            .iter()
+            .zip(sents.iter())
+            .map(|(succeeds, sent)| {
+                Box::new(MockNotifier {
+                    succeeds: *succeeds,
+                    sent: sent.clone(),
+                }) as Box<dyn Notifier>
+            })
+            .collect();
+        let alert_sender = AlertSender {
+            notifiers,
+            alert_file_path: alert_file_path.to_owned(),
+            retry_interval: Duration::from_millis(1),
+        };
+        (alert_sender, sents)
+    }
+
+    #[tokio::test]
+    async fn test_send_fans_out_to_every_notifier() {
+        let alert_file_path =
+            env::temp_dir().join(format!("debot_alerts_{}_fan_out.log", std::process::id()));
+        let alert_file_path = alert_file_path.to_string_lossy().into_owned();
+
+        // One failing backend does not stop the others
+        let (alert_sender, sents) = alert_sender(&[false, true, true], &alert_file_path);
+        alert_sender.deliver("[debot] Draw down!", "test-db").await;
+
+        for sent in sents {
+            assert_eq!(*sent.lock().unwrap(), vec!["[debot] Draw down!".to_owned()]);
+        }
+        assert!(!std::path::Path::new(&alert_file_path).exists());
+    }
+
+    #[tokio::test]
+    async fn test_send_falls_back_to_alert_file() {
+        let alert_file_path =
+            env::temp_dir().join(format!("debot_alerts_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&alert_file_path);
+
+        // Every notifier fails
+        let (alert_sender, sents) =
+            alert_sender(&[false, false], &alert_file_path.to_string_lossy());
+        alert_sender.deliver("[debot] Draw down!", "test-db").await;
+
+        // Retried before giving up
+        for sent in sents {
+            assert_eq!(sent.lock().unwrap().len(), ALERT_RETRY_MAX as usize + 1);
+        }
+        let alerts = std::fs::read_to_string(&alert_file_path).unwrap();
+        assert!(alerts.contains("[debot] Draw down! test-db"));
+        let _ = std::fs::remove_file(&alert_file_path);
+    }
+
+    #[tokio::test]
+    async fn test_send_drops_oldest_on_overflow() {
+        let (alert_sender, sents) = alert_sender(&[true], "unused");
+        let queue = Arc::new(AlertQueue::new(2));
+
+        // Enqueued before the sender runs, as while a slow delivery is in flight
+        for i in 0..5 {
+            queue.push(&format!("alert {}", i), "test-db");
+        }
+        let error_manager = ErrorManager {
+            first_error_time: None,
+            alerts: queue.clone(),
+        };
+        tokio::spawn(alert_sender.run(queue));
+        error_manager.flush(Duration::from_secs(5)).await;
+
+        assert_eq!(
+            *sents[0].lock().unwrap(),
+            vec!["alert 3".to_owned(), "alert 4".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_circuit_break_cooldown() {
+        assert_eq!(circuit_break_cooldown(false, 300), None);
+        assert_eq!(
+            circuit_break_cooldown(true, 300),
+            Some(Duration::from_secs(300))
+        );
+        // Never busy-loop on a zero cooldown
+        assert_eq!(
+            circuit_break_cooldown(true, 0),
+            Some(Duration::from_secs(1))
+        );
+    }
+}
@@ -1,38 +1,70 @@
 // main.rs
 
-use backtest::download_data;
+use backfill::{backfill_prices, write_backfilled_point, BackfillMode};
+use backtest::{download_data, download_walk_forward_data};
+use checkpoint::TrainingCheckpoint;
 use chrono::{DateTime, FixedOffset, Utc};
+use compaction::{compact_prices, total_volume, RetentionPolicy, DEFAULT_RETENTION_POLICY};
 use config::EnvConfig;
-use debot_db::{ModelParams, PricePoint, TransactionLog};
+use control::{ControlState, SharedControl};
+use debot_db::{ModelParams, PositionLog, PriceLog, PricePoint, TransactionLog};
 use debot_market_analyzer::{TradingStrategy, TrendType};
 use debot_ml::{grid_search_and_train_classifier, grid_search_and_train_regressor};
 use debot_utils::DateTimeUtils;
+use dex_connector::{DexConnector, DexError};
 use env_logger::Builder;
-use error_manager::ErrorManager;
+use error_manager::{circuit_break_cooldown, ErrorManager};
+use futures::future::join_all;
 use log::LevelFilter;
+use metrics::{MetricsSnapshot, SharedMetrics};
 use rust_decimal::Decimal;
 use std::env;
+use std::future::Future;
 use std::io::Write;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU64, Ordering};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::time::Instant;
-use trade::{trader_config, DerivativeTrader};
+use trade::derivative_trader::DerivativeTraderConfig;
+use trade::dex_connector_box::{DexConnectorBox, DexConnectorExt};
+use trade::dex_emulator::EmulatorFillSettings;
+use trade::{fund_config, trader_config, DerivativeTrader};
+use walk_forward::{train_classifier_on_folds, train_regressor_on_folds, RegressorTarget};
 
+use crate::trade::db_handler::DBHandlerConfig;
 use crate::trade::DBHandler;
 use csv::Writer;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+mod backfill;
 mod backtest;
+mod checkpoint;
+mod compaction;
 mod config;
+mod control;
 mod email_client;
 mod error_manager;
+mod metrics;
+mod notifier;
 mod trade;
+mod walk_forward;
+
+const KILL_SWITCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// How long a signal waits for the orders of the current tick before shutting down
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+const WALK_FORWARD_FOLDS: usize = 5;
+// Sizes closer than this are the same position, as the DB size is derived from USD amounts
+const RECONCILE_SIZE_TOLERANCE: Decimal = Decimal::from_parts(1, 0, 0, false, 4);
 
 static MAX_ELAPSED: AtomicU64 = AtomicU64::new(0);
+static EMA_ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+type TraderInstance<'a> = (DerivativeTrader, &'a EnvConfig, ErrorManager);
 
 #[cfg(test)]
 #[macro_use]
@@ -81,6 +113,12 @@ async fn main() -> std::io::Result<()> {
 
     let command = &args[1];
     let key = &args[2];
+
+    // A replay reads the prices from the file, and connects to neither the DB nor a venue
+    if command == "replay" {
+        return run_replay_program(key).await;
+    }
+
     let mongodb_uri = env::var("MONGODB_URI").expect("MONGODB_URI must be set");
 
     log::info!("Received key: {}", key);
@@ -104,7 +142,108 @@ async fn main() -> std::io::Result<()> {
             TransactionLog::copy_price(&db_r, &db_w, None).await;
             log::info!("Price copied to {}", key);
         }
+        // The key is the retention policy, e.g. "0:60,86400:3600", or "default"
+        "compact" => {
+            let policy = if key == "default" {
+                DEFAULT_RETENTION_POLICY
+            } else {
+                key.as_str()
+            };
+            let policy = RetentionPolicy::parse(policy).expect("Invalid retention policy");
+            let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
+            let db_w_name = env::var("DB_W_NAME").expect("DB_W_NAME must be set");
+            let transaction_log = TransactionLog::new(
+                Some(0),
+                Some(0),
+                Some(0),
+                &mongodb_uri,
+                &db_r_name,
+                &db_w_name,
+                false,
+            )
+            .await;
+            let db_r = transaction_log.get_r_db().await.expect("db_r is none");
+            let db_w = transaction_log.get_w_db().await.expect("db_w is none");
+            let prices = TransactionLog::get_price_market_data(&db_r, None, None, true).await;
+            let now = Utc::now().timestamp();
+
+            // The ids restart from 1, so DB_W_NAME is expected to be a fresh DB as for copy
+            let mut id = 0;
+            for (name, token_prices) in prices {
+                for (token_name, price_points) in token_prices {
+                    let bars = compact_prices(&price_points, now, &policy);
+                    log::info!(
+                        "{} {}: {} -> {} points, volume = {}",
+                        name,
+                        token_name,
+                        price_points.len(),
+                        bars.len(),
+                        total_volume(&bars)
+                    );
+                    for bar in bars {
+                        id += 1;
+                        let item = PriceLog {
+                            id: Some(id),
+                            name: name.clone(),
+                            token_name: token_name.clone(),
+                            price_point: bar,
+                        };
+                        if let Err(e) = TransactionLog::update_price(&db_w, item).await {
+                            log::error!("compact: {:?}", e);
+                        }
+                    }
+                }
+            }
+            log::info!("Price compacted to {}", db_w_name);
+        }
+        // The key is the expected interval of the prices in seconds
+        "backfill" => {
+            let interval_secs: i64 = key.parse().expect("Invalid interval");
+            let mode: BackfillMode = env::var("BACKFILL_MODE")
+                .unwrap_or_else(|_| "interpolate".to_owned())
+                .parse()
+                .expect("Invalid BACKFILL_MODE");
+            let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
+            let db_w_name = env::var("DB_W_NAME").expect("DB_W_NAME must be set");
+            let transaction_log = TransactionLog::new(
+                Some(0),
+                Some(0),
+                Some(0),
+                &mongodb_uri,
+                &db_r_name,
+                &db_w_name,
+                false,
+            )
+            .await;
+            let db_r = transaction_log.get_r_db().await.expect("db_r is none");
+            let db_w = transaction_log.get_w_db().await.expect("db_w is none");
+            let prices = TransactionLog::get_price_market_data(&db_r, None, None, true).await;
+
+            // The ids restart from 1, so DB_W_NAME is expected to be a fresh DB as for copy
+            let mut id = 0;
+            for (name, token_prices) in prices {
+                for (token_name, price_points) in token_prices {
+                    let backfilled = backfill_prices(&price_points, interval_secs, mode);
+                    log::info!(
+                        "{} {}: {} points are filled",
+                        name,
+                        token_name,
+                        backfilled.iter().filter(|point| point.interpolated).count()
+                    );
+                    for point in backfilled {
+                        id += 1;
+                        if let Err(e) =
+                            write_backfilled_point(&db_w, id, &name, &token_name, &point).await
+                        {
+                            log::error!("backfill: {:?}", e);
+                        }
+                    }
+                }
+            }
+            log::info!("Price backfilled to {}", db_w_name);
+        }
         "get" => {
+            let paper = args.get(3).is_some_and(|arg| arg == "--paper");
             let db_w_name = "unused";
             let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
             let transaction_log = TransactionLog::new(
@@ -118,16 +257,81 @@ async fn main() -> std::io::Result<()> {
             )
             .await;
             let db = transaction_log.get_r_db().await.expect("db is none");
-            let positions = TransactionLog::get_all_open_positions(&db).await;
+            let positions = if paper {
+                paper_positions(&mongodb_uri, &db_r_name).await
+            } else {
+                TransactionLog::get_all_positions(&db).await
+            };
+            let prices = TransactionLog::get_price_market_data(&db, None, None, true).await;
+            let latest_prices = latest_prices(&prices);
+            let now = Utc::now().timestamp();
 
             let mut wtr = Writer::from_writer(File::create(&key)?);
 
-            wtr.write_record(&["position_type", "pnl"])?;
+            wtr.write_record(POSITION_AGING_HEADER)?;
+
+            for record in position_aging_records(&positions, &latest_prices, now) {
+                wtr.write_record(record)?;
+            }
+
+            wtr.flush()?;
+
+            log::info!("Positions saved to {}", key);
+        }
+        "get_full" => {
+            let paper = args.get(3).is_some_and(|arg| arg == "--paper");
+            let db_w_name = "unused";
+            let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
+            let transaction_log = TransactionLog::new(
+                Some(0),
+                Some(0),
+                Some(0),
+                &mongodb_uri,
+                &db_r_name,
+                db_w_name,
+                false,
+            )
+            .await;
+            let db = transaction_log.get_r_db().await.expect("db is none");
+            let positions = if paper {
+                paper_positions(&mongodb_uri, &db_r_name).await
+            } else {
+                TransactionLog::get_all_positions(&db).await
+            };
+
+            let mut wtr = Writer::from_writer(File::create(key)?);
+
+            wtr.write_record([
+                "fund_name",
+                "token_name",
+                "position_type",
+                "state",
+                "open_time",
+                "close_time",
+                "average_open_price",
+                "close_price",
+                "asset_in_usd",
+                "fee",
+                "pnl",
+                "atr",
+                "rsi",
+            ])?;
 
             for position in positions {
-                wtr.write_record(&[
+                wtr.write_record([
+                    position.fund_name.clone(),
+                    position.token_name.clone(),
                     position.position_type.clone(),
+                    position.state.clone(),
+                    position.open_time_str.clone(),
+                    position.close_time_str.clone(),
+                    position.average_open_price.round_dp(6).to_string(),
+                    position.close_price.round_dp(6).to_string(),
+                    position.asset_in_usd.round_dp(3).to_string(),
+                    position.fee.round_dp(6).to_string(),
                     position.pnl.round_dp(3).to_string(),
+                    position.debug.input_4.to_string(),
+                    position.debug.input_9.to_string(),
                 ])?;
             }
 
@@ -198,67 +402,291 @@ async fn main() -> std::io::Result<()> {
             )
             .await;
 
-            let (x, y_classifier, y_regressor_1, y_regressor_2) =
-                download_data(&transaction_logs, key, &strategy).await;
-
-            grid_search_and_train_classifier(&file_key, &model_params, x.clone(), y_classifier, 5)
-                .await;
-            grid_search_and_train_regressor(
-                &file_key,
-                &model_params,
-                x.clone(),
-                y_regressor_1,
-                5,
-                30,
-                1,
-                Some(0.0),
+            // With WALK_FORWARD, the hyperparameters are chosen on the time-ordered folds
+            let walk_forward = config::get_bool_env_var("WALK_FORWARD", false);
+            let (folds, (x, y_classifier, y_regressor_1, y_regressor_2)) = if walk_forward {
+                let data = download_walk_forward_data(
+                    &transaction_logs,
+                    key,
+                    &strategy,
+                    WALK_FORWARD_FOLDS,
+                )
+                .await
+                .expect("Not enough samples for walk-forward");
+                (Some(data.folds), data.all)
+            } else {
+                (None, download_data(&transaction_logs, key, &strategy).await)
+            };
+
+            // With RESUME_TRAINING, the grid searches completed by an interrupted run are skipped,
+            // and so are the walk-forward candidates it has scored
+            let resume = env::var("RESUME_TRAINING").is_ok_and(|val| val == "true");
+            let checkpoint_dir =
+                env::var("TRAINING_CHECKPOINT_DIR").unwrap_or_else(|_| ".".to_owned());
+            let mut checkpoint =
+                TrainingCheckpoint::load(Path::new(&checkpoint_dir), &file_key, resume);
+
+            if !checkpoint.is_completed("classifier") {
+                match &folds {
+                    Some(folds) => {
+                        train_classifier_on_folds(
+                            &file_key,
+                            &model_params,
+                            folds,
+                            x.clone(),
+                            y_classifier,
+                            &mut checkpoint,
+                        )
+                        .await
+                    }
+                    None => {
+                        grid_search_and_train_classifier(
+                            &file_key,
+                            &model_params,
+                            x.clone(),
+                            y_classifier,
+                            5,
+                        )
+                        .await
+                    }
+                }
+                checkpoint.complete("classifier")?;
+            }
+            if !checkpoint.is_completed("regressor_1") {
+                match &folds {
+                    Some(folds) => {
+                        let target = RegressorTarget {
+                            suffix: 1,
+                            num_features: 30,
+                            exclude_value: Some(0.0),
+                        };
+                        train_regressor_on_folds(
+                            &file_key,
+                            &model_params,
+                            folds,
+                            x.clone(),
+                            y_regressor_1,
+                            &target,
+                            &mut checkpoint,
+                        )
+                        .await
+                    }
+                    None => {
+                        grid_search_and_train_regressor(
+                            &file_key,
+                            &model_params,
+                            x.clone(),
+                            y_regressor_1,
+                            5,
+                            30,
+                            1,
+                            Some(0.0),
+                        )
+                        .await
+                    }
+                }
+                checkpoint.complete("regressor_1")?;
+            }
+            if !checkpoint.is_completed("regressor_2") {
+                match &folds {
+                    Some(folds) => {
+                        let target = RegressorTarget {
+                            suffix: 2,
+                            num_features: 30,
+                            exclude_value: Some(-1.0),
+                        };
+                        train_regressor_on_folds(
+                            &file_key,
+                            &model_params,
+                            folds,
+                            x,
+                            y_regressor_2,
+                            &target,
+                            &mut checkpoint,
+                        )
+                        .await
+                    }
+                    None => {
+                        grid_search_and_train_regressor(
+                            &file_key,
+                            &model_params,
+                            x,
+                            y_regressor_2,
+                            5,
+                            30,
+                            2,
+                            Some(-1.0),
+                        )
+                        .await
+                    }
+                }
+                checkpoint.complete("regressor_2")?;
+            }
+            checkpoint.clear();
+        }
+        "reconcile" => {
+            let dex_name = key;
+            let fix = args.get(3).is_some_and(|arg| arg == "--fix");
+            let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
+            let db_w_name = if fix {
+                env::var("DB_W_NAME").expect("DB_W_NAME must be set")
+            } else {
+                "unused".to_owned()
+            };
+            let transaction_log = TransactionLog::new(
+                Some(0),
+                Some(0),
+                Some(0),
+                &mongodb_uri,
+                &db_r_name,
+                &db_w_name,
+                false,
             )
             .await;
-            grid_search_and_train_regressor(
-                &file_key,
-                &model_params,
-                x,
-                y_regressor_2,
-                5,
-                30,
-                2,
-                Some(-1.0),
+            let db = transaction_log.get_r_db().await.expect("db is none");
+            let positions = TransactionLog::get_all_positions(&db).await;
+
+            let rest_endpoint = env::var("REST_ENDPOINT").expect("REST_ENDPOINT must be set");
+            let web_socket_endpoint =
+                env::var("WEB_SOCKET_ENDPOINT").expect("WEB_SOCKET_ENDPOINT must be set");
+            let dex_connector = DexConnectorBox::create(
+                dex_name,
+                &rest_endpoint,
+                &web_socket_endpoint,
+                false,
+                EmulatorFillSettings::default(),
+                None,
             )
+            .await
+            .expect("Failed to create the dex connector");
+            dex_connector
+                .start()
+                .await
+                .expect("Failed to start the dex connector");
+            let reconciliation = reconcile_positions(positions, &dex_connector).await;
+            let _ = dex_connector.stop().await;
+            let reconciliation = reconciliation.expect("Failed to get the positions");
+
+            if reconciliation.drifts.is_empty() {
+                log::info!("No drift between {} and {}", db_r_name, dex_name);
+            }
+            for drift in &reconciliation.drifts {
+                log::info!("{}", drift);
+            }
+
+            if fix {
+                // Only the positions the venue no longer has are closed, size mismatches are left to the operator
+                let db_w = transaction_log.get_w_db().await.expect("db_w is none");
+                for mut position in reconciliation.flat_positions {
+                    position.state = "Closed(Reconciled)".to_owned();
+                    match TransactionLog::update_transaction(&db_w, &position).await {
+                        Ok(_) => log::info!(
+                            "reconcile: closed position {:?} of {}",
+                            position.id,
+                            position.token_name
+                        ),
+                        Err(e) => log::error!("reconcile: {:?}", e),
+                    }
+                }
+            }
+        }
+        "stats" => {
+            let days: u32 = key.parse().expect("days must be a number");
+            let db_r_name = env::var("DB_R_NAME").expect("DB_R_NAME must be set");
+            let db_handler = DBHandler::new(DBHandlerConfig {
+                max_position_counter: Some(0),
+                max_price_counter: Some(0),
+                max_balance_counter: Some(0),
+                mongodb_uri: mongodb_uri.clone(),
+                db_w_name: "unused".to_owned(),
+                db_r_name: db_r_name.clone(),
+                back_test: false,
+                path_to_models: None,
+                dry_run: false,
+            })
             .await;
+
+            match db_handler.compute_performance_stats(days).await {
+                Some(stats) => {
+                    log::info!("days: {}", stats.days);
+                    log::info!("annualized return: {:.2}", stats.annualized_return);
+                    log::info!("volatility: {:.2}", stats.volatility);
+                    log::info!("sharpe: {:.3}", stats.sharpe);
+                    log::info!("sortino: {:.3}", stats.sortino);
+                    log::info!("max drawdown: {:.2}", stats.max_drawdown);
+                }
+                None => log::info!("Not enough PnL logs in {}", db_r_name),
+            }
         }
         _ => {}
     }
     Ok(())
 }
 
+// The positions logged by dry runs, which are kept apart from the live ones
+async fn paper_positions(mongodb_uri: &str, db_r_name: &str) -> Vec<PositionLog> {
+    let db_handler = DBHandler::new(DBHandlerConfig {
+        max_position_counter: Some(0),
+        max_price_counter: Some(0),
+        max_balance_counter: Some(0),
+        mongodb_uri: mongodb_uri.to_owned(),
+        db_w_name: "unused".to_owned(),
+        db_r_name: db_r_name.to_owned(),
+        back_test: false,
+        path_to_models: None,
+        dry_run: true,
+    })
+    .await;
+    db_handler.get_paper_positions().await
+}
+
+async fn create_db_handler(config: &EnvConfig) -> Arc<Mutex<DBHandler>> {
+    let max_position_counter = config.position_log_limit;
+
+    // Size the price buffer by the tokens actually traded, not the whole token list
+    let (_, _, dex_name) = &trader_config::get(&config.strategy)[0];
+    let fund_configs = fund_config::get(dex_name, &config.strategy, config.leverage);
+    let max_price_size = config.max_price_size * fund_config::token_count(&fund_configs);
+    Arc::new(Mutex::new(
+        DBHandler::new(DBHandlerConfig {
+            max_position_counter,
+            max_price_counter: Some(max_price_size),
+            max_balance_counter: Some(365),
+            mongodb_uri: config.mongodb_uri.clone(),
+            db_w_name: config.db_w_name.clone(),
+            db_r_name: config.db_r_name.clone(),
+            back_test: config.back_test,
+            path_to_models: config.path_to_models.clone(),
+            dry_run: config.dry_run,
+        })
+        .await,
+    ))
+}
+
 async fn run_default_program() -> std::io::Result<()> {
     // Load the configs
     let config = config::get_config_from_env().expect("Invalid configuration");
 
     // Set up the DB handler
-    let max_position_counter = config.position_log_limit;
-    let max_price_size = config.max_price_size * trade::TOKEN_LIST_SIZE;
-    let db_handler = Arc::new(Mutex::new(
-        DBHandler::new(
-            max_position_counter,
-            Some(max_price_size),
-            Some(365),
-            &config.mongodb_uri,
-            &config.db_w_name,
-            &config.db_r_name,
-            config.back_test,
-            config.path_to_models.as_ref(),
-        )
-        .await,
-    ));
+    let db_handler = create_db_handler(&config).await;
 
     // Read the last App state, and the market data from thd DB
-    let (last_execution_time, last_equity, curcuit_break) =
-        db_handler.lock().await.get_app_state().await;
-    if curcuit_break {
-        log::warn!("curcuit break!");
-        loop {}
-    }
+    let tripped_at = Instant::now();
+    let (last_execution_time, last_equity) = loop {
+        let (last_execution_time, last_equity, curcuit_break) =
+            db_handler.lock().await.get_app_state().await;
+        match circuit_break_cooldown(curcuit_break, config.circuit_break_cooldown_secs) {
+            Some(cooldown) => {
+                log::warn!(
+                    "curcuit break! elapsed = {} secs, check again in {} secs",
+                    tripped_at.elapsed().as_secs(),
+                    cooldown.as_secs()
+                );
+                tokio::time::sleep(cooldown).await;
+            }
+            None => break (last_execution_time, last_equity),
+        }
+    };
 
     let price_size = if config.back_test {
         None
@@ -271,98 +699,254 @@ async fn run_default_program() -> std::io::Result<()> {
         .get_latest_price_market_data(price_size)
         .await;
 
-    // Initialize a trader instance
-    let mut trader_instance = prepare_trader_instance(&config, db_handler, price_market_data).await;
+    if !config.back_test {
+        let mut dex_names: Vec<String> = trader_config::get(&config.strategy)
+            .into_iter()
+            .map(|(_, _, dex_name)| dex_name)
+            .collect();
+        dex_names.sort();
+        dex_names.dedup();
+        for dex_name in &dex_names {
+            if let Err(e) = DerivativeTrader::preflight(
+                dex_name,
+                &config.rest_endpoint,
+                &config.web_socket_endpoint,
+                fund_config::TOKEN_LIST,
+            )
+            .await
+            {
+                log::error!("{}", e);
+                return Err(std::io::Error::other(e));
+            }
+        }
+    }
+
+    // Initialize the trader instances
+    let mut trader_instances =
+        prepare_trader_instance(&config, db_handler, price_market_data).await;
+
+    // Start the metrics server
+    let metrics = match config.metrics_port {
+        Some(port) => {
+            let shared_metrics: SharedMetrics = Arc::new(Mutex::new(MetricsSnapshot::default()));
+            match metrics::start_server(port, shared_metrics.clone()) {
+                Ok(_) => Some(shared_metrics),
+                Err(e) => {
+                    log::error!("Failed to start the metrics server: {:?}", e);
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+
+    // Start the control server
+    let control = match (config.control_port, &config.control_token) {
+        (Some(port), Some(token)) => {
+            let shared_control: SharedControl = Arc::new(Mutex::new(ControlState::default()));
+            match control::start_server(
+                config.control_bind,
+                port,
+                token.to_owned(),
+                shared_control.clone(),
+            ) {
+                Ok(_) => Some(shared_control),
+                Err(e) => {
+                    log::error!("Failed to start the control server: {:?}", e);
+                    None
+                }
+            }
+        }
+        (Some(_), None) => {
+            log::error!("CONTROL_PORT is set without CONTROL_TOKEN, the control server is off");
+            None
+        }
+        (None, _) => None,
+    };
 
     // Start main loop
-    main_loop(&mut trader_instance, last_execution_time, last_equity, None).await
+    main_loop(
+        &mut trader_instances,
+        last_execution_time,
+        last_equity,
+        None,
+        metrics,
+        control,
+    )
+    .await
+}
+
+async fn run_replay_program(path: &str) -> std::io::Result<()> {
+    let file = File::open(path)?;
+    let price_market_data: HashMap<String, HashMap<String, Vec<PricePoint>>> =
+        serde_json::from_reader(file)?;
+    log::info!("prices loaded from {}", path);
+
+    // Replay the saved prices as a back test
+    let mut config = config::get_config_from_env().expect("Invalid configuration");
+    config.back_test = true;
+    config.dry_run = true;
+    config.load_prices = false;
+    config.save_prices = false;
+    config.replay = true;
+
+    let db_handler = Arc::new(Mutex::new(
+        DBHandler::offline(config.dry_run, config.path_to_models.as_ref()).await,
+    ));
+
+    let mut trader_instances =
+        prepare_trader_instance(&config, db_handler, price_market_data).await;
+
+    main_loop(&mut trader_instances, None, None, None, None, None).await
 }
 
 async fn prepare_trader_instance(
     config: &EnvConfig,
     db_handler: Arc<Mutex<DBHandler>>,
     price_market_data: HashMap<String, HashMap<String, Vec<PricePoint>>>,
-) -> (DerivativeTrader, &EnvConfig, ErrorManager) {
-    // todo: support multiple traders
-    let (trading_interval, interval, dex_name) = &trader_config::get(&config.strategy)[0];
-
-    // Create an error manager
-    let error_manager = ErrorManager::new();
-
-    let trader = DerivativeTrader::new(
-        &dex_name,
-        config.dry_run,
-        *trading_interval,
-        interval.clone(),
-        config.interval_secs,
-        config.max_price_size,
-        db_handler,
-        price_market_data.clone(),
-        config.load_prices,
-        config.save_prices,
-        config.max_dd_ratio,
-        config.close_order_effective_duration_secs,
-        config.use_market_order,
-        &config.rest_endpoint,
-        &config.web_socket_endpoint,
-        config.leverage,
-        &config.strategy,
-        config.only_read_price,
-        config.back_test,
-    )
-    .await;
+) -> Vec<TraderInstance<'_>> {
+    let mut trader_instances = vec![];
+
+    for (trading_interval, interval, dex_name) in &trader_config::get(&config.strategy) {
+        // Create an error manager
+        let error_manager = ErrorManager::new();
+
+        let trader_config = DerivativeTraderConfig {
+            dex_name: dex_name.clone(),
+            dry_run: config.dry_run,
+            trade_interval: *trading_interval,
+            sample_interval: interval.clone(),
+            interval_secs: config.interval_secs,
+            max_price_size: config.max_price_size,
+            load_prices: config.load_prices,
+            save_prices: config.save_prices,
+            max_dd_ratio: config.max_dd_ratio,
+            close_order_effective_duration_secs: config.close_order_effective_duration_secs,
+            use_market_order: config.use_market_order,
+            rest_endpoint: config.rest_endpoint.clone(),
+            web_socket_endpoint: config.web_socket_endpoint.clone(),
+            leverage: config.leverage,
+            strategy: config.strategy,
+            trading_mode: config.trading_mode,
+            back_test: config.back_test,
+            dd_basis: config.dd_basis,
+            sort_filled_orders: config.sort_filled_orders,
+            max_token_notional: config.max_token_notional,
+            log_fund_equity: config.log_fund_equity,
+            equity_sample_ticks: config.equity_sample_ticks,
+            balance_retry_attempts: config.balance_retry_attempts,
+            balance_retry_backoff_millis: config.balance_retry_backoff_millis,
+            fill_settings: EmulatorFillSettings {
+                slippage_bps: config.slippage_bps,
+                taker_fee_bps: config.taker_fee_bps,
+                maker_fee_bps: config.maker_fee_bps,
+            },
+            max_trades_per_token_per_hour: config.max_trades_per_token_per_hour,
+            dry_run_price_source: config.dry_run_price_source.clone(),
+            liquidate_retry_max: config.liquidate_retry_max,
+            random_seed: config.random_seed,
+            trace_decisions: config.trace_decisions,
+            max_price_age_secs: config.max_price_age_secs,
+            stale_price_restart_ticks: config.stale_price_restart_ticks,
+            backtest_report_path: config.backtest_report_path.clone(),
+            rounding_mode: config.rounding_mode,
+            open_resubmit_attempts: config.open_resubmit_attempts,
+            min_margin_ratio: config.min_margin_ratio,
+            skip_precompute_in_backtest: config.skip_precompute_in_backtest,
+            max_concurrent_open_tokens: config.max_concurrent_open_tokens,
+            token_blacklist: config.token_blacklist.clone(),
+            token_whitelist: config.token_whitelist.clone(),
+            order_verify_ticks: config.order_verify_ticks,
+            replay: config.replay,
+        };
+
+        let trader = DerivativeTrader::new(
+            trader_config,
+            db_handler.clone(),
+            error_manager.alert_handle(),
+            price_market_data.clone(),
+        )
+        .await;
 
-    (trader, config, error_manager)
+        trader_instances.push((trader, config, error_manager));
+    }
+
+    trader_instances
 }
 
 async fn main_loop(
-    trader_instance: &mut (DerivativeTrader, &EnvConfig, ErrorManager),
+    trader_instances: &mut [TraderInstance<'_>],
     mut last_execution_time: Option<SystemTime>,
     mut last_equity: Option<Decimal>,
     mut last_dd_check_time: Option<SystemTime>,
+    metrics: Option<SharedMetrics>,
+    control: Option<SharedControl>,
 ) -> std::io::Result<()> {
     log::info!("main_loop() starts");
 
+    let config = match trader_instances.first() {
+        Some((_, config, _)) => *config,
+        None => {
+            log::error!("No trader is configured");
+            return Ok(());
+        }
+    };
+
     let mut sigterm_stream =
         tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
 
-    trader_instance.0.liquidate(false, "start").await;
+    for (trader, _, _) in trader_instances.iter_mut() {
+        trader.liquidate(false, "start").await;
+    }
+
+    let mut dd_ratios = vec![Decimal::ZERO; trader_instances.len()];
+    let mut last_param_reload_time: Option<Instant> = None;
+
+    // Never notified without a kill-switch file
+    let kill_switch = match &config.kill_switch_file {
+        Some(path) => spawn_kill_switch_watcher(path.to_owned(), KILL_SWITCH_POLL_INTERVAL),
+        None => Arc::new(Notify::new()),
+    };
 
     loop {
         let now = SystemTime::now();
-        let one_day = Duration::from_secs(24 * 60 * 60);
         let loop_start = Instant::now();
 
-        let (trader, config, error_manager) = trader_instance;
+        let invested_amount: Decimal = trader_instances
+            .iter()
+            .map(|(trader, _, _)| trader.invested_amount())
+            .sum();
+        let db_handler = trader_instances[0].0.db_handler().clone();
 
-        let invested_amount = trader.invested_amount();
-
-        // Check if last_execution_time is None or it's been more than one day
+        // Check if last_execution_time is None or a new reporting day has started
         if !config.back_test
             && last_execution_time.map_or(true, |last_time| {
-                now.duration_since(last_time)
-                    .map_or(false, |duration| duration > one_day)
+                is_new_reporting_day(last_time, now, &config.reporting_timezone)
             })
         {
             // Update the last_execution_time to now
             last_execution_time = Some(now);
 
-            // Get and log yesterday's PNL;
-            match trader.get_balance().await {
-                Ok(balance) => {
+            // Get and log yesterday's PNL of all the traders
+            let balance = total_balance(trader_instances.iter().map(|(trader, _, _)| trader)).await;
+            match balance {
+                Some(balance) => {
                     let pnl = match last_equity {
                         Some(prev_balance) => balance - prev_balance,
                         None => Decimal::new(0, 0),
                     };
-                    trader.db_handler().lock().await.log_pnl(pnl).await;
+                    db_handler
+                        .lock()
+                        .await
+                        .log_pnl(pnl, &config.reporting_timezone)
+                        .await;
                     last_equity = Some(balance);
                 }
-                Err(_) => log::error!("Failed to get PNL"),
+                None => log::error!("Failed to get PNL"),
             }
 
             // Log the new last_execution_time and equity
-            trader
-                .db_handler()
+            db_handler
                 .lock()
                 .await
                 .log_app_state(
@@ -386,80 +970,171 @@ async fn main_loop(
             last_dd_check_time = Some(now);
 
             // log the invested amount
-            trader
-                .db_handler()
+            db_handler
                 .lock()
                 .await
                 .log_app_state(None, None, false, None, invested_amount)
                 .await;
 
-            match trader.is_max_dd_occurred().await {
-                Ok(is_dd) => {
-                    if is_dd {
-                        log::error!("Draw down!");
-                        trader.liquidate(true, "Draw down").await;
-                        trader
-                            .db_handler()
-                            .lock()
-                            .await
-                            .log_app_state(None, None, true, None, invested_amount)
-                            .await;
-                        log::info!("returned due to Draw down!");
-                        error_manager.send("[debot] Draw down!", &config.db_w_name);
-                        return Ok(());
+            let (net_exposure, gross_exposure) = trader_instances.iter().fold(
+                (Decimal::ZERO, Decimal::ZERO),
+                |(net, gross), (trader, _, _)| {
+                    (net + trader.net_exposure(), gross + trader.gross_exposure())
+                },
+            );
+            log::info!(
+                "exposure: net = {:.2}, gross = {:.2}",
+                net_exposure,
+                gross_exposure
+            );
+
+            let mut max_dd = false;
+            for ((trader, _, error_manager), dd_ratio) in
+                trader_instances.iter_mut().zip(dd_ratios.iter_mut())
+            {
+                match trader.dd_ratio().await {
+                    Ok(ratio) => {
+                        *dd_ratio = ratio;
+                        max_dd |= trader.is_max_dd(ratio);
+                    }
+                    Err(_) => {
+                        error_manager.save_first_error_time();
+                        let _ = trader.reset_dex_client().await;
                     }
                 }
-                Err(_) => {
-                    error_manager.save_first_error_time();
-                    let _ = trader.reset_dex_client().await;
+                if trader.is_margin_thin().await {
+                    log::error!("Margin buffer is thin!");
+                    max_dd = true;
                 }
             }
+
+            if max_dd {
+                log::error!("Draw down!");
+                for (trader, _, _) in trader_instances.iter_mut() {
+                    trader.liquidate(true, "Draw down").await;
+                }
+                db_handler
+                    .lock()
+                    .await
+                    .log_app_state(None, None, true, None, invested_amount)
+                    .await;
+                log::info!("returned due to Draw down!");
+                trader_instances[0].2.send(
+                    "[debot] Draw down!",
+                    &format!(
+                        "{}: net exposure = {:.2}, gross exposure = {:.2}",
+                        config.db_w_name, net_exposure, gross_exposure
+                    ),
+                );
+                trader_instances[0].2.flush(SHUTDOWN_GRACE_PERIOD).await;
+                return Ok(());
+            }
         }
 
-        // Create a non-mutable borrow for the function
-        let trader_future = Box::pin(handle_trader_activities(trader, config, error_manager));
+        // Reload the fund parameters tuned in the DB
+        if let Some(param_reload_secs) = config.param_reload_secs {
+            if last_param_reload_time.is_none_or(|last_time| {
+                last_time.elapsed() >= Duration::from_secs(param_reload_secs)
+            }) {
+                last_param_reload_time = Some(Instant::now());
+                for (trader, _, _) in trader_instances.iter_mut() {
+                    trader.reload_fund_params().await;
+                }
+            }
+        }
 
-        let mut exit;
-        tokio::select! {
-            _ = sigterm_stream.recv() => {
-                log::info!("SIGTERM received. Shutting down...");
-                exit = true;
-            },
-            _ = tokio::signal::ctrl_c() => {
-                log::info!("SIGINT received. Shutting down...");
-                exit = true;
-            },
-            result = trader_future => {
-                match result {
-                    Ok(_) => {
-                        exit = false;
-                    },
-                    Err(_) => {
-                        exit = true;
-                    }
+        if let Some(metrics) = &metrics {
+            let mut snapshot = MetricsSnapshot::default();
+            for ((trader, _, _), dd_ratio) in trader_instances.iter().zip(dd_ratios.iter()) {
+                snapshot.merge(trader.metrics_snapshot(*dd_ratio));
+            }
+            *metrics.lock().await = snapshot;
+        }
+
+        if let Some(control) = &control {
+            let (paused, liquidate_requested) = {
+                let mut control = control.lock().await;
+                let liquidate_requested = control.liquidate_requested;
+                control.liquidate_requested = false;
+                (control.paused, liquidate_requested)
+            };
+            for (trader, _, _) in trader_instances.iter_mut() {
+                if liquidate_requested {
+                    trader.liquidate(true, "control").await;
+                    trader.end_liquidation();
                 }
+                trader.pause_opening(paused);
             }
         }
 
-        if exit {
-            if config.liquidate_when_exit {
-                trader.liquidate(true, "reboot").await;
+        // Drive all the traders concurrently
+        let signal = async {
+            tokio::select! {
+                _ = kill_switch.notified() => {
+                    log::info!("Kill switch triggered. Liquidating...");
+                    TickEnd::Killed
+                },
+                _ = sigterm_stream.recv() => {
+                    log::info!("SIGTERM received. Shutting down...");
+                    TickEnd::Interrupted
+                },
+                _ = tokio::signal::ctrl_c() => {
+                    log::info!("SIGINT received. Shutting down...");
+                    TickEnd::Interrupted
+                },
             }
-            std::process::exit(0);
+        };
+        let tick_end = run_tick(
+            trader_instances,
+            async |trader_instances: &mut [TraderInstance<'_>]| {
+                join_trader_activities(trader_instances.iter_mut().map(
+                    |(trader, config, error_manager)| {
+                        handle_trader_activities(trader, config, error_manager)
+                    },
+                ))
+                .await
+            },
+            signal,
+            DerivativeTrader::stop_opening,
+            async |trader_instances: &mut [TraderInstance<'_>]| {
+                kill_traders(
+                    trader_instances.iter_mut().map(|(trader, _, _)| trader),
+                    invested_amount,
+                )
+                .await
+            },
+            SHUTDOWN_GRACE_PERIOD,
+        )
+        .await;
+
+        let mut exit = match tick_end {
+            TickEnd::Finished(result) => result.is_err(),
+            TickEnd::Interrupted => true,
+            TickEnd::Killed => return Ok(()),
+        };
+
+        if exit {
+            shutdown_traders(trader_instances).await;
         }
 
         let elapsed = loop_start.elapsed();
         let elapsed_millis = elapsed.as_millis() as u64;
 
-        let max_elapsed = MAX_ELAPSED.load(Ordering::Relaxed);
-        let elapsed_ave_millis = (max_elapsed + elapsed_millis) / 2;
-        if elapsed_ave_millis > max_elapsed {
+        if update_elapsed(
+            &MAX_ELAPSED,
+            &EMA_ELAPSED,
+            elapsed_millis,
+            config.elapsed_ema_alpha,
+        ) {
             log::warn!(
                 "New max elapsed time: {:.1} s",
-                elapsed_ave_millis as f64 / 1000.0
+                elapsed_millis as f64 / 1000.0
             );
-            MAX_ELAPSED.store(elapsed_ave_millis, Ordering::Relaxed);
         }
+        log::debug!(
+            "Average elapsed time: {:.1} s",
+            EMA_ELAPSED.load(Ordering::Relaxed) as f64 / 1000.0
+        );
 
         if elapsed.as_secs() > config.interval_secs.try_into().unwrap() {
             log::error!(
@@ -485,6 +1160,14 @@ async fn main_loop(
         tokio::pin!(sleep);
 
         tokio::select! {
+            _ = kill_switch.notified() => {
+                kill_traders(
+                    trader_instances.iter_mut().map(|(trader, _, _)| trader),
+                    invested_amount,
+                )
+                .await;
+                return Ok(());
+            },
             _ = sigterm_stream.recv() => {
                 log::info!("SIGTERM received. Shutting down...");
                 exit = true;
@@ -499,12 +1182,331 @@ async fn main_loop(
         }
 
         if exit {
-            if config.liquidate_when_exit {
-                trader.liquidate(true, "reboot").await;
+            shutdown_traders(trader_instances).await;
+        }
+    }
+}
+
+// Updates the max and the exponential moving average of the loop time, and returns true on a new max
+fn update_elapsed(max: &AtomicU64, ema: &AtomicU64, elapsed_millis: u64, alpha: f64) -> bool {
+    let prev_ema = ema.load(Ordering::Relaxed);
+    let new_ema = if prev_ema == 0 {
+        elapsed_millis
+    } else {
+        (alpha * elapsed_millis as f64 + (1.0 - alpha) * prev_ema as f64).round() as u64
+    };
+    ema.store(new_ema, Ordering::Relaxed);
+
+    max.fetch_max(elapsed_millis, Ordering::Relaxed) < elapsed_millis
+}
+
+fn is_new_reporting_day(last_time: SystemTime, now: SystemTime, timezone: &FixedOffset) -> bool {
+    let reporting_date = |time: SystemTime| {
+        DateTime::<Utc>::from(time)
+            .with_timezone(timezone)
+            .date_naive()
+    };
+    reporting_date(now) > reporting_date(last_time)
+}
+
+const POSITION_AGING_HEADER: [&str; 6] = [
+    "position_type",
+    "pnl",
+    "open_timestamp",
+    "age_seconds",
+    "average_open_price",
+    "unrealized_pnl",
+];
+
+// The price of the most recent point of each token, over all the traders
+fn latest_prices(
+    price_market_data: &HashMap<String, HashMap<String, Vec<PricePoint>>>,
+) -> HashMap<String, Decimal> {
+    let mut latest: HashMap<String, (i64, Decimal)> = HashMap::new();
+    for price_points_map in price_market_data.values() {
+        for (token_name, price_points) in price_points_map {
+            if let Some(point) = price_points.iter().max_by_key(|point| point.timestamp) {
+                let entry = latest
+                    .entry(token_name.to_owned())
+                    .or_insert((point.timestamp, point.price));
+                if point.timestamp > entry.0 {
+                    *entry = (point.timestamp, point.price);
+                }
+            }
+        }
+    }
+    latest
+        .into_iter()
+        .map(|(token_name, (_, price))| (token_name, price))
+        .collect()
+}
+
+// The unrealized PnL is left blank when the token has no recent price
+fn position_aging_record(
+    position_type: &str,
+    pnl: Decimal,
+    open_timestamp: i64,
+    average_open_price: Decimal,
+    asset_in_usd: Decimal,
+    latest_price: Option<Decimal>,
+    now: i64,
+) -> Vec<String> {
+    let unrealized_pnl = match latest_price {
+        Some(price) if !average_open_price.is_zero() => {
+            // asset_in_usd is what was paid (negative) or received (positive) on open
+            let amount = -asset_in_usd / average_open_price;
+            (amount * price + asset_in_usd).round_dp(3).to_string()
+        }
+        _ => String::new(),
+    };
+
+    vec![
+        position_type.to_owned(),
+        pnl.round_dp(3).to_string(),
+        open_timestamp.to_string(),
+        (now - open_timestamp).max(0).to_string(),
+        average_open_price.round_dp(6).to_string(),
+        unrealized_pnl,
+    ]
+}
+
+// Only the positions still held age; the DB also keeps the closed and canceled ones
+fn position_aging_records(
+    positions: &[PositionLog],
+    latest_prices: &HashMap<String, Decimal>,
+    now: i64,
+) -> Vec<Vec<String>> {
+    positions
+        .iter()
+        .filter(|position| is_open_state(&position.state))
+        .map(|position| {
+            position_aging_record(
+                &position.position_type,
+                position.pnl,
+                position.open_timestamp,
+                position.average_open_price,
+                position.asset_in_usd,
+                latest_prices.get(&position.token_name).copied(),
+                now,
+            )
+        })
+        .collect()
+}
+
+fn is_open_state(state: &str) -> bool {
+    state == "Open" || state.starts_with("Closing")
+}
+
+// Signed size of a position, from what was paid (negative) or received (positive) on open
+fn position_size(asset_in_usd: Decimal, average_open_price: Decimal) -> Decimal {
+    if average_open_price.is_zero() {
+        return Decimal::ZERO;
+    }
+    -asset_in_usd / average_open_price
+}
+
+struct Reconciliation {
+    drifts: Vec<String>,
+    // The open positions of the DB on the tokens the venue is flat on
+    flat_positions: Vec<PositionLog>,
+}
+
+// Compares the open positions of the DB with the ones the venue holds
+async fn reconcile_positions(
+    positions: Vec<PositionLog>,
+    dex_connector: &DexConnectorBox,
+) -> Result<Reconciliation, DexError> {
+    let venue_positions: HashMap<String, Decimal> =
+        dex_connector.get_positions().await?.into_iter().collect();
+
+    let positions: Vec<PositionLog> = positions
+        .into_iter()
+        .filter(|position| is_open_state(&position.state))
+        .collect();
+    let mut db_positions: HashMap<String, Decimal> = HashMap::new();
+    for position in &positions {
+        *db_positions
+            .entry(position.token_name.to_owned())
+            .or_default() += position_size(position.asset_in_usd, position.average_open_price);
+    }
+
+    let drifts = position_drifts(&db_positions, &venue_positions, RECONCILE_SIZE_TOLERANCE);
+    let flat_positions = positions
+        .into_iter()
+        .filter(|position| {
+            venue_positions
+                .get(&position.token_name)
+                .is_none_or(|size| size.is_zero())
+        })
+        .collect();
+    Ok(Reconciliation {
+        drifts,
+        flat_positions,
+    })
+}
+
+// One line per token whose net size differs between the DB and the venue
+fn position_drifts(
+    db_positions: &HashMap<String, Decimal>,
+    venue_positions: &HashMap<String, Decimal>,
+    tolerance: Decimal,
+) -> Vec<String> {
+    let mut tokens: Vec<&String> = db_positions.keys().chain(venue_positions.keys()).collect();
+    tokens.sort();
+    tokens.dedup();
+
+    tokens
+        .into_iter()
+        .filter_map(|token| {
+            let db_size = db_positions.get(token).copied().unwrap_or_default();
+            let venue_size = venue_positions.get(token).copied().unwrap_or_default();
+            let is_db_flat = db_size.abs() <= tolerance;
+            let is_venue_flat = venue_size.abs() <= tolerance;
+            if is_db_flat && is_venue_flat {
+                None
+            } else if is_venue_flat {
+                Some(format!(
+                    "{}: open in DB({}) but flat on venue",
+                    token,
+                    db_size.round_dp(6)
+                ))
+            } else if is_db_flat {
+                Some(format!(
+                    "{}: flat in DB but open on venue({})",
+                    token, venue_size
+                ))
+            } else if (db_size - venue_size).abs() > tolerance {
+                Some(format!(
+                    "{}: size mismatch, DB({}) venue({})",
+                    token,
+                    db_size.round_dp(6),
+                    venue_size
+                ))
+            } else {
+                None
             }
-            std::process::exit(0);
+        })
+        .collect()
+}
+
+// The traders on one venue trade from the same account, so its balance is read only once
+async fn total_balance<'a>(
+    traders: impl IntoIterator<Item = &'a DerivativeTrader>,
+) -> Option<Decimal> {
+    let mut venues = HashSet::new();
+    let mut balance = Decimal::ZERO;
+    for trader in traders {
+        if venues.insert(trader.dex_name()) {
+            balance += trader.get_balance().await.ok()?;
+        }
+    }
+    Some(balance)
+}
+
+// Waits for all the traders, and fails if any of them failed
+async fn join_trader_activities<F>(activities: impl IntoIterator<Item = F>) -> Result<(), ()>
+where
+    F: Future<Output = Result<(), ()>>,
+{
+    let results = join_all(activities).await;
+    if results.iter().any(|result| result.is_err()) {
+        Err(())
+    } else {
+        Ok(())
+    }
+}
+
+// Polls for the file rather than relying on inotify, and notifies once it exists
+fn spawn_kill_switch_watcher(path: String, interval: Duration) -> Arc<Notify> {
+    let kill_switch = Arc::new(Notify::new());
+    let notify = kill_switch.clone();
+    tokio::spawn(async move {
+        while tokio::fs::metadata(&path).await.is_err() {
+            tokio::time::sleep(interval).await;
+        }
+        log::warn!("kill switch file found: {}", path);
+        notify.notify_one();
+    });
+    kill_switch
+}
+
+// Liquidates every trader and leaves the exit to the caller
+async fn kill_traders<'a>(
+    traders: impl IntoIterator<Item = &'a mut DerivativeTrader>,
+    invested_amount: Decimal,
+) {
+    let mut db_handler = None;
+    for trader in traders {
+        trader.liquidate(true, "kill switch").await;
+        db_handler.get_or_insert_with(|| trader.db_handler().clone());
+    }
+    if let Some(db_handler) = db_handler {
+        db_handler
+            .lock()
+            .await
+            .log_app_state(None, None, true, None, invested_amount)
+            .await;
+    }
+    log::info!("exit by the kill switch");
+}
+
+#[derive(Debug, PartialEq)]
+enum TickEnd {
+    Finished(Result<(), ()>),
+    Interrupted,
+    Killed,
+}
+
+// Runs the activities of a tick until they finish or the signal ends the tick. On the signal,
+// opening stops, the orders being sent are awaited, since dropping the tick midway would leave
+// them unknown to us, and then the kill switch liquidates. The kill switch waits the same grace
+// period: an order still being sent when the positions are closed could open one after the
+// liquidation, and the wait ends as soon as the sends do.
+async fn run_tick<T: ?Sized>(
+    traders: &mut T,
+    activities: impl AsyncFnOnce(&mut T) -> Result<(), ()>,
+    signal: impl Future<Output = TickEnd>,
+    stop_opening: impl FnOnce(),
+    liquidate: impl AsyncFnOnce(&mut T),
+    grace_period: Duration,
+) -> TickEnd {
+    let mut in_flight = Box::pin(activities(traders));
+    let tick_end = tokio::select! {
+        tick_end = signal => tick_end,
+        result = &mut in_flight => return TickEnd::Finished(result),
+    };
+
+    stop_opening();
+    await_in_flight(in_flight, grace_period).await;
+    if tick_end == TickEnd::Killed {
+        liquidate(traders).await;
+    }
+    tick_end
+}
+
+// Returns false when the in-flight operations did not finish within the grace period
+async fn await_in_flight<F: Future>(in_flight: F, grace_period: Duration) -> bool {
+    match tokio::time::timeout(grace_period, in_flight).await {
+        Ok(_) => true,
+        Err(_) => {
+            log::warn!("The in-flight orders did not finish in {:?}", grace_period);
+            false
+        }
+    }
+}
+
+async fn shutdown_traders(trader_instances: &mut [TraderInstance<'_>]) -> ! {
+    for (trader, config, error_manager) in trader_instances.iter_mut() {
+        if config.liquidate_when_exit {
+            trader.liquidate(true, "reboot").await;
         }
+        trader.flush_fund_equity().await;
+        if config.back_test {
+            trader.log_statistics();
+        }
+        error_manager.flush(SHUTDOWN_GRACE_PERIOD).await;
     }
+    std::process::exit(0);
 }
 
 async fn handle_trader_activities(
@@ -532,6 +1534,7 @@ async fn handle_trader_activities(
             )
             .await;
         error_manager.send("[debot] Continous error!", &config.db_w_name);
+        error_manager.flush(SHUTDOWN_GRACE_PERIOD).await;
         return Err(());
     }
 
@@ -563,7 +1566,6 @@ mod tests {
     use std::{env, sync::Arc, time::Duration};
     use tokio::time::sleep;
 
-    #[ctor::ctor]
     fn setup() {
         env_logger::init();
     }
@@ -746,4 +1748,474 @@ mod tests {
     //         client.cancel_all_orders(None).await.unwrap();
     //     }
     // }
+
+    #[test]
+    fn test_position_aging_record() {
+        let now = 10_000;
+        let records = vec![
+            // Long 2 @ 100, now 110
+            super::position_aging_record(
+                "Long",
+                Decimal::ZERO,
+                9_000,
+                Decimal::new(100, 0),
+                Decimal::new(-200, 0),
+                Some(Decimal::new(110, 0)),
+                now,
+            ),
+            // Short 1 @ 50, now 45
+            super::position_aging_record(
+                "Short",
+                Decimal::ZERO,
+                9_900,
+                Decimal::new(50, 0),
+                Decimal::new(50, 0),
+                Some(Decimal::new(45, 0)),
+                now,
+            ),
+            // No recent price
+            super::position_aging_record(
+                "Long",
+                Decimal::ZERO,
+                9_990,
+                Decimal::new(100, 0),
+                Decimal::new(-100, 0),
+                None,
+                now,
+            ),
+        ];
+
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.write_record(super::POSITION_AGING_HEADER).unwrap();
+        for record in &records {
+            wtr.write_record(record).unwrap();
+        }
+        let csv = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "position_type,pnl,open_timestamp,age_seconds,average_open_price,unrealized_pnl\n\
+             Long,0,9000,1000,100,20\n\
+             Short,0,9900,100,50,5\n\
+             Long,0,9990,10,100,\n"
+        );
+    }
+
+    #[test]
+    fn test_position_aging_records_skip_closed_positions() {
+        use debot_db::PositionLog;
+        use std::collections::HashMap;
+
+        let position = |token: &str, state: &str| PositionLog {
+            token_name: token.to_owned(),
+            state: state.to_owned(),
+            position_type: "Long".to_owned(),
+            open_timestamp: 9_000,
+            asset_in_usd: Decimal::new(-200, 0),
+            average_open_price: Decimal::new(100, 0),
+            ..Default::default()
+        };
+        let positions = vec![
+            position("BTC-USD", "Open"),
+            position("ETH-USD", "Closing(TakeProfit)"),
+            position("SOL-USD", "Closed(TakeProfit)"),
+            position("XRP-USD", "Canceled(Expired)"),
+        ];
+        let latest_prices = HashMap::from([
+            ("BTC-USD".to_owned(), Decimal::new(110, 0)),
+            ("ETH-USD".to_owned(), Decimal::new(90, 0)),
+            ("SOL-USD".to_owned(), Decimal::new(120, 0)),
+        ]);
+
+        let records = super::position_aging_records(&positions, &latest_prices, 10_000);
+        assert_eq!(
+            records,
+            vec![
+                vec!["Long", "0", "9000", "1000", "100", "20"],
+                vec!["Long", "0", "9000", "1000", "100", "-20"],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_await_in_flight() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let placed = Arc::new(AtomicBool::new(false));
+        let slow_order = {
+            let placed = placed.clone();
+            async move {
+                sleep(Duration::from_millis(50)).await;
+                placed.store(true, Ordering::SeqCst);
+            }
+        };
+        assert!(super::await_in_flight(slow_order, Duration::from_secs(5)).await);
+        assert!(placed.load(Ordering::SeqCst));
+
+        // A stuck order does not hold the shutdown beyond the grace period
+        assert!(
+            !super::await_in_flight(sleep(Duration::from_secs(60)), Duration::from_millis(10))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_tick_shuts_down_in_order() {
+        use super::TickEnd;
+        use std::sync::Mutex;
+
+        let events = Arc::new(Mutex::new(vec![]));
+        let run = |signal: TickEnd, order_millis: u64| {
+            let events = events.clone();
+            async move {
+                events.lock().unwrap().clear();
+                let tick_end = super::run_tick(
+                    &mut (),
+                    {
+                        let events = events.clone();
+                        async move |_: &mut ()| {
+                            events.lock().unwrap().push("send");
+                            sleep(Duration::from_millis(order_millis)).await;
+                            events.lock().unwrap().push("sent");
+                            Ok(())
+                        }
+                    },
+                    async move {
+                        sleep(Duration::from_millis(10)).await;
+                        signal
+                    },
+                    || events.lock().unwrap().push("stop opening"),
+                    async |_: &mut ()| events.lock().unwrap().push("liquidate"),
+                    Duration::from_millis(200),
+                )
+                .await;
+                let events = events.lock().unwrap().clone();
+                (tick_end, events)
+            }
+        };
+
+        // The slow order is sent before the liquidation
+        assert_eq!(
+            run(TickEnd::Killed, 50).await,
+            (
+                TickEnd::Killed,
+                vec!["send", "stop opening", "sent", "liquidate"]
+            )
+        );
+        // Nothing is liquidated on an interrupt
+        assert_eq!(
+            run(TickEnd::Interrupted, 50).await,
+            (TickEnd::Interrupted, vec!["send", "stop opening", "sent"])
+        );
+        // A stuck order does not hold the liquidation beyond the grace period
+        assert_eq!(
+            run(TickEnd::Killed, 60_000).await,
+            (TickEnd::Killed, vec!["send", "stop opening", "liquidate"])
+        );
+        // A tick that finishes before any signal runs none of the shutdown
+        assert_eq!(
+            run(TickEnd::Killed, 0).await,
+            (TickEnd::Finished(Ok(())), vec!["send", "sent"])
+        );
+    }
+
+    #[test]
+    fn test_position_drifts() {
+        use std::collections::HashMap;
+
+        let tolerance = super::RECONCILE_SIZE_TOLERANCE;
+        let db_positions: HashMap<String, Decimal> = [
+            // Long 2 @ 100
+            (
+                "BTC-USD".to_owned(),
+                super::position_size(Decimal::new(-200, 0), Decimal::new(100, 0)),
+            ),
+            ("ETH-USD".to_owned(), Decimal::new(-1, 0)),
+            ("SOL-USD".to_owned(), Decimal::new(3, 0)),
+        ]
+        .into_iter()
+        .collect();
+        let venue_positions: HashMap<String, Decimal> = [
+            ("BTC-USD".to_owned(), Decimal::new(2, 0)),
+            ("ETH-USD".to_owned(), Decimal::new(-5, 1)),
+            ("XRP-USD".to_owned(), Decimal::new(10, 0)),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            super::position_drifts(&db_positions, &venue_positions, tolerance),
+            vec![
+                "ETH-USD: size mismatch, DB(-1) venue(-0.5)".to_owned(),
+                "SOL-USD: open in DB(3) but flat on venue".to_owned(),
+                "XRP-USD: flat in DB but open on venue(10)".to_owned(),
+            ]
+        );
+        assert!(super::position_drifts(&db_positions, &db_positions, tolerance).is_empty());
+        assert!(super::is_open_state("Closing(TakeProfit)"));
+        assert!(!super::is_open_state("Closed(TakeProfit)"));
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_positions() {
+        use crate::trade::dex_connector_box::DexConnectorBox;
+        use crate::trade::mock_dex::{Mock, MockDex};
+        use async_trait::async_trait;
+        use debot_db::PositionLog;
+        use dex_connector::DexError;
+
+        struct PositionsVenue(Option<Vec<(String, Decimal)>>);
+
+        #[async_trait]
+        impl MockDex for PositionsVenue {
+            async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+                self.0
+                    .clone()
+                    .ok_or_else(|| DexError::Other("unreachable".to_owned()))
+            }
+        }
+
+        let position = |id, token: &str, state: &str, asset_in_usd| PositionLog {
+            id: Some(id),
+            token_name: token.to_owned(),
+            state: state.to_owned(),
+            asset_in_usd: Decimal::new(asset_in_usd, 0),
+            average_open_price: Decimal::new(100, 0),
+            ..Default::default()
+        };
+        // Long 2 BTC and short 1 ETH, both @ 100, and a closed SOL that is left out
+        let positions = vec![
+            position(1, "BTC-USD", "Open", -200),
+            position(2, "ETH-USD", "Closing(TakeProfit)", 100),
+            position(3, "SOL-USD", "Closed(TakeProfit)", -300),
+        ];
+        let venue = PositionsVenue(Some(vec![
+            ("BTC-USD".to_owned(), Decimal::new(2, 0)),
+            ("ETH-USD".to_owned(), Decimal::ZERO),
+            ("XRP-USD".to_owned(), Decimal::new(10, 0)),
+        ]));
+        let dex_connector = DexConnectorBox::from_connector("test", Box::new(Mock(venue)));
+
+        let reconciliation = super::reconcile_positions(positions.clone(), &dex_connector)
+            .await
+            .unwrap();
+        assert_eq!(
+            reconciliation.drifts,
+            vec![
+                "ETH-USD: open in DB(-1) but flat on venue".to_owned(),
+                "XRP-USD: flat in DB but open on venue(10)".to_owned(),
+            ]
+        );
+        // Only the open position on a token the venue is flat on is closed by --fix
+        let flat_ids: Vec<_> = reconciliation
+            .flat_positions
+            .iter()
+            .map(|position| position.id)
+            .collect();
+        assert_eq!(flat_ids, vec![Some(2)]);
+
+        let dex_connector =
+            DexConnectorBox::from_connector("test", Box::new(Mock(PositionsVenue(None))));
+        assert!(super::reconcile_positions(positions, &dex_connector)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch_watcher() {
+        let path = std::env::temp_dir().join(format!("debot_kill_switch_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let kill_switch = super::spawn_kill_switch_watcher(
+            path.to_string_lossy().into_owned(),
+            Duration::from_millis(10),
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), kill_switch.notified())
+                .await
+                .is_err()
+        );
+
+        std::fs::File::create(&path).unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), kill_switch.notified())
+                .await
+                .is_ok()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_kill_switch_liquidates_every_trader() {
+        use crate::error_manager::AlertHandle;
+        use crate::trade::derivative_trader::DerivativeTraderConfig;
+        use crate::trade::dex_connector_box::DexConnectorBox;
+        use crate::trade::mock_dex::{Mock, MockDex};
+        use crate::trade::{DBHandler, DerivativeTrader};
+        use async_trait::async_trait;
+        use debot_market_analyzer::{TradingStrategy, TrendType};
+        use dex_connector::DexError;
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use tokio::sync::Mutex;
+
+        // A venue counting the calls to close all its positions
+        struct Venue(Arc<AtomicU32>);
+
+        #[async_trait]
+        impl MockDex for Venue {
+            async fn close_all_positions(&self, _symbol: Option<String>) -> Result<(), DexError> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let mut traders = vec![];
+        let mut closes = vec![];
+        for dex_name in ["hyperliquid", "bybit"] {
+            let count = Arc::new(AtomicU32::new(0));
+            closes.push(count.clone());
+            let dex_connector =
+                DexConnectorBox::from_connector(dex_name, Box::new(Mock(Venue(count))));
+            traders.push(
+                DerivativeTrader::with_dex_connector(
+                    DerivativeTraderConfig::replay(
+                        dex_name,
+                        TradingStrategy::RandomWalk(TrendType::Up),
+                    ),
+                    Arc::new(dex_connector),
+                    db_handler.clone(),
+                    AlertHandle::detached(),
+                    HashMap::new(),
+                )
+                .await,
+            );
+        }
+
+        let path = std::env::temp_dir().join(format!("debot_kill_traders_{}", std::process::id()));
+        let kill_switch = super::spawn_kill_switch_watcher(
+            path.to_string_lossy().into_owned(),
+            Duration::from_millis(10),
+        );
+        std::fs::File::create(&path).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), kill_switch.notified())
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // Returns rather than exiting the process, with every venue closed
+        super::kill_traders(traders.iter_mut(), Decimal::ZERO).await;
+        assert!(closes.iter().all(|count| count.load(Ordering::SeqCst) == 1));
+    }
+
+    #[test]
+    fn test_update_elapsed() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let max = AtomicU64::new(0);
+        let ema = AtomicU64::new(0);
+        let samples = [
+            (1000, true, 1000),
+            (2000, true, 1500),
+            (500, false, 1000),
+            (1000, false, 1000),
+        ];
+        for (elapsed_millis, is_new_max, expected_ema) in samples {
+            assert_eq!(
+                super::update_elapsed(&max, &ema, elapsed_millis, 0.5),
+                is_new_max
+            );
+            assert_eq!(ema.load(Ordering::Relaxed), expected_ema);
+        }
+        assert_eq!(max.load(Ordering::Relaxed), 2000);
+    }
+
+    #[test]
+    fn test_is_new_reporting_day() {
+        use chrono::FixedOffset;
+        use std::time::UNIX_EPOCH;
+
+        // 2024-01-01T22:30:00Z and 2024-01-01T23:30:00Z
+        let last_time = UNIX_EPOCH + Duration::from_secs(1_704_148_200);
+        let now = last_time + Duration::from_secs(60 * 60);
+
+        let utc = FixedOffset::east_opt(0).unwrap();
+        assert!(!super::is_new_reporting_day(last_time, now, &utc));
+
+        // The local day changes at 23:00Z in UTC+1
+        let cet = FixedOffset::east_opt(3600).unwrap();
+        assert!(super::is_new_reporting_day(last_time, now, &cet));
+
+        // Going back in time never starts a new day
+        assert!(!super::is_new_reporting_day(now, last_time, &cet));
+    }
+
+    #[tokio::test]
+    async fn test_total_balance_reads_each_venue_once() {
+        use crate::error_manager::AlertHandle;
+        use crate::trade::derivative_trader::DerivativeTraderConfig;
+        use crate::trade::dex_connector_box::DexConnectorBox;
+        use crate::trade::mock_dex::{Mock, MockDex};
+        use crate::trade::{DBHandler, DerivativeTrader};
+        use async_trait::async_trait;
+        use debot_market_analyzer::{TradingStrategy, TrendType};
+        use dex_connector::{BalanceResponse, DexError};
+        use std::collections::HashMap;
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use tokio::sync::Mutex;
+
+        // The account of a venue, counting the balance reads
+        struct Account {
+            equity: Decimal,
+            reads: Arc<AtomicU32>,
+        }
+
+        #[async_trait]
+        impl MockDex for Account {
+            async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+                self.reads.fetch_add(1, Ordering::SeqCst);
+                Ok(BalanceResponse {
+                    equity: self.equity,
+                    ..Default::default()
+                })
+            }
+        }
+
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let mut traders = vec![];
+        let mut reads = HashMap::new();
+        // Two traders on the Hyperliquid account and one on the Bybit account
+        for (dex_name, equity) in [("hyperliquid", 1000), ("hyperliquid", 1000), ("bybit", 500)] {
+            let account_reads = reads
+                .entry(dex_name)
+                .or_insert_with(|| Arc::new(AtomicU32::new(0)))
+                .clone();
+            let account = Account {
+                equity: Decimal::new(equity, 0),
+                reads: account_reads,
+            };
+            let dex_connector = DexConnectorBox::from_connector(dex_name, Box::new(Mock(account)));
+            traders.push(
+                DerivativeTrader::with_dex_connector(
+                    DerivativeTraderConfig::replay(
+                        dex_name,
+                        TradingStrategy::RandomWalk(TrendType::Up),
+                    ),
+                    Arc::new(dex_connector),
+                    db_handler.clone(),
+                    AlertHandle::detached(),
+                    HashMap::new(),
+                )
+                .await,
+            );
+        }
+        // Each trader read its initial balance
+        assert_eq!(reads["hyperliquid"].load(Ordering::SeqCst), 2);
+        assert_eq!(reads["bybit"].load(Ordering::SeqCst), 1);
+
+        let balance = super::total_balance(&traders).await;
+        assert_eq!(balance, Some(Decimal::new(1500, 0)));
+        assert_eq!(reads["hyperliquid"].load(Ordering::SeqCst), 3);
+        assert_eq!(reads["bybit"].load(Ordering::SeqCst), 2);
+    }
 }
@@ -0,0 +1,147 @@
+// metrics.rs
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use rust_decimal::Decimal;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Default, Clone)]
+pub struct FundMetrics {
+    pub fund_name: String,
+    pub pnl: Decimal,
+    pub order_count: i32,
+    pub fill_count: i32,
+}
+
+#[derive(Default, Clone)]
+pub struct MetricsSnapshot {
+    pub funds: Vec<FundMetrics>,
+    pub invested_amount: Decimal,
+    pub net_exposure: Decimal,
+    pub gross_exposure: Decimal,
+    pub initial_balance: Decimal,
+    pub dd_ratio: Decimal,
+}
+
+impl MetricsSnapshot {
+    // Folds the snapshot of another trader into this one
+    pub fn merge(&mut self, other: MetricsSnapshot) {
+        self.funds.extend(other.funds);
+        self.invested_amount += other.invested_amount;
+        self.net_exposure += other.net_exposure;
+        self.gross_exposure += other.gross_exposure;
+        self.initial_balance += other.initial_balance;
+        self.dd_ratio = self.dd_ratio.max(other.dd_ratio);
+    }
+}
+
+pub type SharedMetrics = Arc<Mutex<MetricsSnapshot>>;
+
+// The name of a gauge and how to read it from the metrics of a fund
+type FundGauge = (&'static str, fn(&FundMetrics) -> String);
+
+pub fn render(snapshot: &MetricsSnapshot) -> String {
+    let mut text = String::new();
+
+    let fund_gauges: [FundGauge; 3] = [
+        ("debot_pnl", |fund| fund.pnl.to_string()),
+        ("debot_order_count", |fund| fund.order_count.to_string()),
+        ("debot_fill_count", |fund| fund.fill_count.to_string()),
+    ];
+    for (name, value) in fund_gauges {
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        for fund in &snapshot.funds {
+            let _ = writeln!(
+                text,
+                "{}{{fund=\"{}\"}} {}",
+                name,
+                fund.fund_name.replace('\\', "\\\\").replace('"', "\\\""),
+                value(fund)
+            );
+        }
+    }
+
+    let gauges = [
+        ("debot_invested_amount", snapshot.invested_amount),
+        ("debot_net_exposure", snapshot.net_exposure),
+        ("debot_gross_exposure", snapshot.gross_exposure),
+        ("debot_initial_balance", snapshot.initial_balance),
+        ("debot_dd_ratio", snapshot.dd_ratio),
+    ];
+    for (name, value) in gauges {
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+
+    text
+}
+
+// Serves the latest snapshot in the Prometheus text format, and returns the bound address
+pub fn start_server(port: u16, metrics: SharedMetrics) -> Result<SocketAddr, hyper::Error> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+
+    let make_service = make_service_fn(move |_conn| {
+        let metrics = metrics.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |_req| {
+                let metrics = metrics.clone();
+                async move {
+                    let body = render(&*metrics.lock().await);
+                    Ok::<_, Infallible>(
+                        Response::builder()
+                            .header("Content-Type", "text/plain; version=0.0.4")
+                            .body(Body::from(body))
+                            .unwrap(),
+                    )
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    log::info!("metrics server is listening on {}", local_addr);
+
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("metrics server: {:?}", e);
+        }
+    });
+
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_scrape_pnl() {
+        let metrics = Arc::new(Mutex::new(MetricsSnapshot {
+            funds: vec![FundMetrics {
+                fund_name: "test-fund".to_owned(),
+                pnl: Decimal::new(125, 1),
+                order_count: 3,
+                fill_count: 2,
+            }],
+            initial_balance: Decimal::new(1000, 0),
+            ..Default::default()
+        }));
+
+        let addr = start_server(0, metrics).unwrap();
+        let body = reqwest::get(format!("http://127.0.0.1:{}/metrics", addr.port()))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        assert!(body.contains("debot_pnl{fund=\"test-fund\"} 12.5\n"));
+        assert!(body.contains("debot_fill_count{fund=\"test-fund\"} 2\n"));
+        assert!(body.contains("debot_initial_balance 1000\n"));
+    }
+}
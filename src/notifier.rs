@@ -0,0 +1,126 @@
+// notifier.rs
+
+use crate::email_client::EmailClient;
+use async_trait::async_trait;
+use std::env;
+
+#[async_trait]
+pub(crate) trait Notifier: Send + Sync {
+    fn name(&self) -> &str;
+
+    async fn send(&self, subject: &str, body: &str) -> Result<(), ()>;
+}
+
+#[async_trait]
+impl Notifier for EmailClient {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    // The SMTP transport of lettre blocks, so it is kept off the async workers
+    async fn send(&self, subject: &str, body: &str) -> Result<(), ()> {
+        let email_client = self.clone();
+        let (subject, body) = (subject.to_owned(), body.to_owned());
+        tokio::task::spawn_blocking(move || email_client.try_send(&subject, &body))
+            .await
+            .unwrap_or(Err(()))
+    }
+}
+
+pub(crate) struct DiscordNotifier {
+    webhook_url: String,
+}
+
+impl DiscordNotifier {
+    pub fn from_env() -> Option<Self> {
+        let webhook_url = env::var("DISCORD_WEBHOOK_URL").ok()?;
+        Some(DiscordNotifier { webhook_url })
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        "discord"
+    }
+
+    async fn send(&self, subject: &str, body: &str) -> Result<(), ()> {
+        let content = format!("{}\n{}", subject, body);
+        post_json(
+            self.name(),
+            &self.webhook_url,
+            serde_json::json!({ "content": content }),
+        )
+        .await
+    }
+}
+
+pub(crate) struct TelegramNotifier {
+    bot_token: String,
+    chat_id: String,
+}
+
+impl TelegramNotifier {
+    pub fn from_env() -> Option<Self> {
+        let bot_token = env::var("TELEGRAM_BOT_TOKEN").ok()?;
+        let chat_id = env::var("TELEGRAM_CHAT_ID").ok()?;
+        Some(TelegramNotifier { bot_token, chat_id })
+    }
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    async fn send(&self, subject: &str, body: &str) -> Result<(), ()> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+        let text = format!("{}\n{}", subject, body);
+        post_json(
+            self.name(),
+            &url,
+            serde_json::json!({ "chat_id": self.chat_id, "text": text }),
+        )
+        .await
+    }
+}
+
+async fn post_json(name: &str, url: &str, payload: serde_json::Value) -> Result<(), ()> {
+    match reqwest::Client::new().post(url).json(&payload).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) => {
+            log::warn!("Failed to send a {} message: {}", name, res.status());
+            Err(())
+        }
+        Err(e) => {
+            log::warn!("Failed to send a {} message: {:?}", name, e);
+            Err(())
+        }
+    }
+}
+
+// NOTIFIERS is a comma separated list of the backends, e.g. "email,telegram".
+// A backend without its credentials is skipped.
+pub(crate) fn from_env() -> Vec<Box<dyn Notifier>> {
+    let backends = env::var("NOTIFIERS").unwrap_or_else(|_| "email,discord".to_owned());
+
+    let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+    for backend in backends.split(',').map(|v| v.trim()) {
+        let notifier: Option<Box<dyn Notifier>> = match backend {
+            "email" => Some(Box::new(EmailClient::new())),
+            "discord" => DiscordNotifier::from_env().map(|v| Box::new(v) as Box<dyn Notifier>),
+            "telegram" => TelegramNotifier::from_env().map(|v| Box::new(v) as Box<dyn Notifier>),
+            "" => continue,
+            other => {
+                log::error!("Unknown notifier: {}", other);
+                continue;
+            }
+        };
+        match notifier {
+            Some(notifier) => notifiers.push(notifier),
+            None => log::warn!("{} notifier is not configured", backend),
+        }
+    }
+    notifiers
+}
@@ -0,0 +1,1020 @@
+// bybit_connector.rs
+
+use super::dex_connector_box::{DexConnectorExt, TimeInForce};
+use async_trait::async_trait;
+use dex_connector::{
+    BalanceResponse, CreateOrderResponse, DexConnector, DexError, FilledOrder,
+    FilledOrdersResponse, OrderSide, TickerResponse,
+};
+use futures::{SinkExt, StreamExt};
+use hmac::{Hmac, Mac};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+
+const CATEGORY: &str = "linear";
+const SETTLE_COIN: &str = "USDT";
+const RECV_WINDOW: &str = "5000";
+const RET_CODE_LEVERAGE_NOT_MODIFIED: i64 = 110043;
+// Max number of orders in a batch request for the linear category
+const BATCH_CANCEL_SIZE: usize = 10;
+// The public stream is closed by the venue without a ping in 30 seconds
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq)]
+struct InstrumentInfo {
+    tick_size: Decimal,
+    qty_step: Decimal,
+}
+
+pub struct BybitConnector {
+    client: reqwest::Client,
+    rest_endpoint: String,
+    web_socket_endpoint: String,
+    api_key: String,
+    api_secret: String,
+    // The venue symbols, e.g. BTCUSDT. DexConnectorBox translates the internal ones.
+    symbols: Vec<String>,
+    instruments: Mutex<HashMap<String, InstrumentInfo>>,
+    cleared_trade_ids: Mutex<HashSet<String>>,
+    // The latest ticker of each venue symbol on the public stream. The REST tickers are
+    // read while the stream is down.
+    tickers: Arc<RwLock<HashMap<String, Value>>>,
+    ticker_task: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl BybitConnector {
+    pub async fn new(
+        rest_endpoint: &str,
+        web_socket_endpoint: &str,
+        api_key: &str,
+        api_secret: &str,
+        symbols: &[&str],
+    ) -> Result<Self, DexError> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(10))
+            .build()
+            .map_err(|e| DexError::Other(format!("Failed to create a http client: {:?}", e)))?;
+
+        Ok(BybitConnector {
+            client,
+            rest_endpoint: rest_endpoint.trim_end_matches('/').to_owned(),
+            web_socket_endpoint: web_socket_endpoint.to_owned(),
+            api_key: api_key.to_owned(),
+            api_secret: api_secret.to_owned(),
+            symbols: symbols.iter().map(|s| s.to_string()).collect(),
+            instruments: Mutex::new(HashMap::new()),
+            cleared_trade_ids: Mutex::new(HashSet::new()),
+            tickers: Arc::new(RwLock::new(HashMap::new())),
+            ticker_task: Mutex::new(None),
+        })
+    }
+
+    fn timestamp_millis() -> String {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    fn sign(&self, timestamp: &str, payload: &str) -> Result<String, DexError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.api_secret.as_bytes())
+            .map_err(|e| DexError::Other(format!("Invalid api secret: {:?}", e)))?;
+        mac.update(timestamp.as_bytes());
+        mac.update(self.api_key.as_bytes());
+        mac.update(RECV_WINDOW.as_bytes());
+        mac.update(payload.as_bytes());
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+
+    async fn get(&self, path: &str, query: &str, signed: bool) -> Result<Value, DexError> {
+        let url = format!("{}{}?{}", self.rest_endpoint, path, query);
+        let mut request = self.client.get(&url);
+        if signed {
+            let timestamp = Self::timestamp_millis();
+            let signature = self.sign(&timestamp, query)?;
+            request = request
+                .header("X-BAPI-API-KEY", &self.api_key)
+                .header("X-BAPI-TIMESTAMP", &timestamp)
+                .header("X-BAPI-RECV-WINDOW", RECV_WINDOW)
+                .header("X-BAPI-SIGN", signature);
+        }
+        let res = request
+            .send()
+            .await
+            .map_err(|e| DexError::Other(format!("GET {} failed: {:?}", path, e)))?;
+        let body = res
+            .text()
+            .await
+            .map_err(|e| DexError::Other(format!("GET {} failed: {:?}", path, e)))?;
+        Self::parse_result(path, &body)
+    }
+
+    async fn post(&self, path: &str, payload: Value) -> Result<Value, DexError> {
+        let body = self.post_body(path, payload).await?;
+        Self::parse_result(path, &body)
+    }
+
+    async fn post_body(&self, path: &str, payload: Value) -> Result<String, DexError> {
+        let url = format!("{}{}", self.rest_endpoint, path);
+        let body = payload.to_string();
+        let timestamp = Self::timestamp_millis();
+        let signature = self.sign(&timestamp, &body)?;
+        let res = self
+            .client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .header("X-BAPI-API-KEY", &self.api_key)
+            .header("X-BAPI-TIMESTAMP", &timestamp)
+            .header("X-BAPI-RECV-WINDOW", RECV_WINDOW)
+            .header("X-BAPI-SIGN", signature)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| DexError::Other(format!("POST {} failed: {:?}", path, e)))?;
+        res.text()
+            .await
+            .map_err(|e| DexError::Other(format!("POST {} failed: {:?}", path, e)))
+    }
+
+    fn parse_result(path: &str, body: &str) -> Result<Value, DexError> {
+        let value: Value = serde_json::from_str(body)
+            .map_err(|e| DexError::Other(format!("{}: invalid response: {:?}", path, e)))?;
+        let ret_code = value["retCode"].as_i64().unwrap_or(-1);
+        if ret_code != 0 {
+            return Err(DexError::Other(format!(
+                "{}: retCode = {}, retMsg = {}",
+                path, ret_code, value["retMsg"]
+            )));
+        }
+        Ok(value["result"].clone())
+    }
+
+    // A batch succeeds with retCode 0 even when some of its orders fail, which are reported
+    // in retExtInfo. One failed cancel fails the batch, so that the caller cancels the orders
+    // one by one.
+    fn parse_cancel_batch(path: &str, body: &str) -> Result<(), DexError> {
+        Self::parse_result(path, body)?;
+        let value: Value = serde_json::from_str(body)
+            .map_err(|e| DexError::Other(format!("{}: invalid response: {:?}", path, e)))?;
+        let errors: Vec<String> = value["retExtInfo"]["list"]
+            .as_array()
+            .map(|list| {
+                list.iter()
+                    .filter(|item| item["code"].as_i64().unwrap_or(-1) != 0)
+                    .map(|item| format!("code = {}, msg = {}", item["code"], item["msg"]))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(DexError::Other(format!("{}: {}", path, errors.join(", "))))
+        }
+    }
+
+    fn decimal_field(value: &Value, key: &str) -> Option<Decimal> {
+        value[key]
+            .as_str()
+            .filter(|s| !s.is_empty())
+            .and_then(|s| Decimal::from_str(s).ok())
+    }
+
+    fn parse_instrument(result: &Value) -> Option<InstrumentInfo> {
+        let item = result["list"].get(0)?;
+        Some(InstrumentInfo {
+            tick_size: Self::decimal_field(&item["priceFilter"], "tickSize")?,
+            qty_step: Self::decimal_field(&item["lotSizeFilter"], "qtyStep")?,
+        })
+    }
+
+    fn parse_ticker(result: &Value, min_tick: Option<Decimal>) -> Option<TickerResponse> {
+        Self::parse_ticker_item(result["list"].get(0)?, min_tick)
+    }
+
+    // The REST tickers and the stream share the field names
+    fn parse_ticker_item(item: &Value, min_tick: Option<Decimal>) -> Option<TickerResponse> {
+        Some(TickerResponse {
+            price: Self::decimal_field(item, "lastPrice")?,
+            min_tick,
+            volume: Self::decimal_field(item, "volume24h"),
+            num_trades: None,
+            funding_rate: Self::decimal_field(item, "fundingRate"),
+            open_interest: Self::decimal_field(item, "openInterest"),
+            oracle_price: Self::decimal_field(item, "indexPrice"),
+            ..Default::default()
+        })
+    }
+
+    // A snapshot replaces the ticker of its symbol, a delta only carries the changed fields
+    fn merge_ticker_message(tickers: &mut HashMap<String, Value>, text: &str) {
+        let message: Value = match serde_json::from_str(text) {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+        let venue_symbol = match message["topic"]
+            .as_str()
+            .and_then(|topic| topic.strip_prefix("tickers."))
+        {
+            Some(v) => v,
+            None => return,
+        };
+        let data = match message["data"].as_object() {
+            Some(v) => v,
+            None => return,
+        };
+        match message["type"].as_str() {
+            Some("snapshot") => {
+                tickers.insert(venue_symbol.to_owned(), Value::Object(data.clone()));
+            }
+            _ => {
+                if let Some(Value::Object(ticker)) = tickers.get_mut(venue_symbol) {
+                    ticker.extend(data.clone());
+                }
+            }
+        }
+    }
+
+    async fn stream_tickers(
+        endpoint: String,
+        venue_symbols: Vec<String>,
+        tickers: Arc<RwLock<HashMap<String, Value>>>,
+    ) {
+        let topics: Vec<String> = venue_symbols
+            .iter()
+            .map(|symbol| format!("tickers.{}", symbol))
+            .collect();
+        let subscribe = json!({ "op": "subscribe", "args": topics }).to_string();
+        let ping = json!({ "op": "ping" }).to_string();
+
+        loop {
+            match connect_async(endpoint.as_str()).await {
+                Ok((ws_stream, _)) => {
+                    log::info!("websocket is connected to {}", endpoint);
+                    let (mut write, mut read) = ws_stream.split();
+                    if let Err(e) = write.send(Message::Text(subscribe.clone())).await {
+                        log::error!("Failed to subscribe the tickers: {:?}", e);
+                    } else {
+                        let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+                        loop {
+                            tokio::select! {
+                                message = read.next() => match message {
+                                    Some(Ok(Message::Text(text))) => {
+                                        Self::merge_ticker_message(&mut *tickers.write().await, &text);
+                                    }
+                                    Some(Ok(_)) => {}
+                                    Some(Err(e)) => {
+                                        log::error!("Failed to read: {:?}", e);
+                                        break;
+                                    }
+                                    None => {
+                                        log::info!("WebSocket stream ended");
+                                        break;
+                                    }
+                                },
+                                _ = ping_interval.tick() => {
+                                    if let Err(e) = write.send(Message::Text(ping.clone())).await {
+                                        log::error!("Failed to send a ping: {:?}", e);
+                                        break;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(e) => log::error!("Failed to connect to {}: {:?}", endpoint, e),
+            }
+
+            // The tickers would go stale until the stream is back
+            tickers.write().await.clear();
+            tokio::time::sleep(RECONNECT_DELAY).await;
+        }
+    }
+
+    fn parse_filled_orders(result: &Value, cleared: &HashSet<String>) -> Vec<FilledOrder> {
+        let list = match result["list"].as_array() {
+            Some(v) => v,
+            None => return vec![],
+        };
+        list.iter()
+            .filter_map(|item| {
+                let trade_id = item["execId"].as_str()?.to_owned();
+                if cleared.contains(&trade_id) {
+                    return None;
+                }
+                let filled_side = match item["side"].as_str()? {
+                    "Buy" => OrderSide::Long,
+                    _ => OrderSide::Short,
+                };
+                Some(FilledOrder {
+                    order_id: item["orderId"].as_str()?.to_owned(),
+                    trade_id,
+                    filled_side: Some(filled_side),
+                    filled_size: Self::decimal_field(item, "execQty"),
+                    filled_value: Self::decimal_field(item, "execValue"),
+                    filled_fee: Self::decimal_field(item, "execFee"),
+                    is_rejected: false,
+                })
+            })
+            .collect()
+    }
+
+    fn order_payload(
+        venue_symbol: &str,
+        side: &OrderSide,
+        size: Decimal,
+        price: Option<Decimal>,
+        time_in_force: TimeInForce,
+    ) -> Value {
+        let mut payload = json!({
+            "category": CATEGORY,
+            "symbol": venue_symbol,
+            "side": Self::side_str(side),
+            "qty": size.to_string(),
+        });
+        match price {
+            Some(p) => {
+                payload["orderType"] = json!("Limit");
+                payload["price"] = json!(p.to_string());
+                payload["timeInForce"] = json!(match time_in_force {
+                    TimeInForce::Gtc => "GTC",
+                    TimeInForce::Ioc => "IOC",
+                    TimeInForce::Fok => "FOK",
+                });
+            }
+            None => {
+                payload["orderType"] = json!("Market");
+            }
+        }
+        payload
+    }
+
+    fn parse_create_order(
+        result: &Value,
+        ordered_size: Decimal,
+        ordered_price: Decimal,
+    ) -> Result<CreateOrderResponse, DexError> {
+        let order_id = result["orderId"]
+            .as_str()
+            .ok_or_else(|| DexError::Other("orderId is missing".to_owned()))?;
+        Ok(CreateOrderResponse {
+            order_id: order_id.to_owned(),
+            ordered_price,
+            ordered_size,
+        })
+    }
+
+    fn parse_symbols(result: &Value) -> Vec<String> {
+        let mut symbols: Vec<String> = result["list"]
+            .as_array()
+            .map(|list| {
+                list.iter()
+                    .filter_map(|item| item["symbol"].as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default();
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    fn parse_order_ids(result: &Value) -> Vec<String> {
+        result["list"]
+            .as_array()
+            .map(|list| {
+                list.iter()
+                    .filter_map(|item| item["orderId"].as_str().map(str::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // Sizes are unsigned on Bybit, so a Sell position is negated
+    fn parse_positions(result: &Value) -> Vec<(String, Decimal)> {
+        result["list"]
+            .as_array()
+            .map(|list| {
+                list.iter()
+                    .filter_map(|item| {
+                        let size = Self::decimal_field(item, "size")?;
+                        if size.is_zero() {
+                            return None;
+                        }
+                        let symbol = item["symbol"].as_str()?.to_owned();
+                        match item["side"].as_str() {
+                            Some("Sell") => Some((symbol, -size)),
+                            _ => Some((symbol, size)),
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn round_to_step(value: Decimal, step: Decimal) -> Decimal {
+        if step.is_zero() {
+            return value;
+        }
+        ((value / step).round_dp_with_strategy(0, RoundingStrategy::ToZero) * step).normalize()
+    }
+
+    async fn instrument(&self, venue_symbol: &str) -> Result<InstrumentInfo, DexError> {
+        if let Some(info) = self.instruments.lock().await.get(venue_symbol) {
+            return Ok(info.clone());
+        }
+        let result = self
+            .get(
+                "/v5/market/instruments-info",
+                &format!("category={}&symbol={}", CATEGORY, venue_symbol),
+                false,
+            )
+            .await?;
+        let info = Self::parse_instrument(&result).ok_or_else(|| {
+            DexError::Other(format!(
+                "instrument info of {} is not available",
+                venue_symbol
+            ))
+        })?;
+        self.instruments
+            .lock()
+            .await
+            .insert(venue_symbol.to_owned(), info.clone());
+        Ok(info)
+    }
+
+    fn cancel_batch_payloads(symbol: &str, order_ids: &[String]) -> Vec<Value> {
+        order_ids
+            .chunks(BATCH_CANCEL_SIZE)
+            .map(|chunk| {
+                let request: Vec<Value> = chunk
+                    .iter()
+                    .map(|order_id| json!({ "symbol": symbol, "orderId": order_id }))
+                    .collect();
+                json!({ "category": CATEGORY, "request": request })
+            })
+            .collect()
+    }
+
+    fn side_str(side: &OrderSide) -> &'static str {
+        match side {
+            OrderSide::Long => "Buy",
+            OrderSide::Short => "Sell",
+        }
+    }
+}
+
+#[async_trait]
+impl DexConnector for BybitConnector {
+    async fn start(&self) -> Result<(), DexError> {
+        for symbol in &self.symbols {
+            self.instrument(symbol).await?;
+        }
+
+        let mut ticker_task = self.ticker_task.lock().await;
+        if ticker_task.is_none() && !self.web_socket_endpoint.is_empty() {
+            *ticker_task = Some(tokio::spawn(Self::stream_tickers(
+                self.web_socket_endpoint.clone(),
+                self.symbols.clone(),
+                self.tickers.clone(),
+            )));
+        }
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), DexError> {
+        if let Some(handle) = self.ticker_task.lock().await.take() {
+            handle.abort();
+        }
+        self.tickers.write().await.clear();
+        Ok(())
+    }
+
+    async fn restart(&self) -> Result<(), DexError> {
+        self.stop().await?;
+        self.instruments.lock().await.clear();
+        self.start().await
+    }
+
+    async fn set_leverage(&self, symbol: &str, leverage: u32) -> Result<(), DexError> {
+        let payload = json!({
+            "category": CATEGORY,
+            "symbol": symbol,
+            "buyLeverage": leverage.to_string(),
+            "sellLeverage": leverage.to_string(),
+        });
+        match self.post("/v5/position/set-leverage", payload).await {
+            Ok(_) => Ok(()),
+            Err(DexError::Other(msg))
+                if msg.contains(&RET_CODE_LEVERAGE_NOT_MODIFIED.to_string()) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_ticker(
+        &self,
+        symbol: &str,
+        test_price: Option<Decimal>,
+    ) -> Result<TickerResponse, DexError> {
+        let instrument = self.instrument(symbol).await?;
+        let streamed = self
+            .tickers
+            .read()
+            .await
+            .get(symbol)
+            .and_then(|ticker| Self::parse_ticker_item(ticker, Some(instrument.tick_size)));
+        let mut res = match streamed {
+            Some(res) => res,
+            None => {
+                let result = self
+                    .get(
+                        "/v5/market/tickers",
+                        &format!("category={}&symbol={}", CATEGORY, symbol),
+                        false,
+                    )
+                    .await?;
+                Self::parse_ticker(&result, Some(instrument.tick_size)).ok_or_else(|| {
+                    DexError::Other(format!("ticker of {} is not available", symbol))
+                })?
+            }
+        };
+        if let Some(price) = test_price {
+            res.price = price;
+        }
+        Ok(res)
+    }
+
+    async fn get_filled_orders(&self, symbol: &str) -> Result<FilledOrdersResponse, DexError> {
+        let result = self
+            .get(
+                "/v5/execution/list",
+                &format!("category={}&symbol={}", CATEGORY, symbol),
+                true,
+            )
+            .await?;
+        let cleared = self.cleared_trade_ids.lock().await;
+        Ok(FilledOrdersResponse {
+            orders: Self::parse_filled_orders(&result, &cleared),
+        })
+    }
+
+    async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+        let result = self
+            .get("/v5/account/wallet-balance", "accountType=UNIFIED", true)
+            .await?;
+        let equity = result["list"]
+            .get(0)
+            .and_then(|item| Self::decimal_field(item, "totalEquity"))
+            .ok_or_else(|| DexError::Other("equity is not available".to_owned()))?;
+        Ok(BalanceResponse {
+            equity,
+            ..Default::default()
+        })
+    }
+
+    async fn clear_filled_order(&self, _symbol: &str, trade_id: &str) -> Result<(), DexError> {
+        self.cleared_trade_ids
+            .lock()
+            .await
+            .insert(trade_id.to_owned());
+        Ok(())
+    }
+
+    async fn clear_all_filled_order(&self) -> Result<(), DexError> {
+        for symbol in &self.symbols {
+            let filled_orders = self.get_filled_orders(symbol).await?;
+            let mut cleared = self.cleared_trade_ids.lock().await;
+            for order in filled_orders.orders {
+                cleared.insert(order.trade_id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_order(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        spread: Option<i64>,
+    ) -> Result<CreateOrderResponse, DexError> {
+        self.create_order_with_tif(symbol, size, side, price, spread, TimeInForce::Gtc)
+            .await
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<(), DexError> {
+        let payload = json!({
+            "category": CATEGORY,
+            "symbol": symbol,
+            "orderId": order_id,
+        });
+        self.post("/v5/order/cancel", payload).await.map(|_| ())
+    }
+
+    async fn cancel_all_orders(&self, symbol: Option<String>) -> Result<(), DexError> {
+        let payload = match symbol {
+            Some(symbol) => json!({
+                "category": CATEGORY,
+                "symbol": symbol,
+            }),
+            None => json!({
+                "category": CATEGORY,
+                "settleCoin": SETTLE_COIN,
+            }),
+        };
+        self.post("/v5/order/cancel-all", payload).await.map(|_| ())
+    }
+
+    async fn close_all_positions(&self, symbol: Option<String>) -> Result<(), DexError> {
+        let query = match &symbol {
+            Some(symbol) => format!("category={}&symbol={}", CATEGORY, symbol),
+            None => format!("category={}&settleCoin={}", CATEGORY, SETTLE_COIN),
+        };
+        let result = self.get("/v5/position/list", &query, true).await?;
+        let positions = result["list"].as_array().cloned().unwrap_or_default();
+
+        for position in positions {
+            let size = Self::decimal_field(&position, "size").unwrap_or_default();
+            if size.is_zero() {
+                continue;
+            }
+            let side = match position["side"].as_str() {
+                Some("Buy") => "Sell",
+                Some("Sell") => "Buy",
+                _ => continue,
+            };
+            let payload = json!({
+                "category": CATEGORY,
+                "symbol": position["symbol"],
+                "side": side,
+                "orderType": "Market",
+                "qty": size.to_string(),
+                "reduceOnly": true,
+            });
+            self.post("/v5/order/create", payload).await?;
+        }
+        Ok(())
+    }
+
+    async fn clear_last_trades(&self, _symbol: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DexConnectorExt for BybitConnector {
+    async fn create_order_with_tif(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        _spread: Option<i64>,
+        time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        let instrument = self.instrument(symbol).await?;
+        let size = Self::round_to_step(size, instrument.qty_step);
+        if size.is_zero() {
+            return Err(DexError::Other(format!(
+                "order size is smaller than the qty step({})",
+                instrument.qty_step
+            )));
+        }
+        let price = price.map(|p| Self::round_to_step(p, instrument.tick_size));
+        // The response has no price, so a market order is taken at the last price
+        let ordered_price = match price {
+            Some(price) => price,
+            None => self.get_ticker(symbol, None).await?.price,
+        };
+
+        let payload = Self::order_payload(symbol, &side, size, price, time_in_force);
+        let result = self.post("/v5/order/create", payload).await?;
+        Self::parse_create_order(&result, size, ordered_price)
+    }
+
+    async fn cancel_orders(&self, symbol: &str, order_ids: &[String]) -> Result<usize, DexError> {
+        let payloads = Self::cancel_batch_payloads(symbol, order_ids);
+        let requests = payloads.len();
+        for payload in payloads {
+            let path = "/v5/order/cancel-batch";
+            Self::parse_cancel_batch(path, &self.post_body(path, payload).await?)?;
+        }
+        Ok(requests)
+    }
+
+    async fn get_open_order_symbols(&self) -> Result<Vec<String>, DexError> {
+        let result = self
+            .get(
+                "/v5/order/realtime",
+                &format!("category={}&settleCoin={}", CATEGORY, SETTLE_COIN),
+                true,
+            )
+            .await?;
+        Ok(Self::parse_symbols(&result))
+    }
+
+    async fn get_open_order_ids(&self, symbol: &str) -> Result<Vec<String>, DexError> {
+        let result = self
+            .get(
+                "/v5/order/realtime",
+                &format!("category={}&symbol={}", CATEGORY, symbol),
+                true,
+            )
+            .await?;
+        Ok(Self::parse_order_ids(&result))
+    }
+
+    async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+        let result = self
+            .get(
+                "/v5/position/list",
+                &format!("category={}&settleCoin={}", CATEGORY, SETTLE_COIN),
+                true,
+            )
+            .await?;
+        Ok(Self::parse_positions(&result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_positions() {
+        let result = serde_json::from_str(
+            r#"{"list":[{"symbol":"BTCUSDT","side":"Buy","size":"0.01"},{"symbol":"ETHUSDT","side":"","size":"0"},{"symbol":"SOLUSDT","side":"Sell","size":"2"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            BybitConnector::parse_positions(&result),
+            vec![
+                ("BTCUSDT".to_owned(), Decimal::new(1, 2)),
+                ("SOLUSDT".to_owned(), Decimal::new(-2, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_order_ids() {
+        let result = serde_json::from_str(
+            r#"{"list":[{"orderId":"a1","symbol":"BTCUSDT"},{"orderId":"b2","symbol":"BTCUSDT"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            BybitConnector::parse_order_ids(&result),
+            vec!["a1".to_owned(), "b2".to_owned()]
+        );
+        assert!(BybitConnector::parse_order_ids(&serde_json::json!({})).is_empty());
+    }
+
+    #[test]
+    fn test_order_payload_time_in_force() {
+        let price = Some(Decimal::new(100, 0));
+        for (time_in_force, expected) in [
+            (TimeInForce::Gtc, "GTC"),
+            (TimeInForce::Ioc, "IOC"),
+            (TimeInForce::Fok, "FOK"),
+        ] {
+            let payload = BybitConnector::order_payload(
+                "BTCUSDT",
+                &OrderSide::Long,
+                Decimal::ONE,
+                price,
+                time_in_force,
+            );
+            assert_eq!(payload["orderType"], "Limit");
+            assert_eq!(payload["timeInForce"], expected);
+        }
+
+        // Market orders are immediate by nature
+        let payload = BybitConnector::order_payload(
+            "BTCUSDT",
+            &OrderSide::Short,
+            Decimal::ONE,
+            None,
+            TimeInForce::Fok,
+        );
+        assert_eq!(payload["orderType"], "Market");
+        assert!(payload.get("timeInForce").is_none());
+    }
+
+    // A venue on localhost that serves canned bodies by path and records the requests
+    async fn mock_rest(
+        routes: Vec<(&'static str, &'static str)>,
+    ) -> (String, Arc<std::sync::Mutex<Vec<MockRequest>>>) {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Request, Response, Server};
+        use std::convert::Infallible;
+
+        let requests = Arc::new(std::sync::Mutex::new(vec![]));
+        let recorded = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let routes = routes.clone();
+            let recorded = recorded.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let routes = routes.clone();
+                    let recorded = recorded.clone();
+                    async move {
+                        let path = req.uri().path().to_owned();
+                        let signed = req.headers().contains_key("X-BAPI-SIGN");
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let body: Value = serde_json::from_slice(&body).unwrap_or_default();
+                        let result = routes
+                            .iter()
+                            .find(|(route, _)| *route == path)
+                            .map_or("{}", |(_, result)| result);
+                        recorded
+                            .lock()
+                            .unwrap()
+                            .push(MockRequest { path, signed, body });
+                        Ok::<_, Infallible>(Response::new(Body::from(format!(
+                            r#"{{"retCode":0,"retMsg":"OK","result":{}}}"#,
+                            result
+                        ))))
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        (endpoint, requests)
+    }
+
+    struct MockRequest {
+        path: String,
+        signed: bool,
+        body: Value,
+    }
+
+    const INSTRUMENTS: &str = r#"{"list":[{"symbol":"BTCUSDT","priceFilter":{"tickSize":"0.10"},"lotSizeFilter":{"qtyStep":"0.001"}}]}"#;
+
+    #[tokio::test]
+    async fn test_create_order_round_trip() {
+        let (endpoint, requests) = mock_rest(vec![
+            ("/v5/market/instruments-info", INSTRUMENTS),
+            (
+                "/v5/market/tickers",
+                r#"{"list":[{"lastPrice":"65000.5"}]}"#,
+            ),
+            ("/v5/order/create", r#"{"orderId":"1321003749386327552"}"#),
+        ])
+        .await;
+        let connector = BybitConnector::new(&endpoint, "", "key", "secret", &["BTCUSDT"])
+            .await
+            .unwrap();
+
+        // The size and the price are rounded to the steps of the instrument
+        let res = connector
+            .create_order(
+                "BTCUSDT",
+                Decimal::new(123456, 5),
+                OrderSide::Long,
+                Some(Decimal::new(6543217, 2)),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.order_id, "1321003749386327552");
+        assert_eq!(res.ordered_size, Decimal::new(1234, 3));
+        assert_eq!(res.ordered_price, Decimal::new(654321, 1));
+        {
+            let requests = requests.lock().unwrap();
+            let order = requests.last().unwrap();
+            assert_eq!(order.path, "/v5/order/create");
+            assert!(order.signed);
+            assert_eq!(order.body["symbol"], "BTCUSDT");
+            assert_eq!(order.body["qty"], "1.234");
+            assert_eq!(order.body["price"], "65432.1");
+        }
+
+        // A market order is taken at the last price
+        let res = connector
+            .create_order("BTCUSDT", Decimal::ONE, OrderSide::Short, None, None)
+            .await
+            .unwrap();
+        assert_eq!(res.ordered_price, Decimal::new(650005, 1));
+        let requests = requests.lock().unwrap();
+        let order = requests.last().unwrap();
+        assert_eq!(order.body["orderType"], "Market");
+        assert_eq!(order.body["side"], "Sell");
+    }
+
+    #[tokio::test]
+    async fn test_ticker_from_stream() {
+        use tokio::net::TcpListener;
+
+        let (endpoint, _) = mock_rest(vec![
+            ("/v5/market/instruments-info", INSTRUMENTS),
+            ("/v5/market/tickers", r#"{"list":[{"lastPrice":"1"}]}"#),
+        ])
+        .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_endpoint = format!("ws://{}", listener.local_addr().unwrap());
+        let stream = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws_stream = tokio_tungstenite::accept_async(socket).await.unwrap();
+            let subscribe = ws_stream.next().await.unwrap().unwrap();
+            for message in [
+                r#"{"topic":"tickers.BTCUSDT","type":"snapshot","data":{"symbol":"BTCUSDT","lastPrice":"65000.5","fundingRate":"0.0001"}}"#,
+                r#"{"topic":"tickers.BTCUSDT","type":"delta","data":{"symbol":"BTCUSDT","lastPrice":"65010"}}"#,
+            ] {
+                ws_stream
+                    .send(Message::Text(message.to_owned()))
+                    .await
+                    .unwrap();
+            }
+            // Stays open until the connector stops
+            while let Some(Ok(_)) = ws_stream.next().await {}
+            subscribe
+        });
+
+        let connector = BybitConnector::new(&endpoint, &ws_endpoint, "key", "secret", &["BTCUSDT"])
+            .await
+            .unwrap();
+        connector.start().await.unwrap();
+        let mut ticker = connector.get_ticker("BTCUSDT", None).await.unwrap();
+        for _ in 0..100 {
+            if ticker.price == Decimal::new(65010, 0) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            ticker = connector.get_ticker("BTCUSDT", None).await.unwrap();
+        }
+        assert_eq!(ticker.price, Decimal::new(65010, 0));
+        assert_eq!(ticker.funding_rate, Some(Decimal::new(1, 4)));
+        assert_eq!(ticker.min_tick, Some(Decimal::new(10, 2)));
+
+        // Back to the REST tickers once stopped
+        connector.stop().await.unwrap();
+        let ticker = connector.get_ticker("BTCUSDT", None).await.unwrap();
+        assert_eq!(ticker.price, Decimal::ONE);
+        let subscribe = stream.await.unwrap();
+        let subscribe: Value = serde_json::from_str(&subscribe.into_text().unwrap()).unwrap();
+        assert_eq!(
+            subscribe,
+            json!({ "op": "subscribe", "args": ["tickers.BTCUSDT"] })
+        );
+    }
+
+    #[test]
+    fn test_cancel_batch_payloads() {
+        let order_ids: Vec<String> = (0..12).map(|i| i.to_string()).collect();
+        let payloads = BybitConnector::cancel_batch_payloads("BTCUSDT", &order_ids);
+
+        assert_eq!(payloads.len(), 2);
+        assert_eq!(payloads[0]["request"].as_array().unwrap().len(), 10);
+        assert_eq!(payloads[1]["request"].as_array().unwrap().len(), 2);
+        assert_eq!(payloads[1]["request"][1]["orderId"], "11");
+        assert_eq!(payloads[0]["request"][0]["symbol"], "BTCUSDT");
+    }
+
+    #[test]
+    fn test_cancel_batch_fails_on_any_order() {
+        let path = "/v5/order/cancel-batch";
+        let response = |codes: Value| {
+            json!({
+                "retCode": 0,
+                "retMsg": "OK",
+                "result": { "list": [{ "orderId": "1" }, { "orderId": "2" }] },
+                "retExtInfo": { "list": codes },
+            })
+            .to_string()
+        };
+        let succeeded = response(json!([
+            { "code": 0, "msg": "OK" },
+            { "code": 0, "msg": "OK" },
+        ]));
+        assert!(BybitConnector::parse_cancel_batch(path, &succeeded).is_ok());
+
+        let partially_failed = response(json!([
+            { "code": 0, "msg": "OK" },
+            { "code": 110001, "msg": "Order does not exist" },
+        ]));
+        let e = BybitConnector::parse_cancel_batch(path, &partially_failed).unwrap_err();
+        assert!(format!("{:?}", e).contains("110001"), "{:?}", e);
+    }
+
+    #[test]
+    fn test_error_response() {
+        let res = BybitConnector::parse_result(
+            "/v5/order/create",
+            r#"{"retCode":10001,"retMsg":"params error","result":{}}"#,
+        );
+        assert!(res.is_err());
+    }
+}
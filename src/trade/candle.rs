@@ -0,0 +1,225 @@
+// candle.rs
+
+use rust_decimal::Decimal;
+use std::collections::VecDeque;
+
+// A body within this share of the range is a doji
+const DOJI_BODY_RATIO: Decimal = Decimal::from_parts(1, 0, 0, false, 1);
+// The long shadow of a hammer or a shooting star is at least this many bodies
+const SHADOW_BODY_FACTOR: Decimal = Decimal::TWO;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Candle {
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+}
+
+impl Candle {
+    fn from_prices(prices: &[Decimal]) -> Option<Self> {
+        let open = *prices.first()?;
+        let close = *prices.last()?;
+        let high = prices.iter().copied().max()?;
+        let low = prices.iter().copied().min()?;
+        Some(Self {
+            open,
+            high,
+            low,
+            close,
+        })
+    }
+
+    fn body(&self) -> Decimal {
+        (self.close - self.open).abs()
+    }
+
+    fn range(&self) -> Decimal {
+        self.high - self.low
+    }
+
+    fn upper_shadow(&self) -> Decimal {
+        self.high - self.open.max(self.close)
+    }
+
+    fn lower_shadow(&self) -> Decimal {
+        self.open.min(self.close) - self.low
+    }
+
+    fn is_bullish(&self) -> bool {
+        self.close > self.open
+    }
+
+    fn is_bearish(&self) -> bool {
+        self.close < self.open
+    }
+}
+
+// debot_db::CandlePattern is fixed by the position log, so the aggregated patterns have their own
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CandleShape {
+    #[default]
+    None,
+    Doji,
+    Hammer,
+    ShootingStar,
+    BullishEngulfing,
+    BearishEngulfing,
+}
+
+// The pattern of the last candle, with the one before it for the engulfing patterns
+pub fn detect_pattern(prev: &Candle, last: &Candle) -> CandleShape {
+    if prev.is_bearish()
+        && last.is_bullish()
+        && last.open <= prev.close
+        && last.close >= prev.open
+        && last.body() > prev.body()
+    {
+        return CandleShape::BullishEngulfing;
+    }
+    if prev.is_bullish()
+        && last.is_bearish()
+        && last.open >= prev.close
+        && last.close <= prev.open
+        && last.body() > prev.body()
+    {
+        return CandleShape::BearishEngulfing;
+    }
+
+    if last.range().is_zero() {
+        return CandleShape::None;
+    }
+    if last.body() <= last.range() * DOJI_BODY_RATIO {
+        return CandleShape::Doji;
+    }
+    if last.lower_shadow() >= last.body() * SHADOW_BODY_FACTOR && last.upper_shadow() <= last.body()
+    {
+        return CandleShape::Hammer;
+    }
+    if last.upper_shadow() >= last.body() * SHADOW_BODY_FACTOR && last.lower_shadow() <= last.body()
+    {
+        return CandleShape::ShootingStar;
+    }
+    CandleShape::None
+}
+
+// Ticks are grouped into candles by their count since the start, so a candle of n ticks
+// closes on every n-th tick whatever the interval asked for
+pub struct CandleHistory {
+    prices: VecDeque<Decimal>,
+    tick_count: u64,
+    max_ticks: usize,
+}
+
+impl CandleHistory {
+    pub fn new(max_interval: usize) -> Self {
+        Self {
+            prices: VecDeque::new(),
+            tick_count: 0,
+            max_ticks: 3 * max_interval.max(1),
+        }
+    }
+
+    pub fn add_price(&mut self, price: Decimal) {
+        self.prices.push_back(price);
+        self.tick_count += 1;
+        if self.prices.len() > self.max_ticks {
+            self.prices.pop_front();
+        }
+    }
+
+    // The closed candles of n ticks still in the history, oldest first
+    pub fn candles(&self, n: usize) -> Vec<Candle> {
+        let n = n.max(1) as u64;
+        let first_tick = self.tick_count - self.prices.len() as u64;
+        let first_candle = first_tick.div_ceil(n);
+        let last_candle = self.tick_count / n;
+
+        (first_candle..last_candle)
+            .filter_map(|candle| {
+                let start = (candle * n - first_tick) as usize;
+                let prices: Vec<Decimal> = self
+                    .prices
+                    .range(start..start + n as usize)
+                    .copied()
+                    .collect();
+                Candle::from_prices(&prices)
+            })
+            .collect()
+    }
+
+    pub fn candle_pattern_for_interval(&self, n: usize) -> CandleShape {
+        match self.candles(n).as_slice() {
+            [.., prev, last] => detect_pattern(prev, last),
+            _ => CandleShape::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history_of(prices: &[i64]) -> CandleHistory {
+        let mut history = CandleHistory::new(4);
+        for price in prices {
+            history.add_price(Decimal::new(*price, 0));
+        }
+        history
+    }
+
+    #[test]
+    fn test_candle_pattern_for_interval() {
+        // A falling 4-tick candle, 104 -> 101, then a rising one, 100 -> 106, that engulfs it
+        let history = history_of(&[104, 103, 102, 101, 100, 102, 104, 106]);
+        assert_eq!(
+            history.candles(4),
+            vec![
+                Candle {
+                    open: Decimal::new(104, 0),
+                    high: Decimal::new(104, 0),
+                    low: Decimal::new(101, 0),
+                    close: Decimal::new(101, 0),
+                },
+                Candle {
+                    open: Decimal::new(100, 0),
+                    high: Decimal::new(106, 0),
+                    low: Decimal::new(100, 0),
+                    close: Decimal::new(106, 0),
+                },
+            ]
+        );
+        assert_eq!(
+            history.candle_pattern_for_interval(4),
+            CandleShape::BullishEngulfing
+        );
+
+        // Tick by tick, the last move is not engulfing
+        assert_ne!(
+            history.candle_pattern_for_interval(1),
+            CandleShape::BullishEngulfing
+        );
+
+        // The open candle of the next interval is not taken into account
+        let history = history_of(&[104, 103, 102, 101, 100, 102, 104, 106, 90]);
+        assert_eq!(
+            history.candle_pattern_for_interval(4),
+            CandleShape::BullishEngulfing
+        );
+    }
+
+    #[test]
+    fn test_candle_pattern_hammer_and_doji() {
+        // Dips to 90 and closes a little higher, 100 -> 103
+        let history = history_of(&[110, 111, 109, 110, 100, 90, 95, 103]);
+        assert_eq!(history.candle_pattern_for_interval(4), CandleShape::Hammer);
+
+        // Opens and closes at 100 in a 10-wide range
+        let history = history_of(&[110, 111, 109, 110, 100, 105, 95, 100]);
+        assert_eq!(history.candle_pattern_for_interval(4), CandleShape::Doji);
+
+        // Not enough ticks for two candles
+        let history = history_of(&[100, 105, 95]);
+        assert_eq!(history.candle_pattern_for_interval(2), CandleShape::None);
+    }
+}
@@ -1,19 +1,137 @@
 // db_operations.rs
 
+use super::fund_manager::{FundManagerStatics, FundParams};
+use bson::{doc, Document};
+use chrono::{FixedOffset, Utc};
 use debot_db::{
     CandlePattern, CounterType, DebugLog, ModelParams, PnlLog, PositionLog, PriceLog, PricePoint,
     TransactionLog,
 };
+use debot_market_analyzer::TradeAction;
 use debot_ml::RandomForest;
 use debot_position_manager::{PositionType, State, TradePosition};
-use debot_utils::DateTimeUtils;
+use futures::TryStreamExt;
 use lazy_static::lazy_static;
+use mongodb::{options::ReplaceOptions, Database};
 use rust_decimal::Decimal;
-use std::{collections::HashMap, env, sync::Arc, time::SystemTime};
+use std::{
+    collections::HashMap,
+    env,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+// Stats of the daily PnL in USD. The risk-free rate is taken as zero.
+#[derive(Debug, Default, PartialEq)]
+pub struct PerformanceStats {
+    pub days: usize,
+    pub annualized_return: f64,
+    pub volatility: f64,
+    pub sharpe: f64,
+    pub sortino: f64,
+    pub max_drawdown: f64,
+}
+
+impl PerformanceStats {
+    // Needs at least two days of PnL
+    pub fn from_daily_pnl(daily_pnl: &[Decimal]) -> Option<Self> {
+        const DAYS_IN_YEAR: f64 = 365.0;
+
+        let pnls: Vec<f64> = daily_pnl
+            .iter()
+            .filter_map(|pnl| pnl.to_string().parse::<f64>().ok())
+            .collect();
+        if pnls.len() < 2 {
+            return None;
+        }
+
+        let n = pnls.len() as f64;
+        let mean = pnls.iter().sum::<f64>() / n;
+        let std_dev = (pnls.iter().map(|pnl| (pnl - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+        let downside_dev = (pnls.iter().map(|pnl| pnl.min(0.0).powi(2)).sum::<f64>() / n).sqrt();
+
+        let mut cumulative = 0.0;
+        let mut peak = 0.0;
+        let mut max_drawdown = 0.0;
+        for pnl in &pnls {
+            cumulative += pnl;
+            peak = f64::max(peak, cumulative);
+            max_drawdown = f64::max(max_drawdown, peak - cumulative);
+        }
+
+        let ratio = |deviation: f64| {
+            if deviation > 0.0 {
+                mean / deviation * DAYS_IN_YEAR.sqrt()
+            } else {
+                0.0
+            }
+        };
+
+        Some(Self {
+            days: pnls.len(),
+            annualized_return: mean * DAYS_IN_YEAR,
+            volatility: std_dev * DAYS_IN_YEAR.sqrt(),
+            sharpe: ratio(std_dev),
+            sortino: ratio(downside_dev),
+            max_drawdown,
+        })
+    }
+}
+
+const POSITION_COLLECTION: &str = "position";
+// The positions of a dry run go to their own collection, so that paper trades
+// never mix with the live ones
+const PAPER_POSITION_COLLECTION: &str = "paper_position";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PositionSink {
+    Live,
+    Paper,
+}
+
+impl PositionSink {
+    fn new(dry_run: bool) -> Self {
+        if dry_run {
+            PositionSink::Paper
+        } else {
+            PositionSink::Live
+        }
+    }
+
+    fn collection(&self) -> &'static str {
+        match self {
+            PositionSink::Live => POSITION_COLLECTION,
+            PositionSink::Paper => PAPER_POSITION_COLLECTION,
+        }
+    }
+}
+
+// Where a handler connects to, and how many documents of each kind it keeps
+#[derive(Debug, Clone, PartialEq)]
+pub struct DBHandlerConfig {
+    pub max_position_counter: Option<u32>,
+    pub max_price_counter: Option<u32>,
+    pub max_balance_counter: Option<u32>,
+    pub mongodb_uri: String,
+    pub db_w_name: String,
+    pub db_r_name: String,
+    pub back_test: bool,
+    pub path_to_models: Option<String>,
+    pub dry_run: bool,
+}
 
 pub struct DBHandler {
-    transaction_log: Arc<TransactionLog>,
+    // None without a DB, where nothing is logged
+    transaction_log: Option<Arc<TransactionLog>>,
     model_params: Arc<ModelParams>,
+    position_sink: PositionSink,
+    // The ids given out without a DB
+    offline_counter: AtomicU32,
+    // The documents written without a DB, by collection, so that they can be read back
+    offline_documents: std::sync::Mutex<HashMap<String, Vec<Document>>>,
 }
 
 lazy_static! {
@@ -26,53 +144,128 @@ lazy_static! {
 }
 
 impl DBHandler {
-    pub async fn new(
-        max_position_counter: Option<u32>,
-        max_price_counter: Option<u32>,
-        max_balance_counter: Option<u32>,
-        mongodb_uri: &str,
-        db_w_name: &str,
-        db_r_name: &str,
-        back_test: bool,
-        path_to_models: Option<&String>,
-    ) -> Self {
+    pub async fn new(config: DBHandlerConfig) -> Self {
         let transaction_log = Arc::new(
             TransactionLog::new(
-                max_position_counter,
-                max_price_counter,
-                max_balance_counter,
-                mongodb_uri,
-                db_r_name,
-                db_w_name,
-                back_test,
+                config.max_position_counter,
+                config.max_price_counter,
+                config.max_balance_counter,
+                &config.mongodb_uri,
+                &config.db_r_name,
+                &config.db_w_name,
+                config.back_test,
             )
             .await,
         );
 
         let model_params = ModelParams::new(
-            &mongodb_uri,
-            &db_r_name,
-            path_to_models.is_none(),
-            path_to_models.cloned(),
+            &config.mongodb_uri,
+            &config.db_r_name,
+            config.path_to_models.is_none(),
+            config.path_to_models.clone(),
         )
         .await;
         let model_params = Arc::new(model_params);
 
         Self {
-            transaction_log,
+            transaction_log: Some(transaction_log),
             model_params,
+            position_sink: PositionSink::new(config.dry_run),
+            offline_counter: AtomicU32::new(0),
+            offline_documents: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    // A handler that connects to no DB, for the replays and the tests. The models are read
+    // from the files, if any.
+    pub async fn offline(dry_run: bool, path_to_models: Option<&String>) -> Self {
+        let model_params =
+            ModelParams::new("mongodb://localhost", "", false, path_to_models.cloned()).await;
+
+        Self {
+            transaction_log: None,
+            model_params: Arc::new(model_params),
+            position_sink: PositionSink::new(dry_run),
+            offline_counter: AtomicU32::new(0),
+            offline_documents: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn w_db(&self) -> Option<Database> {
+        self.transaction_log.as_ref()?.get_w_db().await
+    }
+
+    async fn r_db(&self) -> Option<Database> {
+        self.transaction_log.as_ref()?.get_r_db().await
+    }
+
+    // Replaces the document that matches the filter in one operation, or inserts it
+    async fn replace_document(
+        &self,
+        collection: &str,
+        filter: Document,
+        document: Document,
+    ) -> Result<(), mongodb::error::Error> {
+        if self.transaction_log.is_none() {
+            let mut offline_documents = self.offline_documents.lock().unwrap();
+            let documents = offline_documents.entry(collection.to_owned()).or_default();
+            documents.retain(|other| !Self::matches_filter(other, &filter));
+            documents.push(document);
+            return Ok(());
+        }
+
+        if let Some(db) = self.w_db().await {
+            let options = ReplaceOptions::builder().upsert(true).build();
+            db.collection::<Document>(collection)
+                .replace_one(filter, document, options)
+                .await?;
         }
+        Ok(())
+    }
+
+    async fn find_document(
+        &self,
+        collection: &str,
+        filter: Document,
+    ) -> Result<Option<Document>, mongodb::error::Error> {
+        if self.transaction_log.is_none() {
+            let offline_documents = self.offline_documents.lock().unwrap();
+            return Ok(offline_documents.get(collection).and_then(|documents| {
+                documents
+                    .iter()
+                    .find(|document| Self::matches_filter(document, &filter))
+                    .cloned()
+            }));
+        }
+
+        match self.w_db().await {
+            Some(db) => {
+                db.collection::<Document>(collection)
+                    .find_one(filter, None)
+                    .await
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn matches_filter(document: &Document, filter: &Document) -> bool {
+        filter
+            .iter()
+            .all(|(key, value)| document.get(key) == Some(value))
     }
 }
 
 impl DBHandler {
-    pub async fn log_pnl(&self, pnl: Decimal) {
+    pub async fn log_pnl(&self, pnl: Decimal, timezone: &FixedOffset) {
         log::info!("log_pnl: {:6.6}", pnl);
 
-        if let Some(db) = self.transaction_log.get_w_db().await {
+        if let Some(db) = self.w_db().await {
             let mut item = PnlLog::default();
             item.id = self.increment_counter(CounterType::Pnl);
-            item.date = DateTimeUtils::get_current_date_string();
+            item.date = Utc::now()
+                .with_timezone(timezone)
+                .format("%Y-%m-%d")
+                .to_string();
             item.pnl = pnl;
 
             if let Err(e) = TransactionLog::insert_pnl(&db, item).await {
@@ -81,6 +274,43 @@ impl DBHandler {
         }
     }
 
+    // Computes the stats over the last `days` PnL logs of the read DB
+    pub async fn compute_performance_stats(&self, days: u32) -> Option<PerformanceStats> {
+        let db = self.r_db().await?;
+        let mut cursor = match db.collection::<Document>("pnl").find(None, None).await {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                log::error!("compute_performance_stats: {:?}", e);
+                return None;
+            }
+        };
+
+        let mut pnl_logs: Vec<PnlLog> = vec![];
+        loop {
+            match cursor.try_next().await {
+                Ok(Some(item)) => match bson::from_document(item) {
+                    Ok(pnl_log) => pnl_logs.push(pnl_log),
+                    Err(e) => log::warn!("compute_performance_stats: {:?}", e),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("compute_performance_stats: {:?}", e);
+                    return None;
+                }
+            }
+        }
+
+        pnl_logs.sort_by_key(|pnl_log| pnl_log.id);
+        let daily_pnl: Vec<Decimal> = pnl_logs
+            .iter()
+            .rev()
+            .take(days as usize)
+            .rev()
+            .map(|pnl_log| pnl_log.pnl)
+            .collect();
+        PerformanceStats::from_daily_pnl(&daily_pnl)
+    }
+
     pub async fn log_app_state(
         &self,
         last_execution_time: Option<SystemTime>,
@@ -91,14 +321,21 @@ impl DBHandler {
     ) {
         log::info!("log_app_state: {:?}", last_execution_time);
 
-        if let Some(db) = self.transaction_log.get_w_db().await {
+        if let Some(db) = self.w_db().await {
             if let Err(e) = TransactionLog::update_app_state(
                 &db,
                 last_execution_time,
                 last_equity,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
                 circuit_break,
                 error_time,
-                invested_amount,
+                Some(invested_amount),
             )
             .await
             {
@@ -116,122 +353,183 @@ impl DBHandler {
             return;
         }
 
-        if let Some(db) = self.transaction_log.get_w_db().await {
-            log::debug!("candle_pattern = {:?}", position.candle_pattern());
+        let position_log = match bson::to_document(&Self::position_log(position)) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("log_position: {:?}", e);
+                return;
+            }
+        };
+        let filter = doc! { "id": position_log.get("id").cloned() };
+        if let Err(e) = self
+            .replace_document(self.position_sink.collection(), filter, position_log)
+            .await
+        {
+            log::error!("log_position: {:?}", e);
+        }
+    }
+
+    // The positions logged without a DB, of the paper or the live collection
+    #[cfg(test)]
+    pub fn offline_position_logs(&self, paper: bool) -> Vec<PositionLog> {
+        let collection = PositionSink::new(paper).collection();
+        let offline_documents = self.offline_documents.lock().unwrap();
+        offline_documents
+            .get(collection)
+            .map(|documents| {
+                documents
+                    .iter()
+                    .map(|document| bson::from_document(document.clone()).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    // The paper positions of the read DB, which are all closed ones
+    pub async fn get_paper_positions(&self) -> Vec<PositionLog> {
+        let mut positions = vec![];
+        let db = match self.r_db().await {
+            Some(db) => db,
+            None => return positions,
+        };
+        let mut cursor = match db
+            .collection::<Document>(PAPER_POSITION_COLLECTION)
+            .find(None, None)
+            .await
+        {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                log::error!("get_paper_positions: {:?}", e);
+                return positions;
+            }
+        };
 
-            let valid_data = || match position.state() {
-                State::Closed(reason) => match reason.as_str() {
-                    "TakeProfit" | "CutLoss" | "Expired" => true,
-                    _ => false,
+        loop {
+            match cursor.try_next().await {
+                Ok(Some(item)) => match bson::from_document(item) {
+                    Ok(position) => positions.push(position),
+                    Err(e) => log::warn!("get_paper_positions: {:?}", e),
                 },
-                _ => false,
-            };
-
-            let position_log = PositionLog {
-                id: Some(position.id()),
-                fund_name: position.fund_name().to_owned(),
-                order_id: position.order_id().to_owned(),
-                ordered_price: position.ordered_price(),
-                state: position.state().to_string(),
-                token_name: position.token_name().to_owned(),
-                open_time_str: position.open_time_str().to_owned(),
-                open_timestamp: position.open_timestamp(),
-                close_time_str: position.close_time_str().to_owned(),
-                average_open_price: position.average_open_price(),
-                position_type: if position.position_type() == PositionType::Long {
-                    "Long"
-                } else {
-                    "Short"
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("get_paper_positions: {:?}", e);
+                    break;
                 }
-                .to_string(),
-                close_price: position.close_price(),
-                asset_in_usd: position.asset_in_usd(),
-                pnl: position.pnl().0,
-                fee: position.fee(),
-                debug: DebugLog {
-                    input_1: position.price().3.round_dp(4),
-                    input_2: position.price().4.round_dp(4),
-                    input_3: position.price().5.round_dp(4),
-                    input_4: position.atr().1.round_dp(4),
-                    input_5: position.atr().2.round_dp(4),
-                    input_6: position.atr().3.round_dp(4),
-                    input_7: position.atr().4.round_dp(4),
-                    input_8: position.atr().5.round_dp(4),
-                    input_9: position.rsi().1.round_dp(4),
-                    input_10: position.rsi().2.round_dp(4),
-                    input_11: position.rsi().3.round_dp(4),
-                    input_12: position.rsi().4.round_dp(4),
-                    input_13: position.rsi().5.round_dp(4),
-                    input_14: position.last_volume().unwrap_or_default().round_dp(4),
-                    input_15: position.last_num_trades().unwrap_or_default().into(),
-                    input_16: position.last_funding_rate().unwrap_or_default().round_dp(4),
-                    input_17: position
-                        .last_open_interest()
-                        .unwrap_or_default()
-                        .round_dp(4),
-                    input_18: position.last_oracle_price().unwrap_or_default().round_dp(4)
-                        - position.price().0.round_dp(4),
-                    input_19: Decimal::ZERO,
-                    input_20: position.atr_spread().round_dp(4),
-                    input_21: position.open_tick_count_max().into(),
-                    input_22: if position.take_profit_ratio().is_zero() {
-                        Decimal::ZERO
-                    } else {
+            }
+        }
+        positions
+    }
+
+    fn position_log(position: &TradePosition) -> PositionLog {
+        log::debug!("candle_pattern = {:?}", position.candle_pattern());
+
+        let valid_data = || match position.state() {
+            State::Closed(reason) => {
+                matches!(reason.as_str(), "TakeProfit" | "CutLoss" | "Expired")
+            }
+            _ => false,
+        };
+
+        PositionLog {
+            id: Some(position.id()),
+            fund_name: position.fund_name().to_owned(),
+            order_id: position.order_id().to_owned(),
+            ordered_price: position.ordered_price(),
+            state: position.state().to_string(),
+            token_name: position.token_name().to_owned(),
+            open_time_str: position.open_time_str().to_owned(),
+            open_timestamp: position.open_timestamp(),
+            close_time_str: position.close_time_str().to_owned(),
+            average_open_price: position.average_open_price(),
+            position_type: if position.position_type() == PositionType::Long {
+                "Long"
+            } else {
+                "Short"
+            }
+            .to_string(),
+            close_price: position.close_price(),
+            asset_in_usd: position.asset_in_usd(),
+            pnl: position.pnl().0,
+            fee: position.fee(),
+            debug: DebugLog {
+                input_1: position.price().3.round_dp(4),
+                input_2: position.price().4.round_dp(4),
+                input_3: position.price().5.round_dp(4),
+                input_4: position.atr().1.round_dp(4),
+                input_5: position.atr().2.round_dp(4),
+                input_6: position.atr().3.round_dp(4),
+                input_7: position.atr().4.round_dp(4),
+                input_8: position.atr().5.round_dp(4),
+                input_9: position.rsi().1.round_dp(4),
+                input_10: position.rsi().2.round_dp(4),
+                input_11: position.rsi().3.round_dp(4),
+                input_12: position.rsi().4.round_dp(4),
+                input_13: position.rsi().5.round_dp(4),
+                input_14: position.last_volume().unwrap_or_default().round_dp(4),
+                input_15: position.last_num_trades().unwrap_or_default().into(),
+                input_16: position.last_funding_rate().unwrap_or_default().round_dp(4),
+                input_17: position
+                    .last_open_interest()
+                    .unwrap_or_default()
+                    .round_dp(4),
+                input_18: position.last_oracle_price().unwrap_or_default().round_dp(4)
+                    - position.price().0.round_dp(4),
+                input_19: Decimal::ZERO,
+                input_20: position.atr_spread().round_dp(4),
+                input_21: position.open_tick_count_max().into(),
+                input_22: if position.take_profit_ratio().is_zero() {
+                    Decimal::ZERO
+                } else {
+                    Decimal::ONE
+                },
+                input_23: position.atr_term().round_dp(4),
+                input_24: Decimal::ZERO,
+                input_25: Decimal::ZERO,
+                input_26: Decimal::ZERO,
+                input_27: Decimal::ZERO,
+                input_28: Decimal::ZERO,
+                input_29: Decimal::ZERO,
+                input_30: position.candle_pattern().0,
+                input_31: position.candle_pattern().1,
+                input_32: position.candle_pattern().2,
+                input_33: position.candle_pattern().3,
+                input_34: CandlePattern::None,
+                input_35: CandlePattern::None,
+                input_36: CandlePattern::None,
+                input_37: CandlePattern::None,
+                input_38: CandlePattern::None,
+                input_39: CandlePattern::None,
+                output_1: if valid_data() {
+                    if position.pnl().0 > Decimal::ZERO {
                         Decimal::ONE
-                    },
-                    input_23: position.atr_term().round_dp(4),
-                    input_24: Decimal::ZERO,
-                    input_25: Decimal::ZERO,
-                    input_26: Decimal::ZERO,
-                    input_27: Decimal::ZERO,
-                    input_28: Decimal::ZERO,
-                    input_29: Decimal::ZERO,
-                    input_30: position.candle_pattern().0,
-                    input_31: position.candle_pattern().1,
-                    input_32: position.candle_pattern().2,
-                    input_33: position.candle_pattern().3,
-                    input_34: CandlePattern::None,
-                    input_35: CandlePattern::None,
-                    input_36: CandlePattern::None,
-                    input_37: CandlePattern::None,
-                    input_38: CandlePattern::None,
-                    input_39: CandlePattern::None,
-                    output_1: if valid_data() {
-                        if position.pnl().0 > Decimal::ZERO {
-                            Decimal::ONE
-                        } else {
-                            Decimal::ZERO
-                        }
                     } else {
                         Decimal::ZERO
-                    },
-                    output_2: if valid_data() {
-                        if position.pnl().1 > Decimal::ZERO {
-                            position.pnl().1.round_dp(4)
-                        } else {
-                            Decimal::ZERO
-                        }
+                    }
+                } else {
+                    Decimal::ZERO
+                },
+                output_2: if valid_data() {
+                    if position.pnl().1 > Decimal::ZERO {
+                        position.pnl().1.round_dp(4)
                     } else {
                         Decimal::ZERO
-                    },
-                    output_3: if position.pnl().0 > Decimal::ZERO {
-                        Some(position.tick_to_fill().into())
-                    } else {
-                        Some(Decimal::new(-1, 0))
-                    },
-                    output_4: None,
-                    output_5: None,
+                    }
+                } else {
+                    Decimal::ZERO
                 },
-            };
-
-            if let Err(e) = TransactionLog::update_transaction(&db, &position_log).await {
-                log::error!("log_position: {:?}", e);
-            }
+                output_3: if position.pnl().0 > Decimal::ZERO {
+                    Some(position.tick_to_fill().into())
+                } else {
+                    Some(Decimal::new(-1, 0))
+                },
+                output_4: None,
+                output_5: None,
+            },
         }
     }
 
     pub async fn log_price(&self, name: &str, token_name: &str, price_point: PricePoint) {
-        if let Some(db) = self.transaction_log.get_w_db().await {
+        if let Some(db) = self.w_db().await {
             let mut item = PriceLog::default();
             item.id = self.increment_counter(CounterType::Price);
             item.name = name.to_owned();
@@ -243,17 +541,197 @@ impl DBHandler {
         }
     }
 
+    pub async fn log_fund_equity(&self, fund_name: &str, equity_points: &[(i64, Decimal)]) {
+        log::debug!(
+            "log_fund_equity: {}, {} points",
+            fund_name,
+            equity_points.len()
+        );
+
+        if let Some(db) = self.w_db().await {
+            let items: Vec<Document> = equity_points
+                .iter()
+                .map(|(timestamp, equity)| {
+                    doc! {
+                        "fund_name": fund_name,
+                        "timestamp": *timestamp,
+                        "equity": equity.round_dp(4).to_string(),
+                    }
+                })
+                .collect();
+            if let Err(e) = db
+                .collection::<Document>("fund_equity")
+                .insert_many(items, None)
+                .await
+            {
+                log::error!("log_fund_equity: {:?}", e);
+            }
+        }
+    }
+
+    pub async fn log_fill_latency(
+        &self,
+        dex_name: &str,
+        token_name: &str,
+        latency: Duration,
+        p50: Duration,
+        p95: Duration,
+    ) {
+        if let Some(db) = self.w_db().await {
+            if let Err(e) = db
+                .collection::<Document>("fill_latency")
+                .insert_one(
+                    doc! {
+                        "dex_name": dex_name,
+                        "token_name": token_name,
+                        "timestamp": Utc::now().timestamp(),
+                        "latency_ms": latency.as_millis() as i64,
+                        "p50_ms": p50.as_millis() as i64,
+                        "p95_ms": p95.as_millis() as i64,
+                    },
+                    None,
+                )
+                .await
+            {
+                log::error!("log_fill_latency: {:?}", e);
+            }
+        }
+    }
+
+    pub async fn log_signals(
+        &self,
+        fund_name: &str,
+        token_name: &str,
+        price: Decimal,
+        actions: &[TradeAction],
+    ) {
+        if let Some(db) = self.w_db().await {
+            let signals: Vec<Document> = actions
+                .iter()
+                .map(|action| {
+                    doc! {
+                        "action": format!("{:?}", action),
+                        "confidence": action.confidence().map(|v| v.round_dp(4).to_string()),
+                    }
+                })
+                .collect();
+            if let Err(e) = db
+                .collection::<Document>("model_signals")
+                .insert_one(
+                    doc! {
+                        "fund_name": fund_name,
+                        "token_name": token_name,
+                        "timestamp": Utc::now().timestamp(),
+                        "price": price.to_string(),
+                        "signals": signals,
+                    },
+                    None,
+                )
+                .await
+            {
+                log::error!("log_signals: {:?}", e);
+            }
+        }
+    }
+
+    pub async fn log_fund_statistics(&self, fund_name: &str, statistics: &FundManagerStatics) {
+        let statistics = match bson::to_document(statistics) {
+            Ok(v) => v,
+            Err(e) => {
+                log::error!("log_fund_statistics: {:?}", e);
+                return;
+            }
+        };
+        if let Err(e) = self
+            .replace_document(
+                "fund_statistics",
+                doc! { "fund_name": fund_name },
+                doc! { "fund_name": fund_name, "statistics": statistics },
+            )
+            .await
+        {
+            log::error!("log_fund_statistics: {:?}", e);
+        }
+    }
+
+    pub async fn load_fund_statistics(&self, fund_name: &str) -> Option<FundManagerStatics> {
+        let item = match self
+            .find_document("fund_statistics", doc! { "fund_name": fund_name })
+            .await
+        {
+            Ok(item) => item?,
+            Err(e) => {
+                log::error!("load_fund_statistics: {:?}", e);
+                return None;
+            }
+        };
+        let statistics = item.get_document("statistics").ok()?.clone();
+        match bson::from_document(statistics) {
+            Ok(statistics) => Some(statistics),
+            Err(e) => {
+                log::error!("load_fund_statistics: {:?}", e);
+                None
+            }
+        }
+    }
+
+    pub async fn load_fund_params(&self) -> HashMap<String, FundParams> {
+        let mut fund_params = HashMap::new();
+        let db = match self.w_db().await {
+            Some(db) => db,
+            None => return fund_params,
+        };
+        let mut cursor = match db
+            .collection::<Document>("fund_params")
+            .find(None, None)
+            .await
+        {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                log::error!("load_fund_params: {:?}", e);
+                return fund_params;
+            }
+        };
+
+        loop {
+            match cursor.try_next().await {
+                Ok(Some(item)) => {
+                    let fund_name = match item.get_str("fund_name") {
+                        Ok(fund_name) => fund_name.to_owned(),
+                        Err(_) => continue,
+                    };
+                    match bson::from_document(item) {
+                        Ok(params) => {
+                            fund_params.insert(fund_name, params);
+                        }
+                        Err(e) => log::error!("load_fund_params: {}: {:?}", fund_name, e),
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::error!("load_fund_params: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        fund_params
+    }
+
     pub fn increment_counter(&self, counter_type: CounterType) -> Option<u32> {
         let counter_type = match counter_type {
             CounterType::Position => debot_db::CounterType::Position,
             CounterType::Price => debot_db::CounterType::Price,
             CounterType::Pnl => debot_db::CounterType::Pnl,
         };
-        Some(self.transaction_log.increment_counter(counter_type))
+        match &self.transaction_log {
+            Some(transaction_log) => Some(transaction_log.increment_counter(counter_type)),
+            None => Some(self.offline_counter.fetch_add(1, Ordering::SeqCst) + 1),
+        }
     }
 
     pub async fn get_app_state(&self) -> (Option<SystemTime>, Option<Decimal>, bool) {
-        if let Some(db) = self.transaction_log.get_w_db().await {
+        if let Some(db) = self.w_db().await {
             let app_state = TransactionLog::get_app_state(&db).await;
             (
                 app_state.last_execution_time,
@@ -269,7 +747,7 @@ impl DBHandler {
         &self,
         limit: Option<u32>,
     ) -> HashMap<String, HashMap<String, Vec<PricePoint>>> {
-        if let Some(db) = self.transaction_log.get_r_db().await {
+        if let Some(db) = self.r_db().await {
             let mut data = TransactionLog::get_price_market_data(&db, None, None, true).await;
 
             if let Some(data_size) = limit {
@@ -293,3 +771,45 @@ impl DBHandler {
         RandomForest::new(key, &self.model_params).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_performance_stats() {
+        let daily_pnl: Vec<Decimal> = [1, -1, 2, -2, 3]
+            .iter()
+            .map(|v| Decimal::from(*v))
+            .collect();
+        let stats = PerformanceStats::from_daily_pnl(&daily_pnl).unwrap();
+
+        // mean = 0.6, sample std = sqrt(4.3), downside deviation = sqrt((1 + 4) / 5) = 1
+        assert_eq!(stats.days, 5);
+        assert!((stats.annualized_return - 219.0).abs() < 1e-9);
+        assert!((stats.sharpe - 0.6 / 4.3_f64.sqrt() * 365_f64.sqrt()).abs() < 1e-9);
+        assert!((stats.sharpe - 5.5279).abs() < 1e-4);
+        assert!((stats.sortino - 0.6 * 365_f64.sqrt()).abs() < 1e-9);
+        assert!((stats.sortino - 11.4630).abs() < 1e-4);
+        assert!((stats.max_drawdown - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_position_sink() {
+        assert_eq!(PositionSink::new(true), PositionSink::Paper);
+        assert_eq!(PositionSink::new(false), PositionSink::Live);
+        assert_eq!(PositionSink::Paper.collection(), "paper_position");
+        assert_eq!(PositionSink::Live.collection(), "position");
+    }
+
+    #[test]
+    fn test_performance_stats_needs_enough_data() {
+        assert_eq!(PerformanceStats::from_daily_pnl(&[]), None);
+        assert_eq!(PerformanceStats::from_daily_pnl(&[Decimal::ONE]), None);
+
+        // No volatility
+        let stats = PerformanceStats::from_daily_pnl(&[Decimal::ONE, Decimal::ONE]).unwrap();
+        assert_eq!(stats.sharpe, 0.0);
+        assert_eq!(stats.sortino, 0.0);
+    }
+}
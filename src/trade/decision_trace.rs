@@ -0,0 +1,100 @@
+// decision_trace.rs
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+// What a fund saw and decided on a tick, emitted as one record.
+// A disabled trace ignores everything recorded into it.
+#[derive(Debug, Default, Serialize)]
+pub struct DecisionTrace {
+    #[serde(skip)]
+    enabled: bool,
+    fund_name: String,
+    tick: u64,
+    price: Decimal,
+    indicators: Vec<(String, Option<Decimal>)>,
+    signals: Vec<String>,
+    guards: Vec<(String, bool)>,
+    actions: Vec<String>,
+}
+
+impl DecisionTrace {
+    pub fn new(enabled: bool, fund_name: &str, tick: u64, price: Decimal) -> Self {
+        if !enabled {
+            return Self::default();
+        }
+        Self {
+            enabled,
+            fund_name: fund_name.to_owned(),
+            tick,
+            price,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn indicator(&mut self, name: &str, value: Option<Decimal>) {
+        if self.enabled {
+            self.indicators.push((name.to_owned(), value));
+        }
+    }
+
+    pub fn signal(&mut self, signal: impl std::fmt::Debug) {
+        if self.enabled {
+            self.signals.push(format!("{:?}", signal));
+        }
+    }
+
+    // Returns `passed` so that a guard can be traced where it is checked
+    pub fn guard(&mut self, name: &str, passed: bool) -> bool {
+        if self.enabled {
+            self.guards.push((name.to_owned(), passed));
+        }
+        passed
+    }
+
+    pub fn action(&mut self, action: String) {
+        if self.enabled {
+            self.actions.push(action);
+        }
+    }
+
+    pub fn emit(&self) {
+        if !self.enabled {
+            return;
+        }
+        match serde_json::to_string(self) {
+            Ok(record) => log::info!("decision trace: {}", record),
+            Err(e) => log::error!("decision trace: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_trace() {
+        let mut trace = DecisionTrace::new(true, "fund", 3, Decimal::new(100, 0));
+        trace.indicator("rsi", Some(Decimal::new(30, 0)));
+        trace.signal("BuyOpen");
+        assert!(trace.guard("bar_closed", true));
+        assert!(!trace.guard("funding_rate", false));
+        trace.action("skip".to_owned());
+
+        let record = serde_json::to_value(&trace).unwrap();
+        assert_eq!(record["tick"], 3);
+        assert_eq!(record["guards"][1][0], "funding_rate");
+        assert_eq!(record["guards"][1][1], false);
+        assert_eq!(record["actions"][0], "skip");
+
+        let mut disabled = DecisionTrace::new(false, "fund", 3, Decimal::new(100, 0));
+        assert!(!disabled.guard("bar_closed", false));
+        disabled.action("skip".to_owned());
+        assert!(disabled.actions.is_empty());
+    }
+}
@@ -1,27 +1,113 @@
 // derivative_trader.rs
 
-use super::dex_connector_box::DexConnectorBox;
+use super::dex_connector_box::{DexConnectorBox, DexConnectorExt};
+use super::dex_emulator::EmulatorFillSettings;
 use super::fund_config;
+use super::fund_manager::FundManagerConfig;
 use super::DBHandler;
 use super::FundManager;
+use crate::config::{DrawdownBasis, RoundingMode, TradingMode};
+use crate::error_manager::AlertHandle;
+use crate::metrics::{FundMetrics, MetricsSnapshot};
 use debot_db::PricePoint;
 use debot_market_analyzer::MarketData;
+use debot_market_analyzer::SampleTerm;
 use debot_market_analyzer::TradingStrategy;
+use debot_position_manager::{PositionType, TradePosition};
 use dex_connector::DexConnector;
 use dex_connector::DexError;
 use dex_connector::FilledOrder;
 use futures::future::join_all;
 use rust_decimal::Decimal;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::error::Error;
+use std::fs::File;
 use std::io;
 use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use tokio::sync::RwLock;
 use tokio::time::{timeout, Duration};
 
+// Interval between the checks that a liquidation has flattened everything
+const LIQUIDATE_RETRY_INTERVAL_SECS: u64 = 2;
+
+// Set on shutdown, so that a tick still in flight opens no new position
+static STOP_OPENING: AtomicBool = AtomicBool::new(false);
+
+const BACKTEST_REPORT_HEADER: [&str; 10] = [
+    "open_time",
+    "close_time",
+    "token_name",
+    "position_type",
+    "size",
+    "average_open_price",
+    "close_price",
+    "fee",
+    "pnl",
+    "state",
+];
+
+// A closed position in the back-test report. The state carries the reason for the close,
+// e.g. Closed(TakeProfit).
+struct BacktestReportRow<'a> {
+    open_time_str: &'a str,
+    close_time_str: &'a str,
+    token_name: &'a str,
+    position_type: &'a str,
+    asset_in_usd: Decimal,
+    average_open_price: Decimal,
+    close_price: Decimal,
+    fee: Decimal,
+    pnl: Decimal,
+    state: String,
+}
+
+impl<'a> BacktestReportRow<'a> {
+    fn from_position(position: &'a TradePosition) -> Self {
+        Self {
+            open_time_str: position.open_time_str(),
+            close_time_str: position.close_time_str(),
+            token_name: position.token_name(),
+            position_type: if position.position_type() == PositionType::Long {
+                "Long"
+            } else {
+                "Short"
+            },
+            asset_in_usd: position.asset_in_usd(),
+            average_open_price: position.average_open_price(),
+            close_price: position.close_price(),
+            fee: position.fee(),
+            pnl: position.pnl().0,
+            state: position.state().to_string(),
+        }
+    }
+
+    fn record(&self) -> Vec<String> {
+        let size = if self.average_open_price.is_zero() {
+            Decimal::ZERO
+        } else {
+            (self.asset_in_usd / self.average_open_price).abs()
+        };
+        vec![
+            self.open_time_str.to_owned(),
+            self.close_time_str.to_owned(),
+            self.token_name.to_owned(),
+            self.position_type.to_owned(),
+            size.round_dp(6).normalize().to_string(),
+            self.average_open_price.to_string(),
+            self.close_price.to_string(),
+            self.fee.round_dp(6).normalize().to_string(),
+            self.pnl.round_dp(6).normalize().to_string(),
+            self.state.clone(),
+        ]
+    }
+}
+
 #[derive(Clone)]
 pub struct SampleInterval {
     short_term: usize,
@@ -37,32 +123,151 @@ impl SampleInterval {
     }
 }
 
+// The settings of a trader. The trade and sample intervals are in minutes.
 #[derive(Clone)]
-struct DerivativeTraderConfig {
-    trader_name: String,
-    dex_name: String,
-    dry_run: bool,
-    short_trade_period: usize,
-    long_trade_period: usize,
-    trade_period: usize,
-    max_price_size: u32,
-    initial_balance: Decimal,
-    max_dd_ratio: Decimal,
-    rest_endpoint: String,
-    web_socket_endpoint: String,
-    save_prices: bool,
-    only_read_price: bool,
-    back_test: bool,
-    interval_secs: i64,
+pub struct DerivativeTraderConfig {
+    pub dex_name: String,
+    pub dry_run: bool,
+    pub trade_interval: usize,
+    pub sample_interval: SampleInterval,
+    pub interval_secs: i64,
+    pub max_price_size: u32,
+    pub load_prices: bool,
+    pub save_prices: bool,
+    pub max_dd_ratio: Decimal,
+    pub close_order_effective_duration_secs: i64,
+    pub use_market_order: bool,
+    pub rest_endpoint: String,
+    pub web_socket_endpoint: String,
+    pub leverage: u32,
+    pub strategy: TradingStrategy,
+    pub trading_mode: TradingMode,
+    pub back_test: bool,
+    pub dd_basis: DrawdownBasis,
+    pub sort_filled_orders: bool,
+    pub max_token_notional: Option<Decimal>,
+    pub log_fund_equity: bool,
+    pub equity_sample_ticks: u32,
+    pub balance_retry_attempts: u32,
+    pub balance_retry_backoff_millis: u64,
+    pub fill_settings: EmulatorFillSettings,
+    pub max_trades_per_token_per_hour: Option<u32>,
+    pub dry_run_price_source: Option<String>,
+    pub liquidate_retry_max: u32,
+    pub random_seed: Option<u64>,
+    pub trace_decisions: bool,
+    pub max_price_age_secs: Option<i64>,
+    pub stale_price_restart_ticks: Option<u32>,
+    pub backtest_report_path: Option<String>,
+    pub rounding_mode: RoundingMode,
+    pub open_resubmit_attempts: u32,
+    pub min_margin_ratio: Option<Decimal>,
+    pub skip_precompute_in_backtest: Option<u32>,
+    pub max_concurrent_open_tokens: Option<usize>,
+    pub token_blacklist: Vec<String>,
+    pub token_whitelist: Option<Vec<String>>,
+    pub order_verify_ticks: Option<u64>,
+    // Saved prices are replayed on an emulated venue, without any connection
+    pub replay: bool,
+}
+
+impl DerivativeTraderConfig {
+    // Converts a period in minutes into ticks
+    fn period_ticks(&self, minutes: usize) -> usize {
+        const SECONDS_IN_MINUTE: usize = 60;
+        minutes * SECONDS_IN_MINUTE / self.interval_secs as usize
+    }
+
+    fn trade_period(&self) -> usize {
+        self.period_ticks(self.trade_interval)
+    }
+
+    fn short_trade_period(&self) -> usize {
+        self.period_ticks(self.sample_interval.short_term)
+    }
+
+    fn long_trade_period(&self) -> usize {
+        self.period_ticks(self.sample_interval.long_term)
+    }
+}
+
+#[cfg(test)]
+impl DerivativeTraderConfig {
+    // A dry run with the default settings, which connects to nothing
+    pub fn replay(dex_name: &str, strategy: TradingStrategy) -> Self {
+        Self {
+            dex_name: dex_name.to_owned(),
+            dry_run: true,
+            trade_interval: 60,
+            sample_interval: SampleInterval::new(12 * 60, 26 * 60),
+            interval_secs: 60,
+            max_price_size: 60,
+            load_prices: false,
+            save_prices: false,
+            max_dd_ratio: Decimal::new(1, 1),
+            close_order_effective_duration_secs: 300,
+            use_market_order: false,
+            rest_endpoint: String::new(),
+            web_socket_endpoint: String::new(),
+            leverage: 1,
+            strategy,
+            trading_mode: TradingMode::Trade,
+            back_test: true,
+            dd_basis: DrawdownBasis::Equity,
+            sort_filled_orders: true,
+            max_token_notional: None,
+            log_fund_equity: false,
+            equity_sample_ticks: 60,
+            balance_retry_attempts: 1,
+            balance_retry_backoff_millis: 0,
+            fill_settings: EmulatorFillSettings::default(),
+            max_trades_per_token_per_hour: None,
+            dry_run_price_source: None,
+            liquidate_retry_max: 3,
+            random_seed: None,
+            trace_decisions: false,
+            max_price_age_secs: None,
+            stale_price_restart_ticks: None,
+            backtest_report_path: None,
+            rounding_mode: RoundingMode::Nearest,
+            open_resubmit_attempts: 0,
+            min_margin_ratio: None,
+            skip_precompute_in_backtest: None,
+            max_concurrent_open_tokens: None,
+            token_blacklist: vec![],
+            token_whitelist: None,
+            order_verify_ticks: None,
+            replay: true,
+        }
+    }
 }
 
 struct DerivativeTraderState {
     db_handler: Arc<Mutex<DBHandler>>,
     dex_connector: Arc<DexConnectorBox>,
+    alerts: AlertHandle,
     fund_manager_map: HashMap<String, FundManager>,
     market_data_map: Arc<RwLock<HashMap<(String, TradingStrategy), Arc<RwLock<MarketData>>>>>,
     back_test_data: HashMap<String, HashMap<String, Vec<PricePoint>>>,
     back_test_counter: usize,
+    // Set while liquidating, so that no new tick starts against positions being closed
+    liquidating: AtomicBool,
+    // Left set if a tick was cancelled midway, e.g. by SIGTERM
+    finding_chances: AtomicBool,
+    // Set through the control server
+    opening_paused: bool,
+    tick_count: u64,
+    // Ticks at which opens were ordered, per token across all the funds
+    token_trade_ticks: HashMap<String, VecDeque<u64>>,
+    // The last price of each token and when it changed to it
+    price_changes: HashMap<String, (Decimal, i64)>,
+    stale_price_ticks: u32,
+    // The closed positions of a back test, written out when it finishes
+    backtest_records: Vec<Vec<String>>,
+    // The drawdown ratio of the last check
+    dd_ratio: Decimal,
+    // The balance when the trader was created
+    initial_balance: Decimal,
 }
 
 pub struct DerivativeTrader {
@@ -72,96 +277,65 @@ pub struct DerivativeTrader {
 
 impl DerivativeTrader {
     pub async fn new(
-        dex_name: &str,
-        dry_run: bool,
-        trade_interval: usize,
-        sample_interval: SampleInterval,
-        interval_secs: i64,
-        max_price_size: u32,
+        mut config: DerivativeTraderConfig,
         db_handler: Arc<Mutex<DBHandler>>,
+        alerts: AlertHandle,
         price_market_data: HashMap<String, HashMap<String, Vec<PricePoint>>>,
-        load_prices: bool,
-        save_prices: bool,
-        max_dd_ratio: Decimal,
-        close_order_effective_duration_secs: i64,
-        use_market_order: bool,
-        rest_endpoint: &str,
-        web_socket_endpoint: &str,
-        leverage: u32,
-        strategy: &TradingStrategy,
-        only_read_price: bool,
-        back_test: bool,
     ) -> Self {
         log::info!("DerivativeTrader::new");
-        const SECONDS_IN_MINUTE: usize = 60;
 
-        let mut config = DerivativeTraderConfig {
-            trader_name: dex_name.to_owned(),
-            dex_name: dex_name.to_owned(),
-            dry_run,
-            short_trade_period: sample_interval.short_term * SECONDS_IN_MINUTE
-                / interval_secs as usize,
-            long_trade_period: sample_interval.long_term * SECONDS_IN_MINUTE
-                / interval_secs as usize,
-            trade_period: trade_interval * SECONDS_IN_MINUTE / interval_secs as usize,
-            max_price_size: max_price_size,
-            initial_balance: Decimal::new(0, 0),
-            max_dd_ratio,
-            rest_endpoint: rest_endpoint.to_owned(),
-            web_socket_endpoint: web_socket_endpoint.to_owned(),
-            save_prices,
-            only_read_price,
-            back_test,
-            interval_secs,
-        };
+        if !config.back_test {
+            config.random_seed = None;
+        }
+
+        let dex_connector = Self::create_dex_connector(&config)
+            .await
+            .expect("Failed to initialize DexConnector");
 
+        Self::with_dex_connector(config, dex_connector, db_handler, alerts, price_market_data).await
+    }
+
+    // Builds the trader on a connector that is already started
+    pub async fn with_dex_connector(
+        config: DerivativeTraderConfig,
+        dex_connector: Arc<DexConnectorBox>,
+        db_handler: Arc<Mutex<DBHandler>>,
+        alerts: AlertHandle,
+        price_market_data: HashMap<String, HashMap<String, Vec<PricePoint>>>,
+    ) -> Self {
         let state = Self::initialize_state(
-            &mut config,
+            &config,
+            dex_connector,
             db_handler,
+            alerts,
             price_market_data,
-            load_prices,
-            close_order_effective_duration_secs,
-            use_market_order,
-            leverage,
-            strategy,
         )
         .await;
 
         let mut this = Self { config, state };
 
         let balance = this.get_balance().await.unwrap();
-        this.config.initial_balance = balance;
+        this.state.initial_balance = balance;
 
         this
     }
 
     async fn initialize_state(
-        config: &mut DerivativeTraderConfig,
+        config: &DerivativeTraderConfig,
+        dex_connector: Arc<DexConnectorBox>,
         db_handler: Arc<Mutex<DBHandler>>,
+        alerts: AlertHandle,
         price_market_data: HashMap<String, HashMap<String, Vec<PricePoint>>>,
-        load_prices: bool,
-        close_order_effective_duration_secs: i64,
-        use_market_order: bool,
-        leverage: u32,
-        strategy: &TradingStrategy,
     ) -> DerivativeTraderState {
         log::info!("DerivativeTrader::initialize_state");
-        let dex_connector = Self::create_dex_connector(config)
-            .await
-            .expect("Failed to initialize DexConnector");
-
         let market_data_map = Arc::new(RwLock::new(HashMap::new()));
 
         let fund_managers = Self::create_fund_managers(
             config,
             db_handler.clone(),
             dex_connector.clone(),
+            alerts.clone(),
             &price_market_data,
-            load_prices,
-            close_order_effective_duration_secs,
-            use_market_order,
-            leverage,
-            strategy,
             market_data_map.clone(),
         )
         .await;
@@ -169,35 +343,55 @@ impl DerivativeTrader {
         let mut state = DerivativeTraderState {
             db_handler,
             dex_connector,
+            alerts,
             fund_manager_map: HashMap::new(),
             market_data_map,
             back_test_data: if config.back_test {
                 price_market_data
+            } else if config.dry_run {
+                match &config.dry_run_price_source {
+                    Some(path) => Self::load_price_source(path),
+                    None => HashMap::new(),
+                }
             } else {
                 HashMap::new()
             },
             back_test_counter: 0,
+            liquidating: AtomicBool::new(false),
+            finding_chances: AtomicBool::new(false),
+            opening_paused: false,
+            tick_count: 0,
+            token_trade_ticks: HashMap::new(),
+            price_changes: HashMap::new(),
+            stale_price_ticks: 0,
+            backtest_records: vec![],
+            dd_ratio: Decimal::ZERO,
+            initial_balance: Decimal::ZERO,
         };
 
         log::info!("create_fund_managers() finished");
 
-        let mut processed_tokens = HashSet::new();
-        for fund_manager in fund_managers {
-            let token_name = fund_manager.token_name();
-
-            if !processed_tokens.contains(token_name) {
-                if state
-                    .dex_connector
-                    .set_leverage(token_name, leverage)
-                    .await
-                    .is_err()
-                {
-                    panic!("Failed to set the leverage");
-                }
-
-                processed_tokens.insert(token_name.to_owned());
-            }
+        let token_leverages: Vec<(String, Option<u32>)> = fund_managers
+            .iter()
+            .map(|fund_manager| {
+                (
+                    fund_manager.token_name().to_owned(),
+                    fund_manager.leverage(),
+                )
+            })
+            .collect();
+        if Self::set_token_leverages(
+            state.dex_connector.as_ref(),
+            &token_leverages,
+            config.leverage,
+        )
+        .await
+        .is_err()
+        {
+            panic!("Failed to set the leverage");
+        }
 
+        for fund_manager in fund_managers {
             state
                 .fund_manager_map
                 .insert(fund_manager.fund_name().to_owned(), fund_manager);
@@ -208,24 +402,117 @@ impl DerivativeTrader {
         state
     }
 
+    // Checks the DEX before any trading state is built, so that a broken setup fails fast
+    pub async fn preflight(
+        dex_name: &str,
+        rest_endpoint: &str,
+        web_socket_endpoint: &str,
+        token_names: &[&str],
+    ) -> Result<(), String> {
+        let dex_connector = DexConnectorBox::create(
+            dex_name,
+            rest_endpoint,
+            web_socket_endpoint,
+            false,
+            EmulatorFillSettings::default(),
+            None,
+        )
+        .await
+        .map_err(|e| format!("{}: failed to create the connector: {:?}", dex_name, e))?;
+        dex_connector
+            .start()
+            .await
+            .map_err(|e| format!("{}: failed to start the connector: {:?}", dex_name, e))?;
+
+        let res = Self::check_dex(&dex_connector, token_names).await;
+        let _ = dex_connector.stop().await;
+        res.map_err(|failures| {
+            format!(
+                "{}: preflight failed, unreachable: {}",
+                dex_name,
+                failures.join(", ")
+            )
+        })
+    }
+
+    // The balance has to be fetched, and every token needs a ticker with a min_tick
+    async fn check_dex<T: DexConnector + ?Sized>(
+        dex_connector: &T,
+        token_names: &[&str],
+    ) -> Result<(), Vec<String>> {
+        let mut failures = vec![];
+
+        match dex_connector.get_balance().await {
+            Ok(balance) => log::info!("preflight: {:<10} equity = {}", "balance", balance.equity),
+            Err(e) => failures.push(format!("balance({:?})", e)),
+        }
+
+        for token_name in token_names {
+            match dex_connector.get_ticker(token_name, None).await {
+                Ok(ticker) => match ticker.min_tick {
+                    Some(min_tick) if min_tick > Decimal::ZERO => log::info!(
+                        "preflight: {:<10} price = {}, min_tick = {}",
+                        token_name,
+                        ticker.price,
+                        min_tick
+                    ),
+                    _ => failures.push(format!("{}(no min_tick)", token_name)),
+                },
+                Err(e) => failures.push(format!("{}({:?})", token_name, e)),
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+
+    // Sets the leverage once per token. The per-token override takes precedence over the global leverage.
+    async fn set_token_leverages<T: DexConnector + ?Sized>(
+        dex_connector: &T,
+        token_leverages: &[(String, Option<u32>)],
+        leverage: u32,
+    ) -> Result<(), DexError> {
+        let mut processed_tokens = HashSet::new();
+        for (token_name, token_leverage) in token_leverages {
+            if processed_tokens.insert(token_name.as_str()) {
+                dex_connector
+                    .set_leverage(token_name, token_leverage.unwrap_or(leverage))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
     async fn create_fund_managers(
-        config: &mut DerivativeTraderConfig,
+        config: &DerivativeTraderConfig,
         db_handler: Arc<Mutex<DBHandler>>,
         dex_connector: Arc<DexConnectorBox>,
+        alerts: AlertHandle,
         price_market_data: &HashMap<String, HashMap<String, Vec<PricePoint>>>,
-        load_prices: bool,
-        close_order_effective_duration_secs: i64,
-        use_market_order: bool,
-        leverage: u32,
-        strategy: &TradingStrategy,
         market_data_map: Arc<RwLock<HashMap<(String, TradingStrategy), Arc<RwLock<MarketData>>>>>,
     ) -> Vec<FundManager> {
         log::info!("DerivativeTrader::create_fund_managers");
-        let fund_manager_configurations = fund_config::get(&config.dex_name, strategy, leverage);
+        let mut fund_manager_configurations =
+            fund_config::get(&config.dex_name, &config.strategy, config.leverage);
+        fund_manager_configurations.retain(|fund| {
+            Self::is_token_allowed(
+                &fund.token_name,
+                &config.token_blacklist,
+                config.token_whitelist.as_deref(),
+            )
+        });
+        let active_tokens: BTreeSet<&str> = fund_manager_configurations
+            .iter()
+            .map(|fund| fund.token_name.as_str())
+            .collect();
+        log::info!("active tokens = {:?}", active_tokens);
         let mut token_name_indices = HashMap::new();
         let mut fund_managers = vec![];
 
-        for (
+        for fund_config::FundConfig {
             token_name,
             strategy,
             initial_amount,
@@ -235,14 +522,46 @@ impl DerivativeTrader {
             atr_spread,
             atr_term,
             max_open_hours,
-        ) in fund_manager_configurations.into_iter()
+            min_open_confidence,
+            min_close_confidence,
+            chase_close_orders,
+            max_chase_ticks,
+            trailing_stop_ratio,
+            require_rsi_divergence,
+            max_idle_ticks,
+            alert_when_idle,
+            use_market_order: fund_use_market_order,
+            max_funding_rate_long,
+            max_funding_rate_short,
+            signal_on_bar_close,
+            bar_ticks,
+            max_acceptable_slippage,
+            alert_on_slippage,
+            anchor_to_vwap,
+            leverage: fund_leverage,
+            prefer_maker,
+            maker_fallback_to_taker,
+            max_market_slippage,
+            expire_open_positions,
+            contract_type,
+            order_placement_mode,
+            time_in_force,
+            max_open_positions,
+            take_profit_ladder,
+            daily_loss_limit,
+            min_entry_zscore,
+            use_stoch_rsi_exit,
+            min_order_size,
+            max_order_size,
+            execution_delay_atr_factor,
+            min_holding_secs,
+            allow_averaging,
+            min_average_improvement,
+            trend_change_confirm_ticks,
+            candle_interval_ticks,
+            size_scaling,
+        } in fund_manager_configurations.into_iter()
         {
-            let db_handler = db_handler.clone();
-            let dex_connector = dex_connector.clone();
-            let config = config.clone();
-            let load_prices = load_prices;
-            let use_market_order = use_market_order;
-            let risk_reward = risk_reward;
             let index = *token_name_indices.entry(token_name.clone()).or_insert(0);
             *token_name_indices.get_mut(&token_name).unwrap() += 1;
 
@@ -265,17 +584,17 @@ impl DerivativeTrader {
                     let new_market_data = Arc::new(RwLock::new(
                         Self::create_market_data(
                             db_handler.clone(),
-                            config.clone(),
+                            config,
                             &token_name,
                             &strategy,
                         )
                         .await,
                     ));
 
-                    if !config.back_test && load_prices {
+                    if !config.back_test && config.load_prices {
                         Self::restore_market_data(
                             new_market_data.clone(),
-                            &config.trader_name,
+                            &config.dex_name,
                             &token_name,
                             price_market_data,
                         )
@@ -294,34 +613,110 @@ impl DerivativeTrader {
                 .unwrap();
 
             let open_order_tick_count_max = open_tick_count_max;
-            let close_order_tick_count_max: u32 = (close_order_effective_duration_secs
-                / config.interval_secs)
-                .try_into()
-                .unwrap();
+            let close_order_tick_count_max = Self::duration_to_ticks(
+                config.close_order_effective_duration_secs,
+                config.interval_secs,
+            )
+            .unwrap_or_else(|e| {
+                log::error!("close_order_effective_duration_secs: {}, use 1 tick", e);
+                1
+            });
 
             let execution_delay_tick_count_max = open_tick_count_max;
 
-            let fund_manager = FundManager::new(
-                &fund_name,
+            // VWAP is computed over the same window as the ATR term
+            let vwap_window = match atr_term {
+                SampleTerm::TradingTerm => config.trade_period(),
+                SampleTerm::ShortTerm => config.short_trade_period(),
+                SampleTerm::LongTerm => config.long_trade_period(),
+            };
+
+            let fund_manager_config = FundManagerConfig {
+                fund_name: fund_name.clone(),
                 index,
-                &token_name,
-                market_data.clone(),
+                token_name,
                 strategy,
-                initial_amount * position_size_ratio,
+                trading_amount: initial_amount * position_size_ratio,
                 initial_amount,
-                db_handler,
-                dex_connector,
                 open_order_tick_count_max,
                 close_order_tick_count_max,
                 open_tick_count_max,
                 execution_delay_tick_count_max,
-                use_market_order,
+                use_market_order: fund_use_market_order.unwrap_or(config.use_market_order),
                 take_profit_ratio,
                 risk_reward,
                 atr_spread,
                 atr_term,
+                min_open_confidence,
+                min_close_confidence,
+                chase_close_orders,
+                max_chase_ticks,
+                trailing_stop_ratio,
+                require_rsi_divergence,
+                max_idle_ticks,
+                alert_when_idle,
+                max_funding_rate_long,
+                max_funding_rate_short,
+                signal_on_bar_close,
+                bar_ticks,
+                max_acceptable_slippage,
+                alert_on_slippage,
+                anchor_to_vwap,
+                vwap_window,
+                leverage: fund_leverage,
+                prefer_maker,
+                maker_fallback_to_taker,
+                max_market_slippage,
+                expire_open_positions,
+                contract_type,
+                order_placement_mode,
+                time_in_force,
+                max_open_positions,
+                take_profit_ladder,
+                daily_loss_limit,
+                min_entry_zscore,
+                use_stoch_rsi_exit,
+                min_order_size,
+                max_order_size,
+                execution_delay_atr_factor,
+                min_holding_secs,
+                allow_averaging,
+                min_average_improvement,
+                trend_change_confirm_ticks,
+                candle_interval_ticks,
+                size_scaling,
+                log_fund_equity: config.log_fund_equity,
+                equity_sample_ticks: config.equity_sample_ticks,
+                trace_decisions: config.trace_decisions,
+                rounding_mode: config.rounding_mode,
+                open_resubmit_attempts: config.open_resubmit_attempts,
+                model_ticks: if config.back_test {
+                    config.skip_precompute_in_backtest
+                } else {
+                    None
+                },
+                random_seed: config.random_seed,
+            };
+
+            let mut fund_manager = FundManager::new(
+                fund_manager_config,
+                market_data,
+                db_handler.clone(),
+                dex_connector.clone(),
+                alerts.clone(),
             );
 
+            if !config.back_test {
+                if let Some(statistics) = db_handler
+                    .lock()
+                    .await
+                    .load_fund_statistics(&fund_name)
+                    .await
+                {
+                    fund_manager.restore_statistics(statistics);
+                }
+            }
+
             fund_managers.push(fund_manager);
         }
 
@@ -331,11 +726,22 @@ impl DerivativeTrader {
     async fn create_dex_connector(
         config: &DerivativeTraderConfig,
     ) -> Result<Arc<DexConnectorBox>, DexError> {
+        if config.replay {
+            log::info!("create_dex_connector: replay");
+            return Ok(Arc::new(DexConnectorBox::replay(
+                &config.dex_name,
+                config.fill_settings,
+                config.random_seed,
+            )));
+        }
+
         let dex_connector = DexConnectorBox::create(
             &config.dex_name,
             &config.rest_endpoint,
             &config.web_socket_endpoint,
             config.dry_run,
+            config.fill_settings,
+            config.random_seed,
         )
         .await?;
         log::info!("create_dex_connector");
@@ -346,7 +752,7 @@ impl DerivativeTrader {
 
     async fn create_market_data(
         db_handler: Arc<Mutex<DBHandler>>,
-        config: DerivativeTraderConfig,
+        config: &DerivativeTraderConfig,
         token_name: &str,
         strategy: &TradingStrategy,
     ) -> MarketData {
@@ -377,13 +783,13 @@ impl DerivativeTrader {
         };
 
         MarketData::new(
-            config.trader_name.to_owned(),
-            config.short_trade_period,
-            config.long_trade_period,
-            config.trade_period,
+            config.dex_name.to_owned(),
+            config.short_trade_period(),
+            config.long_trade_period(),
+            config.trade_period(),
             config.max_price_size as usize,
             random_forest,
-            config.only_read_price,
+            config.trading_mode == TradingMode::OnlyReadPrice,
         )
     }
 
@@ -416,11 +822,21 @@ impl DerivativeTrader {
         log::info!("restore_market_data return");
     }
 
+    fn load_price_source(path: &str) -> HashMap<String, HashMap<String, Vec<PricePoint>>> {
+        let file = File::open(path).unwrap_or_else(|e| panic!("Failed to open {}: {:?}", path, e));
+        let prices: HashMap<String, HashMap<String, Vec<PricePoint>>> =
+            serde_json::from_reader(file)
+                .unwrap_or_else(|e| panic!("Failed to read prices from {}: {:?}", path, e));
+        log::info!("dry run prices loaded from {}", path);
+        prices
+    }
+
     fn get_back_test_price(
         trader_name: &str,
         token_name: &str,
         price_market_data: &HashMap<String, HashMap<String, Vec<PricePoint>>>,
         index: usize,
+        wrap_around: bool,
     ) -> Option<PricePoint> {
         if price_market_data.is_empty() {
             return None;
@@ -435,6 +851,15 @@ impl DerivativeTrader {
         }
 
         let price_points = price_points.unwrap();
+        if price_points.is_empty() {
+            return None;
+        }
+        // Dry runs keep cycling through the price source
+        let index = if wrap_around {
+            index % price_points.len()
+        } else {
+            index
+        };
         if price_points.len() <= index {
             return None;
         }
@@ -446,33 +871,208 @@ impl DerivativeTrader {
         Some(price_point)
     }
 
+    fn write_backtest_report(path: &str, records: &[Vec<String>]) -> Result<(), Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(File::create(path)?);
+        wtr.write_record(BACKTEST_REPORT_HEADER)?;
+        for record in records {
+            wtr.write_record(record)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    // A stalled WS feed keeps returning the last price, so a price that stops changing is stale
+    fn stale_tokens<'a>(
+        price_changes: &mut HashMap<String, (Decimal, i64)>,
+        prices: impl Iterator<Item = (&'a str, Decimal)>,
+        now: i64,
+        max_age_secs: i64,
+    ) -> Vec<String> {
+        let mut stale_tokens = vec![];
+        for (token_name, price) in prices {
+            let (last_price, changed_at) = price_changes
+                .entry(token_name.to_owned())
+                .or_insert((price, now));
+            if *last_price != price {
+                *last_price = price;
+                *changed_at = now;
+            }
+            if now - *changed_at > max_age_secs {
+                stale_tokens.push(token_name.to_owned());
+            }
+        }
+        stale_tokens.sort();
+        stale_tokens
+    }
+
+    // A flat price is not proof of a dead feed, so the connector is restarted only when configured
+    fn must_restart_on_stale_prices(
+        stale_price_ticks: &mut u32,
+        is_stale: bool,
+        restart_ticks: Option<u32>,
+    ) -> bool {
+        if !is_stale {
+            *stale_price_ticks = 0;
+            return false;
+        }
+        *stale_price_ticks += 1;
+        match restart_ticks {
+            Some(restart_ticks) if *stale_price_ticks >= restart_ticks => {
+                *stale_price_ticks = 0;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn compare_trade_id(a: &str, b: &str) -> std::cmp::Ordering {
+        match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        }
+    }
+
     fn round_price(price: Decimal, min_tick: Option<Decimal>) -> Decimal {
         let min_tick = min_tick.unwrap_or(Decimal::ONE);
         (price / min_tick).round() * min_tick
     }
 
-    pub async fn is_max_dd_occurred(&self) -> Result<bool, ()> {
-        let balance = match self.get_balance().await {
-            Ok(v) => v,
-            Err(_) => return Err(()),
+    // The latest ratio is kept for the funds that scale their size by the drawdown
+    pub async fn dd_ratio(&mut self) -> Result<Decimal, ()> {
+        let balance = match self.config.dd_basis {
+            DrawdownBasis::Equity => match self.get_balance().await {
+                Ok(v) => v,
+                Err(_) => return Err(()),
+            },
+            // The balance at the start includes the PnL of the previous sessions
+            DrawdownBasis::Realized => self.state.initial_balance + self.session_pnl(),
         };
-        let lost = self.config.initial_balance - balance;
-        if lost.is_sign_positive() {
-            let dd_ratio = lost / self.config.initial_balance;
+        let lost = self.state.initial_balance - balance;
+        let dd_ratio = if lost.is_sign_positive() && !self.state.initial_balance.is_zero() {
+            let dd_ratio = lost / self.state.initial_balance;
             log::info!(
                 "lost = {:.3}, initial_balance = {:.3}, dd_ratio = {:.3}",
                 lost,
-                self.config.initial_balance,
+                self.state.initial_balance,
                 dd_ratio
             );
-            if dd_ratio > self.config.max_dd_ratio {
-                return Ok(true);
+            dd_ratio
+        } else {
+            Decimal::ZERO
+        };
+        self.state.dd_ratio = dd_ratio;
+        Ok(dd_ratio)
+    }
+
+    pub async fn is_margin_thin(&self) -> bool {
+        match self.config.min_margin_ratio {
+            Some(min_margin_ratio) => {
+                Self::is_margin_buffer_thin(self.state.dex_connector.as_ref(), min_margin_ratio)
+                    .await
+            }
+            None => false,
+        }
+    }
+
+    // equity / maintenance margin below the minimum. The ratio is not known without a position,
+    // nor when the connector fails, and neither liquidates.
+    async fn is_margin_buffer_thin<T: DexConnectorExt + ?Sized>(
+        dex_connector: &T,
+        min_margin_ratio: Decimal,
+    ) -> bool {
+        let margin_info = match dex_connector.get_margin_info().await {
+            Ok(v) => v,
+            Err(e) => {
+                log::warn!("failed to get the margin info: {:?}", e);
+                return false;
             }
+        };
+        match margin_info.margin_ratio() {
+            Some(margin_ratio) => {
+                log::info!(
+                    "equity = {:.3}, used_margin = {:.3}, maintenance_margin = {:.3}, margin_ratio = {:.3}",
+                    margin_info.equity,
+                    margin_info.used_margin,
+                    margin_info.maintenance_margin,
+                    margin_ratio
+                );
+                margin_ratio < min_margin_ratio
+            }
+            None => false,
+        }
+    }
+
+    // Rounds to the nearest tick so that e.g. 90s on a 60s interval lasts 2 ticks, not 1
+    fn duration_to_ticks(duration_secs: i64, interval_secs: i64) -> Result<u32, String> {
+        if interval_secs <= 0 {
+            return Err(format!("invalid interval: {} secs", interval_secs));
+        }
+        if duration_secs < 0 {
+            return Err(format!("negative duration: {} secs", duration_secs));
+        }
+        let ticks = (duration_secs + interval_secs / 2) / interval_secs;
+        let ticks: u32 = ticks
+            .try_into()
+            .map_err(|_| format!("too long duration: {} secs", duration_secs))?;
+        Ok(ticks.max(1))
+    }
+
+    pub fn stop_opening() {
+        STOP_OPENING.store(true, Ordering::SeqCst);
+    }
+
+    pub fn pause_opening(&mut self, paused: bool) {
+        if self.state.opening_paused != paused {
+            log::info!("{}: opening paused = {}", self.config.dex_name, paused);
+        }
+        self.state.opening_paused = paused;
+    }
+
+    // Lets the ticks run again after liquidate(true, ..) when the bot keeps running
+    pub fn end_liquidation(&self) {
+        self.state.liquidating.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_max_dd(&self, dd_ratio: Decimal) -> bool {
+        dd_ratio > self.config.max_dd_ratio
+    }
+
+    pub fn metrics_snapshot(&self, dd_ratio: Decimal) -> MetricsSnapshot {
+        MetricsSnapshot {
+            funds: self
+                .state
+                .fund_manager_map
+                .values()
+                .map(|fund_manager| FundMetrics {
+                    fund_name: fund_manager.fund_name().to_owned(),
+                    pnl: fund_manager.realized_pnl(),
+                    order_count: fund_manager.order_count(),
+                    fill_count: fund_manager.fill_count(),
+                })
+                .collect(),
+            invested_amount: self.invested_amount(),
+            net_exposure: self.net_exposure(),
+            gross_exposure: self.gross_exposure(),
+            initial_balance: self.state.initial_balance,
+            dd_ratio,
         }
-        return Ok(false);
     }
 
     pub async fn find_chances(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.state.liquidating.load(Ordering::SeqCst) {
+            log::warn!("find_chances is skipped during liquidation");
+            return Ok(());
+        }
+
+        self.state.finding_chances.store(true, Ordering::SeqCst);
+        let res = self.find_chances_of_funds().await;
+        self.state.finding_chances.store(false, Ordering::SeqCst);
+        res
+    }
+
+    async fn find_chances_of_funds(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.state.tick_count += 1;
+
         // 1. Get token prices
         log::debug!("1. Get token prices: started");
 
@@ -484,16 +1084,27 @@ impl DerivativeTrader {
             if !token_set.contains(&token_name) {
                 token_set.insert(token_name.to_owned());
                 let back_test_price = Self::get_back_test_price(
-                    &self.config.trader_name,
+                    &self.config.dex_name,
                     &token_name,
                     &self.state.back_test_data,
                     self.state.back_test_counter,
+                    !self.config.back_test,
                 );
                 if self.config.back_test && back_test_price.is_none() {
                     log::warn!(
                         "Back test is not available: counter = {}",
                         self.state.back_test_counter
                     );
+                    if let Some(path) = &self.config.backtest_report_path {
+                        match Self::write_backtest_report(path, &self.state.backtest_records) {
+                            Ok(()) => log::info!("Back test report saved to {}", path),
+                            Err(e) => log::error!(
+                                "Failed to write the back test report to {}: {:?}",
+                                path,
+                                e
+                            ),
+                        }
+                    }
                     return Err(Box::new(std::io::Error::new(
                         std::io::ErrorKind::InvalidData,
                         "Back test is finished",
@@ -531,6 +1142,39 @@ impl DerivativeTrader {
         }
         log::debug!("Prices obtained: {:?}", prices);
 
+        // Back test prices are not live, so they are never stale
+        let max_price_age_secs = self
+            .config
+            .max_price_age_secs
+            .filter(|_| !self.config.back_test);
+        if let Some(max_price_age_secs) = max_price_age_secs {
+            let stale_tokens = Self::stale_tokens(
+                &mut self.state.price_changes,
+                prices.iter().filter_map(|(token_name, price_point)| {
+                    price_point.map(|(price, ..)| (token_name.as_str(), price))
+                }),
+                chrono::Utc::now().timestamp(),
+                max_price_age_secs,
+            );
+            for token_name in &stale_tokens {
+                log::warn!(
+                    "{}: the price has not changed for over {} secs, skip it",
+                    token_name,
+                    max_price_age_secs
+                );
+                prices.insert(token_name.to_owned(), None);
+            }
+
+            if Self::must_restart_on_stale_prices(
+                &mut self.state.stale_price_ticks,
+                !stale_tokens.is_empty(),
+                self.config.stale_price_restart_ticks,
+            ) {
+                log::warn!("Prices have been stale for too many ticks, restart the dex connector");
+                self.reset_dex_client().await;
+            }
+        }
+
         self.state.back_test_counter += 1;
 
         let mut saved_tokens = HashSet::new();
@@ -596,7 +1240,7 @@ impl DerivativeTrader {
                     match timeout(Duration::from_secs(5), self.state.db_handler.lock()).await {
                         Ok(db_handler) => {
                             db_handler
-                                .log_price(&self.config.trader_name, token_name, price_point)
+                                .log_price(&self.config.dex_name, token_name, price_point)
                                 .await;
                         }
                         Err(_) => {
@@ -612,10 +1256,30 @@ impl DerivativeTrader {
         }
         log::info!("All market data processed.");
 
-        if self.config.only_read_price {
-            return Ok(());
+        match self.config.trading_mode {
+            TradingMode::Trade => {}
+            TradingMode::OnlyReadPrice => return Ok(()),
+            TradingMode::ComputeNoTrade => {
+                for fund_manager in self.state.fund_manager_map.values_mut() {
+                    if let Some((price, ..)) =
+                        prices.get(fund_manager.token_name()).and_then(|p| *p)
+                    {
+                        fund_manager.log_signals(price).await;
+                    }
+                }
+                return Ok(());
+            }
         }
 
+        // The open orders and the positions are listed before the fills are, so that an order
+        // filled in between is among the fills
+        let open_orders = match self.config.order_verify_ticks {
+            Some(ticks) if self.state.tick_count.is_multiple_of(ticks) => {
+                Some((self.open_order_ids().await, self.venue_positions().await))
+            }
+            _ => None,
+        };
+
         // 2. Check newly filled orders after the new price is queried; otherwise DexEmulator can't fill any orders
         log::debug!("2. Check filled orders: started");
         let mut filled_orders_map: HashMap<String, FilledOrder> = HashMap::new();
@@ -635,8 +1299,14 @@ impl DerivativeTrader {
 
         let mut filled_orders_map_clone = filled_orders_map.clone();
 
+        // Fills from the WS feed are not guaranteed to be in order, so apply them by trade sequence
+        let mut filled_orders: Vec<FilledOrder> = filled_orders_map.into_values().collect();
+        if self.config.sort_filled_orders {
+            filled_orders.sort_by(|a, b| Self::compare_trade_id(&a.trade_id, &b.trade_id));
+        }
+
         for (_, fund_manager) in self.state.fund_manager_map.iter_mut() {
-            for order in filled_orders_map.values() {
+            for order in filled_orders.iter() {
                 if order.is_rejected {
                     fund_manager
                         .cancel_order(&order.order_id.clone(), true)
@@ -645,6 +1315,7 @@ impl DerivativeTrader {
                     let filled = fund_manager
                         .position_filled(
                             &order.order_id.clone(),
+                            &order.trade_id,
                             order.filled_side.clone().unwrap(),
                             order.filled_value.unwrap(),
                             order.filled_size.unwrap(),
@@ -663,6 +1334,11 @@ impl DerivativeTrader {
         }
         self.state.dex_connector.clear_all_filled_order().await?;
 
+        // The close fills that came before their open fills are applied once these are in
+        for fund_manager in self.state.fund_manager_map.values_mut() {
+            fund_manager.apply_deferred_fills().await;
+        }
+
         if !filled_orders_map_clone.is_empty() {
             log::warn!(
                 "Some filled orders are not handled: {:?}",
@@ -671,11 +1347,34 @@ impl DerivativeTrader {
         }
         log::debug!("2. Check filled orders: finished");
 
+        // The orders of the pending positions are verified here rather than in
+        // FundManager::check_positions, as the venue is asked once per token for all the funds,
+        // and before the fills are read
+        if let Some((open_order_ids, venue_positions)) = open_orders {
+            let mut venue_drifts = self.venue_drifts(venue_positions);
+            for fund_manager in self.state.fund_manager_map.values_mut() {
+                let token_name = fund_manager.token_name().to_owned();
+                if let Some(order_ids) = open_order_ids.get(&token_name) {
+                    let mut venue_drift = venue_drifts.get(&token_name).copied();
+                    fund_manager
+                        .reconcile_open_orders(order_ids, &mut venue_drift)
+                        .await;
+                    if let Some(venue_drift) = venue_drift {
+                        venue_drifts.insert(token_name, venue_drift);
+                    }
+                }
+            }
+        }
+
         // 3. Find trade chanes
-        let find_futures: Vec<_> = self
-            .state
-            .fund_manager_map
-            .values_mut()
+        self.share_resting_orders();
+        let mut capped_tokens = self.capped_tokens();
+        capped_tokens.extend(self.rate_limited_tokens());
+        capped_tokens.extend(self.concurrency_capped_tokens());
+        let open_counts_before = self.token_open_order_counts();
+        let dd_ratio = self.state.dd_ratio;
+        let find_futures: Vec<_> = Self::funds_by_name(&mut self.state.fund_manager_map)
+            .into_iter()
             .filter_map(|fund_manager| {
                 let token_name = fund_manager.token_name();
                 if let Some((
@@ -689,7 +1388,10 @@ impl DerivativeTrader {
                     _oracle_price,
                 )) = prices.get(token_name).and_then(|p| *p)
                 {
-                    Some(fund_manager.find_chances(price, self.config.dry_run))
+                    let can_open = !capped_tokens.contains(token_name)
+                        && !self.state.opening_paused
+                        && !STOP_OPENING.load(Ordering::SeqCst);
+                    Some(fund_manager.find_chances(price, self.config.dry_run, can_open, dd_ratio))
                 } else {
                     None
                 }
@@ -700,6 +1402,14 @@ impl DerivativeTrader {
         let find_results = join_all(find_futures).await;
         log::debug!("3. Find trade chances: finished");
 
+        self.record_token_trades(open_counts_before);
+
+        // The takers have booked their side of the crosses, and the owners of the crossed
+        // orders book theirs
+        for fund_manager in Self::funds_by_name(&mut self.state.fund_manager_map) {
+            fund_manager.book_crosses().await;
+        }
+
         for result in find_results {
             if result.is_err() {
                 return result;
@@ -709,11 +1419,200 @@ impl DerivativeTrader {
         // 4. Clean up the canceled positions
         for fund_manager in self.state.fund_manager_map.values_mut() {
             fund_manager.clean_canceled_position();
+            let closed_positions = fund_manager.take_closed_positions();
+            if self.config.back_test && self.config.backtest_report_path.is_some() {
+                self.state.backtest_records.extend(
+                    closed_positions
+                        .iter()
+                        .map(|position| BacktestReportRow::from_position(position).record()),
+                );
+            }
         }
 
         Ok(())
     }
 
+    // Each fund sees the resting opens of the other funds on its token, so that it does not
+    // trade against them on the venue
+    fn share_resting_orders(&mut self) {
+        FundManager::share_resting_orders(
+            Self::funds_by_name(&mut self.state.fund_manager_map).into_iter(),
+        );
+    }
+
+    // The order the funds run in decides which of them crosses which, so it follows their
+    // names to keep a seeded back test reproducible
+    fn funds_by_name(fund_manager_map: &mut HashMap<String, FundManager>) -> Vec<&mut FundManager> {
+        let mut fund_managers: Vec<(&String, &mut FundManager)> =
+            fund_manager_map.iter_mut().collect();
+        fund_managers.sort_by_key(|(fund_name, _)| *fund_name);
+        fund_managers
+            .into_iter()
+            .map(|(_, fund_manager)| fund_manager)
+            .collect()
+    }
+
+    fn token_open_order_counts(&self) -> HashMap<String, u64> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+        for fund_manager in self.state.fund_manager_map.values() {
+            *counts
+                .entry(fund_manager.token_name().to_owned())
+                .or_default() += fund_manager.open_order_count();
+        }
+        counts
+    }
+
+    fn record_token_trades(&mut self, open_counts_before: HashMap<String, u64>) {
+        if self.config.max_trades_per_token_per_hour.is_none() {
+            return;
+        }
+        for (token_name, count) in self.token_open_order_counts() {
+            let new_trades = count - open_counts_before.get(&token_name).copied().unwrap_or(0);
+            let trade_ticks = self.state.token_trade_ticks.entry(token_name).or_default();
+            for _ in 0..new_trades {
+                trade_ticks.push_back(self.state.tick_count);
+            }
+        }
+    }
+
+    // Opens are capped per token across the funds, while closes are always allowed
+    fn rate_limited_tokens(&mut self) -> HashSet<String> {
+        let max_trades = match self.config.max_trades_per_token_per_hour {
+            Some(v) => v,
+            None => return HashSet::new(),
+        };
+        let window_ticks = (60 * 60 / self.config.interval_secs.max(1)) as u64;
+        let tick_count = self.state.tick_count;
+
+        self.state
+            .token_trade_ticks
+            .iter_mut()
+            .filter_map(|(token_name, trade_ticks)| {
+                if Self::is_trade_rate_limited(trade_ticks, tick_count, window_ticks, max_trades) {
+                    log::warn!(
+                        "{}: opening is rate limited: {} trades in the last hour",
+                        token_name,
+                        trade_ticks.len()
+                    );
+                    Some(token_name.clone())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn is_trade_rate_limited(
+        trade_ticks: &mut VecDeque<u64>,
+        tick_count: u64,
+        window_ticks: u64,
+        max_trades: u32,
+    ) -> bool {
+        while let Some(tick) = trade_ticks.front() {
+            if tick + window_ticks > tick_count {
+                break;
+            }
+            trade_ticks.pop_front();
+        }
+        trade_ticks.len() >= max_trades as usize
+    }
+
+    fn capped_tokens(&self) -> HashSet<String> {
+        let max_token_notional = match self.config.max_token_notional {
+            Some(v) => v,
+            None => return HashSet::new(),
+        };
+
+        FundManager::tokens_over_notional(self.state.fund_manager_map.values(), max_token_notional)
+    }
+
+    fn is_token_allowed(
+        token_name: &str,
+        token_blacklist: &[String],
+        token_whitelist: Option<&[String]>,
+    ) -> bool {
+        !token_blacklist.iter().any(|token| token == token_name)
+            && token_whitelist.is_none_or(|tokens| tokens.iter().any(|token| token == token_name))
+    }
+
+    // The opens of a tick run together, so the new tokens are chosen before they are dispatched
+    fn concurrency_capped_tokens(&self) -> HashSet<String> {
+        let max_tokens = match self.config.max_concurrent_open_tokens {
+            Some(v) => v,
+            None => return HashSet::new(),
+        };
+
+        let capped_tokens = FundManager::tokens_over_concurrency(
+            self.state.fund_manager_map.values(),
+            max_tokens,
+            self.state.tick_count,
+        );
+        if !capped_tokens.is_empty() {
+            log::warn!(
+                "opening new tokens is capped: max = {}, blocked = {:?}",
+                max_tokens,
+                capped_tokens
+            );
+        }
+        capped_tokens
+    }
+
+    // A token whose open orders can't be listed is left out, so its orders are kept this time
+    async fn open_order_ids(&self) -> HashMap<String, HashSet<String>> {
+        let token_names: HashSet<&str> = self
+            .state
+            .fund_manager_map
+            .values()
+            .map(|fund_manager| fund_manager.token_name())
+            .collect();
+
+        let mut open_order_ids = HashMap::new();
+        for token_name in token_names {
+            match self
+                .state
+                .dex_connector
+                .get_open_order_ids(token_name)
+                .await
+            {
+                Ok(order_ids) => {
+                    open_order_ids.insert(token_name.to_owned(), order_ids.into_iter().collect());
+                }
+                Err(e) => log::warn!("open orders of {} are not listed: {:?}", token_name, e),
+            }
+        }
+        open_order_ids
+    }
+
+    async fn venue_positions(&self) -> Option<HashMap<String, Decimal>> {
+        match self.state.dex_connector.get_positions().await {
+            Ok(positions) => Some(positions.into_iter().collect()),
+            Err(e) => {
+                log::warn!("positions are not listed: {:?}", e);
+                None
+            }
+        }
+    }
+
+    // The venue's size on each token less the size the funds on it hold
+    fn venue_drifts(
+        &self,
+        venue_positions: Option<HashMap<String, Decimal>>,
+    ) -> HashMap<String, Decimal> {
+        let venue_positions = match venue_positions {
+            Some(venue_positions) => venue_positions,
+            None => return HashMap::new(),
+        };
+        let mut venue_drifts = HashMap::new();
+        for fund_manager in self.state.fund_manager_map.values() {
+            let token_name = fund_manager.token_name();
+            let venue_size = venue_positions.get(token_name).copied().unwrap_or_default();
+            *venue_drifts
+                .entry(token_name.to_owned())
+                .or_insert(venue_size) -= fund_manager.position_size();
+        }
+        venue_drifts
+    }
+
     pub async fn reset_dex_client(&mut self) -> bool {
         log::info!("reset dex_client");
 
@@ -734,6 +1633,11 @@ impl DerivativeTrader {
     }
 
     pub async fn liquidate(&mut self, on_exit: bool, reason: &str) {
+        self.state.liquidating.store(true, Ordering::SeqCst);
+        if self.state.finding_chances.swap(false, Ordering::SeqCst) {
+            log::warn!("liquidate: find_chances was interrupted, positions may be out of sync");
+        }
+
         let res = self.state.dex_connector.cancel_all_orders(None).await;
         if let Err(e) = res {
             log::error!("liquidate failed (cancel): {:?}", e);
@@ -744,6 +1648,26 @@ impl DerivativeTrader {
             log::error!("liquidate failed (close position): {:?}", e);
         }
 
+        // The account-wide calls may fail partially, so check what is left
+        if !self.config.dry_run {
+            if let Err(remaining) = Self::flatten_remaining(
+                self.state.dex_connector.as_ref(),
+                self.config.liquidate_retry_max,
+                Duration::from_secs(LIQUIDATE_RETRY_INTERVAL_SECS),
+            )
+            .await
+            {
+                let message = format!(
+                    "{}: liquidation({}) is incomplete: {:?}",
+                    self.config.dex_name, reason, remaining
+                );
+                log::error!("{}", message);
+                self.state
+                    .alerts
+                    .send("[debot] Liquidation incomplete", &message);
+            }
+        }
+
         if on_exit {
             let mut tasks = vec![];
 
@@ -756,19 +1680,156 @@ impl DerivativeTrader {
             }
 
             join_all(tasks).await;
+        } else {
+            self.state.liquidating.store(false, Ordering::SeqCst);
         }
     }
 
+    // Retries per-symbol cancels and closes until nothing is open.
+    // Returns the symbols left, or an empty list when the state could not be read.
+    async fn flatten_remaining<T: DexConnectorExt + ?Sized>(
+        dex_connector: &T,
+        max_retries: u32,
+        interval: Duration,
+    ) -> Result<(), Vec<String>> {
+        let mut remaining = vec![];
+        for attempt in 0..=max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(interval).await;
+            }
+
+            let order_symbols = dex_connector.get_open_order_symbols().await;
+            let position_symbols = dex_connector.get_position_symbols().await;
+            let (order_symbols, position_symbols) = match (order_symbols, position_symbols) {
+                (Ok(order_symbols), Ok(position_symbols)) => (order_symbols, position_symbols),
+                (Err(e), _) | (_, Err(e)) => {
+                    log::warn!("liquidate: failed to verify({}): {:?}", attempt, e);
+                    remaining.clear();
+                    continue;
+                }
+            };
+
+            if order_symbols.is_empty() && position_symbols.is_empty() {
+                return Ok(());
+            }
+
+            log::warn!(
+                "liquidate: not flat yet({}): orders = {:?}, positions = {:?}",
+                attempt,
+                order_symbols,
+                position_symbols
+            );
+            if attempt == max_retries {
+                remaining = order_symbols;
+                remaining.extend(position_symbols);
+                break;
+            }
+
+            for symbol in &order_symbols {
+                if let Err(e) = dex_connector.cancel_all_orders(Some(symbol.clone())).await {
+                    log::error!("liquidate failed (cancel {}): {:?}", symbol, e);
+                }
+            }
+            for symbol in &position_symbols {
+                if let Err(e) = dex_connector
+                    .close_all_positions(Some(symbol.clone()))
+                    .await
+                {
+                    log::error!("liquidate failed (close position {}): {:?}", symbol, e);
+                }
+            }
+        }
+
+        Err(remaining)
+    }
+
+    pub fn dex_name(&self) -> &str {
+        &self.config.dex_name
+    }
+
     pub fn db_handler(&self) -> &Arc<Mutex<DBHandler>> {
         &self.state.db_handler
     }
 
     pub async fn get_balance(&self) -> Result<Decimal, ()> {
-        if let Ok(res) = self.state.dex_connector.get_balance().await {
-            return Ok(res.equity);
+        Self::get_balance_with_retry(
+            self.state.dex_connector.as_ref(),
+            self.config.balance_retry_attempts,
+            Duration::from_millis(self.config.balance_retry_backoff_millis),
+        )
+        .await
+    }
+
+    async fn get_balance_with_retry<T: DexConnector + ?Sized>(
+        dex_connector: &T,
+        max_attempts: u32,
+        initial_backoff: Duration,
+    ) -> Result<Decimal, ()> {
+        let mut backoff = initial_backoff;
+        for attempt in 1..=max_attempts.max(1) {
+            match dex_connector.get_balance().await {
+                Ok(res) => return Ok(res.equity),
+                Err(e) => {
+                    log::warn!(
+                        "failed to get the balance({}/{}): {:?}",
+                        attempt,
+                        max_attempts,
+                        e
+                    );
+                    if attempt < max_attempts {
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                }
+            }
         }
         log::error!("failed to get the balance");
-        return Err(());
+        Err(())
+    }
+
+    pub async fn flush_fund_equity(&mut self) {
+        for fund_manager in self.state.fund_manager_map.values_mut() {
+            fund_manager.flush_equity().await;
+        }
+    }
+
+    pub fn log_statistics(&self) {
+        let mut fill_count = 0;
+        for fund_manager in self.state.fund_manager_map.values() {
+            fund_manager.log_statistics();
+            fill_count += fund_manager.fill_count();
+        }
+        log::info!(
+            "{}: realized pnl = {:.3}, fill count = {}",
+            self.config.dex_name,
+            self.realized_pnl(),
+            fill_count
+        );
+    }
+
+    pub fn realized_pnl(&self) -> Decimal {
+        self.state
+            .fund_manager_map
+            .values()
+            .map(|fund_manager| fund_manager.realized_pnl())
+            .sum()
+    }
+
+    fn session_pnl(&self) -> Decimal {
+        self.state
+            .fund_manager_map
+            .values()
+            .map(|fund_manager| fund_manager.session_pnl())
+            .sum()
+    }
+
+    pub async fn reload_fund_params(&mut self) {
+        let fund_params = self.state.db_handler.lock().await.load_fund_params().await;
+        for fund_manager in self.state.fund_manager_map.values_mut() {
+            if let Some(params) = fund_params.get(fund_manager.fund_name()) {
+                fund_manager.update_params(params);
+            }
+        }
     }
 
     pub fn invested_amount(&self) -> Decimal {
@@ -778,4 +1839,535 @@ impl DerivativeTrader {
         }
         sum.round_dp(1).abs()
     }
+
+    // Positive when the book is net long, negative when net short
+    pub fn net_exposure(&self) -> Decimal {
+        Self::exposures(self.exposure_iter()).0
+    }
+
+    pub fn gross_exposure(&self) -> Decimal {
+        Self::exposures(self.exposure_iter()).1
+    }
+
+    fn exposure_iter(&self) -> impl Iterator<Item = Decimal> + '_ {
+        self.state
+            .fund_manager_map
+            .values()
+            .map(|fund_manager| fund_manager.exposure())
+    }
+
+    // Returns (net, gross) of the signed amounts
+    fn exposures(amounts: impl Iterator<Item = Decimal>) -> (Decimal, Decimal) {
+        amounts.fold((Decimal::ZERO, Decimal::ZERO), |(net, gross), amount| {
+            (net + amount, gross + amount.abs())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trade::dex_connector_box::{
+        is_would_cross_rejection, MarginInfo, OrderBook, TimeInForce,
+    };
+    use crate::trade::mock_dex::{Mock, MockDex};
+    use async_trait::async_trait;
+    use dex_connector::{BalanceResponse, CreateOrderResponse, OrderSide, TickerResponse};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // Rejects the first limit orders as they would cross the book at 101/102
+    #[derive(Default)]
+    struct CrossRejectingVenue {
+        cross_rejections: AtomicU32,
+        order_prices: std::sync::Mutex<Vec<Option<Decimal>>>,
+    }
+
+    #[async_trait]
+    impl MockDex for CrossRejectingVenue {
+        async fn create_order(
+            &self,
+            _symbol: &str,
+            size: Decimal,
+            _side: OrderSide,
+            price: Option<Decimal>,
+            _time_in_force: TimeInForce,
+        ) -> Result<CreateOrderResponse, DexError> {
+            self.order_prices.lock().unwrap().push(price);
+            let rejected = self
+                .cross_rejections
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            if rejected {
+                return Err(DexError::Other(
+                    "Post only order would have immediately matched".to_owned(),
+                ));
+            }
+            Ok(CreateOrderResponse {
+                order_id: "1".to_owned(),
+                ordered_price: price.unwrap_or_default(),
+                ordered_size: size,
+            })
+        }
+
+        async fn get_orderbook(&self, _symbol: &str, _depth: usize) -> Result<OrderBook, DexError> {
+            Ok(OrderBook {
+                bids: vec![(Decimal::new(101, 0), Decimal::ONE)],
+                asks: vec![(Decimal::new(102, 0), Decimal::ONE)],
+            })
+        }
+    }
+
+    // Reports the margin info, if any
+    struct MarginVenue(Option<MarginInfo>);
+
+    #[async_trait]
+    impl MockDex for MarginVenue {
+        async fn get_margin_info(&self) -> Result<MarginInfo, DexError> {
+            self.0
+                .clone()
+                .ok_or_else(|| DexError::Other("no margin info".to_owned()))
+        }
+    }
+
+    // Ignores the first closes of a symbol
+    struct StuckCloseVenue {
+        positions: std::sync::Mutex<Vec<String>>,
+        stuck_closes: AtomicU32,
+    }
+
+    #[async_trait]
+    impl MockDex for StuckCloseVenue {
+        async fn close_all_positions(&self, symbol: Option<String>) -> Result<(), DexError> {
+            if let Some(symbol) = symbol {
+                let stuck = self
+                    .stuck_closes
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok();
+                if !stuck {
+                    self.positions.lock().unwrap().retain(|s| *s != symbol);
+                }
+            }
+            Ok(())
+        }
+
+        async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+            let positions = self.positions.lock().unwrap();
+            Ok(positions
+                .iter()
+                .map(|symbol| (symbol.clone(), Decimal::ONE))
+                .collect())
+        }
+    }
+
+    // The min_tick of the tickers, and no ticker for the other symbols
+    struct TickerVenue(HashMap<String, Option<Decimal>>);
+
+    #[async_trait]
+    impl MockDex for TickerVenue {
+        async fn get_ticker(&self, symbol: &str) -> Result<TickerResponse, DexError> {
+            match self.0.get(symbol) {
+                Some(min_tick) => Ok(TickerResponse {
+                    price: Decimal::new(100, 0),
+                    min_tick: *min_tick,
+                    ..Default::default()
+                }),
+                None => Err(DexError::Other("unknown symbol".to_owned())),
+            }
+        }
+    }
+
+    // Records the leverages set
+    #[derive(Default)]
+    struct LeverageRecorder {
+        leverages: std::sync::Mutex<Vec<(String, u32)>>,
+    }
+
+    #[async_trait]
+    impl MockDex for LeverageRecorder {
+        async fn set_leverage(&self, symbol: &str, leverage: u32) -> Result<(), DexError> {
+            self.leverages
+                .lock()
+                .unwrap()
+                .push((symbol.to_owned(), leverage));
+            Ok(())
+        }
+    }
+
+    // Times out the first balance queries
+    struct FlakyBalanceVenue {
+        failures: u32,
+        calls: AtomicU32,
+    }
+
+    #[async_trait]
+    impl MockDex for FlakyBalanceVenue {
+        async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+            let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if calls <= self.failures {
+                return Err(DexError::Other("timeout".to_owned()));
+            }
+            Ok(BalanceResponse {
+                equity: Decimal::new(1000, 0),
+                ..Default::default()
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_order_with_resubmit() {
+        let connector = Mock(CrossRejectingVenue {
+            cross_rejections: AtomicU32::new(1),
+            ..Default::default()
+        });
+        // The buy at 100 is rejected as the price moved up, and is resubmitted at the best bid
+        let res = connector
+            .create_order_with_resubmit(
+                "BTC-USD",
+                Decimal::ONE,
+                OrderSide::Long,
+                Some(Decimal::new(100, 0)),
+                TimeInForce::Gtc,
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.ordered_price, Decimal::new(101, 0));
+        assert_eq!(
+            *connector.0.order_prices.lock().unwrap(),
+            vec![Some(Decimal::new(100, 0)), Some(Decimal::new(101, 0))]
+        );
+
+        // Gives up once the attempts are used up
+        let connector = Mock(CrossRejectingVenue {
+            cross_rejections: AtomicU32::new(2),
+            ..Default::default()
+        });
+        let res = connector
+            .create_order_with_resubmit(
+                "BTC-USD",
+                Decimal::ONE,
+                OrderSide::Short,
+                Some(Decimal::new(103, 0)),
+                TimeInForce::Gtc,
+                1,
+            )
+            .await;
+        assert!(res.is_err());
+        assert_eq!(
+            *connector.0.order_prices.lock().unwrap(),
+            vec![Some(Decimal::new(103, 0)), Some(Decimal::new(102, 0))]
+        );
+
+        // Other rejections are not resubmitted
+        assert!(!is_would_cross_rejection(&DexError::Other(
+            "insufficient margin".to_owned()
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_is_margin_buffer_thin() {
+        let connector = |equity: i64, maintenance_margin: i64| {
+            Mock(MarginVenue(Some(MarginInfo {
+                equity: Decimal::new(equity, 0),
+                used_margin: Decimal::new(maintenance_margin * 2, 0),
+                maintenance_margin: Decimal::new(maintenance_margin, 0),
+            })))
+        };
+        let min_margin_ratio = Decimal::new(3, 0);
+
+        // Equity only 2x the maintenance margin requests the liquidation
+        assert!(
+            DerivativeTrader::is_margin_buffer_thin(&connector(100, 50), min_margin_ratio).await
+        );
+        assert!(
+            !DerivativeTrader::is_margin_buffer_thin(&connector(1000, 50), min_margin_ratio).await
+        );
+        // No position
+        assert!(
+            !DerivativeTrader::is_margin_buffer_thin(&connector(1000, 0), min_margin_ratio).await
+        );
+        // The margin info is not available
+        assert!(
+            !DerivativeTrader::is_margin_buffer_thin(&Mock(MarginVenue(None)), min_margin_ratio)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flatten_remaining() {
+        let connector = Mock(StuckCloseVenue {
+            positions: std::sync::Mutex::new(vec!["BTC-USD".to_owned(), "ETH-USD".to_owned()]),
+            stuck_closes: AtomicU32::new(1),
+        });
+        // The first close of BTC-USD is ignored, the retry flattens it
+        assert_eq!(
+            DerivativeTrader::flatten_remaining(&connector, 2, Duration::ZERO).await,
+            Ok(())
+        );
+
+        let connector = Mock(StuckCloseVenue {
+            positions: std::sync::Mutex::new(vec!["BTC-USD".to_owned()]),
+            stuck_closes: AtomicU32::new(10),
+        });
+        assert_eq!(
+            DerivativeTrader::flatten_remaining(&connector, 2, Duration::ZERO).await,
+            Err(vec!["BTC-USD".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_stale_tokens() {
+        let mut price_changes = HashMap::new();
+        let prices = |btc: i64, eth: i64| {
+            vec![
+                ("BTC-USD", Decimal::new(btc, 0)),
+                ("ETH-USD", Decimal::new(eth, 0)),
+            ]
+            .into_iter()
+        };
+
+        assert!(
+            DerivativeTrader::stale_tokens(&mut price_changes, prices(100, 10), 0, 60).is_empty()
+        );
+        assert!(
+            DerivativeTrader::stale_tokens(&mut price_changes, prices(101, 10), 60, 60).is_empty()
+        );
+        // ETH has not changed for 61 secs, BTC still trades
+        assert_eq!(
+            DerivativeTrader::stale_tokens(&mut price_changes, prices(102, 10), 61, 60),
+            vec!["ETH-USD".to_owned()]
+        );
+        // And is fresh again once it moves
+        assert!(
+            DerivativeTrader::stale_tokens(&mut price_changes, prices(103, 11), 62, 60).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_flat_price_does_not_restart_the_connector_by_default() {
+        let mut price_changes = HashMap::new();
+        let mut stale_price_ticks = 0;
+        // A live but quiet market keeps the same price for a long time
+        for tick in 0..10 {
+            let is_stale = !DerivativeTrader::stale_tokens(
+                &mut price_changes,
+                vec![("BTC-USD", Decimal::new(100, 0))].into_iter(),
+                tick * 60,
+                60,
+            )
+            .is_empty();
+            assert!(!DerivativeTrader::must_restart_on_stale_prices(
+                &mut stale_price_ticks,
+                is_stale,
+                None
+            ));
+        }
+        assert!(stale_price_ticks > 0);
+    }
+
+    #[test]
+    fn test_stale_prices_restart_the_connector_when_configured() {
+        let mut stale_price_ticks = 0;
+        let mut restart = |is_stale| {
+            DerivativeTrader::must_restart_on_stale_prices(
+                &mut stale_price_ticks,
+                is_stale,
+                Some(2),
+            )
+        };
+        assert!(!restart(true));
+        // A fresh price resets the count
+        assert!(!restart(false));
+        assert!(!restart(true));
+        assert!(restart(true));
+        assert!(!restart(true));
+    }
+
+    #[test]
+    fn test_backtest_report() {
+        let records = vec![
+            BacktestReportRow {
+                open_time_str: "2024-01-01 00:00:00",
+                close_time_str: "2024-01-01 01:00:00",
+                token_name: "BTC-USD",
+                position_type: "Long",
+                asset_in_usd: Decimal::new(-1000, 0),
+                average_open_price: Decimal::new(100, 0),
+                close_price: Decimal::new(102, 0),
+                fee: Decimal::new(35, 2),
+                pnl: Decimal::new(2000, 2),
+                state: "Closed(TakeProfit)".to_owned(),
+            }
+            .record(),
+            BacktestReportRow {
+                open_time_str: "2024-01-01 02:00:00",
+                close_time_str: "2024-01-01 02:30:00",
+                token_name: "ETH-USD",
+                position_type: "Short",
+                asset_in_usd: Decimal::new(500, 0),
+                average_open_price: Decimal::new(50, 0),
+                close_price: Decimal::new(52, 0),
+                fee: Decimal::new(1, 1),
+                pnl: Decimal::new(-10, 0),
+                state: "Closed(CutLoss)".to_owned(),
+            }
+            .record(),
+        ];
+
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.write_record(BACKTEST_REPORT_HEADER).unwrap();
+        for record in &records {
+            wtr.write_record(record).unwrap();
+        }
+        let csv = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "open_time,close_time,token_name,position_type,size,average_open_price,close_price,fee,pnl,state\n\
+             2024-01-01 00:00:00,2024-01-01 01:00:00,BTC-USD,Long,10,100,102,0.35,20,Closed(TakeProfit)\n\
+             2024-01-01 02:00:00,2024-01-01 02:30:00,ETH-USD,Short,10,50,52,0.1,-10,Closed(CutLoss)\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_dex() {
+        let connector = Mock(TickerVenue(
+            [
+                ("BTC-USD".to_owned(), Some(Decimal::new(1, 1))),
+                ("SOL-USD".to_owned(), None),
+            ]
+            .into_iter()
+            .collect(),
+        ));
+        assert_eq!(
+            DerivativeTrader::check_dex(&connector, &["BTC-USD", "SOL-USD"]).await,
+            Err(vec!["SOL-USD(no min_tick)".to_owned()])
+        );
+        assert_eq!(
+            DerivativeTrader::check_dex(&connector, &["BTC-USD"]).await,
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_token_leverages() {
+        let connector = Mock(LeverageRecorder::default());
+        let token_leverages = vec![
+            ("BTC-USD".to_owned(), Some(10)),
+            ("SOL-USD".to_owned(), Some(3)),
+            ("BTC-USD".to_owned(), Some(10)),
+            ("ETH-USD".to_owned(), None),
+        ];
+        DerivativeTrader::set_token_leverages(&connector, &token_leverages, 5)
+            .await
+            .unwrap();
+        assert_eq!(
+            *connector.0.leverages.lock().unwrap(),
+            vec![
+                ("BTC-USD".to_owned(), 10),
+                ("SOL-USD".to_owned(), 3),
+                ("ETH-USD".to_owned(), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trade_rate_limit() {
+        let window_ticks = 60;
+        let mut trade_ticks: VecDeque<u64> = VecDeque::from(vec![10, 20, 30]);
+
+        assert!(DerivativeTrader::is_trade_rate_limited(
+            &mut trade_ticks,
+            31,
+            window_ticks,
+            3
+        ));
+        assert!(!DerivativeTrader::is_trade_rate_limited(
+            &mut trade_ticks,
+            31,
+            window_ticks,
+            4
+        ));
+
+        // The trade at tick 10 leaves the window
+        assert!(!DerivativeTrader::is_trade_rate_limited(
+            &mut trade_ticks,
+            70,
+            window_ticks,
+            3
+        ));
+        assert_eq!(trade_ticks, VecDeque::from(vec![20, 30]));
+    }
+
+    #[test]
+    fn test_token_filter() {
+        let tokens = ["BTC-USD", "ETH-USD", "SOL-USD"];
+        let allowed = |blacklist: &[String], whitelist: Option<&[String]>| {
+            tokens
+                .iter()
+                .filter(|token_name| {
+                    DerivativeTrader::is_token_allowed(token_name, blacklist, whitelist)
+                })
+                .copied()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(allowed(&[], None), tokens);
+        assert_eq!(
+            allowed(&["ETH-USD".to_owned()], None),
+            vec!["BTC-USD", "SOL-USD"]
+        );
+        let whitelist = ["SOL-USD".to_owned(), "BTC-USD".to_owned()];
+        assert_eq!(allowed(&[], Some(&whitelist)), vec!["BTC-USD", "SOL-USD"]);
+        // The blacklist wins over the whitelist
+        assert_eq!(
+            allowed(&["BTC-USD".to_owned()], Some(&whitelist)),
+            vec!["SOL-USD"]
+        );
+    }
+
+    #[test]
+    fn test_exposures() {
+        let amounts = [
+            Decimal::new(1005, 1),
+            Decimal::new(-2501, 1),
+            Decimal::new(503, 1),
+        ];
+        let (net, gross) = DerivativeTrader::exposures(amounts.into_iter());
+        assert_eq!(net, Decimal::new(-993, 1));
+        assert_eq!(gross, Decimal::new(4009, 1));
+    }
+
+    #[test]
+    fn test_duration_to_ticks() {
+        assert_eq!(DerivativeTrader::duration_to_ticks(90, 60), Ok(2));
+        assert_eq!(DerivativeTrader::duration_to_ticks(80, 60), Ok(1));
+        assert_eq!(DerivativeTrader::duration_to_ticks(300, 60), Ok(5));
+        assert_eq!(DerivativeTrader::duration_to_ticks(10, 60), Ok(1));
+        assert!(DerivativeTrader::duration_to_ticks(-60, 60).is_err());
+        assert!(DerivativeTrader::duration_to_ticks(60, 0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_balance_retries_until_success() {
+        let connector = Mock(FlakyBalanceVenue {
+            failures: 2,
+            calls: AtomicU32::new(0),
+        });
+        let balance =
+            DerivativeTrader::get_balance_with_retry(&connector, 3, Duration::from_millis(1)).await;
+        assert_eq!(balance, Ok(Decimal::new(1000, 0)));
+        assert_eq!(connector.0.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_balance_gives_up_after_max_attempts() {
+        let connector = Mock(FlakyBalanceVenue {
+            failures: 3,
+            calls: AtomicU32::new(0),
+        });
+        let balance =
+            DerivativeTrader::get_balance_with_retry(&connector, 3, Duration::from_millis(1)).await;
+        assert_eq!(balance, Err(()));
+        assert_eq!(connector.0.calls.load(Ordering::SeqCst), 3);
+    }
 }
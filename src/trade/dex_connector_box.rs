@@ -3,12 +3,25 @@ use dex_connector::{
     BalanceResponse, CreateOrderResponse, DexConnector, DexError, FilledOrdersResponse,
     HyperliquidConnector, OrderSide, TickerResponse,
 };
-use rust_decimal::Decimal;
+use ethers::{signers::LocalWallet, types::H160};
+use hyperliquid_rust_sdk::{
+    BaseUrl, ClientCancelRequest, ClientLimit, ClientOrder, ClientOrderRequest, ExchangeClient,
+    ExchangeDataStatus, ExchangeResponseStatus, InfoClient, LOCAL_API_URL, MAINNET_API_URL,
+    TESTNET_API_URL,
+};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 
-use super::{dex_emulator::DexEmulator, fund_config::TOKEN_LIST};
-use crate::config::get_hyperliquid_config_from_env;
+use super::{
+    bybit_connector::BybitConnector,
+    dex_emulator::{DexEmulator, EmulatorFillSettings},
+    fund_config::TOKEN_LIST,
+    replay_venue::ReplayVenue,
+};
+use crate::config::{get_bybit_config_from_env, get_hyperliquid_config_from_env};
 use lazy_static::lazy_static;
 use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 lazy_static! {
     static ref FILLED_PROBABILITY_IN_EMULATION: Decimal = {
@@ -17,10 +30,417 @@ lazy_static! {
             Err(_) => Decimal::new(1, 0),
         }
     };
+    pub static ref HYPERLIQUID_SYMBOLS: SymbolMapper = SymbolMapper::for_dex("hyperliquid");
+}
+
+// Translates the internal symbols of TOKEN_LIST, e.g. BTC-USD, to the names of a venue and back.
+// FundManager and DerivativeTrader only see the internal symbols. DexConnectorBox translates
+// them on every call to its connector.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolMapper {
+    // The venue name with {} for the base coin, e.g. "{}USDT"
+    pattern: String,
+    // (internal, venue) of the symbols that do not follow the pattern
+    overrides: Vec<(String, String)>,
+}
+
+impl SymbolMapper {
+    pub fn new(pattern: &str, overrides: Vec<(String, String)>) -> Self {
+        Self {
+            pattern: pattern.to_owned(),
+            overrides,
+        }
+    }
+
+    // SYMBOL_MAP overrides the symbols per dex, e.g. "hyperliquid:PEPE-USD=kPEPE,bybit:PEPE-USD=1000PEPEUSDT"
+    pub fn for_dex(dex_name: &str) -> Self {
+        let pattern = match dex_name {
+            "hyperliquid" => "{}",
+            "bybit" => "{}USDT",
+            _ => "{}-USD",
+        };
+        let overrides = env::var("SYMBOL_MAP")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (dex, mapping) = entry.trim().split_once(':')?;
+                let (internal, venue) = mapping.split_once('=')?;
+                (dex == dex_name).then(|| (internal.to_owned(), venue.to_owned()))
+            })
+            .collect();
+        Self::new(pattern, overrides)
+    }
+
+    // The symbols the connector of a dex takes. HyperliquidConnector names the markets after
+    // the coins, e.g. kPEPE-USD, and BybitConnector takes the venue symbols.
+    pub fn for_connector(dex_name: &str) -> Self {
+        Self::for_dex(dex_name).connector_symbols(dex_name)
+    }
+
+    fn connector_symbols(self, dex_name: &str) -> Self {
+        match dex_name {
+            "hyperliquid" => Self::new(
+                "{}-USD",
+                self.overrides
+                    .into_iter()
+                    .map(|(internal, coin)| (internal, format!("{}-USD", coin)))
+                    .collect(),
+            ),
+            _ => self,
+        }
+    }
+
+    pub fn to_venue(&self, symbol: &str) -> String {
+        if let Some((_, venue)) = self
+            .overrides
+            .iter()
+            .find(|(internal, _)| internal == symbol)
+        {
+            return venue.clone();
+        }
+        let base = symbol.split('-').next().unwrap_or(symbol);
+        self.pattern.replace("{}", base)
+    }
+
+    pub fn to_internal(&self, venue_symbol: &str) -> String {
+        if let Some((internal, _)) = self
+            .overrides
+            .iter()
+            .find(|(_, venue)| venue == venue_symbol)
+        {
+            return internal.clone();
+        }
+        let (prefix, suffix) = self.pattern.split_once("{}").unwrap_or(("", ""));
+        let base = venue_symbol
+            .strip_prefix(prefix)
+            .and_then(|s| s.strip_suffix(suffix))
+            .unwrap_or(venue_symbol);
+        format!("{}-USD", base)
+    }
+}
+
+// How long a limit order stays on the book: until canceled, or only for what fills immediately
+// (IOC), or only if all of it fills immediately (FOK)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeInForce {
+    Gtc,
+    Ioc,
+    Fok,
+}
+
+// Price levels of (price, size), from the best one
+#[derive(Debug, Default, Clone)]
+pub struct OrderBook {
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub asks: Vec<(Decimal, Decimal)>,
+}
+
+impl OrderBook {
+    // Slippage ratio of a market order of `size` against the best price,
+    // or None when the book is not deep enough to fill it
+    pub fn estimate_slippage(&self, side: &OrderSide, size: Decimal) -> Option<Decimal> {
+        let levels = match side {
+            OrderSide::Long => &self.asks,
+            OrderSide::Short => &self.bids,
+        };
+        let (best_price, _) = *levels.first()?;
+        if best_price.is_zero() || size <= Decimal::ZERO {
+            return None;
+        }
+
+        let mut remaining = size;
+        let mut value = Decimal::ZERO;
+        for (price, level_size) in levels {
+            let filled = remaining.min(*level_size);
+            value += filled * price;
+            remaining -= filled;
+            if remaining.is_zero() {
+                let average_price = value / size;
+                return Some((average_price - best_price).abs() / best_price);
+            }
+        }
+        None
+    }
+
+    // The best price on the side a market order would take
+    pub fn spread_edge(&self, side: &OrderSide) -> Option<Decimal> {
+        let levels = match side {
+            OrderSide::Long => &self.asks,
+            OrderSide::Short => &self.bids,
+        };
+        levels.first().map(|(price, _)| *price)
+    }
+
+    // The best price a maker order of `side` can rest at
+    pub fn touch_price(&self, side: &OrderSide) -> Option<Decimal> {
+        let levels = match side {
+            OrderSide::Long => &self.bids,
+            OrderSide::Short => &self.asks,
+        };
+        levels.first().map(|(price, _)| *price)
+    }
+}
+
+// Margin of the whole account
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MarginInfo {
+    pub equity: Decimal,
+    pub used_margin: Decimal,
+    pub maintenance_margin: Decimal,
+}
+
+impl MarginInfo {
+    // None without a position, as nothing is at the risk of liquidation
+    pub fn margin_ratio(&self) -> Option<Decimal> {
+        if self.maintenance_margin.is_zero() {
+            None
+        } else {
+            Some(self.equity / self.maintenance_margin)
+        }
+    }
+}
+
+// Token bucket shared by all the requests to a venue. A request waits for its token
+// instead of failing with 429.
+pub struct RateLimiter {
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    rate_per_sec: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    // Takes a token, and returns how long to wait for it. The tokens go negative to queue
+    // the requests in order.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate_per_sec).min(self.burst);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate_per_sec)
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(rate_per_sec: f64, burst: f64) -> Self {
+        let burst = burst.max(1.0);
+        RateLimiter {
+            bucket: Mutex::new(TokenBucket {
+                rate_per_sec,
+                burst,
+                tokens: burst,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    // RATE_LIMIT_<DEX> is <requests per second>:<burst>, e.g. RATE_LIMIT_HYPERLIQUID=10:20.
+    // Not limited without it.
+    pub fn from_env(dex_name: &str) -> Option<Self> {
+        let key = format!("RATE_LIMIT_{}", dex_name.to_uppercase());
+        let value = env::var(&key).ok()?;
+        let (rate_per_sec, burst) = value.split_once(':').unwrap_or((&value, &value));
+        match (rate_per_sec.parse::<f64>(), burst.parse::<f64>()) {
+            (Ok(rate_per_sec), Ok(burst)) if rate_per_sec > 0.0 => {
+                Some(Self::new(rate_per_sec, burst))
+            }
+            _ => panic!("Invalid {}: {}", key, value),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        let wait = self.bucket.lock().unwrap().reserve(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// A limit order rejected because the price moved through it, e.g. a post-only order that
+// would have crossed. Hyperliquid reports it as "Post only order would have immediately matched".
+pub fn is_would_cross_rejection(e: &DexError) -> bool {
+    match e {
+        DexError::Other(msg) => {
+            let msg = msg.to_lowercase();
+            msg.contains("would have immediately matched") || msg.contains("would cross")
+        }
+        _ => false,
+    }
+}
+
+// Extensions of DexConnector that are not in dex-connector.
+// Cancels several orders of a symbol and returns the number of requests sent.
+// Connectors without a batch API fall back to one request per order.
+#[async_trait]
+pub trait DexConnectorExt: DexConnector {
+    async fn cancel_orders(&self, symbol: &str, order_ids: &[String]) -> Result<usize, DexError> {
+        for order_id in order_ids {
+            self.cancel_order(symbol, order_id).await?;
+        }
+        Ok(order_ids.len())
+    }
+
+    // Market orders and GTC limit orders are what create_order sends
+    async fn create_order_with_tif(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        spread: Option<i64>,
+        time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        if price.is_some() && time_in_force != TimeInForce::Gtc {
+            return Err(DexError::Other(format!(
+                "{:?} is not supported",
+                time_in_force
+            )));
+        }
+        self.create_order(symbol, size, side, price, spread).await
+    }
+
+    // A limit order rejected as it would cross is resubmitted at the touch price of the
+    // current book, up to `resubmit_attempts` times
+    async fn create_order_with_resubmit(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        time_in_force: TimeInForce,
+        resubmit_attempts: u32,
+    ) -> Result<CreateOrderResponse, DexError> {
+        let mut price = price;
+        let mut attempts = 0;
+        loop {
+            let e = match self
+                .create_order_with_tif(symbol, size, side.clone(), price, None, time_in_force)
+                .await
+            {
+                Err(e) if price.is_some() && is_would_cross_rejection(&e) => e,
+                res => return res,
+            };
+            if attempts >= resubmit_attempts {
+                return Err(e);
+            }
+            attempts += 1;
+
+            let touch_price = match self.get_orderbook(symbol, 1).await {
+                Ok(book) => book.touch_price(&side),
+                Err(_) => None,
+            };
+            match touch_price {
+                Some(touch_price) => {
+                    log::info!(
+                        "{}: resubmit the order rejected at {:?} at {} ({}/{})",
+                        symbol,
+                        price,
+                        touch_price,
+                        attempts,
+                        resubmit_attempts
+                    );
+                    price = Some(touch_price);
+                }
+                None => return Err(e),
+            }
+        }
+    }
+
+    async fn get_orderbook(&self, _symbol: &str, _depth: usize) -> Result<OrderBook, DexError> {
+        Err(DexError::Other("get_orderbook is not supported".to_owned()))
+    }
+
+    // Symbols that still have open orders, used to verify a liquidation
+    async fn get_open_order_symbols(&self) -> Result<Vec<String>, DexError> {
+        Err(DexError::Other(
+            "get_open_order_symbols is not supported".to_owned(),
+        ))
+    }
+
+    // IDs of the open orders of a symbol, used to find the orders gone from the venue
+    async fn get_open_order_ids(&self, _symbol: &str) -> Result<Vec<String>, DexError> {
+        Err(DexError::Other(
+            "get_open_order_ids is not supported".to_owned(),
+        ))
+    }
+
+    // Symbols with their signed position sizes (negative for short)
+    async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+        Err(DexError::Other("get_positions is not supported".to_owned()))
+    }
+
+    async fn get_margin_info(&self) -> Result<MarginInfo, DexError> {
+        Err(DexError::Other(
+            "get_margin_info is not supported".to_owned(),
+        ))
+    }
+
+    // Symbols that still have positions, used to verify a liquidation
+    async fn get_position_symbols(&self) -> Result<Vec<String>, DexError> {
+        let positions = self.get_positions().await?;
+        Ok(positions
+            .into_iter()
+            .filter(|(_, size)| !size.is_zero())
+            .map(|(symbol, _)| symbol)
+            .collect())
+    }
+}
+
+// HyperliquidConnector does not expose the book nor the account state,
+// so they are read from the info API
+struct HyperliquidInfo {
+    endpoint: String,
+    // None in a dry run, where the orders and the positions are those of the emulator
+    user: Option<String>,
+}
+
+// HyperliquidConnector cancels one order per request, places its limit orders as post-only
+// and keeps its exchange client private, so the bulk cancel and the IOC orders go through
+// an exchange client of its own. Hyperliquid has no FOK.
+struct HyperliquidExchange {
+    client: ExchangeClient,
+}
+
+impl DexConnectorExt for HyperliquidConnector {}
+
+#[async_trait]
+impl<T: DexConnector> DexConnectorExt for DexEmulator<T> {
+    async fn create_order_with_tif(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        _spread: Option<i64>,
+        time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        self.place_order(symbol, size, side, price, time_in_force)
+            .await
+    }
+
+    async fn get_open_order_ids(&self, symbol: &str) -> Result<Vec<String>, DexError> {
+        Ok(self.open_order_ids(symbol).await)
+    }
 }
 
 pub struct DexConnectorBox {
-    inner: Box<dyn DexConnector>,
+    dex_name: String,
+    inner: Box<dyn DexConnectorExt>,
+    // The internal symbols to those of `inner`
+    symbols: SymbolMapper,
+    hyperliquid_info: Option<HyperliquidInfo>,
+    hyperliquid_exchange: Option<HyperliquidExchange>,
+    rate_limiter: Option<RateLimiter>,
 }
 
 impl DexConnectorBox {
@@ -29,7 +449,16 @@ impl DexConnectorBox {
         rest_endpoint: &str,
         web_socket_endpoint: &str,
         dry_run: bool,
+        fill_settings: EmulatorFillSettings,
+        random_seed: Option<u64>,
     ) -> Result<Self, DexError> {
+        let symbols = SymbolMapper::for_connector(dex_name);
+        let connector_symbols: Vec<String> = TOKEN_LIST
+            .iter()
+            .map(|symbol| symbols.to_venue(symbol))
+            .collect();
+        let connector_symbols: Vec<&str> = connector_symbols.iter().map(String::as_str).collect();
+
         match dex_name {
             "hyperliquid" => {
                 let hyperliquid_config = match get_hyperliquid_config_from_env().await {
@@ -39,13 +468,23 @@ impl DexConnectorBox {
                     }
                 };
 
+                let hyperliquid_info = HyperliquidInfo::new(
+                    rest_endpoint,
+                    hyperliquid_config
+                        .vault_address
+                        .clone()
+                        .unwrap_or_else(|| hyperliquid_config.evm_wallet_address.clone()),
+                    dry_run,
+                );
+
+                let hyperliquid_vault_address = hyperliquid_config.vault_address.clone();
                 let connector = HyperliquidConnector::new(
                     rest_endpoint,
                     web_socket_endpoint,
                     &hyperliquid_config.agent_private_key,
                     &hyperliquid_config.evm_wallet_address,
                     hyperliquid_config.vault_address,
-                    TOKEN_LIST,
+                    &connector_symbols,
                 )
                 .await?;
 
@@ -53,20 +492,147 @@ impl DexConnectorBox {
                     let dex_emulator = DexEmulator::new(
                         connector,
                         *FILLED_PROBABILITY_IN_EMULATION,
-                        Decimal::new(5, 3),
+                        fill_settings,
+                        random_seed,
                     );
                     Ok(DexConnectorBox {
+                        dex_name: dex_name.to_owned(),
                         inner: Box::new(dex_emulator),
+                        symbols: symbols.clone(),
+                        hyperliquid_info: Some(hyperliquid_info),
+                        hyperliquid_exchange: None,
+                        rate_limiter: RateLimiter::from_env(dex_name),
                     })
                 } else {
+                    let hyperliquid_exchange = HyperliquidExchange::new(
+                        rest_endpoint,
+                        &hyperliquid_config.agent_private_key,
+                        hyperliquid_vault_address.as_deref(),
+                    )
+                    .await?;
                     Ok(DexConnectorBox {
+                        dex_name: dex_name.to_owned(),
                         inner: Box::new(connector),
+                        symbols,
+                        hyperliquid_info: Some(hyperliquid_info),
+                        hyperliquid_exchange: Some(hyperliquid_exchange),
+                        rate_limiter: RateLimiter::from_env(dex_name),
+                    })
+                }
+            }
+            "bybit" => {
+                let bybit_config = match get_bybit_config_from_env().await {
+                    Ok(v) => v,
+                    Err(_) => {
+                        return Err(DexError::Other("Some env vars are missing".to_string()));
+                    }
+                };
+
+                let connector = BybitConnector::new(
+                    rest_endpoint,
+                    web_socket_endpoint,
+                    &bybit_config.api_key,
+                    &bybit_config.api_secret,
+                    &connector_symbols,
+                )
+                .await?;
+
+                if dry_run {
+                    let dex_emulator = DexEmulator::new(
+                        connector,
+                        *FILLED_PROBABILITY_IN_EMULATION,
+                        fill_settings,
+                        random_seed,
+                    );
+                    Ok(DexConnectorBox {
+                        dex_name: dex_name.to_owned(),
+                        inner: Box::new(dex_emulator),
+                        symbols: symbols.clone(),
+                        hyperliquid_info: None,
+                        hyperliquid_exchange: None,
+                        rate_limiter: RateLimiter::from_env(dex_name),
+                    })
+                } else {
+                    Ok(DexConnectorBox {
+                        dex_name: dex_name.to_owned(),
+                        inner: Box::new(connector),
+                        symbols,
+                        hyperliquid_info: None,
+                        hyperliquid_exchange: None,
+                        rate_limiter: RateLimiter::from_env(dex_name),
                     })
                 }
             }
             _ => Err(DexError::Other("Unsupported dex".to_owned())),
         }
     }
+
+    // The emulator over no venue, for the replay of saved prices
+    pub fn replay(
+        dex_name: &str,
+        fill_settings: EmulatorFillSettings,
+        random_seed: Option<u64>,
+    ) -> Self {
+        let dex_emulator = DexEmulator::new(
+            ReplayVenue,
+            *FILLED_PROBABILITY_IN_EMULATION,
+            fill_settings,
+            random_seed,
+        );
+        DexConnectorBox {
+            dex_name: dex_name.to_owned(),
+            inner: Box::new(dex_emulator),
+            symbols: SymbolMapper::for_connector(dex_name),
+            hyperliquid_info: None,
+            hyperliquid_exchange: None,
+            rate_limiter: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_connector(dex_name: &str, inner: Box<dyn DexConnectorExt>) -> Self {
+        DexConnectorBox {
+            dex_name: dex_name.to_owned(),
+            inner,
+            symbols: SymbolMapper::for_connector(dex_name),
+            hyperliquid_info: None,
+            hyperliquid_exchange: None,
+            rate_limiter: None,
+        }
+    }
+
+    // Reads the book and the account from the info API at `rest_endpoint`
+    #[cfg(test)]
+    pub fn with_hyperliquid_info(mut self, rest_endpoint: &str, dry_run: bool) -> Self {
+        self.hyperliquid_info = Some(HyperliquidInfo::new(
+            rest_endpoint,
+            format!("{:#042x}", 1),
+            dry_run,
+        ));
+        self
+    }
+
+    // Sends the bulk cancels of the given coins to `exchange_url` instead of the venue
+    #[cfg(test)]
+    pub async fn with_hyperliquid_exchange(mut self, exchange_url: &str, coins: &[&str]) -> Self {
+        let meta = hyperliquid_rust_sdk::Meta {
+            universe: coins
+                .iter()
+                .map(|coin| hyperliquid_rust_sdk::AssetMeta {
+                    name: coin.to_string(),
+                    sz_decimals: 0,
+                })
+                .collect(),
+        };
+        let wallet: LocalWallet = format!("{:064x}", 1).parse().unwrap();
+        let mut client =
+            ExchangeClient::new(None, wallet, Some(BaseUrl::Localhost), Some(meta), None)
+                .await
+                .unwrap();
+        client.http_client.base_url = exchange_url.to_owned();
+        self.hyperliquid_exchange = Some(HyperliquidExchange { client });
+        self
+    }
 }
 
 #[async_trait]
@@ -84,7 +650,10 @@ impl DexConnector for DexConnectorBox {
     }
 
     async fn set_leverage(&self, symbol: &str, leverage: u32) -> Result<(), DexError> {
-        self.inner.set_leverage(symbol, leverage).await
+        self.throttle().await;
+        self.inner
+            .set_leverage(&self.symbols.to_venue(symbol), leverage)
+            .await
     }
 
     async fn get_ticker(
@@ -92,19 +661,31 @@ impl DexConnector for DexConnectorBox {
         symbol: &str,
         test_price: Option<Decimal>,
     ) -> Result<TickerResponse, DexError> {
-        self.inner.get_ticker(symbol, test_price).await
+        self.throttle().await;
+        let mut res = self
+            .inner
+            .get_ticker(&self.symbols.to_venue(symbol), test_price)
+            .await?;
+        res.symbol = symbol.to_owned();
+        Ok(res)
     }
 
     async fn get_filled_orders(&self, symbol: &str) -> Result<FilledOrdersResponse, DexError> {
-        self.inner.get_filled_orders(symbol).await
+        self.throttle().await;
+        self.inner
+            .get_filled_orders(&self.symbols.to_venue(symbol))
+            .await
     }
 
     async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+        self.throttle().await;
         self.inner.get_balance().await
     }
 
     async fn clear_filled_order(&self, symbol: &str, trade_id: &str) -> Result<(), DexError> {
-        self.inner.clear_filled_order(symbol, trade_id).await
+        self.inner
+            .clear_filled_order(&self.symbols.to_venue(symbol), trade_id)
+            .await
     }
 
     async fn clear_all_filled_order(&self) -> Result<(), DexError> {
@@ -119,24 +700,839 @@ impl DexConnector for DexConnectorBox {
         price: Option<Decimal>,
         spread: Option<i64>,
     ) -> Result<CreateOrderResponse, DexError> {
+        self.throttle().await;
         self.inner
-            .create_order(symbol, size, side, price, spread)
+            .create_order(&self.symbols.to_venue(symbol), size, side, price, spread)
             .await
     }
 
     async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<(), DexError> {
-        self.inner.cancel_order(symbol, order_id).await
+        self.throttle().await;
+        self.inner
+            .cancel_order(&self.symbols.to_venue(symbol), order_id)
+            .await
     }
 
     async fn cancel_all_orders(&self, symbol: Option<String>) -> Result<(), DexError> {
-        self.inner.cancel_all_orders(symbol).await
+        self.throttle().await;
+        self.inner
+            .cancel_all_orders(symbol.map(|symbol| self.symbols.to_venue(&symbol)))
+            .await
     }
 
     async fn close_all_positions(&self, symbol: Option<String>) -> Result<(), DexError> {
-        self.inner.close_all_positions(symbol).await
+        self.throttle().await;
+        self.inner
+            .close_all_positions(symbol.map(|symbol| self.symbols.to_venue(&symbol)))
+            .await
     }
 
     async fn clear_last_trades(&self, symbol: &str) -> Result<(), DexError> {
-        self.inner.clear_last_trades(symbol).await
+        self.inner
+            .clear_last_trades(&self.symbols.to_venue(symbol))
+            .await
+    }
+}
+
+#[async_trait]
+impl DexConnectorExt for DexConnectorBox {
+    async fn cancel_orders(&self, symbol: &str, order_ids: &[String]) -> Result<usize, DexError> {
+        self.throttle().await;
+        match &self.hyperliquid_exchange {
+            Some(exchange) => exchange.cancel_orders(symbol, order_ids).await,
+            None => {
+                self.inner
+                    .cancel_orders(&self.symbols.to_venue(symbol), order_ids)
+                    .await
+            }
+        }
+    }
+
+    async fn create_order_with_tif(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        spread: Option<i64>,
+        time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        self.throttle().await;
+        match (&self.hyperliquid_exchange, price, time_in_force) {
+            (Some(exchange), Some(price), TimeInForce::Ioc) => {
+                let min_tick = self
+                    .inner
+                    .get_ticker(&self.symbols.to_venue(symbol), None)
+                    .await?
+                    .min_tick;
+                exchange
+                    .create_ioc_order(symbol, size, &side, price, min_tick)
+                    .await
+            }
+            _ => {
+                self.inner
+                    .create_order_with_tif(
+                        &self.symbols.to_venue(symbol),
+                        size,
+                        side,
+                        price,
+                        spread,
+                        time_in_force,
+                    )
+                    .await
+            }
+        }
+    }
+
+    async fn get_orderbook(&self, symbol: &str, depth: usize) -> Result<OrderBook, DexError> {
+        self.throttle().await;
+        match &self.hyperliquid_info {
+            Some(info) => {
+                let coin = HYPERLIQUID_SYMBOLS.to_venue(symbol);
+                let res = info
+                    .post(serde_json::json!({ "type": "l2Book", "coin": coin }))
+                    .await?;
+                Ok(Self::parse_hyperliquid_orderbook(&res, depth))
+            }
+            None => {
+                self.inner
+                    .get_orderbook(&self.symbols.to_venue(symbol), depth)
+                    .await
+            }
+        }
+    }
+
+    async fn get_open_order_symbols(&self) -> Result<Vec<String>, DexError> {
+        self.throttle().await;
+        match self.hyperliquid_account() {
+            Some((info, user)) => {
+                let res = info
+                    .post(serde_json::json!({ "type": "openOrders", "user": user }))
+                    .await?;
+                Ok(Self::parse_hyperliquid_open_order_symbols(&res))
+            }
+            None => {
+                let symbols = self.inner.get_open_order_symbols().await?;
+                Ok(symbols
+                    .iter()
+                    .map(|symbol| self.symbols.to_internal(symbol))
+                    .collect())
+            }
+        }
+    }
+
+    async fn get_open_order_ids(&self, symbol: &str) -> Result<Vec<String>, DexError> {
+        self.throttle().await;
+        match self.hyperliquid_account() {
+            Some((info, user)) => {
+                let res = info
+                    .post(serde_json::json!({ "type": "openOrders", "user": user }))
+                    .await?;
+                Ok(Self::parse_hyperliquid_open_order_ids(
+                    &res,
+                    &HYPERLIQUID_SYMBOLS.to_venue(symbol),
+                ))
+            }
+            None => {
+                self.inner
+                    .get_open_order_ids(&self.symbols.to_venue(symbol))
+                    .await
+            }
+        }
+    }
+
+    async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+        self.throttle().await;
+        match self.hyperliquid_account() {
+            Some((info, user)) => {
+                let res = info
+                    .post(serde_json::json!({ "type": "clearinghouseState", "user": user }))
+                    .await?;
+                Ok(Self::parse_hyperliquid_positions(&res))
+            }
+            None => {
+                let positions = self.inner.get_positions().await?;
+                Ok(positions
+                    .into_iter()
+                    .map(|(symbol, size)| (self.symbols.to_internal(&symbol), size))
+                    .collect())
+            }
+        }
+    }
+
+    async fn get_margin_info(&self) -> Result<MarginInfo, DexError> {
+        self.throttle().await;
+        match self.hyperliquid_account() {
+            Some((info, user)) => {
+                let res = info
+                    .post(serde_json::json!({ "type": "clearinghouseState", "user": user }))
+                    .await?;
+                Self::parse_hyperliquid_margin_info(&res)
+            }
+            None => self.inner.get_margin_info().await,
+        }
+    }
+}
+
+impl HyperliquidInfo {
+    fn new(rest_endpoint: &str, user: String, dry_run: bool) -> Self {
+        HyperliquidInfo {
+            endpoint: format!("{}/info", rest_endpoint.trim_end_matches('/')),
+            user: if dry_run { None } else { Some(user) },
+        }
+    }
+
+    async fn post(&self, payload: serde_json::Value) -> Result<serde_json::Value, DexError> {
+        let request_type = payload["type"].clone();
+        reqwest::Client::new()
+            .post(&self.endpoint)
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| DexError::Other(format!("{}: {:?}", request_type, e)))?
+            .json()
+            .await
+            .map_err(|e| DexError::Other(format!("{}: {:?}", request_type, e)))
+    }
+}
+
+impl HyperliquidExchange {
+    // The requests, the asset meta and the network the actions are signed for all follow
+    // `rest_endpoint`, so that a testnet run never reaches mainnet
+    async fn new(
+        rest_endpoint: &str,
+        agent_private_key: &str,
+        vault_address: Option<&str>,
+    ) -> Result<Self, DexError> {
+        let wallet: LocalWallet = agent_private_key
+            .parse()
+            .map_err(|e| DexError::Other(format!("agent private key: {:?}", e)))?;
+        let vault_address = vault_address.and_then(|v| v.parse::<H160>().ok());
+        let endpoint = rest_endpoint.trim_end_matches('/');
+        let base_url = Self::base_url(endpoint)?;
+
+        let mut info = InfoClient::new(None, Some(base_url))
+            .await
+            .map_err(|e| DexError::Other(e.to_string()))?;
+        info.http_client.base_url = endpoint.to_owned();
+        let meta = info
+            .meta()
+            .await
+            .map_err(|e| DexError::Other(format!("meta: {}", e)))?;
+
+        let mut client =
+            ExchangeClient::new(None, wallet, Some(base_url), Some(meta), vault_address)
+                .await
+                .map_err(|e| DexError::Other(e.to_string()))?;
+        client.http_client.base_url = endpoint.to_owned();
+        Ok(Self { client })
+    }
+
+    // The SDK signs for mainnet only when it posts to the mainnet URL. An endpoint of
+    // neither network is refused rather than taken for mainnet.
+    fn base_url(endpoint: &str) -> Result<BaseUrl, DexError> {
+        if endpoint == MAINNET_API_URL {
+            Ok(BaseUrl::Mainnet)
+        } else if endpoint == TESTNET_API_URL {
+            Ok(BaseUrl::Testnet)
+        } else if endpoint == LOCAL_API_URL
+            || endpoint.starts_with("http://localhost:")
+            || endpoint.starts_with("http://127.0.0.1:")
+        {
+            Ok(BaseUrl::Localhost)
+        } else {
+            Err(DexError::Other(format!(
+                "unknown Hyperliquid endpoint: {}",
+                endpoint
+            )))
+        }
+    }
+
+    async fn create_ioc_order(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: &OrderSide,
+        price: Decimal,
+        min_tick: Option<Decimal>,
+    ) -> Result<CreateOrderResponse, DexError> {
+        let coin = HYPERLIQUID_SYMBOLS.to_venue(symbol);
+        let sz_decimals = self
+            .client
+            .meta
+            .universe
+            .iter()
+            .find(|asset| asset.name == coin)
+            .map(|asset| asset.sz_decimals)
+            .ok_or_else(|| DexError::Other(format!("symbol meta is not available: {}", coin)))?;
+        // As HyperliquidConnector does, the price is rounded away from the touch
+        let price = match min_tick {
+            Some(min_tick) => match side {
+                OrderSide::Long => (price / min_tick).floor() * min_tick,
+                OrderSide::Short => (price / min_tick).ceil() * min_tick,
+            },
+            None => price,
+        };
+        let size = size.round_dp(sz_decimals);
+
+        let order = ClientOrderRequest {
+            asset: coin,
+            is_buy: *side == OrderSide::Long,
+            reduce_only: false,
+            limit_px: price
+                .to_f64()
+                .ok_or_else(|| DexError::Other("Conversion to f64 failed".to_owned()))?,
+            sz: size
+                .to_f64()
+                .ok_or_else(|| DexError::Other("Conversion to f64 failed".to_owned()))?,
+            order_type: ClientOrder::Limit(ClientLimit {
+                tif: "Ioc".to_owned(),
+            }),
+        };
+        let res = self
+            .client
+            .order(order, None)
+            .await
+            .map_err(|e| DexError::Other(e.to_string()))?;
+        let order_id = Self::parse_order_response(res)?;
+        Ok(CreateOrderResponse {
+            order_id: order_id.to_string(),
+            ordered_price: price,
+            ordered_size: size,
+        })
+    }
+
+    // An IOC order that matches nothing is an error of its status
+    fn parse_order_response(res: ExchangeResponseStatus) -> Result<u64, DexError> {
+        let res = match res {
+            ExchangeResponseStatus::Ok(res) => res,
+            ExchangeResponseStatus::Err(e) => return Err(DexError::ServerResponse(e)),
+        };
+        match res.data.and_then(|data| data.statuses.into_iter().next()) {
+            Some(ExchangeDataStatus::Filled(order)) => Ok(order.oid),
+            Some(ExchangeDataStatus::Resting(order)) => Ok(order.oid),
+            Some(ExchangeDataStatus::Error(e)) => Err(DexError::ServerResponse(e)),
+            status => Err(DexError::ServerResponse(format!(
+                "unexpected order status: {:?}",
+                status
+            ))),
+        }
+    }
+
+    // All the orders go in one signed request
+    async fn cancel_orders(&self, symbol: &str, order_ids: &[String]) -> Result<usize, DexError> {
+        let cancels = Self::cancel_requests(symbol, order_ids)?;
+        let res = self
+            .client
+            .bulk_cancel(cancels, None)
+            .await
+            .map_err(|e| DexError::Other(e.to_string()))?;
+        Self::parse_cancel_response(res)?;
+        Ok(1)
+    }
+
+    fn cancel_requests(
+        symbol: &str,
+        order_ids: &[String],
+    ) -> Result<Vec<ClientCancelRequest>, DexError> {
+        let asset = HYPERLIQUID_SYMBOLS.to_venue(symbol);
+        order_ids
+            .iter()
+            .map(|order_id| {
+                let oid = order_id
+                    .parse::<u64>()
+                    .map_err(|_| DexError::Other(format!("invalid order id: {}", order_id)))?;
+                Ok(ClientCancelRequest {
+                    asset: asset.clone(),
+                    oid,
+                })
+            })
+            .collect()
+    }
+
+    // A cancel that fails for one of the orders fails the batch, so that the caller
+    // cancels the orders one by one
+    fn parse_cancel_response(res: ExchangeResponseStatus) -> Result<(), DexError> {
+        let res = match res {
+            ExchangeResponseStatus::Ok(res) => res,
+            ExchangeResponseStatus::Err(e) => return Err(DexError::Other(e)),
+        };
+        let errors: Vec<String> = res
+            .data
+            .map(|data| data.statuses)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|status| match status {
+                ExchangeDataStatus::Error(e) => Some(e),
+                _ => None,
+            })
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(DexError::Other(errors.join(", ")))
+        }
+    }
+}
+
+impl DexConnectorBox {
+    pub fn dex_name(&self) -> &str {
+        &self.dex_name
+    }
+
+    async fn throttle(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+
+    // The info API with the account it is asked about, if the account is on the venue
+    fn hyperliquid_account(&self) -> Option<(&HyperliquidInfo, &str)> {
+        let info = self.hyperliquid_info.as_ref()?;
+        Some((info, info.user.as_deref()?))
+    }
+
+    fn parse_hyperliquid_open_order_ids(res: &serde_json::Value, coin: &str) -> Vec<String> {
+        res.as_array()
+            .map(|orders| {
+                orders
+                    .iter()
+                    .filter(|order| order["coin"].as_str() == Some(coin))
+                    .filter_map(|order| order["oid"].as_u64().map(|oid| oid.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn parse_hyperliquid_open_order_symbols(res: &serde_json::Value) -> Vec<String> {
+        let mut symbols: Vec<String> = res
+            .as_array()
+            .map(|orders| {
+                orders
+                    .iter()
+                    .filter_map(|order| {
+                        order["coin"]
+                            .as_str()
+                            .map(|coin| HYPERLIQUID_SYMBOLS.to_internal(coin))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    fn parse_hyperliquid_positions(res: &serde_json::Value) -> Vec<(String, Decimal)> {
+        res["assetPositions"]
+            .as_array()
+            .map(|positions| {
+                positions
+                    .iter()
+                    .filter_map(|position| {
+                        let position = &position["position"];
+                        let size = position["szi"].as_str()?.parse::<Decimal>().ok()?;
+                        if size.is_zero() {
+                            return None;
+                        }
+                        let coin = position["coin"].as_str()?;
+                        Some((HYPERLIQUID_SYMBOLS.to_internal(coin), size))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn parse_hyperliquid_margin_info(res: &serde_json::Value) -> Result<MarginInfo, DexError> {
+        let decimal = |value: &serde_json::Value| -> Result<Decimal, DexError> {
+            value
+                .as_str()
+                .and_then(|value| value.parse::<Decimal>().ok())
+                .ok_or_else(|| DexError::Other(format!("invalid margin: {}", res)))
+        };
+        Ok(MarginInfo {
+            equity: decimal(&res["marginSummary"]["accountValue"])?,
+            used_margin: decimal(&res["marginSummary"]["totalMarginUsed"])?,
+            maintenance_margin: decimal(&res["crossMaintenanceMarginUsed"])?,
+        })
+    }
+
+    fn parse_hyperliquid_orderbook(res: &serde_json::Value, depth: usize) -> OrderBook {
+        let levels = |index: usize| -> Vec<(Decimal, Decimal)> {
+            res["levels"][index]
+                .as_array()
+                .map(|levels| {
+                    levels
+                        .iter()
+                        .take(depth)
+                        .filter_map(|level| {
+                            let price = level["px"].as_str()?.parse::<Decimal>().ok()?;
+                            let size = level["sz"].as_str()?.parse::<Decimal>().ok()?;
+                            Some((price, size))
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        OrderBook {
+            bids: levels(0),
+            asks: levels(1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock_dex::{start_hyperliquid_exchange, Mock, MockDex};
+    use super::*;
+    use std::sync::Arc;
+
+    fn order_book() -> OrderBook {
+        OrderBook {
+            bids: vec![
+                (Decimal::new(99, 0), Decimal::new(1, 0)),
+                (Decimal::new(98, 0), Decimal::new(2, 0)),
+            ],
+            asks: vec![
+                (Decimal::new(100, 0), Decimal::new(1, 0)),
+                (Decimal::new(102, 0), Decimal::new(1, 0)),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_estimate_slippage() {
+        let book = order_book();
+        assert_eq!(
+            book.estimate_slippage(&OrderSide::Long, Decimal::new(1, 0)),
+            Some(Decimal::ZERO)
+        );
+        // (100 + 102) / 2 = 101 against 100
+        assert_eq!(
+            book.estimate_slippage(&OrderSide::Long, Decimal::new(2, 0)),
+            Some(Decimal::new(1, 2))
+        );
+        // Not deep enough
+        assert_eq!(
+            book.estimate_slippage(&OrderSide::Long, Decimal::new(3, 0)),
+            None
+        );
+        assert_eq!(
+            book.spread_edge(&OrderSide::Short),
+            Some(Decimal::new(99, 0))
+        );
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket {
+            rate_per_sec: 10.0,
+            burst: 10.0,
+            tokens: 10.0,
+            last_refill: start,
+        };
+
+        // A burst of 100 at once: 10 right away, the last one after 9 seconds
+        let waits: Vec<Duration> = (0..100).map(|_| bucket.reserve(start)).collect();
+        assert!(waits[..10].iter().all(|wait| wait.is_zero()));
+        assert!(waits[10] > Duration::ZERO);
+        let last = waits[99].as_secs_f64();
+        assert!((8.99..9.01).contains(&last), "{}", last);
+
+        // Refilled after a quiet period, up to the burst only
+        let later = start + Duration::from_secs(60);
+        let waits: Vec<Duration> = (0..11).map(|_| bucket.reserve(later)).collect();
+        assert!(waits[..10].iter().all(|wait| wait.is_zero()));
+        assert!(waits[10] > Duration::ZERO);
+    }
+
+    #[test]
+    fn test_symbol_mapper() {
+        let overrides = vec![("PEPE-USD".to_owned(), "kPEPE".to_owned())];
+        let hyperliquid = SymbolMapper::new("{}", overrides);
+        let bybit = SymbolMapper::new("{}USDT", vec![]);
+        let emulator = SymbolMapper::for_dex("emulator");
+
+        for symbol in ["BTC-USD", "ETH-USD", "PEPE-USD"] {
+            assert_eq!(
+                hyperliquid.to_internal(&hyperliquid.to_venue(symbol)),
+                symbol
+            );
+            assert_eq!(bybit.to_internal(&bybit.to_venue(symbol)), symbol);
+            // The emulator keeps the internal names
+            assert_eq!(emulator.to_venue(symbol), symbol);
+            assert_eq!(emulator.to_internal(symbol), symbol);
+        }
+
+        assert_eq!(hyperliquid.to_venue("BTC-USD"), "BTC");
+        // Differs between the venues
+        assert_eq!(hyperliquid.to_venue("PEPE-USD"), "kPEPE");
+        assert_eq!(bybit.to_venue("PEPE-USD"), "PEPEUSDT");
+        assert_eq!(hyperliquid.to_internal("kPEPE"), "PEPE-USD");
+
+        // HyperliquidConnector names its markets after the coins
+        let connector = hyperliquid.connector_symbols("hyperliquid");
+        assert_eq!(connector.to_venue("BTC-USD"), "BTC-USD");
+        assert_eq!(connector.to_venue("PEPE-USD"), "kPEPE-USD");
+        assert_eq!(connector.to_internal("kPEPE-USD"), "PEPE-USD");
+        assert_eq!(bybit.clone().connector_symbols("bybit"), bybit);
+    }
+
+    // Keeps the symbols of the calls it receives
+    #[derive(Default)]
+    struct SymbolRecorder {
+        symbols: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl SymbolRecorder {
+        fn record(&self, symbol: &str) {
+            self.symbols.lock().unwrap().push(symbol.to_owned());
+        }
+    }
+
+    #[async_trait]
+    impl MockDex for SymbolRecorder {
+        async fn set_leverage(&self, symbol: &str, _leverage: u32) -> Result<(), DexError> {
+            self.record(symbol);
+            Ok(())
+        }
+
+        async fn get_ticker(&self, symbol: &str) -> Result<TickerResponse, DexError> {
+            self.record(symbol);
+            Ok(TickerResponse {
+                symbol: symbol.to_owned(),
+                ..Default::default()
+            })
+        }
+
+        async fn get_filled_orders(&self, symbol: &str) -> Result<FilledOrdersResponse, DexError> {
+            self.record(symbol);
+            Ok(FilledOrdersResponse { orders: vec![] })
+        }
+
+        async fn create_order(
+            &self,
+            symbol: &str,
+            size: Decimal,
+            _side: OrderSide,
+            price: Option<Decimal>,
+            _time_in_force: TimeInForce,
+        ) -> Result<CreateOrderResponse, DexError> {
+            self.record(symbol);
+            Ok(CreateOrderResponse {
+                order_id: "1".to_owned(),
+                ordered_price: price.unwrap_or_default(),
+                ordered_size: size,
+            })
+        }
+
+        async fn cancel_order(&self, symbol: &str, _order_id: &str) -> Result<(), DexError> {
+            self.record(symbol);
+            Ok(())
+        }
+
+        async fn close_all_positions(&self, symbol: Option<String>) -> Result<(), DexError> {
+            self.record(&symbol.unwrap_or_default());
+            Ok(())
+        }
+
+        async fn get_open_order_symbols(&self) -> Result<Vec<String>, DexError> {
+            Ok(vec!["kPEPE-USD".to_owned()])
+        }
+
+        async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+            Ok(vec![("kPEPE-USD".to_owned(), Decimal::ONE)])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_every_call_is_translated_to_the_connector_symbols() {
+        let recorder = SymbolRecorder::default();
+        let symbols = recorder.symbols.clone();
+        let mut dex = DexConnectorBox::from_connector("hyperliquid", Box::new(Mock(recorder)));
+        dex.symbols = SymbolMapper::new("{}", vec![("PEPE-USD".to_owned(), "kPEPE".to_owned())])
+            .connector_symbols("hyperliquid");
+
+        let symbol = "PEPE-USD";
+        dex.set_leverage(symbol, 2).await.unwrap();
+        let ticker = dex.get_ticker(symbol, None).await.unwrap();
+        dex.get_filled_orders(symbol).await.unwrap();
+        dex.create_order(symbol, Decimal::ONE, OrderSide::Long, None, None)
+            .await
+            .unwrap();
+        dex.create_order_with_tif(
+            symbol,
+            Decimal::ONE,
+            OrderSide::Short,
+            Some(Decimal::ONE),
+            None,
+            TimeInForce::Gtc,
+        )
+        .await
+        .unwrap();
+        dex.cancel_order(symbol, "1").await.unwrap();
+        dex.cancel_orders(symbol, &["1".to_owned(), "2".to_owned()])
+            .await
+            .unwrap();
+        dex.close_all_positions(Some(symbol.to_owned()))
+            .await
+            .unwrap();
+
+        let symbols = symbols.lock().unwrap().clone();
+        assert_eq!(symbols.len(), 9);
+        assert!(symbols.iter().all(|symbol| symbol == "kPEPE-USD"));
+        // and back to the internal ones
+        assert_eq!(ticker.symbol, symbol);
+        assert_eq!(
+            dex.get_open_order_symbols().await.unwrap(),
+            vec![symbol.to_owned()]
+        );
+        assert_eq!(dex.get_position_symbols().await.unwrap(), vec![symbol]);
+    }
+
+    #[test]
+    fn test_parse_hyperliquid_account_state() {
+        let open_orders = serde_json::json!([
+            { "coin": "BTC", "oid": 1 },
+            { "coin": "ETH", "oid": 2 },
+            { "coin": "BTC", "oid": 3 },
+        ]);
+        assert_eq!(
+            DexConnectorBox::parse_hyperliquid_open_order_symbols(&open_orders),
+            vec!["BTC-USD".to_owned(), "ETH-USD".to_owned()]
+        );
+        assert_eq!(
+            DexConnectorBox::parse_hyperliquid_open_order_ids(&open_orders, "BTC"),
+            vec!["1".to_owned(), "3".to_owned()]
+        );
+
+        let state = serde_json::json!({
+            "assetPositions": [
+                { "position": { "coin": "BTC", "szi": "-0.01" } },
+                { "position": { "coin": "ETH", "szi": "0.0" } },
+            ]
+        });
+        assert_eq!(
+            DexConnectorBox::parse_hyperliquid_positions(&state),
+            vec![("BTC-USD".to_owned(), Decimal::new(-1, 2))]
+        );
+
+        let state = serde_json::json!({
+            "marginSummary": { "accountValue": "1000.5", "totalMarginUsed": "200.0" },
+            "crossMaintenanceMarginUsed": "50.0",
+        });
+        let margin_info = DexConnectorBox::parse_hyperliquid_margin_info(&state).unwrap();
+        assert_eq!(
+            margin_info,
+            MarginInfo {
+                equity: Decimal::new(10005, 1),
+                used_margin: Decimal::new(200, 0),
+                maintenance_margin: Decimal::new(50, 0),
+            }
+        );
+        assert_eq!(margin_info.margin_ratio(), Some(Decimal::new(2001, 2)));
+        assert!(DexConnectorBox::parse_hyperliquid_margin_info(&serde_json::json!({})).is_err());
+    }
+
+    #[test]
+    fn test_hyperliquid_bulk_cancel_fails_on_any_order() {
+        let cancels =
+            HyperliquidExchange::cancel_requests("BTC-USD", &["11".to_owned(), "12".to_owned()])
+                .unwrap();
+        assert_eq!(cancels.len(), 2);
+        assert!(cancels.iter().all(|cancel| cancel.asset == "BTC"));
+        assert_eq!(cancels[1].oid, 12);
+        assert!(HyperliquidExchange::cancel_requests("BTC-USD", &["x".to_owned()]).is_err());
+
+        let response = |statuses: serde_json::Value| {
+            serde_json::from_value::<ExchangeResponseStatus>(serde_json::json!({
+                "status": "ok",
+                "response": { "type": "cancel", "data": { "statuses": statuses } }
+            }))
+            .unwrap()
+        };
+        assert!(
+            HyperliquidExchange::parse_cancel_response(response(serde_json::json!([
+                "success", "success"
+            ])))
+            .is_ok()
+        );
+        assert!(
+            HyperliquidExchange::parse_cancel_response(response(serde_json::json!([
+                "success",
+                { "error": "Order was never placed, already canceled, or filled." }
+            ])))
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_hyperliquid_network_follows_the_endpoint() {
+        let base_url = |endpoint| HyperliquidExchange::base_url(endpoint).unwrap();
+        assert!(matches!(
+            base_url("https://api.hyperliquid.xyz"),
+            BaseUrl::Mainnet
+        ));
+        assert!(matches!(
+            base_url("https://api.hyperliquid-testnet.xyz"),
+            BaseUrl::Testnet
+        ));
+        assert!(matches!(
+            base_url("http://127.0.0.1:3001"),
+            BaseUrl::Localhost
+        ));
+        assert!(HyperliquidExchange::base_url("https://api.hyperliquid.example").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_hyperliquid_ioc_orders_go_to_the_configured_endpoint() {
+        let (exchange_url, actions) = start_hyperliquid_exchange();
+        let exchange = HyperliquidExchange::new(&exchange_url, &format!("{:064x}", 1), None)
+            .await
+            .unwrap();
+        assert_eq!(exchange.client.http_client.base_url, exchange_url);
+
+        let res = exchange
+            .create_ioc_order(
+                "BTC-USD",
+                Decimal::ONE,
+                &OrderSide::Long,
+                Decimal::new(100, 0),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.order_id, "1");
+
+        // BTC is the second asset of the meta of the endpoint
+        let actions = actions.lock().unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["orders"][0]["a"], 1);
+        assert_eq!(
+            actions[0]["orders"][0]["t"],
+            serde_json::json!({ "limit": { "tif": "Ioc" } })
+        );
+    }
+
+    #[test]
+    fn test_unmatched_hyperliquid_ioc_order_is_an_error() {
+        let response = |status: serde_json::Value| {
+            serde_json::from_value::<ExchangeResponseStatus>(serde_json::json!({
+                "status": "ok",
+                "response": { "type": "order", "data": { "statuses": [status] } }
+            }))
+            .unwrap()
+        };
+        let filled = serde_json::json!({ "filled": { "totalSz": "1", "avgPx": "100", "oid": 7 } });
+        assert_eq!(
+            HyperliquidExchange::parse_order_response(response(filled)).unwrap(),
+            7
+        );
+        let unmatched = serde_json::json!({
+            "error": "Order could not immediately match against any resting orders."
+        });
+        assert!(HyperliquidExchange::parse_order_response(response(unmatched)).is_err());
     }
 }
@@ -10,11 +10,14 @@ use num::FromPrimitive;
 use rand::{rngs::StdRng, Rng, SeedableRng};
 use rust_decimal::{Decimal, RoundingStrategy};
 
+use super::dex_connector_box::TimeInForce;
+
 struct OrderBook {
     price: Option<Decimal>,
     size: Decimal,
     order_id: u32,
     partially_filled: bool,
+    time_in_force: TimeInForce,
 }
 
 struct OrderBooks {
@@ -22,38 +25,181 @@ struct OrderBooks {
     sell_order_books: Arc<Mutex<Vec<OrderBook>>>,
 }
 
+// The slippage of the fills and the fees they pay, in bps
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EmulatorFillSettings {
+    pub slippage_bps: Decimal,
+    pub taker_fee_bps: Decimal,
+    pub maker_fee_bps: Decimal,
+}
+
+impl EmulatorFillSettings {
+    fn ratio(bps: Decimal) -> Decimal {
+        bps / Decimal::new(10000, 0)
+    }
+
+    fn slippage(&self) -> Decimal {
+        Self::ratio(self.slippage_bps)
+    }
+
+    // Market orders take, limit orders make
+    fn fee(&self, is_market_order: bool) -> Decimal {
+        Self::ratio(if is_market_order {
+            self.taker_fee_bps
+        } else {
+            self.maker_fee_bps
+        })
+    }
+}
+
 pub struct DexEmulator<T: DexConnector> {
     dex_connector: T,
     filled_probability: Decimal,
-    slippage: Decimal,
+    fill_settings: EmulatorFillSettings,
     order_books: Arc<Mutex<HashMap<String, OrderBooks>>>,
     order_id_counter: Arc<Mutex<u32>>,
+    // Each fill has its own trade id, in the order of the fills
+    trade_id_counter: Arc<Mutex<u64>>,
     current_price: Arc<Mutex<HashMap<String, Decimal>>>,
+    rng: Arc<Mutex<StdRng>>,
 }
 
 impl<T: DexConnector> DexEmulator<T> {
-    pub fn new(dex_connector: T, filled_probability: Decimal, slippage: Decimal) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new(
+        dex_connector: T,
+        filled_probability: Decimal,
+        fill_settings: EmulatorFillSettings,
+        random_seed: Option<u64>,
+    ) -> Self {
+        let mut rng = Self::create_rng(random_seed);
         let order_id_counter = rng.gen_range(1..=std::u32::MAX);
 
         DexEmulator {
             dex_connector,
             filled_probability,
-            slippage,
+            fill_settings,
             order_books: Arc::new(Mutex::new(HashMap::new())),
             order_id_counter: Arc::new(Mutex::new(order_id_counter)),
+            trade_id_counter: Arc::new(Mutex::new(0)),
             current_price: Arc::new(Mutex::new(HashMap::new())),
+            rng: Arc::new(Mutex::new(rng)),
+        }
+    }
+
+    fn create_rng(random_seed: Option<u64>) -> StdRng {
+        match random_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    // Market orders fill at the current price moved against the taker by the slippage.
+    // Limit orders fill at their price, but only once the price has crossed it by the slippage.
+    fn fill_price(
+        order_price: Option<Decimal>,
+        current_price: Decimal,
+        is_buy_order: bool,
+        slippage: Decimal,
+    ) -> Option<Decimal> {
+        match order_price {
+            None => Some(
+                current_price
+                    * (Decimal::new(1, 0) + if is_buy_order { slippage } else { -slippage }),
+            ),
+            Some(price) => {
+                let is_crossed = if is_buy_order {
+                    current_price <= price * (Decimal::new(1, 0) - slippage)
+                } else {
+                    current_price >= price * (Decimal::new(1, 0) + slippage)
+                };
+                if is_crossed {
+                    Some(price)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub async fn place_order(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        let mut order_id_counter = self.order_id_counter.lock().await;
+        *order_id_counter += 1;
+        let order_id = *order_id_counter;
+        drop(order_id_counter); // Explicitly drop the lock
+
+        let size = size.round_dp_with_strategy(5, RoundingStrategy::ToZero);
+        let price = price.map(|v| v.round_dp_with_strategy(5, RoundingStrategy::ToZero));
+
+        let order_book = OrderBook {
+            price,
+            size,
+            order_id,
+            partially_filled: false,
+            time_in_force,
+        };
+
+        let mut order_books = self.order_books.lock().await;
+        let order_books_entry =
+            order_books
+                .entry(symbol.to_string())
+                .or_insert_with(|| OrderBooks {
+                    buy_order_books: Arc::new(Mutex::new(Vec::new())),
+                    sell_order_books: Arc::new(Mutex::new(Vec::new())),
+                });
+
+        if side == OrderSide::Long {
+            let mut buy_order_books = order_books_entry.buy_order_books.lock().await;
+            buy_order_books.push(order_book);
+        } else {
+            // Assuming side can only be Buy or Sell
+            let mut sell_order_books = order_books_entry.sell_order_books.lock().await;
+            sell_order_books.push(order_book);
+        }
+
+        Ok(CreateOrderResponse {
+            order_id: order_id.to_string(),
+            ordered_price: price.unwrap_or_default(),
+            ordered_size: size,
+        })
+    }
+
+    pub async fn open_order_ids(&self, symbol: &str) -> Vec<String> {
+        let order_books = self.order_books.lock().await;
+        let order_books_entry = match order_books.get(symbol) {
+            Some(entry) => entry,
+            None => return vec![],
+        };
+        let mut order_ids: Vec<String> = vec![];
+        for books in [
+            &order_books_entry.buy_order_books,
+            &order_books_entry.sell_order_books,
+        ] {
+            order_ids.extend(
+                books
+                    .lock()
+                    .await
+                    .iter()
+                    .map(|order_book| order_book.order_id.to_string()),
+            );
         }
+        order_ids
     }
 
     async fn process_order_book(
         order_books: &mut Vec<OrderBook>,
         current_price: Decimal,
-        filled_orders: &mut Vec<(u32, Decimal, Decimal, OrderSide)>,
+        filled_orders: &mut Vec<(u32, Decimal, Decimal, OrderSide, Decimal)>,
         is_buy_order: bool,
         rng: &mut impl Rng,
         filled_probability: Decimal,
-        slippage: Decimal,
+        fill_settings: &EmulatorFillSettings,
     ) {
         order_books.retain_mut(|order_book| {
             let fill = if order_book.partially_filled {
@@ -65,35 +211,39 @@ impl<T: DexConnector> DexEmulator<T> {
                 Decimal::from_f64(rng.gen::<f64>()).unwrap_or(Decimal::new(1, 0)) * order_book.size
             };
 
-            let always_fill_for_market_order = order_book.price.is_none();
-            let adjusted_price = if let Some(price) = order_book.price {
-                price
-            } else {
-                current_price
-                    * (Decimal::new(1, 0) + if is_buy_order { slippage } else { -slippage })
-            };
-
-            let price_condition = if is_buy_order {
-                always_fill_for_market_order || adjusted_price >= current_price
-            } else {
-                always_fill_for_market_order || adjusted_price <= current_price
+            let fee = fill_settings.fee(order_book.price.is_none());
+            let adjusted_price = Self::fill_price(
+                order_book.price,
+                current_price,
+                is_buy_order,
+                fill_settings.slippage(),
+            );
+            let fill = match order_book.time_in_force {
+                TimeInForce::Fok if fill < order_book.size => Decimal::ZERO,
+                _ => fill,
             };
 
-            if price_condition && fill > Decimal::new(0, 0) {
-                filled_orders.push((
-                    order_book.order_id,
-                    fill,
-                    adjusted_price,
-                    if is_buy_order {
-                        OrderSide::Long
-                    } else {
-                        OrderSide::Short
-                    },
-                ));
-                order_book.size -= fill;
+            if let Some(adjusted_price) = adjusted_price {
+                if fill > Decimal::new(0, 0) {
+                    filled_orders.push((
+                        order_book.order_id,
+                        fill,
+                        adjusted_price,
+                        if is_buy_order {
+                            OrderSide::Long
+                        } else {
+                            OrderSide::Short
+                        },
+                        fee,
+                    ));
+                    order_book.size -= fill;
+                }
             }
 
-            order_book.size > Decimal::new(0, 0)
+            // An IOC/FOK limit order is dropped after its first chance to fill
+            let is_immediate =
+                order_book.price.is_some() && order_book.time_in_force != TimeInForce::Gtc;
+            !is_immediate && order_book.size > Decimal::new(0, 0)
         });
     }
 }
@@ -142,7 +292,7 @@ impl<T: DexConnector> DexConnector for DexEmulator<T> {
             }
         };
 
-        let mut rng = StdRng::from_entropy();
+        let mut rng = self.rng.lock().await;
         let order_books = self.order_books.lock().await;
         let order_books_entry = match order_books.get(symbol) {
             Some(entry) => entry,
@@ -162,9 +312,9 @@ impl<T: DexConnector> DexConnector for DexEmulator<T> {
                 current_price,
                 &mut filled_orders,
                 true, // is_buy_order
-                &mut rng,
+                &mut *rng,
                 self.filled_probability,
-                self.slippage,
+                &self.fill_settings,
             )
             .await;
         }
@@ -177,23 +327,27 @@ impl<T: DexConnector> DexConnector for DexEmulator<T> {
                 current_price,
                 &mut filled_orders,
                 false, // is_buy_order
-                &mut rng,
+                &mut *rng,
                 self.filled_probability,
-                self.slippage,
+                &self.fill_settings,
             )
             .await;
         }
 
+        let mut trade_id_counter = self.trade_id_counter.lock().await;
         Ok(FilledOrdersResponse {
             orders: filled_orders
                 .into_iter()
-                .map(|(order_id, size, price, side)| FilledOrder {
+                .map(|(order_id, size, price, side, fee)| FilledOrder {
                     order_id: order_id.to_string(),
-                    trade_id: (order_id + 1000).to_string(),
+                    trade_id: {
+                        *trade_id_counter += 1;
+                        trade_id_counter.to_string()
+                    },
                     filled_side: Some(side),
                     filled_size: Some(size),
                     filled_value: Some(size * price),
-                    filled_fee: Some(size * price * Decimal::new(2, 4)),
+                    filled_fee: Some(size * price * fee),
                     is_rejected: false,
                 })
                 .collect(),
@@ -220,47 +374,8 @@ impl<T: DexConnector> DexConnector for DexEmulator<T> {
         price: Option<Decimal>,
         _spread: Option<i64>,
     ) -> Result<CreateOrderResponse, DexError> {
-        let mut order_id_counter = self.order_id_counter.lock().await;
-        *order_id_counter += 1;
-        let order_id = *order_id_counter;
-        drop(order_id_counter); // Explicitly drop the lock
-
-        let size = size.round_dp_with_strategy(5, RoundingStrategy::ToZero);
-        let price = match price {
-            Some(v) => Some(v.round_dp_with_strategy(5, RoundingStrategy::ToZero)),
-            None => None,
-        };
-
-        let order_book = OrderBook {
-            price,
-            size,
-            order_id,
-            partially_filled: false,
-        };
-
-        let mut order_books = self.order_books.lock().await;
-        let order_books_entry =
-            order_books
-                .entry(symbol.to_string())
-                .or_insert_with(|| OrderBooks {
-                    buy_order_books: Arc::new(Mutex::new(Vec::new())),
-                    sell_order_books: Arc::new(Mutex::new(Vec::new())),
-                });
-
-        if side == OrderSide::Long {
-            let mut buy_order_books = order_books_entry.buy_order_books.lock().await;
-            buy_order_books.push(order_book);
-        } else {
-            // Assuming side can only be Buy or Sell
-            let mut sell_order_books = order_books_entry.sell_order_books.lock().await;
-            sell_order_books.push(order_book);
-        }
-
-        Ok(CreateOrderResponse {
-            order_id: order_id.to_string(),
-            ordered_price: price.unwrap_or_default(),
-            ordered_size: size,
-        })
+        self.place_order(symbol, size, side, price, TimeInForce::Gtc)
+            .await
     }
 
     async fn cancel_order(&self, symbol: &str, order_id_str: &str) -> Result<(), DexError> {
@@ -318,3 +433,103 @@ impl<T: DexConnector> DexConnector for DexEmulator<T> {
         self.dex_connector.clear_last_trades(symbol).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dex_connector::HyperliquidConnector;
+
+    type Emulator = DexEmulator<HyperliquidConnector>;
+
+    #[test]
+    fn test_market_order_fill_price() {
+        let current_price = Decimal::new(100, 0);
+        let slippage = Decimal::new(1, 2);
+
+        assert_eq!(
+            Emulator::fill_price(None, current_price, true, Decimal::ZERO),
+            Some(current_price)
+        );
+        assert_eq!(
+            Emulator::fill_price(None, current_price, true, slippage),
+            Some(Decimal::new(101, 0))
+        );
+        assert_eq!(
+            Emulator::fill_price(None, current_price, false, slippage),
+            Some(Decimal::new(99, 0))
+        );
+    }
+
+    #[test]
+    fn test_seeded_rng_is_reproducible() {
+        let mut a = Emulator::create_rng(Some(42));
+        let mut b = Emulator::create_rng(Some(42));
+        for _ in 0..10 {
+            assert_eq!(a.gen::<f64>(), b.gen::<f64>());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_immediate_orders_leave_the_book() {
+        let limit_order = |order_id, price, time_in_force| OrderBook {
+            price: Some(Decimal::new(price, 0)),
+            size: Decimal::ONE,
+            order_id,
+            partially_filled: false,
+            time_in_force,
+        };
+        let mut order_books = vec![
+            limit_order(1, 100, TimeInForce::Gtc),
+            limit_order(2, 100, TimeInForce::Ioc),
+            limit_order(3, 100, TimeInForce::Fok),
+            limit_order(4, 90, TimeInForce::Ioc),
+        ];
+        let mut filled_orders = vec![];
+
+        // Every order is only partially filled
+        Emulator::process_order_book(
+            &mut order_books,
+            Decimal::new(100, 0),
+            &mut filled_orders,
+            true,
+            &mut Emulator::create_rng(Some(1)),
+            Decimal::ZERO,
+            &EmulatorFillSettings::default(),
+        )
+        .await;
+
+        let filled_ids: Vec<u32> = filled_orders.iter().map(|order| order.0).collect();
+        assert_eq!(filled_ids, vec![1, 2]);
+        let resting_ids: Vec<u32> = order_books.iter().map(|order| order.order_id).collect();
+        assert_eq!(resting_ids, vec![1]);
+    }
+
+    #[test]
+    fn test_limit_order_fill_price() {
+        let limit_price = Some(Decimal::new(100, 0));
+        let slippage = Decimal::new(1, 2);
+
+        // Touching the limit is enough without slippage
+        assert_eq!(
+            Emulator::fill_price(limit_price, Decimal::new(100, 0), true, Decimal::ZERO),
+            limit_price
+        );
+        // With slippage the price must cross the limit by the slippage
+        assert_eq!(
+            Emulator::fill_price(limit_price, Decimal::new(100, 0), true, slippage),
+            None
+        );
+        assert_eq!(
+            Emulator::fill_price(limit_price, Decimal::new(99, 0), true, slippage),
+            limit_price
+        );
+        assert_eq!(
+            Emulator::fill_price(limit_price, Decimal::new(100, 0), false, slippage),
+            None
+        );
+        assert_eq!(
+            Emulator::fill_price(limit_price, Decimal::new(101, 0), false, slippage),
+            limit_price
+        );
+    }
+}
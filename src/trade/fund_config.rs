@@ -1,35 +1,404 @@
+use super::dex_connector_box::TimeInForce;
 use debot_market_analyzer::{SampleTerm, TradingStrategy, TrendType};
 use lazy_static::lazy_static;
 use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
 use std::env;
 
-pub const TOKEN_LIST_SIZE: u32 = 1;
 pub const TOKEN_LIST: &[&str] = &["BTC-USD"];
 
 pub const CUT_LOSS_MIN_RATIO: f64 = 0.01;
 
+const MIN_LEVERAGE: u32 = 1;
+const MAX_LEVERAGE: u32 = 50;
+
 lazy_static! {
     static ref INITIAL_FUND_AMOUNT: Decimal = env::var("INITIAL_FUND_AMOUNT")
         .ok()
         .and_then(|val| val.parse::<Decimal>().ok())
-        .unwrap_or_else(|| Decimal::ZERO);
-}
-
-pub fn get(
-    dex_name: &str,
-    strategy: &TradingStrategy,
-    leverage: u32,
-) -> Vec<(
-    String,
-    TradingStrategy,
-    Decimal,
-    Decimal,
-    Decimal,
-    Option<Decimal>,
-    Option<Decimal>,
-    SampleTerm,
-    i64,
-)> {
+        .unwrap_or(Decimal::ZERO);
+    static ref MIN_CLOSE_CONFIDENCE: Decimal = env::var("MIN_CLOSE_CONFIDENCE")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok())
+        .unwrap_or(Decimal::ZERO);
+    static ref CHASE_CLOSE_ORDERS: bool = env::var("CHASE_CLOSE_ORDERS")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MAX_CHASE_TICKS: u32 = env::var("MAX_CHASE_TICKS")
+        .ok()
+        .and_then(|val| val.parse::<u32>().ok())
+        .unwrap_or(3);
+    static ref TRAILING_STOP_RATIO: Option<Decimal> = env::var("TRAILING_STOP_RATIO")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    static ref MAX_POSITION_SIZE_RATIO: Decimal = env::var("MAX_POSITION_SIZE_RATIO")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok())
+        .unwrap_or(Decimal::ONE);
+    static ref REQUIRE_RSI_DIVERGENCE: bool = env::var("REQUIRE_RSI_DIVERGENCE")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MAX_IDLE_TICKS: Option<u64> = env::var("MAX_IDLE_TICKS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok());
+    static ref ALERT_WHEN_IDLE: bool = env::var("ALERT_WHEN_IDLE")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MAX_FUNDING_RATE_LONG: Option<Decimal> = env::var("MAX_FUNDING_RATE_LONG")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    static ref MAX_FUNDING_RATE_SHORT: Option<Decimal> = env::var("MAX_FUNDING_RATE_SHORT")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    static ref SIGNAL_ON_BAR_CLOSE: bool = env::var("SIGNAL_ON_BAR_CLOSE")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref BAR_TICKS: u64 = env::var("BAR_TICKS")
+        .ok()
+        .and_then(|val| val.parse::<u64>().ok())
+        .unwrap_or(1);
+    static ref MAX_ACCEPTABLE_SLIPPAGE: Option<Decimal> = env::var("MAX_ACCEPTABLE_SLIPPAGE")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    static ref ALERT_ON_SLIPPAGE: bool = env::var("ALERT_ON_SLIPPAGE")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref ANCHOR_TO_VWAP: bool = env::var("ANCHOR_TO_VWAP")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MIN_OPEN_CONFIDENCE: Decimal = env::var("MIN_OPEN_CONFIDENCE")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok())
+        .unwrap_or(Decimal::ZERO);
+    // e.g. "BTC-USD:0.6,ETH-USD:0.8"
+    static ref MIN_OPEN_CONFIDENCE_BY_TOKEN: HashMap<String, Decimal> =
+        parse_token_map(&env::var("MIN_OPEN_CONFIDENCE_BY_TOKEN").unwrap_or_default());
+    // e.g. "BTC-USD:10,SOL-USD:3"
+    static ref LEVERAGE_BY_TOKEN: HashMap<String, u32> =
+        parse_token_map(&env::var("LEVERAGE_BY_TOKEN").unwrap_or_default());
+    // Order sizes in the token accepted by the venue, e.g. "BTC-USD:0.001,ETH-USD:0.01"
+    static ref MIN_ORDER_SIZE_BY_TOKEN: HashMap<String, Decimal> =
+        parse_token_map(&env::var("MIN_ORDER_SIZE_BY_TOKEN").unwrap_or_default());
+    static ref MAX_ORDER_SIZE_BY_TOKEN: HashMap<String, Decimal> =
+        parse_token_map(&env::var("MAX_ORDER_SIZE_BY_TOKEN").unwrap_or_default());
+    static ref PREFER_MAKER: bool = env::var("PREFER_MAKER")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MAKER_FALLBACK_TO_TAKER: bool = env::var("MAKER_FALLBACK_TO_TAKER")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MAX_MARKET_SLIPPAGE: Option<Decimal> = env::var("MAX_MARKET_SLIPPAGE")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    static ref EXPIRE_OPEN_POSITIONS: bool = env::var("EXPIRE_OPEN_POSITIONS")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(true);
+    static ref CONTRACT_TYPE: ContractType =
+        match env::var("CONTRACT_TYPE").unwrap_or_default().as_str() {
+            "" | "linear" => ContractType::Linear,
+            "inverse" => ContractType::Inverse,
+            other => panic!("Unknown CONTRACT_TYPE: {}", other),
+        };
+    static ref ORDER_PLACEMENT_MODE: OrderPlacementMode =
+        match env::var("ORDER_PLACEMENT_MODE").unwrap_or_default().as_str() {
+            "" | "adaptive" => OrderPlacementMode::Adaptive,
+            "always_maker" => OrderPlacementMode::AlwaysMaker,
+            "always_taker" => OrderPlacementMode::AlwaysTaker,
+            other => panic!("Unknown ORDER_PLACEMENT_MODE: {}", other),
+        };
+    // e.g. "0.01:0.5,0.02:0.5" closes half of the rest at +1% and half of that at +2%
+    static ref TAKE_PROFIT_LADDER: Vec<(Decimal, Decimal)> =
+        parse_ladder(&env::var("TAKE_PROFIT_LADDER").unwrap_or_default());
+    // The loss in USD of a UTC day after which a fund stops opening
+    static ref DAILY_LOSS_LIMIT: Option<Decimal> = env::var("DAILY_LOSS_LIMIT")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    // MeanReversion opens need a price z-score of at least this magnitude
+    static ref MIN_ENTRY_ZSCORE: Option<Decimal> = env::var("MIN_ENTRY_ZSCORE")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    // Closes a position when the Stochastic-RSI crosses back from overbought (Long) or oversold (Short)
+    static ref USE_STOCH_RSI_EXIT: bool = env::var("USE_STOCH_RSI_EXIT")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    // The delay after a trade is stretched by (1 + this * ATR / price), to trade less in spikes
+    static ref EXECUTION_DELAY_ATR_FACTOR: Option<Decimal> = env::var("EXECUTION_DELAY_ATR_FACTOR")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok());
+    // Non-stop closes (expiry, target, trim) wait until a position is this old
+    static ref MIN_HOLDING_SECS: Option<i64> = env::var("MIN_HOLDING_SECS")
+        .ok()
+        .and_then(|val| val.parse::<i64>().ok());
+    // A same-direction open at a price this much better than the average is merged into the position
+    static ref ALLOW_AVERAGING: bool = env::var("ALLOW_AVERAGING")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+    static ref MIN_AVERAGE_IMPROVEMENT: Decimal = env::var("MIN_AVERAGE_IMPROVEMENT")
+        .ok()
+        .and_then(|val| val.parse::<Decimal>().ok())
+        .unwrap_or(Decimal::new(5, 3));
+    // Cycles an opposing trend signal has to persist before the position is closed
+    static ref TREND_CHANGE_CONFIRM_TICKS: u32 = env::var("TREND_CHANGE_CONFIRM_TICKS")
+        .ok()
+        .and_then(|val| val.parse::<u32>().ok())
+        .unwrap_or(1);
+    static ref CANDLE_INTERVAL_TICKS: Option<usize> = env::var("CANDLE_INTERVAL_TICKS")
+        .ok()
+        .and_then(|val| val.parse::<usize>().ok())
+        .filter(|ticks| *ticks > 0);
+    static ref SIZE_SCALING: SizeScaling =
+        match env::var("SIZE_SCALING").unwrap_or_default().as_str() {
+            "" | "fixed" => SizeScaling::Fixed,
+            "drawdown_scaled" => SizeScaling::DrawdownScaled {
+                sensitivity: env::var("SIZE_SCALING_SENSITIVITY")
+                    .ok()
+                    .and_then(|val| val.parse::<Decimal>().ok())
+                    .unwrap_or(Decimal::TWO),
+                floor: env::var("SIZE_SCALING_FLOOR")
+                    .ok()
+                    .and_then(|val| val.parse::<Decimal>().ok())
+                    .unwrap_or(Decimal::new(25, 2)),
+            },
+            other => panic!("Unknown SIZE_SCALING: {}", other),
+        };
+    static ref MAX_OPEN_POSITIONS: Option<usize> = env::var("MAX_OPEN_POSITIONS")
+        .ok()
+        .and_then(|val| val.parse::<usize>().ok());
+    static ref ORDER_TIME_IN_FORCE: TimeInForce =
+        match env::var("ORDER_TIME_IN_FORCE").unwrap_or_default().as_str() {
+            "" | "gtc" => TimeInForce::Gtc,
+            "ioc" => TimeInForce::Ioc,
+            "fok" => TimeInForce::Fok,
+            other => panic!("Unknown ORDER_TIME_IN_FORCE: {}", other),
+        };
+    static ref STRICT_POSITION_SIZE_RATIO: bool = env::var("STRICT_POSITION_SIZE_RATIO")
+        .ok()
+        .and_then(|val| val.parse::<bool>().ok())
+        .unwrap_or(false);
+}
+
+fn strategy_env_var<T: std::str::FromStr>(prefix: &str, strategy: &TradingStrategy) -> Option<T> {
+    let suffix = match strategy {
+        TradingStrategy::RandomWalk(_) => "RANDOMWALK",
+        TradingStrategy::MeanReversion(_) => "MEANREVERSION",
+        TradingStrategy::TrendFollow(_) => "TRENDFOLLOW",
+    };
+    env::var(format!("{}_{}", prefix, suffix))
+        .ok()
+        .and_then(|val| val.parse::<T>().ok())
+}
+
+fn parse_token_map<T: std::str::FromStr>(value: &str) -> HashMap<String, T> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let (token, value) = entry.split_once(':')?;
+            match value.trim().parse::<T>() {
+                Ok(value) => Some((token.trim().to_owned(), value)),
+                Err(_) => {
+                    log::warn!("Ignore the invalid token entry: {}", entry);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Rungs of (price ratio from the average open price, fraction of the remaining size),
+// in ascending price ratio
+fn parse_ladder(value: &str) -> Vec<(Decimal, Decimal)> {
+    let mut ladder: Vec<(Decimal, Decimal)> = value
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let (ratio, fraction) = entry.split_once(':')?;
+            match (
+                ratio.trim().parse::<Decimal>(),
+                fraction.trim().parse::<Decimal>(),
+            ) {
+                (Ok(ratio), Ok(fraction))
+                    if ratio > Decimal::ZERO
+                        && fraction > Decimal::ZERO
+                        && fraction <= Decimal::ONE =>
+                {
+                    Some((ratio, fraction))
+                }
+                _ => {
+                    log::warn!("Ignore the invalid ladder rung: {}", entry);
+                    None
+                }
+            }
+        })
+        .collect();
+    ladder.sort_by_key(|rung| rung.0);
+    ladder
+}
+
+// Open signals below this confidence are ignored.
+// The per-token value takes precedence over MIN_OPEN_CONFIDENCE.
+fn min_open_confidence(token_name: &str) -> Decimal {
+    MIN_OPEN_CONFIDENCE_BY_TOKEN
+        .get(token_name)
+        .copied()
+        .unwrap_or(*MIN_OPEN_CONFIDENCE)
+}
+
+fn token_leverage(token_name: &str) -> Option<u32> {
+    let leverage = *LEVERAGE_BY_TOKEN.get(token_name)?;
+    validate_leverage(token_name, leverage);
+    Some(leverage)
+}
+
+fn validate_leverage(token_name: &str, leverage: u32) {
+    if !(MIN_LEVERAGE..=MAX_LEVERAGE).contains(&leverage) {
+        panic!(
+            "leverage of {} must be between {} and {}: {}",
+            token_name, MIN_LEVERAGE, MAX_LEVERAGE, leverage
+        );
+    }
+}
+
+// Hyperliquid takes GTC and IOC limit orders, but has no FOK
+fn validate_time_in_force(dex_name: &str, time_in_force: TimeInForce) {
+    if dex_name == "hyperliquid" && time_in_force == TimeInForce::Fok {
+        panic!("{:?} is not supported on {}", time_in_force, dex_name);
+    }
+}
+
+// Trend-change/trim close signals below this confidence are ignored.
+// The per-strategy value takes precedence over MIN_CLOSE_CONFIDENCE.
+fn min_close_confidence(strategy: &TradingStrategy) -> Decimal {
+    strategy_env_var("MIN_CLOSE_CONFIDENCE", strategy).unwrap_or(*MIN_CLOSE_CONFIDENCE)
+}
+
+// Linear contracts settle in USD. Inverse (coin-margined) ones settle in the base coin,
+// so a value in USD is worth value / price of the coin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContractType {
+    Linear,
+    Inverse,
+}
+
+impl ContractType {
+    pub fn to_settlement(self, value_in_usd: Decimal, price: Decimal) -> Decimal {
+        match self {
+            ContractType::Linear => value_in_usd,
+            ContractType::Inverse => {
+                if price.is_zero() {
+                    Decimal::ZERO
+                } else {
+                    value_in_usd / price
+                }
+            }
+        }
+    }
+}
+
+// AlwaysMaker posts every order a tick inside the spread and reprices unfilled opens,
+// but places the liquidations, cut losses and expired closes as Adaptive does.
+// AlwaysTaker sends market orders, and Adaptive follows use_market_order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderPlacementMode {
+    AlwaysMaker,
+    AlwaysTaker,
+    Adaptive,
+}
+
+// DrawdownScaled shrinks the trading amount by sensitivity times the drawdown ratio,
+// e.g. to 80% at a 10% drawdown with a sensitivity of 2, but not below the floor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SizeScaling {
+    Fixed,
+    DrawdownScaled {
+        sensitivity: Decimal,
+        floor: Decimal,
+    },
+}
+
+impl SizeScaling {
+    pub fn scale(&self, trading_amount: Decimal, dd_ratio: Decimal) -> Decimal {
+        match self {
+            SizeScaling::Fixed => trading_amount,
+            SizeScaling::DrawdownScaled { sensitivity, floor } => {
+                let factor = (Decimal::ONE - *sensitivity * dd_ratio.max(Decimal::ZERO))
+                    .max(*floor)
+                    .min(Decimal::ONE);
+                trading_amount * factor
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct FundConfig {
+    pub token_name: String,
+    pub strategy: TradingStrategy,
+    pub initial_amount: Decimal,
+    pub position_size_ratio: Decimal,
+    pub risk_reward: Decimal,
+    pub take_profit_ratio: Option<Decimal>,
+    pub atr_spread: Option<Decimal>,
+    pub atr_term: SampleTerm,
+    pub max_open_hours: i64,
+    pub min_open_confidence: Decimal,
+    pub min_close_confidence: Decimal,
+    pub chase_close_orders: bool,
+    pub max_chase_ticks: u32,
+    pub trailing_stop_ratio: Option<Decimal>,
+    pub require_rsi_divergence: bool,
+    pub max_idle_ticks: Option<u64>,
+    pub alert_when_idle: bool,
+    // Overrides the global USE_MARKET_ORDER when set
+    pub use_market_order: Option<bool>,
+    pub max_funding_rate_long: Option<Decimal>,
+    pub max_funding_rate_short: Option<Decimal>,
+    pub signal_on_bar_close: bool,
+    pub bar_ticks: u64,
+    pub max_acceptable_slippage: Option<Decimal>,
+    pub alert_on_slippage: bool,
+    // Open orders are placed relative to VWAP instead of the current price
+    pub anchor_to_vwap: bool,
+    // Overrides the global LEVERAGE when set
+    pub leverage: Option<u32>,
+    // A limit open that would cross the market is skipped, or sent as a taker order with the fallback
+    pub prefer_maker: bool,
+    pub maker_fallback_to_taker: bool,
+    // A market open whose slippage estimated from the book exceeds this is placed at the spread edge
+    pub max_market_slippage: Option<Decimal>,
+    // Without it, positions are held past their period until take-profit or cut-loss
+    pub expire_open_positions: bool,
+    pub contract_type: ContractType,
+    pub order_placement_mode: OrderPlacementMode,
+    pub time_in_force: TimeInForce,
+    pub max_open_positions: Option<usize>,
+    pub take_profit_ladder: Vec<(Decimal, Decimal)>,
+    pub daily_loss_limit: Option<Decimal>,
+    pub min_entry_zscore: Option<Decimal>,
+    pub use_stoch_rsi_exit: bool,
+    pub min_order_size: Option<Decimal>,
+    pub max_order_size: Option<Decimal>,
+    pub execution_delay_atr_factor: Option<Decimal>,
+    pub min_holding_secs: Option<i64>,
+    pub allow_averaging: bool,
+    pub min_average_improvement: Decimal,
+    pub trend_change_confirm_ticks: u32,
+    // Candle patterns are detected on OHLC candles of this many ticks when set
+    pub candle_interval_ticks: Option<usize>,
+    pub size_scaling: SizeScaling,
+}
+
+pub fn get(dex_name: &str, strategy: &TradingStrategy, leverage: u32) -> Vec<FundConfig> {
     let atr_term_values = vec![
         SampleTerm::TradingTerm,
         SampleTerm::ShortTerm,
@@ -84,7 +453,9 @@ pub fn get(
 
     let mut strategy_list = Vec::new();
 
-    if dex_name == "hyperliquid" {
+    validate_time_in_force(dex_name, *ORDER_TIME_IN_FORCE);
+
+    if dex_name == "hyperliquid" || dex_name == "bybit" {
         let (take_profit_ratio_values, atr_spread_values, risk_reward_values, open_hours_values) =
             match strategy {
                 TradingStrategy::RandomWalk(_) => (
@@ -122,17 +493,65 @@ pub fn get(
                     for risk_reward in risk_reward_values.clone() {
                         for open_hours in &open_hours_values {
                             for strategy in &strategies {
-                                strategy_list.push((
-                                    TOKEN_LIST[0].to_owned(),
-                                    *strategy,
-                                    Decimal::ZERO,
-                                    Decimal::new(8, 1), // position size ratio
+                                strategy_list.push(FundConfig {
+                                    token_name: TOKEN_LIST[0].to_owned(),
+                                    strategy: *strategy,
+                                    initial_amount: Decimal::ZERO,
+                                    position_size_ratio: Decimal::new(8, 1),
                                     risk_reward,
                                     take_profit_ratio,
-                                    atr_spread,       // spread by ATR
-                                    atr_term.clone(), // ATR SampleTerm
-                                    *open_hours,      // max open hours
-                                ));
+                                    atr_spread,
+                                    atr_term: atr_term.clone(),
+                                    max_open_hours: *open_hours,
+                                    min_open_confidence: min_open_confidence(TOKEN_LIST[0]),
+                                    min_close_confidence: min_close_confidence(strategy),
+                                    chase_close_orders: *CHASE_CLOSE_ORDERS,
+                                    max_chase_ticks: *MAX_CHASE_TICKS,
+                                    trailing_stop_ratio: *TRAILING_STOP_RATIO,
+                                    require_rsi_divergence: *REQUIRE_RSI_DIVERGENCE
+                                        && matches!(strategy, TradingStrategy::MeanReversion(_)),
+                                    max_idle_ticks: *MAX_IDLE_TICKS,
+                                    alert_when_idle: *ALERT_WHEN_IDLE,
+                                    use_market_order: strategy_env_var(
+                                        "USE_MARKET_ORDER",
+                                        strategy,
+                                    ),
+                                    max_funding_rate_long: *MAX_FUNDING_RATE_LONG,
+                                    max_funding_rate_short: *MAX_FUNDING_RATE_SHORT,
+                                    signal_on_bar_close: *SIGNAL_ON_BAR_CLOSE,
+                                    bar_ticks: *BAR_TICKS,
+                                    max_acceptable_slippage: *MAX_ACCEPTABLE_SLIPPAGE,
+                                    alert_on_slippage: *ALERT_ON_SLIPPAGE,
+                                    anchor_to_vwap: *ANCHOR_TO_VWAP,
+                                    leverage: token_leverage(TOKEN_LIST[0]),
+                                    prefer_maker: *PREFER_MAKER,
+                                    maker_fallback_to_taker: *MAKER_FALLBACK_TO_TAKER,
+                                    max_market_slippage: *MAX_MARKET_SLIPPAGE,
+                                    expire_open_positions: *EXPIRE_OPEN_POSITIONS,
+                                    contract_type: *CONTRACT_TYPE,
+                                    order_placement_mode: *ORDER_PLACEMENT_MODE,
+                                    time_in_force: *ORDER_TIME_IN_FORCE,
+                                    max_open_positions: *MAX_OPEN_POSITIONS,
+                                    take_profit_ladder: TAKE_PROFIT_LADDER.clone(),
+                                    daily_loss_limit: *DAILY_LOSS_LIMIT,
+                                    min_entry_zscore: MIN_ENTRY_ZSCORE.filter(|_| {
+                                        matches!(strategy, TradingStrategy::MeanReversion(_))
+                                    }),
+                                    use_stoch_rsi_exit: *USE_STOCH_RSI_EXIT,
+                                    min_order_size: MIN_ORDER_SIZE_BY_TOKEN
+                                        .get(TOKEN_LIST[0])
+                                        .copied(),
+                                    max_order_size: MAX_ORDER_SIZE_BY_TOKEN
+                                        .get(TOKEN_LIST[0])
+                                        .copied(),
+                                    execution_delay_atr_factor: *EXECUTION_DELAY_ATR_FACTOR,
+                                    min_holding_secs: *MIN_HOLDING_SECS,
+                                    allow_averaging: *ALLOW_AVERAGING,
+                                    min_average_improvement: *MIN_AVERAGE_IMPROVEMENT,
+                                    trend_change_confirm_ticks: *TREND_CHANGE_CONFIRM_TICKS,
+                                    candle_interval_ticks: *CANDLE_INTERVAL_TICKS,
+                                    size_scaling: *SIZE_SCALING,
+                                });
                             }
                         }
                     }
@@ -146,7 +565,7 @@ pub fn get(
     // Filtered strategy list
     let filtered_strategy_list: Vec<_> = strategy_list
         .into_iter()
-        .filter(|(_, trading_strategy, _, _, _, _, _, _, _)| strategy == trading_strategy)
+        .filter(|fund| *strategy == fund.strategy)
         .collect();
 
     // Calculate the amount per strategy after filtering
@@ -160,33 +579,179 @@ pub fn get(
 
     log::warn!("amount_per_strategy = {}", filtered_amount_per_strategy);
 
+    validate_position_size_ratio(
+        &filtered_strategy_list
+            .iter()
+            .map(|fund| {
+                (
+                    fund.token_name.as_str(),
+                    filtered_amount_per_strategy,
+                    fund.position_size_ratio,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
     // Update the amount for each filtered strategy
     filtered_strategy_list
         .into_iter()
-        .map(
-            |(
-                token,
-                trading_strategy,
-                _,
-                size_ratio,
-                risk_reward,
-                take_profit_ratio,
-                atr_spread,
-                atr_term,
-                open_hours,
-            )| {
-                (
-                    token,
-                    trading_strategy,
-                    filtered_amount_per_strategy, // Updated amount per strategy
-                    size_ratio,
-                    risk_reward,
-                    take_profit_ratio,
-                    atr_spread,
-                    atr_term,
-                    open_hours,
-                )
-            },
-        )
+        .map(|fund| FundConfig {
+            initial_amount: filtered_amount_per_strategy,
+            ..fund
+        })
         .collect()
 }
+
+pub fn token_count(fund_configs: &[FundConfig]) -> u32 {
+    fund_configs
+        .iter()
+        .map(|fund| fund.token_name.as_str())
+        .collect::<HashSet<_>>()
+        .len() as u32
+}
+
+// Check that the capital implied by position_size_ratio fits in the fund,
+// both per token and overall. Each entry is (token, initial_amount, position_size_ratio).
+fn validate_position_size_ratio(fund_configs: &[(&str, Decimal, Decimal)]) {
+    let total_amount: Decimal = fund_configs.iter().map(|(_, amount, _)| *amount).sum();
+    if total_amount.is_zero() {
+        return;
+    }
+
+    let mut token_amounts: HashMap<&str, Decimal> = HashMap::new();
+    for (token, amount, size_ratio) in fund_configs {
+        *token_amounts.entry(*token).or_insert(Decimal::ZERO) += *amount * *size_ratio;
+    }
+
+    let max_ratio = *MAX_POSITION_SIZE_RATIO;
+    let mut violations = vec![];
+    for (token, amount) in &token_amounts {
+        let ratio = *amount / total_amount;
+        log::info!("position_size_ratio of {} = {:.3}", token, ratio);
+        if ratio > max_ratio {
+            violations.push(format!("{}({:.3})", token, ratio));
+        }
+    }
+
+    let overall_ratio = token_amounts.values().sum::<Decimal>() / total_amount;
+    log::info!("position_size_ratio of all tokens = {:.3}", overall_ratio);
+    if overall_ratio > max_ratio {
+        violations.push(format!("overall({:.3})", overall_ratio));
+    }
+
+    if !violations.is_empty() {
+        let message = format!(
+            "position_size_ratio exceeds {}: {}",
+            max_ratio,
+            violations.join(", ")
+        );
+        if *STRICT_POSITION_SIZE_RATIO {
+            panic!("{}", message);
+        }
+        log::warn!("{}", message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contract_type_round_trip_pnl() {
+        // Long 2 @ 100, closed @ 125
+        let (amount, open_price, close_price) = (
+            Decimal::new(2, 0),
+            Decimal::new(100, 0),
+            Decimal::new(125, 0),
+        );
+        let linear_pnl = amount * (close_price - open_price);
+
+        assert_eq!(
+            ContractType::Linear.to_settlement(linear_pnl, close_price),
+            Decimal::new(50, 0)
+        );
+        // The notional of 200 USD is worth 2 coins at the open and 1.6 at the close
+        let notional = amount * open_price;
+        let inverse_pnl = notional / open_price - notional / close_price;
+        assert_eq!(inverse_pnl, Decimal::new(4, 1));
+        assert_eq!(
+            ContractType::Inverse.to_settlement(linear_pnl, close_price),
+            inverse_pnl
+        );
+        assert_eq!(
+            ContractType::Inverse.to_settlement(linear_pnl, Decimal::ZERO),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_drawdown_scaled_size() {
+        let scaling = SizeScaling::DrawdownScaled {
+            sensitivity: Decimal::TWO,
+            floor: Decimal::new(25, 2),
+        };
+        let trading_amount = Decimal::new(1000, 0);
+
+        let sizes: Vec<Decimal> = (0..=10)
+            .map(|i| scaling.scale(trading_amount, Decimal::new(i * 5, 2)))
+            .collect();
+        assert_eq!(sizes[0], trading_amount);
+        assert_eq!(sizes[2], Decimal::new(800, 0));
+        assert!(sizes.windows(2).all(|w| w[1] <= w[0]));
+        // The floor is reached at a 37.5% drawdown
+        assert_eq!(sizes[8], Decimal::new(250, 0));
+        assert_eq!(sizes[10], Decimal::new(250, 0));
+
+        assert_eq!(
+            SizeScaling::Fixed.scale(trading_amount, Decimal::new(5, 1)),
+            trading_amount
+        );
+    }
+
+    #[test]
+    fn test_parse_token_map() {
+        let map: HashMap<String, Decimal> =
+            parse_token_map("BTC-USD:0.6, ETH-USD : 0.8,SOL-USD:x,invalid");
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("BTC-USD"), Some(&Decimal::new(6, 1)));
+        assert_eq!(map.get("ETH-USD"), Some(&Decimal::new(8, 1)));
+
+        assert!(parse_token_map::<u32>("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_ladder() {
+        assert_eq!(
+            parse_ladder("0.02:0.5, 0.01 : 0.5,0.03:2,x"),
+            vec![
+                (Decimal::new(1, 2), Decimal::new(5, 1)),
+                (Decimal::new(2, 2), Decimal::new(5, 1)),
+            ]
+        );
+        assert!(parse_ladder("").is_empty());
+    }
+
+    #[test]
+    fn test_validate_leverage() {
+        validate_leverage("BTC-USD", MIN_LEVERAGE);
+        validate_leverage("BTC-USD", MAX_LEVERAGE);
+    }
+
+    #[test]
+    #[should_panic(expected = "leverage of SOL-USD must be between")]
+    fn test_validate_leverage_out_of_range() {
+        validate_leverage("SOL-USD", MAX_LEVERAGE + 1);
+    }
+
+    #[test]
+    fn test_validate_time_in_force() {
+        validate_time_in_force("hyperliquid", TimeInForce::Ioc);
+        validate_time_in_force("bybit", TimeInForce::Fok);
+    }
+
+    #[test]
+    #[should_panic(expected = "Fok is not supported on hyperliquid")]
+    fn test_fok_is_rejected_on_hyperliquid() {
+        validate_time_in_force("hyperliquid", TimeInForce::Fok);
+    }
+}
@@ -1,19 +1,55 @@
 // fund_manager.rs
 
 use super::DBHandler;
-use super::{dex_connector_box::DexConnectorBox, fund_config};
+use super::{
+    candle::{CandleHistory, CandleShape},
+    decision_trace::DecisionTrace,
+    dex_connector_box::{DexConnectorBox, DexConnectorExt, OrderBook, TimeInForce},
+    fund_config::{self, ContractType, OrderPlacementMode, SizeScaling},
+    trading_calendar::TRADING_CALENDAR,
+};
+use crate::config::RoundingMode;
+use crate::error_manager::AlertHandle;
 use debot_db::{CandlePattern, PricePoint};
-use debot_market_analyzer::{MarketData, SampleTerm, TradeAction, TradeDetail, TradingStrategy};
+use debot_market_analyzer::{
+    MarketData, SampleTerm, TradeAction, TradeDetail, TradingStrategy, TrendType,
+};
 use debot_position_manager::{PositionType, ReasonForClose, State, TradePosition};
-use debot_utils::is_sunday;
 use dex_connector::{CreateOrderResponse, DexConnector, DexError, OrderSide};
-use num::FromPrimitive;
+use num::{FromPrimitive, ToPrimitive};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rust_decimal::Decimal;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, RwLock};
 
+// Number of equity points buffered before they are written to the DB
+const FUND_EQUITY_BATCH_SIZE: usize = 60;
+
+// Number of (price, rsi) samples looked back for a divergence
+const RSI_DIVERGENCE_WINDOW: usize = 30;
+
+// Stochastic-RSI levels, in percent, crossed back by the USE_STOCH_RSI_EXIT closes
+const STOCH_RSI_OVERBOUGHT: Decimal = Decimal::from_parts(80, 0, 0, false, 0);
+const STOCH_RSI_OVERSOLD: Decimal = Decimal::from_parts(20, 0, 0, false, 0);
+
+// Number of processed trade ids remembered to drop duplicated fills
+const MAX_PROCESSED_TRADE_IDS: usize = 1000;
+
+// Number of the latest fill latencies the percentiles are taken over
+const FILL_LATENCY_WINDOW: usize = 100;
+
+// Number of price levels read to estimate the slippage of a market order
+const ORDERBOOK_DEPTH: usize = 20;
+
+// Chance that a RandomWalk fund opens on a tick, the one of the analyzer
+const RANDOM_WALK_OPEN_PROBABILITY: f64 = 0.1;
+
 #[derive(Debug, Clone)]
 struct TradeChance {
     pub action: TradeAction,
@@ -23,47 +59,292 @@ struct TradeChance {
     pub position_id: Option<u32>,
 }
 
+// Trade ids of the applied fills. The WS feed can deliver the same fill again on reconnect.
+#[derive(Default)]
+struct ProcessedTradeIds {
+    trade_ids: HashSet<String>,
+    order: VecDeque<(u32, String)>,
+}
+
+impl ProcessedTradeIds {
+    // Returns false if the trade id has already been processed
+    fn insert(&mut self, trade_id: &str, position_id: u32) -> bool {
+        if !self.trade_ids.insert(trade_id.to_owned()) {
+            return false;
+        }
+        self.order.push_back((position_id, trade_id.to_owned()));
+        if self.order.len() > MAX_PROCESSED_TRADE_IDS {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.trade_ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    fn remove_position(&mut self, position_id: u32) {
+        let trade_ids = &mut self.trade_ids;
+        self.order.retain(|(id, trade_id)| {
+            if *id == position_id {
+                trade_ids.remove(trade_id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+// Time from the submission of an order to its first fill
+#[derive(Default)]
+struct FillLatencies {
+    submit_times: HashMap<String, Instant>,
+    latencies: VecDeque<Duration>,
+}
+
+impl FillLatencies {
+    fn submitted(&mut self, order_id: &str, at: Instant) {
+        self.submit_times.insert(order_id.to_owned(), at);
+    }
+
+    // Only the first fill of an order is counted
+    fn filled(&mut self, order_id: &str, at: Instant) -> Option<Duration> {
+        let submit_time = self.submit_times.remove(order_id)?;
+        let latency = at.saturating_duration_since(submit_time);
+        self.latencies.push_back(latency);
+        if self.latencies.len() > FILL_LATENCY_WINDOW {
+            self.latencies.pop_front();
+        }
+        Some(latency)
+    }
+
+    fn canceled(&mut self, order_id: &str) {
+        self.submit_times.remove(order_id);
+    }
+
+    // Nearest-rank percentile
+    fn percentile(&self, percent: usize) -> Option<Duration> {
+        let mut latencies: Vec<Duration> = self.latencies.iter().copied().collect();
+        latencies.sort();
+        let rank = (percent * latencies.len()).div_ceil(100).max(1);
+        latencies.get(rank - 1).copied()
+    }
+}
+
 struct FundManagerState {
+    // The amount is in the settlement unit of the contract. An inverse fund converts its
+    // initial amount at the first price it trades at.
     amount: Decimal,
+    initial_amount: Option<Decimal>,
+    // Of the positions closed in this session
+    realized_pnl: Decimal,
     trade_positions: HashMap<u32, TradePosition>,
     latest_open_position_id: Option<u32>,
     db_handler: Arc<Mutex<DBHandler>>,
     dex_connector: Arc<DexConnectorBox>,
+    alerts: AlertHandle,
     market_data: Arc<RwLock<MarketData>>,
     trade_tick_count: u64,
     last_price: Decimal,
+    close_chase_counts: HashMap<u32, u32>,
+    trailing_stop_prices: HashMap<u32, Decimal>,
+    // Number of the take-profit ladder rungs fired per position
+    ladder_rungs_fired: HashMap<u32, usize>,
+    // The unfilled size of the close orders that leave a part of their position
+    partial_close_sizes: HashMap<u32, Decimal>,
+    // Consecutive cycles with an opposing trend signal per position
+    trend_change_counts: HashMap<u32, u32>,
+    equity_tick_count: u32,
+    equity_points: Vec<(i64, Decimal)>,
+    rsi_history: VecDeque<(Decimal, Decimal)>,
+    // Prices over the ATR term window, for the z-score
+    price_history: VecDeque<Decimal>,
+    // The previous and the latest Stochastic-RSI, for the crossover
+    stochastics: VecDeque<Decimal>,
+    tick_count: u64,
+    last_activity_tick: u64,
+    last_open_signal: String,
+    idle_warned: bool,
+    vwap_history: VecDeque<(Decimal, Option<Decimal>)>,
+    processed_trade_ids: ProcessedTradeIds,
+    last_min_tick: Option<Decimal>,
+    current_price: Decimal,
+    open_order_count: u64,
+    immediate_order_ids: HashSet<String>,
+    // Orders of the pending positions that the venue did not list at the last verification
+    missing_order_ids: HashSet<String>,
+    // The close fills that came before the open fills of their position
+    deferred_fills: Vec<DeferredFill>,
+    closed_positions: Vec<TradePosition>,
+    model_evaluation_count: u64,
+    // The open orders by position ID, and the resting orders of all the funds on the token
+    resting_open_orders: HashMap<u32, RestingOrder>,
+    shared_resting_orders: SharedRestingOrders,
+    candle_history: CandleHistory,
+    fill_latencies: FillLatencies,
+    // Draws the RandomWalk opens of a seeded back test
+    rng: Option<StdRng>,
 }
 
-struct FundManagerConfig {
-    fund_name: String,
-    index: usize,
-    token_name: String,
-    strategy: TradingStrategy,
-    trading_amount: Decimal,
-    initial_amount: Decimal,
-    open_order_tick_count_max: u32,
-    close_order_tick_count_max: u32,
-    open_tick_count_max: u32,
-    execution_delay_tick_count_max: u32,
-    use_market_order: bool,
-    take_profit_ratio: Option<Decimal>,
-    risk_reward: Decimal,
-    atr_spread: Option<Decimal>,
-    atr_term: SampleTerm,
+// The settings of a fund, built by the trader from the fund config
+pub struct FundManagerConfig {
+    pub fund_name: String,
+    pub index: usize,
+    pub token_name: String,
+    pub strategy: TradingStrategy,
+    pub trading_amount: Decimal,
+    pub initial_amount: Decimal,
+    pub open_order_tick_count_max: u32,
+    pub close_order_tick_count_max: u32,
+    pub open_tick_count_max: u32,
+    pub execution_delay_tick_count_max: u32,
+    pub use_market_order: bool,
+    pub take_profit_ratio: Option<Decimal>,
+    pub risk_reward: Decimal,
+    pub atr_spread: Option<Decimal>,
+    pub atr_term: SampleTerm,
+    pub min_open_confidence: Decimal,
+    pub min_close_confidence: Decimal,
+    pub chase_close_orders: bool,
+    pub max_chase_ticks: u32,
+    pub trailing_stop_ratio: Option<Decimal>,
+    pub require_rsi_divergence: bool,
+    pub max_idle_ticks: Option<u64>,
+    pub alert_when_idle: bool,
+    pub max_funding_rate_long: Option<Decimal>,
+    pub max_funding_rate_short: Option<Decimal>,
+    pub signal_on_bar_close: bool,
+    pub bar_ticks: u64,
+    pub max_acceptable_slippage: Option<Decimal>,
+    pub alert_on_slippage: bool,
+    pub anchor_to_vwap: bool,
+    pub vwap_window: usize,
+    pub leverage: Option<u32>,
+    pub prefer_maker: bool,
+    pub maker_fallback_to_taker: bool,
+    pub max_market_slippage: Option<Decimal>,
+    pub expire_open_positions: bool,
+    pub contract_type: ContractType,
+    pub order_placement_mode: OrderPlacementMode,
+    pub time_in_force: TimeInForce,
+    pub max_open_positions: Option<usize>,
+    pub take_profit_ladder: Vec<(Decimal, Decimal)>,
+    pub daily_loss_limit: Option<Decimal>,
+    pub min_entry_zscore: Option<Decimal>,
+    pub use_stoch_rsi_exit: bool,
+    pub min_order_size: Option<Decimal>,
+    pub max_order_size: Option<Decimal>,
+    pub execution_delay_atr_factor: Option<Decimal>,
+    pub min_holding_secs: Option<i64>,
+    pub allow_averaging: bool,
+    pub min_average_improvement: Decimal,
+    pub trend_change_confirm_ticks: u32,
+    pub candle_interval_ticks: Option<usize>,
+    pub size_scaling: SizeScaling,
+    pub log_fund_equity: bool,
+    pub equity_sample_ticks: u32,
+    pub trace_decisions: bool,
+    pub rounding_mode: RoundingMode,
+    pub open_resubmit_attempts: u32,
+    pub model_ticks: Option<u32>,
+    pub random_seed: Option<u64>,
 }
 
-#[derive(Default)]
-struct FundManagerStatics {
+#[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FundManagerStatics {
     order_count: i32,
     fill_count: i32,
     take_profit_count: i32,
     cut_loss_count: i32,
+    trailing_stop_count: i32,
     trim_count: i32,
     trend_changed_count: i32,
     expired_count: i32,
+    slippage_exceeded_count: i32,
     pnl: Decimal,
     min_amount: Decimal,
+    // Realized PnL of the UTC day numbered daily_pnl_day since the epoch
+    daily_pnl_day: i64,
+    daily_pnl: Decimal,
+}
+
+impl FundManagerStatics {
+    const SECONDS_IN_DAY: i64 = 86400;
+
+    // The bucket is reset when the first PnL of a new UTC day is added
+    fn add_daily_pnl(&mut self, pnl: Decimal, now: i64) {
+        let day = now.div_euclid(Self::SECONDS_IN_DAY);
+        if day != self.daily_pnl_day {
+            self.daily_pnl_day = day;
+            self.daily_pnl = Decimal::ZERO;
+        }
+        self.daily_pnl += pnl;
+    }
+
+    fn daily_pnl(&self, now: i64) -> Decimal {
+        if now.div_euclid(Self::SECONDS_IN_DAY) == self.daily_pnl_day {
+            self.daily_pnl
+        } else {
+            Decimal::ZERO
+        }
+    }
+}
+
+// A resting open order of a fund, seen by the other funds on the token to avoid trading
+// against each other
+#[derive(Debug, Clone)]
+pub struct RestingOrder {
+    pub order_id: String,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+// A part of a resting order traded between two funds instead of on the venue
+#[derive(Debug, Clone)]
+pub struct InternalCross {
+    pub fund_name: String,
+    pub order_id: String,
+    pub trade_id: String,
+    pub side: OrderSide,
+    pub price: Decimal,
+    pub size: Decimal,
+}
+
+// The resting opens of all the funds on a token, with the fund name, and the crosses taken
+// against them this tick for their funds to book
+#[derive(Debug, Default)]
+pub struct RestingOrderPool {
+    orders: Vec<(String, RestingOrder)>,
+    crosses: Vec<InternalCross>,
+}
+
+pub type SharedRestingOrders = Arc<std::sync::Mutex<RestingOrderPool>>;
+
+// A fill held back until the fills it depends on have been applied
+#[derive(Debug, Clone)]
+struct DeferredFill {
+    order_id: String,
+    trade_id: String,
+    filled_side: OrderSide,
+    filled_value: Decimal,
+    filled_size: Decimal,
+    fee: Decimal,
+}
+
+// Parameters that can be changed without a restart. Unset ones are left as they are.
+#[derive(Default, Debug, Deserialize)]
+#[serde(default)]
+pub struct FundParams {
+    take_profit_ratio: Option<Decimal>,
+    risk_reward: Option<Decimal>,
+    atr_spread: Option<Decimal>,
+    trailing_stop_ratio: Option<Decimal>,
+    open_order_tick_count_max: Option<u32>,
+    close_order_tick_count_max: Option<u32>,
+    open_tick_count_max: Option<u32>,
 }
+
 pub struct FundManager {
     config: FundManagerConfig,
     state: FundManagerState,
@@ -72,54 +353,60 @@ pub struct FundManager {
 
 impl FundManager {
     pub fn new(
-        fund_name: &str,
-        index: usize,
-        token_name: &str,
+        config: FundManagerConfig,
         market_data: Arc<RwLock<MarketData>>,
-        strategy: TradingStrategy,
-        trading_amount: Decimal,
-        initial_amount: Decimal,
         db_handler: Arc<Mutex<DBHandler>>,
         dex_connector: Arc<DexConnectorBox>,
-        open_order_tick_count_max: u32,
-        close_order_tick_count_max: u32,
-        open_tick_count_max: u32,
-        execution_delay_tick_count_max: u32,
-        use_market_order: bool,
-        take_profit_ratio: Option<Decimal>,
-        risk_reward: Decimal,
-        atr_spread: Option<Decimal>,
-        atr_term: SampleTerm,
+        alerts: AlertHandle,
     ) -> Self {
-        let config = FundManagerConfig {
-            fund_name: fund_name.to_owned(),
-            index,
-            token_name: token_name.to_owned(),
-            strategy,
-            trading_amount,
-            initial_amount,
-            open_order_tick_count_max,
-            close_order_tick_count_max,
-            open_tick_count_max,
-            execution_delay_tick_count_max,
-            use_market_order,
-            take_profit_ratio,
-            risk_reward,
-            atr_spread,
-            atr_term,
-        };
+        let initial_amount = config.initial_amount;
 
         log::info!("initial amount = {}", initial_amount);
 
         let state = FundManagerState {
             amount: initial_amount,
+            initial_amount: match config.contract_type {
+                ContractType::Linear => Some(initial_amount),
+                ContractType::Inverse => None,
+            },
+            realized_pnl: Decimal::ZERO,
             trade_positions: HashMap::new(),
             db_handler,
             dex_connector,
+            alerts,
             market_data,
-            trade_tick_count: execution_delay_tick_count_max as u64,
+            trade_tick_count: config.execution_delay_tick_count_max as u64,
             latest_open_position_id: None,
             last_price: Decimal::new(0, 0),
+            close_chase_counts: HashMap::new(),
+            trailing_stop_prices: HashMap::new(),
+            ladder_rungs_fired: HashMap::new(),
+            partial_close_sizes: HashMap::new(),
+            trend_change_counts: HashMap::new(),
+            equity_tick_count: 0,
+            equity_points: vec![],
+            rsi_history: VecDeque::new(),
+            price_history: VecDeque::new(),
+            stochastics: VecDeque::new(),
+            tick_count: 0,
+            last_activity_tick: 0,
+            last_open_signal: String::new(),
+            idle_warned: false,
+            vwap_history: VecDeque::new(),
+            processed_trade_ids: ProcessedTradeIds::default(),
+            last_min_tick: None,
+            current_price: Decimal::ZERO,
+            open_order_count: 0,
+            immediate_order_ids: HashSet::new(),
+            missing_order_ids: HashSet::new(),
+            deferred_fills: vec![],
+            closed_positions: vec![],
+            model_evaluation_count: 0,
+            resting_open_orders: HashMap::new(),
+            shared_resting_orders: SharedRestingOrders::default(),
+            candle_history: CandleHistory::new(config.candle_interval_ticks.unwrap_or(1)),
+            fill_latencies: FillLatencies::default(),
+            rng: Self::create_rng(config.random_seed, &config.fund_name),
         };
 
         let mut statistics = FundManagerStatics::default();
@@ -140,6 +427,10 @@ impl FundManager {
         &self.config.token_name
     }
 
+    pub fn leverage(&self) -> Option<u32> {
+        self.config.leverage
+    }
+
     pub async fn get_token_price(
         &mut self,
         back_test_price: Option<&PricePoint>,
@@ -167,13 +458,21 @@ impl FundManager {
             .await
             .map_err(|e| format!("Failed to get price of {}: {:?}", token_name, e).to_owned())?;
 
+        let min_tick = match Self::resolve_min_tick(res.min_tick, &mut self.state.last_min_tick) {
+            Some(min_tick) => min_tick,
+            None => return Err("min_tick is not available".into()),
+        };
         if res.min_tick.is_none() {
-            return Err(format!("min_tick is not available").into());
+            log::warn!(
+                "{}: min_tick is not available, use the last one: {}",
+                token_name,
+                min_tick
+            );
         }
 
         Ok((
             res.price,
-            res.min_tick.unwrap(),
+            min_tick,
             timestamp,
             res.volume,
             res.num_trades,
@@ -183,30 +482,246 @@ impl FundManager {
         ))
     }
 
+    // Falls back to the last known min_tick when the venue omits it
+    fn resolve_min_tick(
+        min_tick: Option<Decimal>,
+        last_min_tick: &mut Option<Decimal>,
+    ) -> Option<Decimal> {
+        if min_tick.is_some() {
+            *last_min_tick = min_tick;
+        }
+        *last_min_tick
+    }
+
     pub async fn find_chances(
         &mut self,
         price: Decimal,
         dry_run: bool,
+        can_open: bool,
+        dd_ratio: Decimal,
     ) -> Result<(), Box<dyn Error + Send + Sync>> {
         self.state.trade_tick_count += 1;
+        self.state.tick_count += 1;
+        self.state.current_price = price;
 
         self.check_positions(price);
 
+        if self.config.require_rsi_divergence {
+            self.record_rsi(price).await;
+        }
+
+        if self.config.anchor_to_vwap {
+            self.record_vwap(price).await;
+        }
+
+        if self.config.use_stoch_rsi_exit {
+            let stochastic = self.state.market_data.read().await.stochastic().1;
+            self.state.stochastics.push_back(stochastic);
+            if self.state.stochastics.len() > 2 {
+                self.state.stochastics.pop_front();
+            }
+        }
+
+        if self.config.candle_interval_ticks.is_some() {
+            self.state.candle_history.add_price(price);
+        }
+
+        if self.config.min_entry_zscore.is_some() {
+            self.state.price_history.push_back(price);
+            if self.state.price_history.len() > self.config.vwap_window.max(1) {
+                self.state.price_history.pop_front();
+            }
+        }
+
         self.find_expired_orders().await;
 
-        self.find_close_chances(price)
+        if self.config.chase_close_orders {
+            self.chase_close_orders(price)
+                .await
+                .map_err(|_| "Failed to chase close orders".to_owned())?;
+        }
+
+        let mut trace = DecisionTrace::new(
+            self.config.trace_decisions,
+            &self.config.fund_name,
+            self.state.tick_count,
+            price,
+        );
+        if trace.is_enabled() {
+            let market_data = self.state.market_data.read().await;
+            trace.indicator("atr", Some(market_data.atr().1));
+            trace.indicator("rsi", Some(market_data.rsi().1));
+            trace.indicator("funding_rate", market_data.last_funding_rate());
+            trace.indicator("vwap", Self::vwap(&self.state.vwap_history));
+            if let Some(candle_pattern) = self.candle_pattern() {
+                trace.signal(candle_pattern);
+            }
+        }
+
+        self.find_close_chances(price, &mut trace)
             .await
             .map_err(|_| "Failed to find close chances".to_owned())?;
 
-        self.find_open_chances(price, dry_run)
+        self.find_open_chances(price, dry_run, can_open, dd_ratio, &mut trace)
             .await
             .map_err(|_| "Failed to find open chances".to_owned())?;
         self.state.last_price = price;
 
+        trace.emit();
+
+        if self.config.log_fund_equity {
+            self.record_equity(price).await;
+        }
+
+        if let Some(max_idle_ticks) = self.config.max_idle_ticks {
+            self.check_idle(max_idle_ticks);
+        }
+
         Ok(())
     }
 
+    fn check_idle(&mut self, max_idle_ticks: u64) {
+        let idle_ticks = self.state.tick_count - self.state.last_activity_tick;
+        if idle_ticks < max_idle_ticks || self.state.idle_warned {
+            return;
+        }
+        self.state.idle_warned = true;
+
+        let message = format!(
+            "{} has not traded for {} ticks, last open signal: {}",
+            self.config.fund_name,
+            idle_ticks,
+            if self.state.last_open_signal.is_empty() {
+                "none"
+            } else {
+                &self.state.last_open_signal
+            }
+        );
+        log::warn!("{}", message);
+
+        if self.config.alert_when_idle {
+            self.state.alerts.send("Idle fund", &message);
+        }
+    }
+
+    // Every fund draws its own sequence from the seed
+    fn create_rng(random_seed: Option<u64>, fund_name: &str) -> Option<StdRng> {
+        let seed = random_seed?;
+        let mut hasher = DefaultHasher::new();
+        fund_name.hash(&mut hasher);
+        Some(StdRng::seed_from_u64(seed ^ hasher.finish()))
+    }
+
+    // The analyzer draws the RandomWalk opens from an unseeded generator. A seeded back test
+    // draws them from the fund's generator instead, with the chance and the warm-up of the
+    // analyzer, so that it opens the same positions on each run.
+    async fn open_signals(&mut self) -> Vec<TradeAction> {
+        let market_data = self.state.market_data.read().await;
+        match (self.config.strategy, self.state.rng.as_mut()) {
+            (TradingStrategy::RandomWalk(trend_type), Some(rng)) => {
+                // A metric reads as zeros until it has a value
+                let warmed_up = [
+                    market_data.adx(),
+                    market_data.atr(),
+                    market_data.price(),
+                    market_data.rsi(),
+                    market_data.stochastic(),
+                ]
+                .iter()
+                .all(|metric| *metric != Default::default());
+                if !warmed_up || !rng.gen_bool(RANDOM_WALK_OPEN_PROBABILITY) {
+                    return vec![];
+                }
+                let detail = TradeDetail::new(None, None, Decimal::ONE, None);
+                match trend_type {
+                    TrendType::Up => vec![TradeAction::BuyOpen(detail)],
+                    TrendType::Down => vec![TradeAction::SellOpen(detail)],
+                    _ => vec![],
+                }
+            }
+            _ => market_data.is_open_signaled(
+                self.config.strategy,
+                0,
+                self.config.take_profit_ratio.unwrap_or_default(),
+                self.config.atr_spread,
+                self.config.open_order_tick_count_max,
+                &self.config.atr_term,
+            ),
+        }
+    }
+
+    // Logs the open signals of the models without placing any order
+    pub async fn log_signals(&mut self, price: Decimal) {
+        let actions = self.open_signals().await;
+        if actions.is_empty() {
+            return;
+        }
+
+        log::info!("{}: signals = {:?}", self.config.fund_name, actions);
+        self.state
+            .db_handler
+            .lock()
+            .await
+            .log_signals(
+                &self.config.fund_name,
+                &self.config.token_name,
+                price,
+                &actions,
+            )
+            .await;
+    }
+
+    async fn record_equity(&mut self, price: Decimal) {
+        self.state.equity_tick_count += 1;
+        if self.state.equity_tick_count < self.config.equity_sample_ticks {
+            return;
+        }
+        self.state.equity_tick_count = 0;
+
+        let (unrealized_pnl, _) = self.unrealized_pnl_of_open_position(price);
+        let equity = self.config.initial_amount + self.statistics.pnl + unrealized_pnl;
+        self.state
+            .equity_points
+            .push((chrono::Utc::now().timestamp(), equity));
+
+        if self.state.equity_points.len() >= FUND_EQUITY_BATCH_SIZE {
+            self.flush_equity().await;
+        }
+    }
+
+    pub async fn flush_equity(&mut self) {
+        if self.state.equity_points.is_empty() {
+            return;
+        }
+        let equity_points = std::mem::take(&mut self.state.equity_points);
+        self.state
+            .db_handler
+            .lock()
+            .await
+            .log_fund_equity(&self.config.fund_name, &equity_points)
+            .await;
+    }
+
     async fn find_expired_orders(&mut self) {
+        // IOC/FOK orders still pending a tick later were canceled by the venue, as if rejected
+        let immediate_order_ids: Vec<String> = self.state.immediate_order_ids.drain().collect();
+        for order_id in immediate_order_ids {
+            let is_pending = self
+                .find_position_from_order_id(&order_id)
+                .is_some_and(|position| {
+                    matches!(position.state(), State::Opening | State::Closing(_))
+                });
+            if is_pending {
+                log::info!(
+                    "{}: {:?} order is not filled: order_id = {}",
+                    self.config.fund_name,
+                    self.config.time_in_force,
+                    order_id
+                );
+                self.cancel_order(&order_id, true).await;
+            }
+        }
+
         let positions_to_cancel: Vec<TradePosition> = self
             .state
             .trade_positions
@@ -219,87 +734,527 @@ impl FundManager {
         for position in &positions_to_cancel {
             log::debug!("Canceling expired order: order_id:{}", position.order_id());
             self.cancel_order(position.order_id(), false).await;
+
+            if self.config.order_placement_mode == OrderPlacementMode::AlwaysMaker
+                && position.state() == State::Opening
+            {
+                self.reprice_open_order(position).await;
+            }
         }
     }
 
-    async fn find_open_chances(&mut self, current_price: Decimal, dry_run: bool) -> Result<(), ()> {
-        if self.config.trading_amount == Decimal::new(0, 0) {
-            return Ok(());
+    // Re-emits an open order that was canceled without any fill at the current maker price.
+    // An order whose cancel failed may still be on the book, so it is left as it is.
+    async fn reprice_open_order(&mut self, position: &TradePosition) {
+        let is_unfilled = self
+            .state
+            .trade_positions
+            .get(&position.id())
+            .is_some_and(|p| p.amount().is_zero() && matches!(p.state(), State::Canceled(_)));
+        let min_tick = match self.state.last_min_tick {
+            Some(min_tick) => min_tick,
+            None => return,
+        };
+        if !is_unfilled || self.state.current_price.is_zero() {
+            return;
         }
 
-        let mut actions: Vec<TradeAction> = vec![];
-        if !self.can_execute_new_trade() {
-            return self.handle_open_chances(current_price, &actions).await;
-        }
+        let side = match position.position_type() {
+            PositionType::Long => OrderSide::Long,
+            PositionType::Short => OrderSide::Short,
+        };
+        let order_price = self.maker_order_price(&side, min_tick).await;
+        log::info!(
+            "{}: reprice the open order {}: {} -> {}",
+            self.config.fund_name,
+            position.order_id(),
+            position.ordered_price(),
+            order_price
+        );
 
-        if dry_run || !is_sunday() {
-            actions = self.state.market_data.read().await.is_open_signaled(
-                self.config.strategy.clone(),
-                0,
-                self.config.take_profit_ratio.unwrap_or_default(),
-                self.config.atr_spread,
-                self.config.open_order_tick_count_max,
-                &self.config.atr_term,
+        let detail = TradeDetail::new(None, None, Decimal::ONE, None);
+        let chance = TradeChance {
+            token_name: self.config.token_name.clone(),
+            target_price: Some(position.predicted_price()),
+            token_amount: position.unfilled_amount(),
+            action: match side {
+                OrderSide::Long => TradeAction::BuyOpen(detail),
+                OrderSide::Short => TradeAction::SellOpen(detail),
+            },
+            position_id: None,
+        };
+        if self
+            .place_order(Some(order_price), chance, None)
+            .await
+            .is_err()
+        {
+            log::error!(
+                "{}: failed to reprice the open order",
+                self.config.fund_name
             );
         }
+    }
 
-        self.handle_open_chances(current_price, &actions).await
+    // A liquidation, a cut loss and an expired position must not rest as a passive limit
+    // while the price runs away from them
+    fn must_go_through(reason_for_close: &Option<ReasonForClose>) -> bool {
+        matches!(
+            reason_for_close,
+            Some(ReasonForClose::Liquidated)
+                | Some(ReasonForClose::CutLoss)
+                | Some(ReasonForClose::Expired)
+        )
     }
 
-    async fn handle_open_chances(
-        &mut self,
-        current_price: Decimal,
-        actions: &Vec<TradeAction>,
-    ) -> Result<(), ()> {
-        const _GREEN: &str = "\x1b[0;32m";
-        const RED: &str = "\x1b[0;31m";
-        const GREY: &str = "\x1b[0;90m";
-        const RESET: &str = "\x1b[0m";
-        const BLUE: &str = "\x1b[0;34m";
-        const LIGHT_RED: &str = "\x1b[1;31m";
-        const LIGHT_BLUE: &str = "\x1b[1;34m";
+    // A tick inside the spread from the touch of the book, or at the touch when a tick
+    // inside would reach the other side. None without a touch price.
+    fn maker_price(book: &OrderBook, side: &OrderSide, min_tick: Decimal) -> Option<Decimal> {
+        let touch_price = book.touch_price(side)?;
+        let inside_price = match side {
+            OrderSide::Long => touch_price + min_tick,
+            OrderSide::Short => touch_price - min_tick,
+        };
+        let crosses = book.spread_edge(side).is_some_and(|edge_price| match side {
+            OrderSide::Long => inside_price >= edge_price,
+            OrderSide::Short => inside_price <= edge_price,
+        });
+        Some(if crosses { touch_price } else { inside_price })
+    }
 
-        for action in actions.clone() {
-            let is_buy;
-            let (order_price, token_amount, confidence) = match action.clone() {
-                TradeAction::BuyOpen(detail) => {
-                    is_buy = true;
-                    (
-                        detail.order_price(),
-                        detail.amount_in_usd(),
-                        detail.confidence(),
-                    )
-                }
-                TradeAction::SellOpen(detail) => {
-                    is_buy = false;
-                    (
-                        detail.order_price(),
-                        detail.amount_in_usd(),
-                        detail.confidence(),
-                    )
+    // A tick behind the current price on the passive side, for a venue without a book
+    fn passive_price(side: &OrderSide, current_price: Decimal, min_tick: Decimal) -> Decimal {
+        match side {
+            OrderSide::Long => current_price - min_tick,
+            OrderSide::Short => current_price + min_tick,
+        }
+    }
+
+    async fn maker_order_price(&self, side: &OrderSide, min_tick: Decimal) -> Decimal {
+        match self
+            .state
+            .dex_connector
+            .get_orderbook(&self.config.token_name, 1)
+            .await
+        {
+            Ok(book) => {
+                if let Some(price) = Self::maker_price(&book, side, min_tick) {
+                    return price;
                 }
+            }
+            Err(e) => {
+                log::debug!("get_orderbook failed({}): {:?}", self.config.token_name, e);
+            }
+        }
+        Self::passive_price(side, self.state.current_price, min_tick)
+    }
+
+    async fn chase_close_orders(&mut self, current_price: Decimal) -> Result<(), ()> {
+        let trade_positions = &self.state.trade_positions;
+        self.state
+            .close_chase_counts
+            .retain(|id, _| trade_positions.contains_key(id));
+
+        let closing_positions: Vec<TradePosition> = self
+            .state
+            .trade_positions
+            .values()
+            .filter(|v| matches!(v.state(), State::Closing(_)))
+            .cloned()
+            .collect();
+
+        for position in closing_positions {
+            let reason = match position.state() {
+                State::Closing(reason) => Self::reason_for_close_from_str(&reason.to_string()),
                 _ => continue,
             };
 
-            let side = if is_buy {
-                OrderSide::Long
-            } else {
-                OrderSide::Short
-            };
-            let order_price = match self.order_price(current_price, order_price, is_buy).await {
-                Ok(order_price) => order_price,
-                Err(_) => continue,
-            };
-            let token_amount = match token_amount {
-                Some(token_amount) => token_amount * confidence,
-                None => self.config.trading_amount / order_price * confidence,
+            let chase_count = {
+                let count = self
+                    .state
+                    .close_chase_counts
+                    .entry(position.id())
+                    .or_insert(0);
+                *count += 1;
+                *count
             };
-            let target_price = self.target_price(current_price, side, false).await;
-            if target_price.is_none() {
+
+            // Closing --> Open, unless the close order has been filled in the meantime
+            self.cancel_order(position.order_id(), false).await;
+            let is_open = self
+                .state
+                .trade_positions
+                .get(&position.id())
+                .is_some_and(|p| p.state() == State::Open);
+            if !is_open {
+                self.state.close_chase_counts.remove(&position.id());
                 continue;
             }
 
-            if self.state.amount <= token_amount * order_price {
+            let order_price = if chase_count > self.config.max_chase_ticks {
+                log::info!(
+                    "{}: close order of position {} is not filled after {} ticks, fall back to market",
+                    self.config.fund_name,
+                    position.id(),
+                    self.config.max_chase_ticks
+                );
+                None
+            } else {
+                log::debug!(
+                    "{}: chase the close order of position {}: {}/{}, price = {:<6.4}",
+                    self.config.fund_name,
+                    position.id(),
+                    chase_count,
+                    self.config.max_chase_ticks,
+                    current_price
+                );
+                Some(current_price)
+            };
+
+            let chance = TradeChance {
+                token_name: self.config.token_name.clone(),
+                target_price: None,
+                token_amount: position.amount().abs(),
+                action: if position.position_type() == PositionType::Long {
+                    TradeAction::SellClose(TradeDetail::new(None, None, Decimal::ONE, None))
+                } else {
+                    TradeAction::BuyClose(TradeDetail::new(None, None, Decimal::ONE, None))
+                },
+                position_id: Some(position.id()),
+            };
+
+            self.place_order(order_price, chance, Some(reason)).await?;
+        }
+
+        Ok(())
+    }
+
+    fn reason_for_close_from_str(reason: &str) -> ReasonForClose {
+        match reason {
+            "TakeProfit" => ReasonForClose::TakeProfit,
+            "CutLoss" => ReasonForClose::CutLoss,
+            "Expired" => ReasonForClose::Expired,
+            "Liquidated" => ReasonForClose::Liquidated,
+            other => ReasonForClose::Other(other.to_owned()),
+        }
+    }
+
+    async fn find_open_chances(
+        &mut self,
+        current_price: Decimal,
+        dry_run: bool,
+        can_open: bool,
+        dd_ratio: Decimal,
+        trace: &mut DecisionTrace,
+    ) -> Result<(), ()> {
+        if !trace.guard("trading_amount", !self.config.trading_amount.is_zero()) {
+            return Ok(());
+        }
+        let trading_amount = self
+            .config
+            .size_scaling
+            .scale(self.config.trading_amount, dd_ratio);
+        if trading_amount != self.config.trading_amount {
+            trace.indicator("trading_amount", Some(trading_amount));
+        }
+
+        let execution_delay_ticks = self.execution_delay_ticks(current_price).await;
+        let mut actions: Vec<TradeAction> = vec![];
+        if !trace.guard("can_open", can_open)
+            || !trace.guard(
+                "can_execute_new_trade",
+                self.can_execute_new_trade(execution_delay_ticks),
+            )
+            || !trace.guard("max_open_positions", self.is_below_max_open_positions())
+            || !trace.guard(
+                "daily_loss_limit",
+                self.is_within_daily_loss_limit(current_price),
+            )
+        {
+            return self
+                .handle_open_chances(current_price, trading_amount, &actions, trace)
+                .await;
+        }
+
+        if trace.guard(
+            "trading_day",
+            dry_run || TRADING_CALENDAR.is_trading_allowed(chrono::Utc::now()),
+        ) && trace.guard("bar_closed", self.is_bar_closed())
+            && trace.guard(
+                "model_tick",
+                Self::is_model_tick(
+                    self.state.tick_count,
+                    self.config.model_ticks,
+                    !self.state.trade_positions.is_empty(),
+                ),
+            )
+        {
+            self.state.model_evaluation_count += 1;
+            actions = self.open_signals().await;
+        }
+
+        if !actions.is_empty() {
+            self.state.last_open_signal = format!("tick {}: {:?}", self.state.tick_count, actions);
+        }
+        for action in &actions {
+            trace.signal(action);
+        }
+
+        if self.config.require_rsi_divergence && !actions.is_empty() {
+            let divergence = Self::rsi_divergence(&self.state.rsi_history);
+            actions.retain(|action| match (action, &divergence) {
+                (TradeAction::BuyOpen(_), Some(PositionType::Long)) => true,
+                (TradeAction::SellOpen(_), Some(PositionType::Short)) => true,
+                _ => {
+                    log::debug!(
+                        "{}: {:?} is ignored without rsi divergence",
+                        self.config.fund_name,
+                        action
+                    );
+                    false
+                }
+            });
+            trace.guard("rsi_divergence", !actions.is_empty());
+        }
+
+        if let Some(min_entry_zscore) = self.config.min_entry_zscore {
+            if !actions.is_empty() {
+                let zscore = Self::price_zscore(&self.state.price_history, current_price);
+                if !trace.guard("min_entry_zscore", zscore.abs() >= min_entry_zscore) {
+                    log::debug!(
+                        "{}: {:?} is ignored, z-score = {:.3}",
+                        self.config.fund_name,
+                        actions,
+                        zscore
+                    );
+                    actions.clear();
+                }
+            }
+        }
+
+        if !actions.is_empty() {
+            let funding_rate = self.state.market_data.read().await.last_funding_rate();
+            if let Some(funding_rate) = funding_rate {
+                actions.retain(|action| {
+                    let is_acceptable = Self::is_funding_rate_acceptable(
+                        action.is_buy(),
+                        funding_rate,
+                        self.config.max_funding_rate_long,
+                        self.config.max_funding_rate_short,
+                    );
+                    if !is_acceptable {
+                        log::info!(
+                            "{}: skip {} open, funding rate = {}",
+                            self.config.fund_name,
+                            if action.is_buy() { "long" } else { "short" },
+                            funding_rate
+                        );
+                    }
+                    is_acceptable
+                });
+                trace.guard("funding_rate", !actions.is_empty());
+            }
+        }
+
+        self.handle_open_chances(current_price, trading_amount, &actions, trace)
+            .await
+    }
+
+    // Longs pay a positive funding rate and shorts a negative one
+    fn is_funding_rate_acceptable(
+        is_buy: bool,
+        funding_rate: Decimal,
+        max_funding_rate_long: Option<Decimal>,
+        max_funding_rate_short: Option<Decimal>,
+    ) -> bool {
+        if is_buy {
+            max_funding_rate_long.is_none_or(|max_rate| funding_rate <= max_rate)
+        } else {
+            max_funding_rate_short.is_none_or(|max_rate| -funding_rate <= max_rate)
+        }
+    }
+
+    async fn record_rsi(&mut self, price: Decimal) {
+        let rsi = self.state.market_data.read().await.rsi().1;
+        self.state.rsi_history.push_back((price, rsi));
+        if self.state.rsi_history.len() > RSI_DIVERGENCE_WINDOW {
+            self.state.rsi_history.pop_front();
+        }
+    }
+
+    // Bullish divergence (Long): the latest price is a new low of the window while
+    // RSI stays above its value at the previous low. Bearish divergence (Short) is the mirror.
+    fn rsi_divergence(history: &VecDeque<(Decimal, Decimal)>) -> Option<PositionType> {
+        if history.len() < 2 {
+            return None;
+        }
+        let (last_price, last_rsi) = *history.back()?;
+        let previous = history.range(..history.len() - 1);
+
+        let (low_price, low_rsi) = previous.clone().min_by_key(|(price, _)| *price)?;
+        if last_price < *low_price && last_rsi > *low_rsi {
+            return Some(PositionType::Long);
+        }
+
+        let (high_price, high_rsi) = previous.max_by_key(|(price, _)| *price)?;
+        if last_price > *high_price && last_rsi < *high_rsi {
+            return Some(PositionType::Short);
+        }
+
+        None
+    }
+
+    async fn record_vwap(&mut self, price: Decimal) {
+        let volume = self.state.market_data.read().await.last_volume();
+        self.state.vwap_history.push_back((price, volume));
+        if self.state.vwap_history.len() > self.config.vwap_window.max(1) {
+            self.state.vwap_history.pop_front();
+        }
+    }
+
+    // (price - mean) / std over the window. Zero when the prices do not move at all.
+    fn price_zscore(history: &VecDeque<Decimal>, price: Decimal) -> Decimal {
+        if history.len() < 2 {
+            return Decimal::ZERO;
+        }
+        let n = Decimal::from(history.len());
+        let mean = history.iter().sum::<Decimal>() / n;
+        let variance = history
+            .iter()
+            .map(|p| (*p - mean) * (*p - mean))
+            .sum::<Decimal>()
+            / n;
+        let std = variance
+            .to_f64()
+            .and_then(|v| Decimal::from_f64(v.sqrt()))
+            .unwrap_or_default();
+        if std.is_zero() {
+            return Decimal::ZERO;
+        }
+        (price - mean) / std
+    }
+
+    // When any sample lacks a volume, all the samples are weighted equally
+    // and this falls back to the simple average of the prices.
+    fn vwap(history: &VecDeque<(Decimal, Option<Decimal>)>) -> Option<Decimal> {
+        if history.is_empty() {
+            return None;
+        }
+
+        let has_volume = history.iter().all(|(_, volume)| volume.is_some());
+        let (weighted_sum, total_weight) = history.iter().fold(
+            (Decimal::ZERO, Decimal::ZERO),
+            |(weighted_sum, total_weight), (price, volume)| {
+                let weight = match volume {
+                    Some(volume) if has_volume => *volume,
+                    _ => Decimal::ONE,
+                };
+                (weighted_sum + *price * weight, total_weight + weight)
+            },
+        );
+
+        if total_weight.is_zero() {
+            return None;
+        }
+        Some(weighted_sum / total_weight)
+    }
+
+    async fn handle_open_chances(
+        &mut self,
+        current_price: Decimal,
+        trading_amount: Decimal,
+        actions: &Vec<TradeAction>,
+        trace: &mut DecisionTrace,
+    ) -> Result<(), ()> {
+        const _GREEN: &str = "\x1b[0;32m";
+        const RED: &str = "\x1b[0;31m";
+        const GREY: &str = "\x1b[0;90m";
+        const RESET: &str = "\x1b[0m";
+        const BLUE: &str = "\x1b[0;34m";
+        const LIGHT_RED: &str = "\x1b[1;31m";
+        const LIGHT_BLUE: &str = "\x1b[1;34m";
+
+        // The orders are checked against the amount in the settlement unit
+        self.settle_initial_amount(current_price);
+
+        let (confident_actions, below_confidence) =
+            Self::filter_confident_opens(actions, self.config.min_open_confidence);
+        if below_confidence > 0 {
+            trace.guard("min_open_confidence", false);
+            log::info!(
+                "{}: {} open signal(s) below the confidence {:.3} are skipped",
+                self.config.fund_name,
+                below_confidence,
+                self.config.min_open_confidence
+            );
+        }
+
+        // Without an ATR the position would be opened without a cut loss
+        if !confident_actions.is_empty()
+            && !trace.guard("atr_warmup", self.warmed_up_atr().await.is_some())
+        {
+            return Ok(());
+        }
+
+        for action in confident_actions {
+            let is_buy;
+            let (order_price, token_amount, confidence) = match action.clone() {
+                TradeAction::BuyOpen(detail) => {
+                    is_buy = true;
+                    (
+                        detail.order_price(),
+                        detail.amount_in_usd(),
+                        detail.confidence(),
+                    )
+                }
+                TradeAction::SellOpen(detail) => {
+                    is_buy = false;
+                    (
+                        detail.order_price(),
+                        detail.amount_in_usd(),
+                        detail.confidence(),
+                    )
+                }
+                _ => continue,
+            };
+
+            let side = if is_buy {
+                OrderSide::Long
+            } else {
+                OrderSide::Short
+            };
+            let order_price = match self.order_price(current_price, order_price, is_buy).await {
+                Ok(order_price) => order_price,
+                Err(_) => {
+                    trace.guard("order_price", false);
+                    continue;
+                }
+            };
+            let mut token_amount = match token_amount {
+                Some(token_amount) => token_amount * confidence,
+                None => trading_amount / order_price * confidence,
+            };
+            let averaging = self.averaging_position().map(|position| {
+                Self::is_averaging_improvement(
+                    position.position_type(),
+                    position.average_open_price(),
+                    is_buy,
+                    order_price,
+                    self.config.min_average_improvement,
+                )
+            });
+            if !trace.guard("averaging", averaging.unwrap_or(true)) {
+                continue;
+            }
+            let target_price = self.target_price(current_price, side.clone(), false).await;
+            if !trace.guard("target_price", target_price.is_some()) {
+                continue;
+            }
+
+            let order_value = self
+                .config
+                .contract_type
+                .to_settlement(token_amount * order_price, order_price);
+            if !trace.guard("enough_fund", self.state.amount > order_value) {
                 log::warn!(
                     "{} does not have enough fund: {:.6}",
                     self.config.fund_name,
@@ -308,6 +1263,35 @@ impl FundManager {
                 continue;
             }
 
+            // The part that would cross the resting orders of the other funds is traded with
+            // them here, and only the rest is sent to the venue
+            let crosses = Self::reserve_crosses(
+                &self.config.fund_name,
+                &side,
+                order_price,
+                token_amount,
+                &mut self.state.shared_resting_orders.lock().unwrap(),
+            );
+            if !crosses.is_empty() {
+                let netted: Decimal = crosses.iter().map(|cross| cross.size).sum();
+                log::info!(
+                    "{}: net {} against the resting orders of the other funds",
+                    self.config.fund_name,
+                    netted
+                );
+                for cross in &crosses {
+                    self.take_cross(cross, action, target_price).await?;
+                }
+                token_amount -= netted;
+                if !trace.guard("self_trade", !token_amount.is_zero()) {
+                    continue;
+                }
+            }
+
+            trace.action(format!(
+                "open {:?} at {}, amount = {}",
+                action, order_price, token_amount
+            ));
             self.execute_chances(
                 order_price,
                 TradeChance {
@@ -489,8 +1473,49 @@ impl FundManager {
         Ok(())
     }
 
-    async fn find_close_chances(&mut self, current_price: Decimal) -> Result<(), ()> {
+    // Drops the open signals below `min_confidence`, and returns how many were dropped
+    fn filter_confident_opens(
+        actions: &[TradeAction],
+        min_confidence: Decimal,
+    ) -> (Vec<TradeAction>, usize) {
+        let confident_actions: Vec<TradeAction> = actions
+            .iter()
+            .filter(|action| match action {
+                TradeAction::BuyOpen(detail) | TradeAction::SellOpen(detail) => {
+                    let is_confident = detail.confidence() >= min_confidence;
+                    if !is_confident {
+                        log::debug!(
+                            "ignore the open signal({:?}): confidence {:.3} < {:.3}",
+                            action,
+                            detail.confidence(),
+                            min_confidence
+                        );
+                    }
+                    is_confident
+                }
+                _ => true,
+            })
+            .cloned()
+            .collect();
+        let below_confidence = actions.len() - confident_actions.len();
+        (confident_actions, below_confidence)
+    }
+
+    async fn find_close_chances(
+        &mut self,
+        current_price: Decimal,
+        trace: &mut DecisionTrace,
+    ) -> Result<(), ()> {
         let cloned_open_positions = self.state.trade_positions.clone();
+        self.state
+            .ladder_rungs_fired
+            .retain(|id, _| cloned_open_positions.contains_key(id));
+        self.state
+            .partial_close_sizes
+            .retain(|id, _| cloned_open_positions.contains_key(id));
+        self.state
+            .trend_change_counts
+            .retain(|id, _| cloned_open_positions.contains_key(id));
 
         for (position_id, position) in cloned_open_positions.iter() {
             match position.state() {
@@ -502,30 +1527,113 @@ impl FundManager {
                 State::Open => {}
                 _ => continue,
             }
-            let action = self.state.market_data.read().await.is_close_signaled(
-                self.config.strategy.clone(),
-                position.asset_in_usd().abs(),
-                self.is_profitable_position(*position_id).await,
-            );
+            // Take-profit, cut-loss and expiry are still checked within a bar
+            let action = if self.is_bar_closed() {
+                Some(self.state.market_data.read().await.is_close_signaled(
+                    self.config.strategy,
+                    position.asset_in_usd().abs(),
+                    self.is_profitable_position(*position_id).await,
+                ))
+            } else {
+                None
+            };
+            if let Some(action) = &action {
+                trace.signal(action);
+            }
 
-            self.handle_close_chances(current_price, *position_id, position, &action)
-                .await?;
+            self.handle_close_chances(
+                current_price,
+                *position_id,
+                position,
+                action.as_ref(),
+                trace,
+            )
+            .await?;
         }
 
         Ok(())
     }
 
+    // A Long is closed when the Stochastic-RSI falls back below overbought, a Short when it
+    // rises back above oversold
+    fn is_stoch_rsi_crossed(position_type: PositionType, stochastics: &VecDeque<Decimal>) -> bool {
+        let (prev, latest) = match (stochastics.front(), stochastics.back()) {
+            (Some(prev), Some(latest)) if stochastics.len() == 2 => (*prev, *latest),
+            _ => return false,
+        };
+        match position_type {
+            PositionType::Long => prev >= STOCH_RSI_OVERBOUGHT && latest < STOCH_RSI_OVERBOUGHT,
+            PositionType::Short => prev <= STOCH_RSI_OVERSOLD && latest > STOCH_RSI_OVERSOLD,
+        }
+    }
+
+    // The model inputs change slowly, so a back test may evaluate it only every model_ticks.
+    // It is evaluated on every tick while a position is open.
+    fn is_model_tick(tick_count: u64, model_ticks: Option<u32>, has_position: bool) -> bool {
+        match model_ticks {
+            Some(model_ticks) if !has_position => {
+                tick_count.is_multiple_of(u64::from(model_ticks.max(1)))
+            }
+            _ => true,
+        }
+    }
+
+    // With signal_on_bar_close, signals are evaluated only on the tick that closes a bar
+    fn is_bar_closed(&self) -> bool {
+        !self.config.signal_on_bar_close
+            || self
+                .state
+                .tick_count
+                .is_multiple_of(self.config.bar_ticks.max(1))
+    }
+
     async fn handle_close_chances(
         &mut self,
         current_price: Decimal,
         position_id: u32,
         position: &TradePosition,
-        action: &TradeAction,
+        action: Option<&TradeAction>,
+        trace: &mut DecisionTrace,
     ) -> Result<(), ()> {
+        // Only the stops close a position younger than the minimum holding period
+        let within_min_holding = Self::is_within_min_holding(
+            position.open_timestamp(),
+            chrono::Utc::now().timestamp(),
+            self.config.min_holding_secs,
+        );
+        if within_min_holding {
+            trace.action(format!("hold {}: min_holding_secs", position_id));
+        }
+        let action = if within_min_holding { None } else { action };
+
+        // Without a new signal in this cycle, e.g. within a bar, the count is kept
+        let trend_change_confirmed = match action {
+            Some(action) => {
+                let signaled = match action {
+                    TradeAction::BuyClose(_) => {
+                        position.position_type() == PositionType::Short
+                            && self.is_close_confident(action)
+                    }
+                    TradeAction::SellClose(_) => {
+                        position.position_type() == PositionType::Long
+                            && self.is_close_confident(action)
+                    }
+                    _ => false,
+                };
+                Self::is_trend_change_confirmed(
+                    &mut self.state.trend_change_counts,
+                    position_id,
+                    signaled,
+                    self.config.trend_change_confirm_ticks,
+                )
+            }
+            None => false,
+        };
+
         let mut confidence = Decimal::ONE;
         let mut reason_for_close = match action {
-            TradeAction::BuyClose(_) => {
-                if position.position_type() == PositionType::Short {
+            Some(action @ TradeAction::BuyClose(_)) => {
+                if trend_change_confirmed {
                     self.statistics.trend_changed_count += 1;
                     confidence = action.confidence().unwrap_or_default();
                     self.cancel_all_orders().await;
@@ -534,8 +1642,8 @@ impl FundManager {
                     None
                 }
             }
-            TradeAction::SellClose(_) => {
-                if position.position_type() == PositionType::Long {
+            Some(action @ TradeAction::SellClose(_)) => {
+                if trend_change_confirmed {
                     self.statistics.trend_changed_count += 1;
                     confidence = action.confidence().unwrap_or_default();
                     self.cancel_all_orders().await;
@@ -544,8 +1652,10 @@ impl FundManager {
                     None
                 }
             }
-            TradeAction::BuyTrim(_) => {
-                if position.position_type() == PositionType::Short {
+            Some(action @ TradeAction::BuyTrim(_)) => {
+                if position.position_type() == PositionType::Short
+                    && self.is_close_confident(action)
+                {
                     self.statistics.trim_count += 1;
                     confidence = action.confidence().unwrap_or_default();
                     self.cancel_all_orders().await;
@@ -554,8 +1664,10 @@ impl FundManager {
                     None
                 }
             }
-            TradeAction::SellTrim(_) => {
-                if position.position_type() == PositionType::Long {
+            Some(action @ TradeAction::SellTrim(_)) => {
+                if position.position_type() == PositionType::Long
+                    && self.is_close_confident(action)
+                {
                     self.statistics.trim_count += 1;
                     confidence = action.confidence().unwrap_or_default();
                     self.cancel_all_orders().await;
@@ -567,27 +1679,93 @@ impl FundManager {
             _ => None,
         };
 
+        if reason_for_close.is_none() && self.is_trailing_stop_hit(position, current_price) {
+            self.statistics.trailing_stop_count += 1;
+            reason_for_close = Some(ReasonForClose::Other("TrailingStop".to_owned()));
+        }
+
+        if reason_for_close.is_none()
+            && !within_min_holding
+            && self.config.use_stoch_rsi_exit
+            && position.state() == State::Open
+            && Self::is_stoch_rsi_crossed(position.position_type(), &self.state.stochastics)
+        {
+            reason_for_close = Some(ReasonForClose::Other("StochCross".to_owned()));
+        }
+
         if reason_for_close.is_none() {
             reason_for_close = position.should_close(current_price);
+            if within_min_holding && matches!(reason_for_close, Some(ReasonForClose::TakeProfit)) {
+                reason_for_close = None;
+            }
             if let Some(reason) = reason_for_close.clone() {
                 match reason {
                     ReasonForClose::TakeProfit => self.statistics.take_profit_count += 1,
                     ReasonForClose::CutLoss => self.statistics.cut_loss_count += 1,
                     _ => {}
                 }
-            } else if position.should_open_expired() {
+            } else if !within_min_holding
+                && self.config.expire_open_positions
+                && position.should_open_expired()
+            {
                 reason_for_close = Some(ReasonForClose::Expired);
                 self.statistics.expired_count += 1;
             }
         }
 
+        let mut token_amount = position.amount().abs() * confidence;
+
+        if reason_for_close.is_none()
+            && !within_min_holding
+            && !self.config.take_profit_ladder.is_empty()
+            && position.state() == State::Open
+        {
+            let fired = self
+                .state
+                .ladder_rungs_fired
+                .get(&position_id)
+                .copied()
+                .unwrap_or(0);
+            let reached = Self::reached_ladder_rungs(
+                position.position_type(),
+                position.average_open_price(),
+                current_price,
+                &self.config.take_profit_ladder,
+            );
+            if reached > fired {
+                let fractions: Vec<Decimal> = self.config.take_profit_ladder[fired..reached]
+                    .iter()
+                    .map(|(_, fraction)| *fraction)
+                    .collect();
+                token_amount = Self::ladder_close_amount(position.amount().abs(), &fractions);
+                self.state.ladder_rungs_fired.insert(position_id, reached);
+
+                // The rest rides with the stop moved to breakeven
+                let breakeven = position.average_open_price();
+                let stop_price = match (
+                    position.position_type(),
+                    self.state.trailing_stop_prices.get(&position_id),
+                ) {
+                    (PositionType::Long, Some(stop)) => std::cmp::max(*stop, breakeven),
+                    (PositionType::Short, Some(stop)) => std::cmp::min(*stop, breakeven),
+                    (_, None) => breakeven,
+                };
+                self.state
+                    .trailing_stop_prices
+                    .insert(position_id, stop_price);
+
+                reason_for_close = Some(ReasonForClose::Other("TakeProfitLadder".to_owned()));
+            }
+        }
+
         let mut chance: Option<TradeChance> = None;
 
-        if reason_for_close.is_some() {
+        if let Some(reason) = &reason_for_close {
+            trace.action(format!("close {}: {}", position_id, reason));
             chance = Some(TradeChance {
                 token_name: self.config.token_name.clone(),
                 target_price: None,
-                token_amount: position.amount().abs() * confidence,
+                token_amount,
                 action: if position.position_type() == PositionType::Long {
                     TradeAction::SellClose(TradeDetail::new(None, None, Decimal::ONE, None))
                 } else {
@@ -605,12 +1783,55 @@ impl FundManager {
         Ok(())
     }
 
-    fn can_execute_new_trade(&self) -> bool {
-        if !self.state.trade_positions.is_empty() {
+    // The number of the rungs the price has reached, as the ladder is in ascending price ratio
+    fn reached_ladder_rungs(
+        position_type: PositionType,
+        average_open_price: Decimal,
+        price: Decimal,
+        ladder: &[(Decimal, Decimal)],
+    ) -> usize {
+        if average_open_price.is_zero() {
+            return 0;
+        }
+        let gain_ratio = match position_type {
+            PositionType::Long => price / average_open_price - Decimal::ONE,
+            PositionType::Short => Decimal::ONE - price / average_open_price,
+        };
+        ladder
+            .iter()
+            .take_while(|(ratio, _)| gain_ratio >= *ratio)
+            .count()
+    }
+
+    // Each fraction applies to what is left after the previous rungs
+    fn ladder_close_amount(amount: Decimal, fractions: &[Decimal]) -> Decimal {
+        let remaining = fractions.iter().fold(amount, |remaining, fraction| {
+            remaining * (Decimal::ONE - fraction)
+        });
+        amount - remaining
+    }
+
+    fn is_close_confident(&self, action: &TradeAction) -> bool {
+        let confidence = action.confidence().unwrap_or_default();
+        if confidence < self.config.min_close_confidence {
+            log::debug!(
+                "{}: ignore the close signal({:?}): confidence {:.3} < {:.3}",
+                self.config.fund_name,
+                action,
+                confidence,
+                self.config.min_close_confidence
+            );
+            return false;
+        }
+        true
+    }
+
+    fn can_execute_new_trade(&self, execution_delay_ticks: u64) -> bool {
+        if !self.state.trade_positions.is_empty() && self.averaging_position().is_none() {
             return false;
         }
 
-        if self.state.trade_tick_count < self.config.execution_delay_tick_count_max.into() {
+        if self.state.trade_tick_count < execution_delay_ticks {
             log::info!(
                 "{}: Waiting for delay period to pass before executing new trades",
                 self.config.fund_name
@@ -621,10 +1842,125 @@ impl FundManager {
         true
     }
 
-    async fn execute_chances(
-        &mut self,
+    // With allow_averaging, the only position when it is fully open and nothing is pending
+    fn averaging_position(&self) -> Option<&TradePosition> {
+        if !self.config.allow_averaging || self.state.trade_positions.len() != 1 {
+            return None;
+        }
+        let position = self
+            .state
+            .trade_positions
+            .get(&self.state.latest_open_position_id?)?;
+        (position.state() == State::Open).then_some(position)
+    }
+
+    // A same-direction open at least min_improvement better than the average price
+    fn is_averaging_improvement(
+        position_type: PositionType,
+        average_open_price: Decimal,
+        is_buy: bool,
         order_price: Decimal,
-        chance: TradeChance,
+        min_improvement: Decimal,
+    ) -> bool {
+        match position_type {
+            PositionType::Long => {
+                is_buy && order_price <= average_open_price * (Decimal::ONE - min_improvement)
+            }
+            PositionType::Short => {
+                !is_buy && order_price >= average_open_price * (Decimal::ONE + min_improvement)
+            }
+        }
+    }
+
+    async fn execution_delay_ticks(&self, price: Decimal) -> u64 {
+        let delay_ticks = self.config.execution_delay_tick_count_max;
+        match self.config.execution_delay_atr_factor {
+            Some(factor) => {
+                let atr = self
+                    .state
+                    .market_data
+                    .read()
+                    .await
+                    .atr_by_term(&self.config.atr_term);
+                Self::scaled_execution_delay(delay_ticks, factor, atr, price)
+            }
+            None => delay_ticks.into(),
+        }
+    }
+
+    // delay * (1 + factor * ATR / price), rounded up. The ATR is zero while warming up.
+    fn scaled_execution_delay(
+        delay_ticks: u32,
+        factor: Decimal,
+        atr: Decimal,
+        price: Decimal,
+    ) -> u64 {
+        if price.is_zero() {
+            return delay_ticks.into();
+        }
+        let scale = Decimal::ONE + factor * atr / price;
+        (Decimal::from(delay_ticks) * scale)
+            .ceil()
+            .to_u64()
+            .unwrap_or(delay_ticks.into())
+    }
+
+    // Realized plus unrealized loss of the UTC day. Closes are not affected.
+    fn is_within_daily_loss_limit(&self, price: Decimal) -> bool {
+        let daily_loss_limit = match self.config.daily_loss_limit {
+            Some(v) => v,
+            None => return true,
+        };
+        let (unrealized_pnl, _) = self.unrealized_pnl_of_open_position(price);
+        let daily_pnl = self.statistics.daily_pnl(chrono::Utc::now().timestamp()) + unrealized_pnl;
+        if Self::is_daily_loss_breached(daily_pnl, daily_loss_limit) {
+            log::info!(
+                "{}: the daily loss {:.3} exceeds the limit {:.3}, skip opening",
+                self.config.fund_name,
+                -daily_pnl,
+                daily_loss_limit
+            );
+            return false;
+        }
+        true
+    }
+
+    fn is_daily_loss_breached(daily_pnl: Decimal, daily_loss_limit: Decimal) -> bool {
+        -daily_pnl > daily_loss_limit
+    }
+
+    fn is_below_max_open_positions(&self) -> bool {
+        let open_positions = Self::count_open_positions(
+            self.state
+                .trade_positions
+                .values()
+                .map(|position| position.state()),
+        );
+        if Self::is_below_cap(open_positions, self.config.max_open_positions) {
+            return true;
+        }
+        log::info!(
+            "{}: {} positions are open, skip opening a new one",
+            self.config.fund_name,
+            open_positions
+        );
+        false
+    }
+
+    fn count_open_positions(states: impl Iterator<Item = State>) -> usize {
+        states
+            .filter(|state| matches!(state, State::Opening | State::Open))
+            .count()
+    }
+
+    fn is_below_cap(count: usize, cap: Option<usize>) -> bool {
+        cap.is_none_or(|cap| count < cap)
+    }
+
+    async fn execute_chances(
+        &mut self,
+        order_price: Decimal,
+        mut chance: TradeChance,
         reason_for_close: Option<ReasonForClose>,
     ) -> Result<(), ()> {
         if chance.token_amount <= Decimal::new(0, 0) {
@@ -635,7 +1971,33 @@ impl FundManager {
             return Err(());
         }
 
-        let symbol = &self.config.token_name;
+        // The venue would reject the order anyway
+        chance.token_amount = match Self::fit_order_size(
+            chance.token_amount,
+            self.config.min_order_size,
+            self.config.max_order_size,
+        ) {
+            Some(size) => {
+                if size != chance.token_amount {
+                    log::info!(
+                        "{}: cap the order size {} to {}",
+                        self.config.fund_name,
+                        chance.token_amount,
+                        size
+                    );
+                }
+                size
+            }
+            None => {
+                log::info!(
+                    "{}: skip the order below the min size: {}",
+                    self.config.fund_name,
+                    chance.token_amount
+                );
+                return Ok(());
+            }
+        };
+
         let size = chance.token_amount;
         let side = if chance.action.is_buy() {
             OrderSide::Long
@@ -674,15 +2036,184 @@ impl FundManager {
             _ => Some(order_price),
         };
 
+        let order_price = match self.config.order_placement_mode {
+            OrderPlacementMode::Adaptive => order_price,
+            OrderPlacementMode::AlwaysTaker => None,
+            // A close that has to go through is placed as Adaptive places it
+            _ if Self::must_go_through(&reason_for_close) => order_price,
+            OrderPlacementMode::AlwaysMaker => {
+                match (self.state.last_min_tick, self.state.current_price) {
+                    (Some(min_tick), current_price) if !current_price.is_zero() => {
+                        Some(self.maker_order_price(&side, min_tick).await)
+                    }
+                    _ => order_price,
+                }
+            }
+        };
+
+        // A post-only limit open that would cross is rejected by the venue
+        let order_price = match order_price {
+            Some(price)
+                if self.config.prefer_maker
+                    && chance.action.is_open()
+                    && Self::would_cross(&side, price, self.state.current_price) =>
+            {
+                if !self.config.maker_fallback_to_taker {
+                    log::info!(
+                        "{}: skip the open that would cross: order_price = {}, current_price = {}",
+                        self.config.fund_name,
+                        price,
+                        self.state.current_price
+                    );
+                    return Ok(());
+                }
+                log::info!(
+                    "{}: fall back to a taker order: order_price = {}, current_price = {}",
+                    self.config.fund_name,
+                    price,
+                    self.state.current_price
+                );
+                None
+            }
+            order_price => order_price,
+        };
+
+        // A large market open on a thin book is placed at the spread edge instead
+        let order_price = match (order_price, self.config.max_market_slippage) {
+            (None, Some(max_slippage)) if chance.action.is_open() => {
+                match self
+                    .state
+                    .dex_connector
+                    .get_orderbook(&self.config.token_name, ORDERBOOK_DEPTH)
+                    .await
+                {
+                    Ok(book) => {
+                        let edge_price =
+                            Self::edge_price_for_slippage(&book, &side, size, max_slippage);
+                        if let Some(price) = edge_price {
+                            log::info!(
+                                "{}: the market order is too large for the book, place it at {}",
+                                self.config.fund_name,
+                                price
+                            );
+                        }
+                        edge_price
+                    }
+                    Err(e) => {
+                        log::warn!("get_orderbook failed({}): {:?}", self.config.token_name, e);
+                        None
+                    }
+                }
+            }
+            (order_price, _) => order_price,
+        };
+
+        self.place_order(order_price, chance, reason_for_close).await
+    }
+
+    // Returns the limit price to use when a market order of `size` would slip
+    // more than `max_slippage`, or the book is not deep enough to tell
+    fn edge_price_for_slippage(
+        book: &OrderBook,
+        side: &OrderSide,
+        size: Decimal,
+        max_slippage: Decimal,
+    ) -> Option<Decimal> {
+        match book.estimate_slippage(side, size) {
+            Some(slippage) if slippage <= max_slippage => None,
+            _ => book.spread_edge(side),
+        }
+    }
+
+    fn round_order_price(
+        price: Decimal,
+        min_tick: Decimal,
+        side: &OrderSide,
+        rounding_mode: RoundingMode,
+    ) -> Decimal {
+        if min_tick.is_zero() {
+            return price;
+        }
+        let ticks = price / min_tick;
+        let ticks = match (rounding_mode, side) {
+            (RoundingMode::Nearest, _) => ticks.round(),
+            (RoundingMode::Favorable, OrderSide::Long) => ticks.floor(),
+            (RoundingMode::Favorable, OrderSide::Short) => ticks.ceil(),
+        };
+        ticks * min_tick
+    }
+
+    // None when the size is below the min, or the size capped to the max
+    fn fit_order_size(
+        size: Decimal,
+        min_order_size: Option<Decimal>,
+        max_order_size: Option<Decimal>,
+    ) -> Option<Decimal> {
+        if min_order_size.is_some_and(|min_size| size < min_size) {
+            return None;
+        }
+        Some(max_order_size.map_or(size, |max_size| size.min(max_size)))
+    }
+
+    fn would_cross(side: &OrderSide, order_price: Decimal, current_price: Decimal) -> bool {
+        if current_price.is_zero() {
+            return false;
+        }
+        match side {
+            OrderSide::Long => order_price >= current_price,
+            OrderSide::Short => order_price <= current_price,
+        }
+    }
+
+    async fn place_order(
+        &mut self,
+        order_price: Option<Decimal>,
+        chance: TradeChance,
+        reason_for_close: Option<ReasonForClose>,
+    ) -> Result<(), ()> {
+        let symbol = &self.config.token_name;
+        let size = chance.token_amount;
+        let side = if chance.action.is_buy() {
+            OrderSide::Long
+        } else {
+            OrderSide::Short
+        };
+        let order_price = match (order_price, self.state.last_min_tick) {
+            (Some(price), Some(min_tick)) => Some(Self::round_order_price(
+                price,
+                min_tick,
+                &side,
+                self.config.rounding_mode,
+            )),
+            (order_price, _) => order_price,
+        };
+
+        let resubmit_attempts = if chance.action.is_open() {
+            self.config.open_resubmit_attempts
+        } else {
+            0
+        };
+        let submitted_at = Instant::now();
         let res: Result<CreateOrderResponse, DexError> = self
             .state
             .dex_connector
-            .create_order(symbol, size, side.clone(), order_price, None)
+            .create_order_with_resubmit(
+                symbol,
+                size,
+                side.clone(),
+                order_price,
+                self.config.time_in_force,
+                resubmit_attempts,
+            )
             .await;
         match res {
             Ok(res) => {
                 if res.ordered_size > Decimal::new(0, 0) {
                     let order_id = res.order_id;
+                    self.state.fill_latencies.submitted(&order_id, submitted_at);
+                    if order_price.is_some() && self.config.time_in_force != TimeInForce::Gtc {
+                        self.state.immediate_order_ids.insert(order_id.clone());
+                    }
                     self.prepare_position(
                         &order_id,
                         if res.ordered_price == Decimal::new(0, 0) {
@@ -784,7 +2315,29 @@ impl FundManager {
                 market_data.last_oracle_price(),
             );
 
+            if let Some(candle_pattern) = self.candle_pattern() {
+                log::info!(
+                    "{}: candle pattern at open = {:?}",
+                    self.config.fund_name,
+                    candle_pattern
+                );
+            }
+
+            self.state.resting_open_orders.insert(
+                position.id(),
+                RestingOrder {
+                    order_id: order_id.to_owned(),
+                    side: if position.position_type() == PositionType::Long {
+                        OrderSide::Long
+                    } else {
+                        OrderSide::Short
+                    },
+                    price: ordered_price.unwrap(),
+                    size: ordered_amount,
+                },
+            );
             self.state.trade_positions.insert(position.id(), position);
+            self.state.open_order_count += 1;
         } else {
             if let Some(position_id) = position_id {
                 let position = self.state.trade_positions.get_mut(&position_id);
@@ -796,7 +2349,14 @@ impl FundManager {
                     return Err(());
                 }
                 let position = position.unwrap();
+                let is_partial = ordered_amount < position.amount().abs();
                 position.request_close(order_id, &reason_for_close.clone().unwrap().to_string())?;
+                self.state.partial_close_sizes.remove(&position_id);
+                if is_partial {
+                    self.state
+                        .partial_close_sizes
+                        .insert(position_id, ordered_amount);
+                }
             } else {
                 log::warn!("prepare_position: position not found(None)");
                 return Err(());
@@ -830,9 +2390,13 @@ impl FundManager {
     fn unrealized_pnl_of_open_position(&self, price: Decimal) -> (Decimal, Decimal) {
         match self.get_open_position() {
             Some(position) => {
-                let pnl = position.amount() * price + position.asset_in_usd();
-                let ratio = if position.asset_in_usd().abs().is_sign_positive() {
-                    pnl / position.asset_in_usd().abs()
+                let contract_type = self.config.contract_type;
+                let pnl = contract_type
+                    .to_settlement(position.amount() * price + position.asset_in_usd(), price);
+                let asset = contract_type
+                    .to_settlement(position.asset_in_usd().abs(), position.average_open_price());
+                let ratio = if !asset.is_zero() {
+                    pnl / asset
                 } else {
                     Decimal::new(0, 0)
                 };
@@ -914,6 +2478,9 @@ impl FundManager {
                         open_position_id
                     );
 
+                    let is_averaging = open_position.position_type() == position_type
+                        && open_position.state() == State::Open;
+
                     open_position.on_filled(
                         position_type,
                         filled_price,
@@ -924,6 +2491,16 @@ impl FundManager {
                         cut_loss_price,
                         market_data.last_price(),
                     )?;
+
+                    if is_averaging {
+                        log::info!(
+                            "{}: averaged into the position {}: size = {}, average price = {:.6}",
+                            self.config.fund_name,
+                            open_position_id,
+                            open_position.amount().abs(),
+                            open_position.average_open_price()
+                        );
+                    }
                 }
                 None => {
                     log::error!(
@@ -951,19 +2528,42 @@ impl FundManager {
         Ok(())
     }
 
-    fn update_state_after_trade(&mut self, filled_value: Decimal) -> Decimal {
+    fn settle_initial_amount(&mut self, price: Decimal) -> Decimal {
+        if let Some(initial_amount) = self.state.initial_amount {
+            return initial_amount;
+        }
+        let initial_amount = self
+            .config
+            .contract_type
+            .to_settlement(self.config.initial_amount, price);
+        log::info!(
+            "{}: initial amount = {} at {}",
+            self.config.fund_name,
+            initial_amount,
+            price
+        );
+        self.state.initial_amount = Some(initial_amount);
+        self.state.amount = initial_amount;
+        self.statistics.min_amount = initial_amount;
+        initial_amount
+    }
+
+    fn update_state_after_trade(
+        &mut self,
+        filled_value: Decimal,
+        filled_price: Decimal,
+    ) -> Decimal {
+        let initial_amount = self.settle_initial_amount(filled_price);
+        let contract_type = self.config.contract_type;
         let prev_amount = self.state.amount;
         match self.state.latest_open_position_id {
             Some(position_id) => {
                 let position = self.state.trade_positions.get(&position_id).unwrap();
-                let position_asset = position.asset_in_usd();
-                self.state.amount = if position_asset.is_sign_positive() {
-                    self.config.initial_amount - position_asset
-                } else {
-                    self.config.initial_amount + position_asset
-                }
+                let position_asset = contract_type
+                    .to_settlement(position.asset_in_usd(), position.average_open_price());
+                self.state.amount = initial_amount + self.state.realized_pnl - position_asset.abs();
             }
-            None => self.state.amount -= filled_value,
+            None => self.state.amount -= contract_type.to_settlement(filled_value, filled_price),
         }
         prev_amount
     }
@@ -983,6 +2583,7 @@ impl FundManager {
     pub async fn position_filled(
         &mut self,
         order_id: &str,
+        trade_id: &str,
         filled_side: OrderSide,
         filled_value: Decimal,
         filled_size: Decimal,
@@ -1006,6 +2607,56 @@ impl FundManager {
             }
         };
 
+        // A close fill can't take more than the position holds. If it does, open fills of the
+        // position are still to come, and applying the close first would flip the position.
+        // It is deferred until they have been applied.
+        if matches!(position.state(), State::Closing(_)) && filled_size > position.amount().abs() {
+            let is_deferred = self
+                .state
+                .deferred_fills
+                .iter()
+                .any(|fill| fill.trade_id == trade_id);
+            if !is_deferred {
+                let message = format!(
+                    "{}: defer the close fill before its open fills: order_id = {}, trade_id = {}, size = {}, position size = {}",
+                    self.fund_name(),
+                    order_id,
+                    trade_id,
+                    filled_size,
+                    position.amount().abs()
+                );
+                log::error!("{}", message);
+                self.state.alerts.send("Out-of-order fill", &message);
+                self.state.deferred_fills.push(DeferredFill {
+                    order_id: order_id.to_owned(),
+                    trade_id: trade_id.to_owned(),
+                    filled_side,
+                    filled_value,
+                    filled_size,
+                    fee,
+                });
+            }
+            return Ok(false);
+        }
+
+        if !self
+            .state
+            .processed_trade_ids
+            .insert(trade_id, position.id())
+        {
+            log::warn!(
+                "{}: Ignore the duplicated fill: order_id = {}, trade_id = {}",
+                self.fund_name(),
+                order_id,
+                trade_id
+            );
+            return Ok(false);
+        }
+
+        if let Some(latency) = self.state.fill_latencies.filled(order_id, Instant::now()) {
+            self.log_fill_latency(latency).await;
+        }
+
         let target_price = position.predicted_price();
         let position_type = match filled_side {
             OrderSide::Long => PositionType::Long,
@@ -1025,6 +2676,16 @@ impl FundManager {
             filled_price,
         );
 
+        if let Some(max_acceptable_slippage) = self.config.max_acceptable_slippage {
+            self.check_slippage(
+                order_id,
+                &filled_side,
+                position.ordered_price(),
+                filled_price,
+                max_acceptable_slippage,
+            );
+        }
+
         let take_profit_price = self.take_profit_price(target_price);
         let cut_loss_price = self.cut_loss_price(filled_price, filled_side).await;
         let open_position_id = self.state.latest_open_position_id;
@@ -1042,15 +2703,32 @@ impl FundManager {
         )
         .await?;
 
-        let prev_amount = self.update_state_after_trade(filled_value);
+        if matches!(position.state(), State::Closing(_)) {
+            self.reopen_after_partial_close(position.id(), filled_size);
+        }
+
+        let prev_amount = self.update_state_after_trade(filled_value, filled_price);
 
+        let mut is_closed = false;
         if let Some(position) = self.get_open_position() {
             if let State::Closed(_reason) = position.state() {
-                self.state.amount += position.close_asset_in_usd() + position.pnl().0;
+                is_closed = true;
+                self.state
+                    .processed_trade_ids
+                    .remove_position(position.id());
+                let contract_type = self.config.contract_type;
+                let close_price = position.close_price();
+                let pnl = contract_type.to_settlement(position.pnl().0, close_price);
+                // Nothing is left in the position, so only the PnL is added
+                self.state.realized_pnl += pnl;
+                self.state.amount += pnl;
                 self.state.latest_open_position_id = None;
                 self.state.trade_positions.remove(&position.id());
-                self.statistics.pnl += position.pnl().0;
-                if position.pnl().0 < Decimal::ZERO {
+                self.state.closed_positions.push(position.clone());
+                self.statistics.pnl += pnl;
+                self.statistics
+                    .add_daily_pnl(pnl, chrono::Utc::now().timestamp());
+                if pnl < Decimal::ZERO {
                     self.state.trade_tick_count = 0;
                 }
             }
@@ -1065,11 +2743,22 @@ impl FundManager {
         }
 
         self.statistics.fill_count += 1;
+        self.state.last_activity_tick = self.state.tick_count;
+        self.state.idle_warned = false;
 
         if self.state.amount < self.statistics.min_amount {
             self.statistics.min_amount = self.state.amount;
         }
 
+        if is_closed {
+            self.state
+                .db_handler
+                .lock()
+                .await
+                .log_fund_statistics(&self.config.fund_name, &self.statistics)
+                .await;
+        }
+
         log::debug!(
             "{} Amount has changed from {:.1} to {:.1}",
             self.config.fund_name,
@@ -1080,29 +2769,214 @@ impl FundManager {
         return Ok(true);
     }
 
+    // Applies the deferred fills again after a batch of fills. The ones still ahead of their
+    // open fills stay deferred, and the ones whose position is no longer closing are dropped.
+    pub async fn apply_deferred_fills(&mut self) {
+        for fill in self.state.deferred_fills.clone() {
+            let filled = self
+                .position_filled(
+                    &fill.order_id,
+                    &fill.trade_id,
+                    fill.filled_side,
+                    fill.filled_value,
+                    fill.filled_size,
+                    fill.fee,
+                )
+                .await;
+            if filled == Ok(true) {
+                log::info!(
+                    "{}: the deferred fill is applied: order_id = {}, trade_id = {}",
+                    self.config.fund_name,
+                    fill.order_id,
+                    fill.trade_id
+                );
+            }
+            let is_closing = self
+                .find_position_from_order_id(&fill.order_id)
+                .is_some_and(|position| matches!(position.state(), State::Closing(_)));
+            if filled != Ok(false) || !is_closing {
+                self.state
+                    .deferred_fills
+                    .retain(|deferred| deferred.trade_id != fill.trade_id);
+            }
+        }
+    }
+
+    // The rest of a position is open again once its partial close is filled
+    fn reopen_after_partial_close(&mut self, position_id: u32, filled_size: Decimal) {
+        let unfilled = match self.state.partial_close_sizes.get_mut(&position_id) {
+            Some(unfilled) => {
+                *unfilled -= filled_size;
+                *unfilled
+            }
+            None => return,
+        };
+        if unfilled > Decimal::ZERO {
+            return;
+        }
+        self.state.partial_close_sizes.remove(&position_id);
+        if let Some(position) = self.state.trade_positions.get_mut(&position_id) {
+            if matches!(position.state(), State::Closing(_)) && position.cancel().is_ok() {
+                log::info!(
+                    "{}: the partial close of the position {} is filled, {} is left open",
+                    self.config.fund_name,
+                    position_id,
+                    position.amount().abs()
+                );
+            }
+        }
+    }
+
+    async fn log_fill_latency(&self, latency: Duration) {
+        let fill_latencies = &self.state.fill_latencies;
+        let (p50, p95) = match (fill_latencies.percentile(50), fill_latencies.percentile(95)) {
+            (Some(p50), Some(p95)) => (p50, p95),
+            _ => return,
+        };
+        log::info!(
+            "{}: fill latency = {:?}, p50 = {:?}, p95 = {:?}",
+            self.config.fund_name,
+            latency,
+            p50,
+            p95
+        );
+        self.state
+            .db_handler
+            .lock()
+            .await
+            .log_fill_latency(
+                self.state.dex_connector.dex_name(),
+                &self.config.token_name,
+                latency,
+                p50,
+                p95,
+            )
+            .await;
+    }
+
+    fn check_slippage(
+        &mut self,
+        order_id: &str,
+        filled_side: &OrderSide,
+        ordered_price: Decimal,
+        filled_price: Decimal,
+        max_acceptable_slippage: Decimal,
+    ) {
+        let slippage = Self::slippage_ratio(filled_side, ordered_price, filled_price);
+        if slippage <= max_acceptable_slippage {
+            return;
+        }
+        self.statistics.slippage_exceeded_count += 1;
+
+        let message = format!(
+            "{}: slippage {:.4} exceeds {:.4}: order_id = {}, side = {}, ordered = {:<6.6}, filled = {:<6.6}",
+            self.config.fund_name,
+            slippage,
+            max_acceptable_slippage,
+            order_id,
+            filled_side,
+            ordered_price,
+            filled_price
+        );
+        log::warn!("{}", message);
+
+        if self.config.alert_on_slippage {
+            self.state.alerts.send("Slippage exceeded", &message);
+        }
+    }
+
+    // Positive when the fill is worse than the ordered price
+    fn slippage_ratio(side: &OrderSide, ordered_price: Decimal, filled_price: Decimal) -> Decimal {
+        if ordered_price.is_zero() {
+            return Decimal::ZERO;
+        }
+        let diff = match side {
+            OrderSide::Long => filled_price - ordered_price,
+            OrderSide::Short => ordered_price - filled_price,
+        };
+        diff / ordered_price
+    }
+
     async fn order_price(
         &self,
         current_price: Decimal,
         order_price: Option<Decimal>,
         is_buy: bool,
     ) -> Result<Decimal, ()> {
-        let market_data = self.state.market_data.read().await;
+        let base_price = if self.config.anchor_to_vwap {
+            Self::vwap(&self.state.vwap_history).unwrap_or(current_price)
+        } else {
+            current_price
+        };
         match order_price {
             Some(v) => Ok(v),
             None => match self.config.atr_spread {
                 Some(atr_spread) => {
-                    let spread = market_data.atr_by_term(&self.config.atr_term) * atr_spread;
+                    let spread = match self.warmed_up_atr().await {
+                        Some(atr) => atr * atr_spread,
+                        None => return Err(()),
+                    };
                     if is_buy {
-                        Ok(current_price - spread)
+                        Ok(base_price - spread)
                     } else {
-                        Ok(current_price + spread)
+                        Ok(base_price + spread)
                     }
                 }
-                None => Ok(current_price),
+                None => Ok(base_price),
             },
         }
     }
 
+    // The configured term first, then the shorter ones
+    fn atr_fallback_terms(atr_term: &SampleTerm) -> Vec<SampleTerm> {
+        match atr_term {
+            SampleTerm::LongTerm => vec![
+                SampleTerm::LongTerm,
+                SampleTerm::ShortTerm,
+                SampleTerm::TradingTerm,
+            ],
+            SampleTerm::ShortTerm => vec![SampleTerm::ShortTerm, SampleTerm::TradingTerm],
+            SampleTerm::TradingTerm => vec![SampleTerm::TradingTerm],
+        }
+    }
+
+    // The index of the first term that has an ATR, with the ATR
+    fn first_available_atr(
+        terms: &[SampleTerm],
+        atr_by_term: impl Fn(&SampleTerm) -> Decimal,
+    ) -> Option<(usize, Decimal)> {
+        terms
+            .iter()
+            .map(atr_by_term)
+            .enumerate()
+            .find(|(_, atr)| !atr.is_zero())
+    }
+
+    // ATR of the configured term, or of a shorter one while the price history is too short.
+    // None while no term has enough history yet
+    async fn warmed_up_atr(&self) -> Option<Decimal> {
+        let terms = Self::atr_fallback_terms(&self.config.atr_term);
+        let market_data = self.state.market_data.read().await;
+        match Self::first_available_atr(&terms, |term| market_data.atr_by_term(term)) {
+            Some((0, atr)) => Some(atr),
+            Some((index, atr)) => {
+                log::debug!(
+                    "{}: warming up, ATR of a shorter term({}) is used",
+                    self.config.fund_name,
+                    terms[index].to_numeric()
+                );
+                Some(atr)
+            }
+            None => {
+                log::info!(
+                    "{}: warming up, no ATR is available yet",
+                    self.config.fund_name
+                );
+                None
+            }
+        }
+    }
+
     async fn take_profit_distance(&self, current_price: Decimal) -> Option<Decimal> {
         let market_data = self.state.market_data.read().await;
         match self.config.take_profit_ratio {
@@ -1140,15 +3014,10 @@ impl FundManager {
     }
 
     async fn cut_loss_price(&self, filled_price: Decimal, side: OrderSide) -> Option<Decimal> {
-        let market_data = self.state.market_data.read().await;
-        let atr = market_data.atr_by_term(&self.config.atr_term);
-        let cut_loss_distance = if atr == Decimal::ZERO {
-            return None;
-        } else {
-            let least_distance = filled_price
-                * Decimal::from_f64(fund_config::CUT_LOSS_MIN_RATIO).unwrap_or_default();
-            std::cmp::max(least_distance, atr)
-        };
+        let atr = self.warmed_up_atr().await?;
+        let least_distance =
+            filled_price * Decimal::from_f64(fund_config::CUT_LOSS_MIN_RATIO).unwrap_or_default();
+        let cut_loss_distance = std::cmp::max(least_distance, atr);
 
         match side {
             OrderSide::Long => Some(filled_price - cut_loss_distance),
@@ -1156,6 +3025,11 @@ impl FundManager {
         }
     }
 
+    // The positions closed since the last call
+    pub fn take_closed_positions(&mut self) -> Vec<TradePosition> {
+        std::mem::take(&mut self.state.closed_positions)
+    }
+
     pub fn clean_canceled_position(&mut self) {
         self.state
             .trade_positions
@@ -1163,6 +3037,7 @@ impl FundManager {
     }
 
     pub async fn cancel_order(&mut self, order_id: &str, is_already_rejected: bool) {
+        self.state.fill_latencies.canceled(order_id);
         if !is_already_rejected {
             if let Err(e) = self
                 .state
@@ -1218,15 +3093,171 @@ impl FundManager {
         log::info!("cancel_order succeeded: order_id = {}", order_id);
     }
 
-    pub async fn cancel_all_orders(&mut self) {
-        let positions_to_cancel: Vec<TradePosition> = self
+    // An order of a pending position that the venue has not listed in two verifications in a
+    // row is taken as gone, e.g. filled without the fill reaching us, canceled outside or
+    // expired. A single miss is not enough, as the fill of the order may still be on its way.
+    // venue_drift is the venue's size on the token less the size the funds on it hold, or
+    // None when the venue does not tell. A gone order the drift accounts for is booked as
+    // filled, and takes its size out of the drift; any other is canceled.
+    pub async fn reconcile_open_orders(
+        &mut self,
+        open_order_ids: &HashSet<String>,
+        venue_drift: &mut Option<Decimal>,
+    ) {
+        let missing_order_ids: HashSet<String> = self
             .state
             .trade_positions
-            .iter()
-            .filter(|(_k, v)| matches!(v.state(), State::Opening))
+            .values()
+            .filter(|position| matches!(position.state(), State::Opening | State::Closing(_)))
+            .map(|position| position.order_id().to_owned())
+            .filter(|order_id| !open_order_ids.contains(order_id))
+            .collect();
+
+        let gone_order_ids: Vec<String> = missing_order_ids
+            .intersection(&self.state.missing_order_ids)
+            .cloned()
+            .collect();
+        self.state.missing_order_ids = missing_order_ids;
+
+        for order_id in gone_order_ids {
+            log::warn!(
+                "{}: the order is gone from the venue: order_id = {}",
+                self.config.fund_name,
+                order_id
+            );
+            self.state.missing_order_ids.remove(&order_id);
+            let filled_size = match venue_drift {
+                Some(drift) => self.fill_shown_by_venue(&order_id, drift).await,
+                None => None,
+            };
+            match filled_size {
+                Some(size) => log::warn!(
+                    "{}: the venue holds the fill of the gone order, book it: order_id = {}, size = {}",
+                    self.config.fund_name,
+                    order_id,
+                    size
+                ),
+                None => self.cancel_order(&order_id, true).await,
+            }
+        }
+    }
+
+    // Books the rest of a gone order as filled when the venue's size is off by it, and
+    // returns the size booked
+    async fn fill_shown_by_venue(
+        &mut self,
+        order_id: &str,
+        drift: &mut Decimal,
+    ) -> Option<Decimal> {
+        let position = self.find_position_from_order_id(order_id)?;
+        let (side, size, price) = match position.state() {
+            State::Opening => (
+                match position.position_type() {
+                    PositionType::Long => OrderSide::Long,
+                    PositionType::Short => OrderSide::Short,
+                },
+                position.unfilled_amount(),
+                position.ordered_price(),
+            ),
+            State::Closing(_) => (
+                match position.position_type() {
+                    PositionType::Long => OrderSide::Short,
+                    PositionType::Short => OrderSide::Long,
+                },
+                self.state
+                    .partial_close_sizes
+                    .get(&position.id())
+                    .copied()
+                    .unwrap_or(position.amount().abs()),
+                if self.state.current_price.is_zero() {
+                    position.ordered_price()
+                } else {
+                    self.state.current_price
+                },
+            ),
+            _ => return None,
+        };
+        let signed_size = match side {
+            OrderSide::Long => size,
+            OrderSide::Short => -size,
+        };
+        if !Self::is_shown_by_venue(*drift, signed_size) {
+            return None;
+        }
+
+        let trade_id = format!("reconcile-{}", order_id);
+        match self
+            .position_filled(order_id, &trade_id, side, price * size, size, Decimal::ZERO)
+            .await
+        {
+            Ok(true) => {
+                *drift -= signed_size;
+                Some(size)
+            }
+            _ => None,
+        }
+    }
+
+    // Whether the venue's size is off from the funds' by at least the signed size of an order
+    fn is_shown_by_venue(drift: Decimal, signed_size: Decimal) -> bool {
+        !signed_size.is_zero()
+            && drift.is_sign_negative() == signed_size.is_sign_negative()
+            && drift.abs() >= signed_size.abs()
+    }
+
+    // The signed size the fund holds on the venue. Once there is an open position, the fills
+    // of the pending opens are in it.
+    pub fn position_size(&self) -> Decimal {
+        match self.get_open_position() {
+            Some(position) => position.amount(),
+            None => self
+                .state
+                .trade_positions
+                .values()
+                .map(|position| position.amount())
+                .sum(),
+        }
+    }
+
+    pub async fn cancel_all_orders(&mut self) {
+        let positions_to_cancel: Vec<TradePosition> = self
+            .state
+            .trade_positions
+            .iter()
+            .filter(|(_k, v)| matches!(v.state(), State::Opening))
             .map(|(_k, v)| v.clone())
             .collect();
 
+        if positions_to_cancel.len() > 1 {
+            let order_ids: Vec<String> = positions_to_cancel
+                .iter()
+                .map(|position| position.order_id().to_owned())
+                .collect();
+            match self
+                .state
+                .dex_connector
+                .cancel_orders(&self.config.token_name, &order_ids)
+                .await
+            {
+                Ok(requests) => {
+                    log::info!(
+                        "{}: canceled {} orders with {} requests, {} requests saved",
+                        self.config.fund_name,
+                        order_ids.len(),
+                        requests,
+                        order_ids.len().saturating_sub(requests)
+                    );
+                    for order_id in &order_ids {
+                        self.cancel_order(order_id, true).await;
+                    }
+                    return;
+                }
+                Err(e) => {
+                    log::warn!("cancel_all_orders: batch cancel failed: {:?}", e);
+                }
+            }
+        }
+
         for position in &positions_to_cancel {
             self.cancel_order(position.order_id(), false).await;
         }
@@ -1273,6 +3304,64 @@ impl FundManager {
         }
     }
 
+    pub fn restore_statistics(&mut self, statistics: FundManagerStatics) {
+        log::info!("{}: restore {:?}", self.config.fund_name, statistics);
+        self.statistics = statistics;
+    }
+
+    // Any position being opened, open or being closed
+    pub fn has_position(&self) -> bool {
+        !self.state.trade_positions.is_empty()
+    }
+
+    pub fn open_order_count(&self) -> u64 {
+        self.state.open_order_count
+    }
+
+    pub fn order_count(&self) -> i32 {
+        self.statistics.order_count
+    }
+
+    pub fn fill_count(&self) -> i32 {
+        self.statistics.fill_count
+    }
+
+    pub fn log_statistics(&self) {
+        log::info!(
+            "{}: pnl = {:.3}, order/fill = {}/{}, take_profit/cut_loss/trailing_stop/expired = {}/{}/{}/{}, trend_changed/trim = {}/{}, slippage_exceeded = {}, min_amount = {:.3}",
+            self.config.fund_name,
+            self.statistics.pnl,
+            self.statistics.order_count,
+            self.statistics.fill_count,
+            self.statistics.take_profit_count,
+            self.statistics.cut_loss_count,
+            self.statistics.trailing_stop_count,
+            self.statistics.expired_count,
+            self.statistics.trend_changed_count,
+            self.statistics.trim_count,
+            self.statistics.slippage_exceeded_count,
+            self.statistics.min_amount,
+        );
+        if self.config.model_ticks.is_some() && self.state.model_evaluation_count > 0 {
+            log::info!(
+                "{}: the model was evaluated {} times in {} ticks ({:.1}x fewer)",
+                self.config.fund_name,
+                self.state.model_evaluation_count,
+                self.state.tick_count,
+                self.state.tick_count as f64 / self.state.model_evaluation_count as f64
+            );
+        }
+    }
+
+    pub fn realized_pnl(&self) -> Decimal {
+        self.statistics.pnl
+    }
+
+    // Without the PnL restored from the previous sessions
+    pub fn session_pnl(&self) -> Decimal {
+        self.state.realized_pnl
+    }
+
     pub fn asset_in_usd(&self) -> Decimal {
         let mut sum = Decimal::ZERO;
         for (_, position) in &self.state.trade_positions {
@@ -1281,14 +3370,2691 @@ impl FundManager {
         sum
     }
 
+    // The notional of the positions and of the open orders not filled yet, which will add to
+    // them once they are
+    pub fn open_notional(&self) -> Decimal {
+        let pending_notional: Decimal = self
+            .state
+            .trade_positions
+            .values()
+            .filter(|position| matches!(position.state(), State::Opening))
+            .map(|position| position.unfilled_amount() * position.ordered_price())
+            .sum();
+        self.asset_in_usd().abs() + pending_notional
+    }
+
+    // The value of the positions, positive when long. asset_in_usd is the cash paid for them,
+    // which is negative for a long.
+    pub fn exposure(&self) -> Decimal {
+        -self.asset_in_usd()
+    }
+
     pub fn check_positions(&mut self, price: Decimal) {
         for (_, position) in &mut self.state.trade_positions {
             position.update_counter();
             position.print_info(price);
         }
+
+        if let Some(trailing_stop_ratio) = self.config.trailing_stop_ratio {
+            self.update_trailing_stops(price, trailing_stop_ratio);
+        }
+    }
+
+    fn update_trailing_stops(&mut self, price: Decimal, trailing_stop_ratio: Decimal) {
+        let trade_positions = &self.state.trade_positions;
+        self.state
+            .trailing_stop_prices
+            .retain(|id, _| trade_positions.contains_key(id));
+
+        for (id, position) in &self.state.trade_positions {
+            if position.state() != State::Open {
+                continue;
+            }
+            let current_stop = self.state.trailing_stop_prices.get(id).cloned();
+            if let Some(stop_price) = Self::trailing_stop_price(
+                position.position_type(),
+                position.average_open_price(),
+                current_stop,
+                price,
+                trailing_stop_ratio,
+            ) {
+                if current_stop != Some(stop_price) {
+                    log::debug!(
+                        "{}: trailing stop of position {} is moved to {:<6.4}",
+                        self.config.fund_name,
+                        id,
+                        stop_price
+                    );
+                    self.state.trailing_stop_prices.insert(*id, stop_price);
+                }
+            }
+        }
+    }
+
+    // The stop is armed once the price has moved favorably by more than the trailing distance,
+    // and after that it only moves in the direction of the position.
+    fn trailing_stop_price(
+        position_type: PositionType,
+        average_open_price: Decimal,
+        current_stop: Option<Decimal>,
+        price: Decimal,
+        trailing_stop_ratio: Decimal,
+    ) -> Option<Decimal> {
+        let (candidate, is_armed) = match position_type {
+            PositionType::Long => {
+                let candidate = price * (Decimal::ONE - trailing_stop_ratio);
+                (candidate, candidate > average_open_price)
+            }
+            PositionType::Short => {
+                let candidate = price * (Decimal::ONE + trailing_stop_ratio);
+                (candidate, candidate < average_open_price)
+            }
+        };
+
+        match current_stop {
+            Some(stop) => match position_type {
+                PositionType::Long => Some(std::cmp::max(stop, candidate)),
+                PositionType::Short => Some(std::cmp::min(stop, candidate)),
+            },
+            None if is_armed => Some(candidate),
+            None => None,
+        }
+    }
+
+    // An opposing trend signal closes the position once it has persisted for confirm_ticks cycles
+    fn is_trend_change_confirmed(
+        counts: &mut HashMap<u32, u32>,
+        position_id: u32,
+        signaled: bool,
+        confirm_ticks: u32,
+    ) -> bool {
+        if !signaled {
+            counts.remove(&position_id);
+            return false;
+        }
+        let count = counts.entry(position_id).or_insert(0);
+        *count += 1;
+        *count >= confirm_ticks.max(1)
+    }
+
+    // The pattern of the last closed candle, when the candles are aggregated
+    pub fn candle_pattern(&self) -> Option<CandleShape> {
+        self.config
+            .candle_interval_ticks
+            .map(|ticks| self.state.candle_history.candle_pattern_for_interval(ticks))
+    }
+
+    // The unfilled part of the open orders still on the book
+    fn resting_orders(&self) -> Vec<RestingOrder> {
+        let mut resting_open_orders: Vec<_> = self.state.resting_open_orders.iter().collect();
+        resting_open_orders.sort_by_key(|(position_id, _)| **position_id);
+        resting_open_orders
+            .into_iter()
+            .filter_map(|(position_id, order)| {
+                let position = self.state.trade_positions.get(position_id)?;
+                if position.state() != State::Opening {
+                    return None;
+                }
+                let size = order.size - position.amount().abs();
+                (size > Decimal::ZERO).then(|| RestingOrder {
+                    size,
+                    ..order.clone()
+                })
+            })
+            .collect()
+    }
+
+    // Gathers the resting opens of the funds into one pool per token
+    pub fn share_resting_orders<'a>(fund_managers: impl Iterator<Item = &'a mut FundManager>) {
+        let mut pools: HashMap<String, SharedRestingOrders> = HashMap::new();
+        for fund_manager in fund_managers {
+            let trade_positions = &fund_manager.state.trade_positions;
+            fund_manager
+                .state
+                .resting_open_orders
+                .retain(|position_id, _| {
+                    trade_positions
+                        .get(position_id)
+                        .is_some_and(|position| position.state() == State::Opening)
+                });
+
+            let pool = pools
+                .entry(fund_manager.config.token_name.clone())
+                .or_default();
+            pool.lock().unwrap().orders.extend(
+                fund_manager
+                    .resting_orders()
+                    .into_iter()
+                    .map(|order| (fund_manager.config.fund_name.clone(), order)),
+            );
+            fund_manager.state.shared_resting_orders = pool.clone();
+        }
+    }
+
+    // The tokens without a position that may not open on this tick. Only as many new tokens
+    // as the cap leaves room for are let through, taking turns from one tick to the next.
+    pub fn tokens_over_concurrency<'a>(
+        fund_managers: impl Iterator<Item = &'a FundManager>,
+        max_tokens: usize,
+        tick_count: u64,
+    ) -> HashSet<String> {
+        let mut active_tokens = HashSet::new();
+        let mut tokens = HashSet::new();
+        for fund_manager in fund_managers {
+            tokens.insert(fund_manager.token_name());
+            if fund_manager.has_position() {
+                active_tokens.insert(fund_manager.token_name());
+            }
+        }
+
+        let mut new_tokens: Vec<&str> = tokens.difference(&active_tokens).copied().collect();
+        if new_tokens.is_empty() {
+            return HashSet::new();
+        }
+        new_tokens.sort_unstable();
+        let turn = (tick_count % new_tokens.len() as u64) as usize;
+        new_tokens.rotate_left(turn);
+
+        let room = max_tokens.saturating_sub(active_tokens.len());
+        new_tokens
+            .into_iter()
+            .skip(room)
+            .map(str::to_owned)
+            .collect()
+    }
+
+    // The tokens whose notional across the funds has reached the cap
+    pub fn tokens_over_notional<'a>(
+        fund_managers: impl Iterator<Item = &'a FundManager>,
+        max_token_notional: Decimal,
+    ) -> HashSet<String> {
+        let mut token_notionals: HashMap<&str, Decimal> = HashMap::new();
+        for fund_manager in fund_managers {
+            *token_notionals
+                .entry(fund_manager.token_name())
+                .or_default() += fund_manager.open_notional();
+        }
+
+        token_notionals
+            .into_iter()
+            .filter_map(|(token_name, notional)| {
+                if notional >= max_token_notional {
+                    log::warn!(
+                        "{}: opening is capped: notional = {:.3}, max = {:.3}, over by {:.3}",
+                        token_name,
+                        notional,
+                        max_token_notional,
+                        notional - max_token_notional
+                    );
+                    Some(token_name.to_owned())
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Takes up to size out of the opposite resting orders of the other funds a new order at
+    // order_price would trade against. The crosses are left in the pool for the owners of the
+    // resting orders to book, and returned for the taker to book.
+    fn reserve_crosses(
+        fund_name: &str,
+        side: &OrderSide,
+        order_price: Decimal,
+        size: Decimal,
+        pool: &mut RestingOrderPool,
+    ) -> Vec<InternalCross> {
+        let mut crosses = vec![];
+        let mut reserved = Decimal::ZERO;
+        for (owner, other) in pool.orders.iter_mut() {
+            let crosses_other = match (side, &other.side) {
+                (OrderSide::Long, OrderSide::Short) => other.price <= order_price,
+                (OrderSide::Short, OrderSide::Long) => other.price >= order_price,
+                _ => false,
+            };
+            if owner == fund_name || !crosses_other || other.size.is_zero() {
+                continue;
+            }
+            let taken = other.size.min(size - reserved);
+            other.size -= taken;
+            reserved += taken;
+            crosses.push(InternalCross {
+                fund_name: owner.clone(),
+                order_id: other.order_id.clone(),
+                trade_id: format!("cross-{}-{}", fund_name, other.order_id),
+                side: other.side.clone(),
+                price: other.price,
+                size: taken,
+            });
+            if reserved == size {
+                break;
+            }
+        }
+        pool.crosses.extend(crosses.iter().cloned());
+        crosses
+    }
+
+    // The taker's side of a cross is a position filled at once at the resting order's price
+    async fn take_cross(
+        &mut self,
+        cross: &InternalCross,
+        action: TradeAction,
+        target_price: Option<Decimal>,
+    ) -> Result<(), ()> {
+        let token_name = self.config.token_name.clone();
+        self.prepare_position(
+            &cross.trade_id,
+            Some(cross.price),
+            cross.size,
+            action,
+            target_price,
+            None,
+            &token_name,
+            None,
+        )
+        .await?;
+        let side = match cross.side {
+            OrderSide::Long => OrderSide::Short,
+            OrderSide::Short => OrderSide::Long,
+        };
+        self.position_filled(
+            &cross.trade_id,
+            &cross.trade_id,
+            side,
+            cross.price * cross.size,
+            cross.size,
+            Decimal::ZERO,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // Books the crosses the other funds took against the resting orders of this fund. The
+    // crossed order is canceled on the venue, and the rest of it placed again.
+    pub async fn book_crosses(&mut self) {
+        let crosses: Vec<InternalCross> = {
+            let mut pool = self.state.shared_resting_orders.lock().unwrap();
+            let (own, others) = std::mem::take(&mut pool.crosses)
+                .into_iter()
+                .partition(|cross| cross.fund_name == self.config.fund_name);
+            pool.crosses = others;
+            own
+        };
+
+        for cross in crosses {
+            log::info!(
+                "{}: {} of the order {} is traded with another fund at {}",
+                self.config.fund_name,
+                cross.size,
+                cross.order_id,
+                cross.price
+            );
+            if let Err(e) = self
+                .state
+                .dex_connector
+                .cancel_order(&self.config.token_name, &cross.order_id)
+                .await
+            {
+                log::error!("cancel_order: {}: order_id = {}", e, cross.order_id);
+            }
+            if self
+                .position_filled(
+                    &cross.order_id,
+                    &cross.trade_id,
+                    cross.side.clone(),
+                    cross.price * cross.size,
+                    cross.size,
+                    Decimal::ZERO,
+                )
+                .await
+                .is_err()
+            {
+                log::error!(
+                    "{}: failed to book the cross: {:?}",
+                    self.config.fund_name,
+                    cross
+                );
+                continue;
+            }
+
+            let position = match self.find_position_from_order_id(&cross.order_id) {
+                Some(position) if position.state() == State::Opening => position,
+                _ => continue,
+            };
+            let rest = position.unfilled_amount();
+            self.cancel_order(&cross.order_id, true).await;
+            let detail = TradeDetail::new(Some(cross.price), Some(rest), Decimal::ONE, None);
+            let action = match cross.side {
+                OrderSide::Long => TradeAction::BuyOpen(detail),
+                OrderSide::Short => TradeAction::SellOpen(detail),
+            };
+            let _ = self
+                .place_order(
+                    Some(cross.price),
+                    TradeChance {
+                        token_name: self.config.token_name.clone(),
+                        target_price: Some(position.predicted_price()),
+                        token_amount: rest,
+                        action,
+                        position_id: None,
+                    },
+                    None,
+                )
+                .await;
+        }
+    }
+
+    fn is_within_min_holding(open_timestamp: i64, now: i64, min_holding_secs: Option<i64>) -> bool {
+        match min_holding_secs {
+            Some(min_holding_secs) => now - open_timestamp < min_holding_secs,
+            None => false,
+        }
+    }
+
+    fn is_trailing_stop_hit(&self, position: &TradePosition, price: Decimal) -> bool {
+        match self.state.trailing_stop_prices.get(&position.id()) {
+            Some(stop_price) => match position.position_type() {
+                PositionType::Long => price <= *stop_price,
+                PositionType::Short => price >= *stop_price,
+            },
+            None => false,
+        }
+    }
+
+    pub fn update_params(&mut self, params: &FundParams) {
+        log::debug!("{}: update params: {:?}", self.config.fund_name, params);
+
+        if params.take_profit_ratio.is_some() {
+            self.config.take_profit_ratio = params.take_profit_ratio;
+        }
+        if let Some(risk_reward) = params.risk_reward {
+            self.config.risk_reward = risk_reward;
+        }
+        if params.atr_spread.is_some() {
+            self.config.atr_spread = params.atr_spread;
+        }
+        if params.trailing_stop_ratio.is_some() {
+            self.config.trailing_stop_ratio = params.trailing_stop_ratio;
+        }
+        if let Some(tick_count) = params.open_order_tick_count_max {
+            self.config.open_order_tick_count_max = tick_count;
+        }
+        if let Some(tick_count) = params.close_order_tick_count_max {
+            self.config.close_order_tick_count_max = tick_count;
+        }
+        if let Some(tick_count) = params.open_tick_count_max {
+            self.config.open_tick_count_max = tick_count;
+        }
     }
 
     pub fn reset_dex_client(&mut self, dex_connector: Arc<DexConnectorBox>) {
         self.state.dex_connector = dex_connector;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::dex_emulator::{DexEmulator, EmulatorFillSettings};
+    use super::super::mock_dex::{
+        start_hyperliquid_exchange, start_hyperliquid_info, Mock, MockDex,
+    };
+    use super::*;
+    use async_trait::async_trait;
+    use debot_market_analyzer::{TradeDetail, TrendType};
+
+    // Records the orders the venue receives
+    #[derive(Default)]
+    struct OrderRecorder {
+        orders: Arc<std::sync::Mutex<Vec<(OrderSide, Decimal)>>>,
+    }
+
+    #[async_trait]
+    impl MockDex for OrderRecorder {
+        async fn create_order(
+            &self,
+            _symbol: &str,
+            size: Decimal,
+            side: OrderSide,
+            price: Option<Decimal>,
+            _time_in_force: TimeInForce,
+        ) -> Result<CreateOrderResponse, DexError> {
+            let mut orders = self.orders.lock().unwrap();
+            orders.push((side, size));
+            Ok(CreateOrderResponse {
+                order_id: orders.len().to_string(),
+                ordered_price: price.unwrap_or(Decimal::new(100, 0)),
+                ordered_size: size,
+            })
+        }
+    }
+
+    // Refuses the cancels, as for an order the venue has already lost track of
+    struct CancelRejectingVenue(OrderRecorder);
+
+    #[async_trait]
+    impl MockDex for CancelRejectingVenue {
+        async fn create_order(
+            &self,
+            symbol: &str,
+            size: Decimal,
+            side: OrderSide,
+            price: Option<Decimal>,
+            time_in_force: TimeInForce,
+        ) -> Result<CreateOrderResponse, DexError> {
+            self.0
+                .create_order(symbol, size, side, price, time_in_force)
+                .await
+        }
+
+        async fn cancel_order(&self, _symbol: &str, _order_id: &str) -> Result<(), DexError> {
+            Err(DexError::Other("order not found".to_owned()))
+        }
+    }
+
+    // Records the orders canceled one by one
+    #[derive(Default)]
+    struct SingleCancelRecorder {
+        orders: OrderRecorder,
+        canceled: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl MockDex for SingleCancelRecorder {
+        async fn create_order(
+            &self,
+            symbol: &str,
+            size: Decimal,
+            side: OrderSide,
+            price: Option<Decimal>,
+            time_in_force: TimeInForce,
+        ) -> Result<CreateOrderResponse, DexError> {
+            self.orders
+                .create_order(symbol, size, side, price, time_in_force)
+                .await
+        }
+
+        async fn cancel_order(&self, _symbol: &str, order_id: &str) -> Result<(), DexError> {
+            self.canceled.lock().unwrap().push(order_id.to_owned());
+            Ok(())
+        }
+    }
+
+    // Market data around 100 with an ATR on every term
+    fn warmed_up_market_data() -> Arc<RwLock<MarketData>> {
+        let mut market_data = MarketData::new("BTC".to_owned(), 4, 8, 2, 64, None, false);
+        for i in 0..32 {
+            let price = Decimal::new(100 + (i % 5) - 2, 0);
+            market_data.add_price(Some(price), Some(i * 60), None, None, None, None, None);
+        }
+        Arc::new(RwLock::new(market_data))
+    }
+
+    // A BTC fund with a limit of 1000 USD on the given venue
+    async fn test_fund_manager(
+        fund_name: &str,
+        index: usize,
+        market_data: Arc<RwLock<MarketData>>,
+        db_handler: Arc<Mutex<DBHandler>>,
+        dex_connector: Arc<DexConnectorBox>,
+        contract_type: ContractType,
+    ) -> FundManager {
+        let initial_amount = Decimal::new(1000, 0);
+        let config = FundManagerConfig {
+            fund_name: fund_name.to_owned(),
+            index,
+            token_name: "BTC".to_owned(),
+            strategy: TradingStrategy::TrendFollow(TrendType::Up),
+            trading_amount: initial_amount,
+            initial_amount,
+            open_order_tick_count_max: 10,
+            close_order_tick_count_max: 10,
+            open_tick_count_max: 100,
+            execution_delay_tick_count_max: 0,
+            use_market_order: false,
+            take_profit_ratio: Some(Decimal::new(1, 2)),
+            risk_reward: Decimal::ONE,
+            atr_spread: None,
+            atr_term: SampleTerm::TradingTerm,
+            min_open_confidence: Decimal::ZERO,
+            min_close_confidence: Decimal::ZERO,
+            chase_close_orders: false,
+            max_chase_ticks: 0,
+            trailing_stop_ratio: None,
+            require_rsi_divergence: false,
+            max_idle_ticks: None,
+            alert_when_idle: false,
+            max_funding_rate_long: None,
+            max_funding_rate_short: None,
+            signal_on_bar_close: false,
+            bar_ticks: 1,
+            max_acceptable_slippage: None,
+            alert_on_slippage: false,
+            anchor_to_vwap: false,
+            vwap_window: 0,
+            leverage: None,
+            prefer_maker: false,
+            maker_fallback_to_taker: false,
+            max_market_slippage: None,
+            expire_open_positions: false,
+            contract_type,
+            order_placement_mode: OrderPlacementMode::Adaptive,
+            time_in_force: TimeInForce::Gtc,
+            max_open_positions: None,
+            take_profit_ladder: vec![],
+            daily_loss_limit: None,
+            min_entry_zscore: None,
+            use_stoch_rsi_exit: false,
+            min_order_size: None,
+            max_order_size: None,
+            execution_delay_atr_factor: None,
+            min_holding_secs: None,
+            allow_averaging: false,
+            min_average_improvement: Decimal::ZERO,
+            trend_change_confirm_ticks: 1,
+            candle_interval_ticks: None,
+            size_scaling: SizeScaling::Fixed,
+            log_fund_equity: false,
+            equity_sample_ticks: 1,
+            trace_decisions: false,
+            rounding_mode: RoundingMode::Nearest,
+            open_resubmit_attempts: 0,
+            model_ticks: None,
+            random_seed: None,
+        };
+        FundManager::new(
+            config,
+            market_data,
+            db_handler,
+            dex_connector,
+            AlertHandle::detached(),
+        )
+    }
+
+    async fn open(fund_manager: &mut FundManager, action: TradeAction) {
+        let price = Decimal::new(100, 0);
+        let mut trace = DecisionTrace::new(false, fund_manager.fund_name(), 0, price);
+        fund_manager
+            .handle_open_chances(price, Decimal::new(100, 0), &vec![action], &mut trace)
+            .await
+            .unwrap();
+    }
+
+    fn rsi_history(samples: &[(i64, i64)]) -> VecDeque<(Decimal, Decimal)> {
+        samples
+            .iter()
+            .map(|(price, rsi)| (Decimal::new(*price, 0), Decimal::new(*rsi, 0)))
+            .collect()
+    }
+
+    #[test]
+    fn test_rsi_divergence_detects_bullish_and_bearish() {
+        // Lower low in price, higher low in RSI
+        let history = rsi_history(&[(100, 40), (95, 25), (98, 35), (94, 30)]);
+        assert_eq!(
+            FundManager::rsi_divergence(&history),
+            Some(PositionType::Long)
+        );
+
+        // Higher high in price, lower high in RSI
+        let history = rsi_history(&[(100, 60), (105, 75), (102, 65), (106, 70)]);
+        assert_eq!(
+            FundManager::rsi_divergence(&history),
+            Some(PositionType::Short)
+        );
+    }
+
+    #[test]
+    fn test_rsi_divergence_ignores_confirmed_moves() {
+        // RSI confirms the new low
+        let history = rsi_history(&[(100, 40), (95, 25), (98, 35), (94, 20)]);
+        assert_eq!(FundManager::rsi_divergence(&history), None);
+
+        // No new extreme in price
+        let history = rsi_history(&[(100, 40), (95, 25), (98, 35), (97, 45)]);
+        assert_eq!(FundManager::rsi_divergence(&history), None);
+    }
+
+    #[test]
+    fn test_vwap() {
+        let history: VecDeque<_> = [(100, Some(1)), (102, Some(3)), (98, Some(4))]
+            .iter()
+            .map(|(price, volume)| {
+                (
+                    Decimal::new(*price, 0),
+                    volume.map(|volume| Decimal::new(volume, 0)),
+                )
+            })
+            .collect();
+        // (100 * 1 + 102 * 3 + 98 * 4) / 8
+        assert_eq!(FundManager::vwap(&history), Some(Decimal::new(99750, 3)));
+
+        // Equal weighting without volume
+        let history: VecDeque<_> = history.iter().map(|(price, _)| (*price, None)).collect();
+        assert_eq!(FundManager::vwap(&history), Some(Decimal::new(100, 0)));
+
+        assert_eq!(FundManager::vwap(&VecDeque::new()), None);
+    }
+
+    #[test]
+    fn test_duplicated_trade_id_is_processed_once() {
+        let mut processed_trade_ids = ProcessedTradeIds::default();
+        assert!(processed_trade_ids.insert("trade-1", 1));
+        // Redelivered on reconnect
+        assert!(!processed_trade_ids.insert("trade-1", 1));
+        assert!(processed_trade_ids.insert("trade-2", 1));
+        assert!(processed_trade_ids.insert("trade-3", 2));
+
+        processed_trade_ids.remove_position(1);
+        assert!(processed_trade_ids.insert("trade-1", 3));
+        assert!(!processed_trade_ids.insert("trade-3", 2));
+    }
+
+    #[test]
+    fn test_processed_trade_ids_are_bounded() {
+        let mut processed_trade_ids = ProcessedTradeIds::default();
+        for i in 0..=MAX_PROCESSED_TRADE_IDS {
+            assert!(processed_trade_ids.insert(&i.to_string(), 1));
+        }
+        assert_eq!(processed_trade_ids.trade_ids.len(), MAX_PROCESSED_TRADE_IDS);
+        // The oldest one has been evicted
+        assert!(processed_trade_ids.insert("0", 1));
+    }
+
+    // Feeds the fills of the venue to the fund, as DerivativeTrader does
+    async fn process_fills(fund_manager: &mut FundManager, dex_connector: &DexConnectorBox) {
+        let filled_orders = dex_connector.get_filled_orders("BTC").await.unwrap();
+        for order in filled_orders.orders {
+            fund_manager
+                .position_filled(
+                    &order.order_id,
+                    &order.trade_id,
+                    order.filled_side.unwrap(),
+                    order.filled_value.unwrap(),
+                    order.filled_size.unwrap(),
+                    order.filled_fee.unwrap(),
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    // The emulator on a venue at 100, without fees nor slippage
+    async fn emulated_venue(filled_probability: Decimal) -> Arc<DexConnectorBox> {
+        struct QuietDex;
+        impl MockDex for QuietDex {}
+
+        let emulator = DexEmulator::new(
+            Mock(QuietDex),
+            filled_probability,
+            EmulatorFillSettings::default(),
+            Some(1),
+        );
+        let dex_connector = Arc::new(DexConnectorBox::from_connector("test", Box::new(emulator)));
+        dex_connector.get_ticker("BTC", None).await.unwrap();
+        dex_connector
+    }
+
+    fn open_detail(size: i64) -> TradeDetail {
+        TradeDetail::new(
+            Some(Decimal::new(100, 0)),
+            Some(Decimal::new(size, 0)),
+            Decimal::ONE,
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_partial_fills_of_the_emulator_are_all_processed() {
+        // Without a full fill probability, the first fill is a partial one
+        let dex_connector = emulated_venue(Decimal::ZERO).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        process_fills(&mut fund_manager, &dex_connector).await;
+        process_fills(&mut fund_manager, &dex_connector).await;
+
+        // The remainder has its own trade id, so it is not dropped as a duplicate
+        assert_eq!(fund_manager.fill_count(), 2);
+        let position = fund_manager.get_open_position().unwrap();
+        assert_eq!(position.amount(), Decimal::new(2, 0));
+        assert_eq!(fund_manager.state.amount, Decimal::new(800, 0));
+    }
+
+    // Opens a long of 2 at 100 and takes the profit at 110. Returns the amounts while open and
+    // after the close.
+    async fn round_trip(contract_type: ContractType) -> (FundManager, Decimal, Decimal) {
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            contract_type,
+        )
+        .await;
+        let (open_amount, close_amount) = trade_round_trip(&mut fund_manager, &dex_connector).await;
+        (fund_manager, open_amount, close_amount)
+    }
+
+    // Opens 2 at 100 and closes them at 110, and returns the amounts after each
+    async fn trade_round_trip(
+        fund_manager: &mut FundManager,
+        dex_connector: &DexConnectorBox,
+    ) -> (Decimal, Decimal) {
+        dex_connector
+            .get_ticker("BTC", Some(Decimal::new(100, 0)))
+            .await
+            .unwrap();
+        open(fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        process_fills(fund_manager, dex_connector).await;
+        let open_amount = fund_manager.state.amount;
+
+        let close_price = Decimal::new(110, 0);
+        dex_connector
+            .get_ticker("BTC", Some(close_price))
+            .await
+            .unwrap();
+        let mut trace = DecisionTrace::new(false, "a", 1, close_price);
+        fund_manager
+            .find_close_chances(close_price, &mut trace)
+            .await
+            .unwrap();
+        process_fills(fund_manager, dex_connector).await;
+        assert!(fund_manager.get_open_position().is_none());
+        (open_amount, fund_manager.state.amount)
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_accounting() {
+        // 200 USD is put in the position out of 1000, and it comes back with 2 * 10 USD
+        let (fund_manager, open_amount, close_amount) = round_trip(ContractType::Linear).await;
+        assert_eq!(open_amount, Decimal::new(800, 0));
+        assert_eq!(close_amount, Decimal::new(1020, 0));
+        assert_eq!(fund_manager.statistics.pnl, Decimal::new(20, 0));
+
+        // In the coin, 1000 USD is 10 at 100 and the position 2. The 20 USD come at 110.
+        let (fund_manager, open_amount, close_amount) = round_trip(ContractType::Inverse).await;
+        let pnl = Decimal::new(20, 0) / Decimal::new(110, 0);
+        assert_eq!(open_amount, Decimal::new(8, 0));
+        assert_eq!(close_amount, Decimal::new(10, 0) + pnl);
+        assert_eq!(fund_manager.statistics.pnl, pnl);
+    }
+
+    #[tokio::test]
+    async fn test_statistics_carry_over_a_restart() {
+        let (fund_manager, _, _) = round_trip(ContractType::Linear).await;
+        let db_handler = fund_manager.state.db_handler.clone();
+        let statistics = db_handler.lock().await.load_fund_statistics("a").await;
+        assert_eq!(statistics.as_ref().unwrap().pnl, Decimal::new(20, 0));
+
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            db_handler.clone(),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.restore_statistics(statistics.unwrap());
+        // The PnL of the previous session is in the balance already
+        assert_eq!(fund_manager.realized_pnl(), Decimal::new(20, 0));
+        assert_eq!(fund_manager.session_pnl(), Decimal::ZERO);
+
+        trade_round_trip(&mut fund_manager, &dex_connector).await;
+        assert_eq!(fund_manager.realized_pnl(), Decimal::new(40, 0));
+        assert_eq!(fund_manager.session_pnl(), Decimal::new(20, 0));
+        let statistics = db_handler.lock().await.load_fund_statistics("a").await;
+        assert_eq!(statistics.unwrap().pnl, Decimal::new(40, 0));
+    }
+
+    #[tokio::test]
+    async fn test_positions_of_a_dry_run_are_logged_as_paper_ones() {
+        for dry_run in [true, false] {
+            let dex_connector = emulated_venue(Decimal::ONE).await;
+            let db_handler = Arc::new(Mutex::new(DBHandler::offline(dry_run, None).await));
+            let mut fund_manager = test_fund_manager(
+                "a",
+                0,
+                warmed_up_market_data(),
+                db_handler.clone(),
+                dex_connector.clone(),
+                ContractType::Linear,
+            )
+            .await;
+            trade_round_trip(&mut fund_manager, &dex_connector).await;
+
+            let db_handler = db_handler.lock().await;
+            let (logged, other) = (
+                db_handler.offline_position_logs(dry_run),
+                db_handler.offline_position_logs(!dry_run),
+            );
+            assert_eq!(logged.len(), 1, "dry_run = {}", dry_run);
+            assert_eq!(logged[0].pnl, Decimal::new(20, 0));
+            assert!(other.is_empty(), "dry_run = {}", dry_run);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_order_is_repriced_only_once_canceled() {
+        for cancel_fails in [true, false] {
+            let recorder = OrderRecorder::default();
+            let orders = recorder.orders.clone();
+            let venue: Box<dyn DexConnectorExt> = if cancel_fails {
+                Box::new(Mock(CancelRejectingVenue(recorder)))
+            } else {
+                Box::new(Mock(recorder))
+            };
+            let mut fund_manager = test_fund_manager(
+                "a",
+                0,
+                warmed_up_market_data(),
+                Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+                Arc::new(DexConnectorBox::from_connector("test", venue)),
+                ContractType::Linear,
+            )
+            .await;
+            fund_manager.config.order_placement_mode = OrderPlacementMode::AlwaysMaker;
+            fund_manager.state.last_min_tick = Some(Decimal::new(1, 2));
+
+            // The open order expires on the next tick
+            let detail = TradeDetail::new(
+                Some(Decimal::new(100, 0)),
+                Some(Decimal::ONE),
+                Decimal::ONE,
+                Some(0),
+            );
+            open(&mut fund_manager, TradeAction::BuyOpen(detail)).await;
+            fund_manager.check_positions(Decimal::new(101, 0));
+            fund_manager.state.current_price = Decimal::new(101, 0);
+            fund_manager.find_expired_orders().await;
+
+            let orders = orders.lock().unwrap();
+            if cancel_fails {
+                assert_eq!(orders.len(), 1);
+            } else {
+                assert_eq!(
+                    *orders,
+                    vec![
+                        (OrderSide::Long, Decimal::ONE),
+                        (OrderSide::Long, Decimal::ONE)
+                    ]
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_always_maker_open_is_placed_inside_the_spread() {
+        // Quotes 99.5/101
+        struct BookVenue;
+
+        #[async_trait]
+        impl MockDex for BookVenue {
+            async fn get_orderbook(
+                &self,
+                _symbol: &str,
+                _depth: usize,
+            ) -> Result<OrderBook, DexError> {
+                Ok(OrderBook {
+                    bids: vec![(Decimal::new(995, 1), Decimal::ONE)],
+                    asks: vec![(Decimal::new(101, 0), Decimal::ONE)],
+                })
+            }
+        }
+
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            Arc::new(DexConnectorBox::from_connector(
+                "test",
+                Box::new(Mock(BookVenue)),
+            )),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.order_placement_mode = OrderPlacementMode::AlwaysMaker;
+        fund_manager.state.last_min_tick = Some(Decimal::new(5, 1));
+        fund_manager.state.current_price = Decimal::new(100, 0);
+
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(1))).await;
+        let position = fund_manager.find_position_from_order_id("1").unwrap();
+        assert_eq!(position.ordered_price(), Decimal::new(100, 0));
+    }
+
+    #[tokio::test]
+    async fn test_pending_orders_are_canceled_in_one_hyperliquid_request() {
+        let (exchange_url, actions) = start_hyperliquid_exchange();
+        let venue = SingleCancelRecorder::default();
+        let canceled = venue.canceled.clone();
+        let dex_connector = DexConnectorBox::from_connector("hyperliquid", Box::new(Mock(venue)))
+            .with_hyperliquid_exchange(&exchange_url, &["ETH", "BTC"])
+            .await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            Arc::new(dex_connector),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.order_placement_mode = OrderPlacementMode::AlwaysMaker;
+        fund_manager.state.last_min_tick = Some(Decimal::new(1, 2));
+
+        for _ in 0..2 {
+            let detail = TradeDetail::new(
+                Some(Decimal::new(100, 0)),
+                Some(Decimal::ONE),
+                Decimal::ONE,
+                None,
+            );
+            open(&mut fund_manager, TradeAction::BuyOpen(detail)).await;
+        }
+        let is_opening = |fund_manager: &FundManager| {
+            fund_manager
+                .state
+                .trade_positions
+                .values()
+                .filter(|position| matches!(position.state(), State::Opening))
+                .count()
+        };
+        assert_eq!(is_opening(&fund_manager), 2);
+
+        fund_manager.cancel_all_orders().await;
+
+        // Both orders go in a single bulk cancel of BTC, the second asset of the meta
+        let actions = actions.lock().unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["type"], "cancel");
+        let cancels = actions[0]["cancels"].as_array().unwrap();
+        assert!(cancels.iter().all(|cancel| cancel["a"] == 1));
+        let mut oids: Vec<u64> = cancels
+            .iter()
+            .map(|cancel| cancel["o"].as_u64().unwrap())
+            .collect();
+        oids.sort();
+        assert_eq!(oids, vec![1, 2]);
+        assert!(canceled.lock().unwrap().is_empty());
+        assert_eq!(is_opening(&fund_manager), 0);
+    }
+
+    #[tokio::test]
+    async fn test_orders_gone_from_the_venue_twice_are_canceled() {
+        let venue = SingleCancelRecorder::default();
+        let canceled = venue.canceled.clone();
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            Arc::new(DexConnectorBox::from_connector(
+                "test",
+                Box::new(Mock(venue)),
+            )),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.order_placement_mode = OrderPlacementMode::AlwaysMaker;
+        fund_manager.state.last_min_tick = Some(Decimal::new(1, 2));
+
+        for _ in 0..2 {
+            let detail = TradeDetail::new(
+                Some(Decimal::new(100, 0)),
+                Some(Decimal::ONE),
+                Decimal::ONE,
+                None,
+            );
+            open(&mut fund_manager, TradeAction::BuyOpen(detail)).await;
+        }
+        let opening_order_ids = |fund_manager: &FundManager| {
+            let mut order_ids: Vec<String> = fund_manager
+                .state
+                .trade_positions
+                .values()
+                .filter(|position| matches!(position.state(), State::Opening))
+                .map(|position| position.order_id().to_owned())
+                .collect();
+            order_ids.sort();
+            order_ids
+        };
+        assert_eq!(opening_order_ids(&fund_manager), vec!["1", "2"]);
+
+        let listed = |order_ids: &[&str]| -> HashSet<String> {
+            order_ids
+                .iter()
+                .map(|order_id| order_id.to_string())
+                .collect()
+        };
+
+        // A single miss may be a fill on its way, and a listed order starts over
+        fund_manager
+            .reconcile_open_orders(&listed(&["1"]), &mut None)
+            .await;
+        fund_manager
+            .reconcile_open_orders(&listed(&["2"]), &mut None)
+            .await;
+        assert!(canceled.lock().unwrap().is_empty());
+        assert_eq!(opening_order_ids(&fund_manager), vec!["1", "2"]);
+
+        // Missing twice in a row, and with no word from the venue on its position, the first
+        // order is given up without a cancel to the venue
+        fund_manager
+            .reconcile_open_orders(&listed(&["2"]), &mut None)
+            .await;
+        assert!(canceled.lock().unwrap().is_empty());
+        assert_eq!(opening_order_ids(&fund_manager), vec!["2"]);
+    }
+
+    #[tokio::test]
+    async fn test_gone_orders_the_venue_filled_are_booked() {
+        let venue = SingleCancelRecorder::default();
+        let canceled = venue.canceled.clone();
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            Arc::new(DexConnectorBox::from_connector(
+                "test",
+                Box::new(Mock(venue)),
+            )),
+            ContractType::Linear,
+        )
+        .await;
+        let not_listed = HashSet::new();
+
+        // The open of 2 is gone, and the venue holds 2 more than the fund
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        let mut venue_drift = Some(Decimal::new(2, 0));
+        for _ in 0..2 {
+            fund_manager
+                .reconcile_open_orders(&not_listed, &mut venue_drift)
+                .await;
+        }
+        let position = fund_manager.get_open_position().unwrap();
+        assert_eq!(position.state(), State::Open);
+        assert_eq!(position.amount(), Decimal::new(2, 0));
+        assert_eq!(venue_drift, Some(Decimal::ZERO));
+
+        // The close is gone too, and the venue is flat, so the position is closed rather than
+        // open again
+        let close_price = Decimal::new(110, 0);
+        let mut trace = DecisionTrace::new(false, "a", 1, close_price);
+        fund_manager
+            .find_close_chances(close_price, &mut trace)
+            .await
+            .unwrap();
+        let position = fund_manager.get_open_position().unwrap();
+        assert!(matches!(position.state(), State::Closing(_)));
+        let mut venue_drift = Some(Decimal::new(-2, 0));
+        for _ in 0..2 {
+            fund_manager
+                .reconcile_open_orders(&not_listed, &mut venue_drift)
+                .await;
+        }
+        assert!(fund_manager.get_open_position().is_none());
+        assert_eq!(fund_manager.state.closed_positions.len(), 1);
+        assert_eq!(venue_drift, Some(Decimal::ZERO));
+        assert!(canceled.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_shown_by_venue() {
+        let shown = |drift, signed_size| {
+            FundManager::is_shown_by_venue(Decimal::new(drift, 0), Decimal::new(signed_size, 0))
+        };
+        assert!(shown(2, 2));
+        assert!(shown(3, 2));
+        assert!(shown(-2, -2));
+        assert!(!shown(1, 2));
+        assert!(!shown(-2, 2));
+        assert!(!shown(0, 2));
+        assert!(!shown(0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_emulated_orders_of_a_dry_run_are_kept() {
+        struct QuietDex;
+        impl MockDex for QuietDex {}
+
+        // The account on the venue has no open order, the emulator has the order of the fund
+        let (info_url, requests) = start_hyperliquid_info();
+        let emulator = DexEmulator::new(
+            Mock(QuietDex),
+            Decimal::ZERO,
+            EmulatorFillSettings::default(),
+            Some(1),
+        );
+        let dex_connector = Arc::new(
+            DexConnectorBox::from_connector("hyperliquid", Box::new(emulator))
+                .with_hyperliquid_info(&info_url, true),
+        );
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(1))).await;
+        let position_id = *fund_manager.state.trade_positions.keys().next().unwrap();
+        let order_id = fund_manager.state.trade_positions[&position_id]
+            .order_id()
+            .to_owned();
+
+        for _ in 0..3 {
+            let order_ids = dex_connector.get_open_order_ids("BTC").await.unwrap();
+            assert_eq!(order_ids, vec![order_id.clone()]);
+            fund_manager
+                .reconcile_open_orders(&order_ids.into_iter().collect(), &mut None)
+                .await;
+        }
+        let position = &fund_manager.state.trade_positions[&position_id];
+        assert_eq!(position.state(), State::Opening);
+        assert!(requests.lock().unwrap().is_empty());
+
+        // A live connector asks the account
+        let live_connector =
+            DexConnectorBox::from_connector("hyperliquid", Box::new(Mock(QuietDex)));
+        let live_connector = live_connector.with_hyperliquid_info(&info_url, false);
+        let order_ids = live_connector.get_open_order_ids("BTC").await.unwrap();
+        assert!(order_ids.is_empty());
+        assert_eq!(requests.lock().unwrap()[0]["type"], "openOrders");
+    }
+
+    #[tokio::test]
+    async fn test_ioc_orders_are_sent_to_hyperliquid_as_ioc() {
+        let (exchange_url, actions) = start_hyperliquid_exchange();
+        let venue = OrderRecorder::default();
+        let orders = venue.orders.clone();
+        let dex_connector = DexConnectorBox::from_connector("hyperliquid", Box::new(Mock(venue)))
+            .with_hyperliquid_exchange(&exchange_url, &["BTC"])
+            .await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            Arc::new(dex_connector),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.order_placement_mode = OrderPlacementMode::AlwaysMaker;
+        fund_manager.config.time_in_force = TimeInForce::Ioc;
+        fund_manager.state.last_min_tick = Some(Decimal::new(1, 2));
+
+        let detail = TradeDetail::new(
+            Some(Decimal::new(100, 0)),
+            Some(Decimal::ONE),
+            Decimal::ONE,
+            None,
+        );
+        open(&mut fund_manager, TradeAction::BuyOpen(detail)).await;
+
+        // The limit order skips the post-only path of HyperliquidConnector
+        assert!(orders.lock().unwrap().is_empty());
+        let actions = actions.lock().unwrap();
+        assert_eq!(actions.len(), 1);
+        assert_eq!(actions[0]["type"], "order");
+        assert_eq!(
+            actions[0]["orders"][0]["t"],
+            serde_json::json!({ "limit": { "tif": "Ioc" } })
+        );
+        assert_eq!(actions[0]["orders"][0]["b"], true);
+        assert!(fund_manager.find_position_from_order_id("1").is_some());
+        assert!(fund_manager.state.immediate_order_ids.contains("1"));
+    }
+
+    #[tokio::test]
+    async fn test_close_orders_are_chased_then_sent_at_market() {
+        // Records the limit price of each order, None for a market order
+        #[derive(Default)]
+        struct PriceRecorder(Arc<std::sync::Mutex<Vec<Option<Decimal>>>>);
+
+        #[async_trait]
+        impl MockDex for PriceRecorder {
+            async fn create_order(
+                &self,
+                _symbol: &str,
+                size: Decimal,
+                _side: OrderSide,
+                price: Option<Decimal>,
+                _time_in_force: TimeInForce,
+            ) -> Result<CreateOrderResponse, DexError> {
+                let mut prices = self.0.lock().unwrap();
+                prices.push(price);
+                Ok(CreateOrderResponse {
+                    order_id: prices.len().to_string(),
+                    ordered_price: price.unwrap_or(Decimal::new(100, 0)),
+                    ordered_size: size,
+                })
+            }
+        }
+
+        let venue = PriceRecorder::default();
+        let prices = venue.0.clone();
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            Arc::new(DexConnectorBox::from_connector(
+                "test",
+                Box::new(Mock(venue)),
+            )),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.chase_close_orders = true;
+        fund_manager.config.max_chase_ticks = 2;
+
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        fund_manager
+            .position_filled(
+                "1",
+                "t1",
+                OrderSide::Long,
+                Decimal::new(200, 0),
+                Decimal::new(2, 0),
+                Decimal::ZERO,
+            )
+            .await
+            .unwrap();
+        let position_id = fund_manager.get_open_position().unwrap().id();
+
+        // The take-profit puts the close order
+        let price = Decimal::new(110, 0);
+        let mut trace = DecisionTrace::new(false, "a", 1, price);
+        fund_manager
+            .find_close_chances(price, &mut trace)
+            .await
+            .unwrap();
+        assert_eq!(prices.lock().unwrap().len(), 2);
+
+        // Re-posted at the price of each tick, and at market past max_chase_ticks
+        for (tick, price) in [(1, 109), (2, 108), (3, 107)] {
+            fund_manager
+                .chase_close_orders(Decimal::new(price, 0))
+                .await
+                .unwrap();
+            assert_eq!(fund_manager.state.close_chase_counts[&position_id], tick);
+        }
+        assert_eq!(
+            prices.lock().unwrap()[2..],
+            [Some(Decimal::new(109, 0)), Some(Decimal::new(108, 0)), None]
+        );
+        let position = fund_manager.get_open_position().unwrap();
+        assert!(matches!(position.state(), State::Closing(_)));
+
+        // The count goes with the position once the close is filled
+        fund_manager
+            .position_filled(
+                position.order_id(),
+                "t2",
+                OrderSide::Short,
+                Decimal::new(214, 0),
+                Decimal::new(2, 0),
+                Decimal::ZERO,
+            )
+            .await
+            .unwrap();
+        fund_manager
+            .chase_close_orders(Decimal::new(107, 0))
+            .await
+            .unwrap();
+        assert_eq!(prices.lock().unwrap().len(), 5);
+        assert!(fund_manager.state.close_chase_counts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_seeded_random_walk_opens_are_reproducible() {
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let dex_connector = Arc::new(DexConnectorBox::from_connector(
+            "test",
+            Box::new(Mock(OrderRecorder::default())),
+        ));
+        let mut runs = vec![];
+        for fund_name in ["a", "a", "b"] {
+            let mut fund_manager = test_fund_manager(
+                fund_name,
+                0,
+                warmed_up_market_data(),
+                db_handler.clone(),
+                dex_connector.clone(),
+                ContractType::Linear,
+            )
+            .await;
+            fund_manager.config.strategy = TradingStrategy::RandomWalk(TrendType::Up);
+            // Only a seeded back test has a generator of the fund
+            assert!(fund_manager.state.rng.is_none());
+            fund_manager.state.rng = FundManager::create_rng(Some(1), fund_name);
+
+            let mut opens = vec![];
+            for _ in 0..100 {
+                opens.push(fund_manager.open_signals().await.len());
+            }
+            runs.push(opens);
+        }
+        assert!(runs[0].contains(&1));
+        assert_eq!(runs[0], runs[1]);
+        // Every fund draws its own sequence
+        assert_ne!(runs[0], runs[2]);
+    }
+
+    #[tokio::test]
+    async fn test_pending_orders_count_toward_the_token_notional() {
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let dex_connector = Arc::new(DexConnectorBox::from_connector(
+            "test",
+            Box::new(Mock(OrderRecorder::default())),
+        ));
+        let mut fund_managers = vec![];
+        for (index, token_name) in ["BTC", "BTC", "ETH"].into_iter().enumerate() {
+            let mut fund_manager = test_fund_manager(
+                &format!("{}-{}", token_name, index),
+                index,
+                warmed_up_market_data(),
+                db_handler.clone(),
+                dex_connector.clone(),
+                ContractType::Linear,
+            )
+            .await;
+            fund_manager.config.token_name = token_name.to_owned();
+            fund_manager.config.order_placement_mode = OrderPlacementMode::AlwaysMaker;
+            fund_manager.state.last_min_tick = Some(Decimal::new(1, 2));
+            fund_managers.push(fund_manager);
+        }
+
+        // Each BTC fund rests a buy of 1 at about 100, which the venue never fills
+        for fund_manager in fund_managers.iter_mut().take(2) {
+            open(fund_manager, TradeAction::BuyOpen(open_detail(1))).await;
+            assert_eq!(fund_manager.asset_in_usd(), Decimal::ZERO);
+            assert!(fund_manager.open_notional() > Decimal::new(99, 0));
+        }
+
+        // Neither fund reaches 150 alone, but the two together do
+        assert!(FundManager::tokens_over_notional(
+            fund_managers.iter().skip(1),
+            Decimal::new(150, 0)
+        )
+        .is_empty());
+        assert_eq!(
+            FundManager::tokens_over_notional(fund_managers.iter(), Decimal::new(150, 0)),
+            HashSet::from(["BTC".to_owned()])
+        );
+        assert!(
+            FundManager::tokens_over_notional(fund_managers.iter(), Decimal::new(250, 0))
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_tokens_are_chosen_within_the_cap() {
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let dex_connector = Arc::new(DexConnectorBox::from_connector(
+            "test",
+            Box::new(Mock(OrderRecorder::default())),
+        ));
+        let mut fund_managers = vec![];
+        for (index, token_name) in ["BTC", "ETH", "SOL", "BTC"].into_iter().enumerate() {
+            let mut fund_manager = test_fund_manager(
+                &format!("{}-{}", token_name, index),
+                index,
+                warmed_up_market_data(),
+                db_handler.clone(),
+                dex_connector.clone(),
+                ContractType::Linear,
+            )
+            .await;
+            fund_manager.config.token_name = token_name.to_owned();
+            fund_managers.push(fund_manager);
+        }
+
+        // All the tokens may open on the same tick, only two of them are let through
+        let capped_tokens = FundManager::tokens_over_concurrency(fund_managers.iter(), 2, 0);
+        assert_eq!(capped_tokens, HashSet::from(["SOL".to_owned()]));
+        // The next tick lets another one through
+        assert_eq!(
+            FundManager::tokens_over_concurrency(fund_managers.iter(), 2, 1),
+            HashSet::from(["BTC".to_owned()])
+        );
+
+        for fund_manager in fund_managers[..2].iter_mut() {
+            open(fund_manager, TradeAction::BuyOpen(open_detail(1))).await;
+            assert!(fund_manager.has_position());
+        }
+
+        // The new token is blocked, while the other BTC fund can still add
+        for tick_count in 0..3 {
+            assert_eq!(
+                FundManager::tokens_over_concurrency(fund_managers.iter(), 2, tick_count),
+                HashSet::from(["SOL".to_owned()])
+            );
+        }
+        assert!(FundManager::tokens_over_concurrency(fund_managers.iter(), 3, 0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_slippage_is_alerted_through_the_queue() {
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            emulated_venue(Decimal::ONE).await,
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.alert_on_slippage = true;
+
+        // 1% worse than ordered, with 0.5% acceptable
+        fund_manager.check_slippage(
+            "1",
+            &OrderSide::Long,
+            Decimal::new(100, 0),
+            Decimal::new(101, 0),
+            Decimal::new(5, 3),
+        );
+        let alerts = fund_manager.state.alerts.queued();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, "Slippage exceeded");
+        assert_eq!(fund_manager.statistics.slippage_exceeded_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_close_fill_before_its_open_fills_is_deferred() {
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        dex_connector
+            .get_ticker("BTC", Some(Decimal::new(100, 0)))
+            .await
+            .unwrap();
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        process_fills(&mut fund_manager, &dex_connector).await;
+
+        let close_price = Decimal::new(110, 0);
+        dex_connector
+            .get_ticker("BTC", Some(close_price))
+            .await
+            .unwrap();
+        let mut trace = DecisionTrace::new(false, "a", 1, close_price);
+        fund_manager
+            .find_close_chances(close_price, &mut trace)
+            .await
+            .unwrap();
+        let position = fund_manager.get_open_position().unwrap();
+        assert!(matches!(position.state(), State::Closing(_)));
+        let order_id = position.order_id().to_owned();
+
+        // A close of 3 while 2 are held
+        let filled = fund_manager
+            .position_filled(
+                &order_id,
+                "late-1",
+                OrderSide::Short,
+                Decimal::new(330, 0),
+                Decimal::new(3, 0),
+                Decimal::ZERO,
+            )
+            .await;
+        assert_eq!(filled, Ok(false));
+        let alerts = fund_manager.state.alerts.queued();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].0, "Out-of-order fill");
+        let position = fund_manager.get_open_position().unwrap();
+        assert_eq!(position.amount(), Decimal::new(2, 0));
+
+        // The close of what is held goes through
+        let filled = fund_manager
+            .position_filled(
+                &order_id,
+                "late-2",
+                OrderSide::Short,
+                Decimal::new(220, 0),
+                Decimal::new(2, 0),
+                Decimal::ZERO,
+            )
+            .await;
+        assert_eq!(filled, Ok(true));
+        assert!(fund_manager.get_open_position().is_none());
+        assert_eq!(fund_manager.statistics.pnl, Decimal::new(20, 0));
+    }
+
+    #[tokio::test]
+    async fn test_deferred_close_fill_completes_after_its_open_fill() {
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        dex_connector
+            .get_ticker("BTC", Some(Decimal::new(100, 0)))
+            .await
+            .unwrap();
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        process_fills(&mut fund_manager, &dex_connector).await;
+
+        // An order adding 1 to the position is pending when the position is closed
+        fund_manager
+            .prepare_position(
+                "add-1",
+                Some(Decimal::new(100, 0)),
+                Decimal::ONE,
+                TradeAction::BuyOpen(open_detail(1)),
+                Some(Decimal::new(110, 0)),
+                None,
+                "BTC",
+                None,
+            )
+            .await
+            .unwrap();
+        let close_price = Decimal::new(110, 0);
+        let mut trace = DecisionTrace::new(false, "a", 1, close_price);
+        fund_manager
+            .find_close_chances(close_price, &mut trace)
+            .await
+            .unwrap();
+        let order_id = fund_manager
+            .get_open_position()
+            .unwrap()
+            .order_id()
+            .to_owned();
+
+        // The venue closes all 3 and the close fill comes first
+        let filled = fund_manager
+            .position_filled(
+                &order_id,
+                "close-1",
+                OrderSide::Short,
+                Decimal::new(330, 0),
+                Decimal::new(3, 0),
+                Decimal::ZERO,
+            )
+            .await;
+        assert_eq!(filled, Ok(false));
+        fund_manager.apply_deferred_fills().await;
+        assert_eq!(
+            fund_manager.get_open_position().unwrap().amount(),
+            Decimal::new(2, 0)
+        );
+
+        // The fill of the added 1 comes on the next tick, and the deferred close then
+        // completes the position
+        let filled = fund_manager
+            .position_filled(
+                "add-1",
+                "open-2",
+                OrderSide::Long,
+                Decimal::new(100, 0),
+                Decimal::ONE,
+                Decimal::ZERO,
+            )
+            .await;
+        assert_eq!(filled, Ok(true));
+        assert_eq!(
+            fund_manager.get_open_position().unwrap().amount(),
+            Decimal::new(3, 0)
+        );
+        fund_manager.apply_deferred_fills().await;
+
+        assert!(fund_manager.get_open_position().is_none());
+        assert_eq!(fund_manager.state.closed_positions.len(), 1);
+        assert_eq!(fund_manager.statistics.pnl, Decimal::new(30, 0));
+        assert!(fund_manager.state.deferred_fills.is_empty());
+        assert_eq!(fund_manager.state.alerts.queued().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_exposure_is_positive_for_a_long() {
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let market_data = warmed_up_market_data();
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let mut long_fund = test_fund_manager(
+            "a",
+            0,
+            market_data.clone(),
+            db_handler.clone(),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        let mut short_fund = test_fund_manager(
+            "b",
+            1,
+            market_data,
+            db_handler,
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+
+        open(&mut long_fund, TradeAction::BuyOpen(open_detail(2))).await;
+        open(&mut short_fund, TradeAction::SellOpen(open_detail(1))).await;
+        let filled_orders = dex_connector.get_filled_orders("BTC").await.unwrap();
+        for fund_manager in [&mut long_fund, &mut short_fund] {
+            for order in filled_orders.orders.iter() {
+                fund_manager
+                    .position_filled(
+                        &order.order_id,
+                        &order.trade_id,
+                        order.filled_side.clone().unwrap(),
+                        order.filled_value.unwrap(),
+                        order.filled_size.unwrap(),
+                        order.filled_fee.unwrap(),
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+
+        assert!(long_fund.asset_in_usd().is_sign_negative());
+        assert_eq!(long_fund.exposure(), Decimal::new(200, 0));
+        assert_eq!(short_fund.exposure(), Decimal::new(-100, 0));
+    }
+
+    #[test]
+    fn test_fill_latency() {
+        let mut fill_latencies = FillLatencies::default();
+        fill_latencies.submitted("1", Instant::now());
+        std::thread::sleep(Duration::from_millis(50));
+        let latency = fill_latencies.filled("1", Instant::now()).unwrap();
+        assert!(latency >= Duration::from_millis(50));
+        assert!(latency < Duration::from_millis(500));
+
+        // The partial fills after the first one are not counted
+        assert_eq!(fill_latencies.filled("1", Instant::now()), None);
+        fill_latencies.submitted("2", Instant::now());
+        fill_latencies.canceled("2");
+        assert_eq!(fill_latencies.filled("2", Instant::now()), None);
+
+        // 10ms, 20ms, ..., 200ms
+        let mut fill_latencies = FillLatencies::default();
+        let submitted_at = Instant::now();
+        for i in 1..=20 {
+            let order_id = i.to_string();
+            fill_latencies.submitted(&order_id, submitted_at);
+            fill_latencies.filled(&order_id, submitted_at + Duration::from_millis(i * 10));
+        }
+        assert_eq!(
+            fill_latencies.percentile(50),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            fill_latencies.percentile(95),
+            Some(Duration::from_millis(190))
+        );
+        assert_eq!(FillLatencies::default().percentile(50), None);
+    }
+
+    #[test]
+    fn test_scaled_execution_delay() {
+        let factor = Decimal::new(50, 0);
+        let price = Decimal::new(100, 0);
+
+        // ATR of 0.2% stretches 10 ticks by 10%, ATR of 2% doubles them
+        assert_eq!(
+            FundManager::scaled_execution_delay(10, factor, Decimal::new(2, 1), price),
+            11
+        );
+        assert_eq!(
+            FundManager::scaled_execution_delay(10, factor, Decimal::new(2, 0), price),
+            20
+        );
+        // Rounded up
+        assert_eq!(
+            FundManager::scaled_execution_delay(10, factor, Decimal::new(1, 1), price),
+            11
+        );
+        // No ATR yet
+        assert_eq!(
+            FundManager::scaled_execution_delay(10, factor, Decimal::ZERO, price),
+            10
+        );
+    }
+
+    #[test]
+    fn test_is_model_tick() {
+        // Flat over 1000 ticks, the model runs every 10th tick
+        let evaluations = (1..=1000)
+            .filter(|tick| FundManager::is_model_tick(*tick, Some(10), false))
+            .count();
+        assert_eq!(evaluations, 100);
+
+        // Every tick with a position, or without the fast path
+        assert!((1..=1000).all(|tick| FundManager::is_model_tick(tick, Some(10), true)));
+        assert!((1..=1000).all(|tick| FundManager::is_model_tick(tick, None, false)));
+    }
+
+    #[test]
+    fn test_averaging() {
+        let average = Decimal::new(100, 0);
+        let improvement = Decimal::new(1, 2);
+
+        // A long averages down, a short averages up, by 1% at least
+        assert!(FundManager::is_averaging_improvement(
+            PositionType::Long,
+            average,
+            true,
+            Decimal::new(99, 0),
+            improvement
+        ));
+        assert!(!FundManager::is_averaging_improvement(
+            PositionType::Long,
+            average,
+            true,
+            Decimal::new(995, 1),
+            improvement
+        ));
+        assert!(!FundManager::is_averaging_improvement(
+            PositionType::Long,
+            average,
+            false,
+            Decimal::new(90, 0),
+            improvement
+        ));
+        assert!(FundManager::is_averaging_improvement(
+            PositionType::Short,
+            average,
+            false,
+            Decimal::new(101, 0),
+            improvement
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fill_at_a_better_price_is_averaged_into_the_position() {
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        fund_manager.config.allow_averaging = true;
+        fund_manager.config.min_average_improvement = Decimal::new(1, 2);
+
+        // A long of 1 at 100, then 3 more at 96
+        for (price, size) in [(100, 1), (96, 3)] {
+            let price = Decimal::new(price, 0);
+            dex_connector.get_ticker("BTC", Some(price)).await.unwrap();
+            let detail =
+                TradeDetail::new(Some(price), Some(Decimal::new(size, 0)), Decimal::ONE, None);
+            let mut trace = DecisionTrace::new(false, "a", 0, price);
+            fund_manager
+                .handle_open_chances(
+                    price,
+                    price,
+                    &vec![TradeAction::BuyOpen(detail)],
+                    &mut trace,
+                )
+                .await
+                .unwrap();
+            process_fills(&mut fund_manager, &dex_connector).await;
+        }
+
+        assert_eq!(fund_manager.state.trade_positions.len(), 1);
+        let position = fund_manager.get_open_position().unwrap();
+        assert_eq!(position.amount(), Decimal::new(4, 0));
+        assert_eq!(position.average_open_price(), Decimal::new(97, 0));
+    }
+
+    // Two funds on one venue, and the orders and cancels the venue received
+    async fn crossing_funds() -> (
+        Vec<FundManager>,
+        Arc<std::sync::Mutex<Vec<(OrderSide, Decimal)>>>,
+        Arc<std::sync::Mutex<Vec<String>>>,
+    ) {
+        let venue = SingleCancelRecorder::default();
+        let orders = venue.orders.orders.clone();
+        let canceled = venue.canceled.clone();
+        let dex_connector = Arc::new(DexConnectorBox::from_connector(
+            "test",
+            Box::new(Mock(venue)),
+        ));
+        let market_data = warmed_up_market_data();
+        let db_handler = Arc::new(Mutex::new(DBHandler::offline(true, None).await));
+        let mut funds = vec![];
+        for (index, fund_name) in ["a", "b"].into_iter().enumerate() {
+            funds.push(
+                test_fund_manager(
+                    fund_name,
+                    index,
+                    market_data.clone(),
+                    db_handler.clone(),
+                    dex_connector.clone(),
+                    ContractType::Linear,
+                )
+                .await,
+            );
+        }
+        (funds, orders, canceled)
+    }
+
+    fn detail_at(price: i64, size: i64) -> TradeDetail {
+        TradeDetail::new(
+            Some(Decimal::new(price, 0)),
+            Some(Decimal::new(size, 0)),
+            Decimal::ONE,
+            None,
+        )
+    }
+
+    fn resting_order_list(fund: &FundManager) -> Vec<(OrderSide, Decimal, Decimal)> {
+        fund.resting_orders()
+            .into_iter()
+            .map(|order| (order.side, order.price, order.size))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_crossing_open_is_netted_between_the_funds() {
+        let (mut funds, orders, canceled) = crossing_funds().await;
+
+        // Fund a rests a sell of 2 at 100, and fund b buys 3 at 101. The 2 that cross are
+        // traded between the funds at 100, and only the net 1 is sent to the venue.
+        open(&mut funds[0], TradeAction::SellOpen(detail_at(100, 2))).await;
+        FundManager::share_resting_orders(funds.iter_mut());
+        open(&mut funds[1], TradeAction::BuyOpen(detail_at(101, 3))).await;
+        funds[0].book_crosses().await;
+
+        assert_eq!(
+            *orders.lock().unwrap(),
+            vec![
+                (OrderSide::Short, Decimal::new(2, 0)),
+                (OrderSide::Long, Decimal::ONE),
+            ]
+        );
+        assert_eq!(*canceled.lock().unwrap(), vec!["1".to_owned()]);
+
+        for (fund, amount) in funds.iter().zip([-2, 2]) {
+            let position = fund.get_open_position().unwrap();
+            assert_eq!(position.amount(), Decimal::new(amount, 0));
+            assert_eq!(position.average_open_price(), Decimal::new(100, 0));
+            assert_eq!(
+                fund.state.amount,
+                fund.state.initial_amount.unwrap() - Decimal::new(200, 0)
+            );
+        }
+        assert!(resting_order_list(&funds[0]).is_empty());
+        assert_eq!(
+            resting_order_list(&funds[1]),
+            vec![(OrderSide::Long, Decimal::new(101, 0), Decimal::ONE)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partly_crossed_resting_order_is_placed_again() {
+        let (mut funds, orders, canceled) = crossing_funds().await;
+
+        // Fund b's buy of 2 is netted in full, and fund a's sell of 3 is placed again for the
+        // 1 left
+        open(&mut funds[0], TradeAction::SellOpen(detail_at(100, 3))).await;
+        FundManager::share_resting_orders(funds.iter_mut());
+        open(&mut funds[1], TradeAction::BuyOpen(detail_at(101, 2))).await;
+        funds[0].book_crosses().await;
+
+        assert_eq!(
+            *orders.lock().unwrap(),
+            vec![
+                (OrderSide::Short, Decimal::new(3, 0)),
+                (OrderSide::Short, Decimal::ONE),
+            ]
+        );
+        assert_eq!(*canceled.lock().unwrap(), vec!["1".to_owned()]);
+
+        for (fund, amount) in funds.iter().zip([-2, 2]) {
+            let position = fund.get_open_position().unwrap();
+            assert_eq!(position.amount(), Decimal::new(amount, 0));
+            assert_eq!(position.average_open_price(), Decimal::new(100, 0));
+        }
+        assert_eq!(
+            resting_order_list(&funds[0]),
+            vec![(OrderSide::Short, Decimal::new(100, 0), Decimal::ONE)]
+        );
+        assert!(resting_order_list(&funds[1]).is_empty());
+    }
+
+    #[test]
+    fn test_trend_change_confirmation() {
+        let mut counts = HashMap::new();
+
+        // A single opposing signal does not close
+        assert!(!FundManager::is_trend_change_confirmed(
+            &mut counts,
+            1,
+            true,
+            3
+        ));
+        assert!(!FundManager::is_trend_change_confirmed(
+            &mut counts,
+            1,
+            false,
+            3
+        ));
+
+        // Three in a row do
+        assert!(!FundManager::is_trend_change_confirmed(
+            &mut counts,
+            1,
+            true,
+            3
+        ));
+        assert!(!FundManager::is_trend_change_confirmed(
+            &mut counts,
+            1,
+            true,
+            3
+        ));
+        assert!(FundManager::is_trend_change_confirmed(
+            &mut counts,
+            1,
+            true,
+            3
+        ));
+
+        // Closes right away by default
+        assert!(FundManager::is_trend_change_confirmed(
+            &mut counts,
+            2,
+            true,
+            1
+        ));
+    }
+
+    #[test]
+    fn test_min_holding_defers_target_close() {
+        let opened_at = 1_700_000_000;
+
+        // A target close 10s after the open waits for the 60s holding period
+        assert!(FundManager::is_within_min_holding(
+            opened_at,
+            opened_at + 10,
+            Some(60)
+        ));
+        assert!(FundManager::is_within_min_holding(
+            opened_at,
+            opened_at + 59,
+            Some(60)
+        ));
+        assert!(!FundManager::is_within_min_holding(
+            opened_at,
+            opened_at + 60,
+            Some(60)
+        ));
+
+        // Not configured
+        assert!(!FundManager::is_within_min_holding(
+            opened_at, opened_at, None
+        ));
+    }
+
+    #[test]
+    fn test_fit_order_size() {
+        let min_size = Some(Decimal::new(1, 3));
+        let max_size = Some(Decimal::new(5, 0));
+
+        // Below the min is skipped
+        assert_eq!(
+            FundManager::fit_order_size(Decimal::new(5, 4), min_size, max_size),
+            None
+        );
+        assert_eq!(
+            FundManager::fit_order_size(Decimal::new(1, 3), min_size, max_size),
+            Some(Decimal::new(1, 3))
+        );
+        // Above the max is capped
+        assert_eq!(
+            FundManager::fit_order_size(Decimal::new(12, 0), min_size, max_size),
+            Some(Decimal::new(5, 0))
+        );
+        // No limits
+        assert_eq!(
+            FundManager::fit_order_size(Decimal::new(12, 0), None, None),
+            Some(Decimal::new(12, 0))
+        );
+    }
+
+    #[test]
+    fn test_round_order_price() {
+        let min_tick = Decimal::new(25, 2);
+        let round = |price: i64, side: &OrderSide, rounding_mode: RoundingMode| {
+            FundManager::round_order_price(Decimal::new(price, 3), min_tick, side, rounding_mode)
+        };
+
+        // 100.130 is between 100.00 and 100.25, nearer to the latter
+        assert_eq!(
+            round(100_130, &OrderSide::Long, RoundingMode::Nearest),
+            Decimal::new(10025, 2)
+        );
+        assert_eq!(
+            round(100_130, &OrderSide::Short, RoundingMode::Nearest),
+            Decimal::new(10025, 2)
+        );
+        assert_eq!(
+            round(100_130, &OrderSide::Long, RoundingMode::Favorable),
+            Decimal::new(10000, 2)
+        );
+        assert_eq!(
+            round(100_130, &OrderSide::Short, RoundingMode::Favorable),
+            Decimal::new(10025, 2)
+        );
+
+        // 100.110 is nearer to 100.00
+        assert_eq!(
+            round(100_110, &OrderSide::Long, RoundingMode::Nearest),
+            Decimal::new(10000, 2)
+        );
+        assert_eq!(
+            round(100_110, &OrderSide::Short, RoundingMode::Favorable),
+            Decimal::new(10025, 2)
+        );
+
+        // A price on the tick is kept in both modes
+        assert_eq!(
+            round(100_250, &OrderSide::Long, RoundingMode::Favorable),
+            Decimal::new(10025, 2)
+        );
+        assert_eq!(
+            round(100_250, &OrderSide::Short, RoundingMode::Favorable),
+            Decimal::new(10025, 2)
+        );
+    }
+
+    #[test]
+    fn test_resolve_min_tick_reuses_cached_tick() {
+        let mut last_min_tick = None;
+        assert_eq!(
+            FundManager::resolve_min_tick(None, &mut last_min_tick),
+            None
+        );
+
+        let min_tick = Some(Decimal::new(1, 1));
+        assert_eq!(
+            FundManager::resolve_min_tick(min_tick, &mut last_min_tick),
+            min_tick
+        );
+        assert_eq!(
+            FundManager::resolve_min_tick(None, &mut last_min_tick),
+            min_tick
+        );
+    }
+
+    #[test]
+    fn test_always_maker_does_not_hold_back_the_forced_closes() {
+        for reason in [
+            ReasonForClose::Liquidated,
+            ReasonForClose::CutLoss,
+            ReasonForClose::Expired,
+        ] {
+            assert!(FundManager::must_go_through(&Some(reason)));
+        }
+        assert!(!FundManager::must_go_through(&Some(
+            ReasonForClose::TakeProfit
+        )));
+        assert!(!FundManager::must_go_through(&None));
+    }
+
+    #[test]
+    fn test_maker_price_reprices_toward_current_price() {
+        let min_tick = Decimal::new(5, 1);
+        // A stale buy at 95 is reposted a tick inside the spread of 99.5/101
+        let stale_price = Decimal::new(95, 0);
+        let current_price = Decimal::new(100, 0);
+        let book = OrderBook {
+            bids: vec![(Decimal::new(995, 1), Decimal::ONE)],
+            asks: vec![(Decimal::new(101, 0), Decimal::ONE)],
+        };
+        let new_price = FundManager::maker_price(&book, &OrderSide::Long, min_tick).unwrap();
+        assert_eq!(new_price, Decimal::new(100, 0));
+        assert!((current_price - new_price).abs() < (current_price - stale_price).abs());
+        let new_price = FundManager::maker_price(&book, &OrderSide::Short, min_tick).unwrap();
+        assert_eq!(new_price, Decimal::new(1005, 1));
+
+        // A tick inside a single tick spread would take, so the order rests at the touch
+        let book = OrderBook {
+            bids: vec![(Decimal::new(100, 0), Decimal::ONE)],
+            asks: vec![(Decimal::new(1005, 1), Decimal::ONE)],
+        };
+        assert_eq!(
+            FundManager::maker_price(&book, &OrderSide::Long, min_tick),
+            Some(Decimal::new(100, 0))
+        );
+        assert_eq!(
+            FundManager::maker_price(&book, &OrderSide::Short, min_tick),
+            Some(Decimal::new(1005, 1))
+        );
+        assert_eq!(
+            FundManager::maker_price(&OrderBook::default(), &OrderSide::Long, min_tick),
+            None
+        );
+
+        // Without a book, a tick behind the current price
+        let new_price = FundManager::passive_price(&OrderSide::Long, current_price, min_tick);
+        assert_eq!(new_price, Decimal::new(995, 1));
+        assert!(!FundManager::would_cross(
+            &OrderSide::Long,
+            new_price,
+            current_price
+        ));
+        let new_price = FundManager::passive_price(&OrderSide::Short, current_price, min_tick);
+        assert_eq!(new_price, Decimal::new(1005, 1));
+        assert!(!FundManager::would_cross(
+            &OrderSide::Short,
+            new_price,
+            current_price
+        ));
+    }
+
+    #[test]
+    fn test_first_available_atr() {
+        let terms = FundManager::atr_fallback_terms(&SampleTerm::LongTerm);
+        assert_eq!(terms.len(), 3);
+
+        // Fewer data points than any term: no ATR, so nothing is opened
+        assert_eq!(
+            FundManager::first_available_atr(&terms, |_| Decimal::ZERO),
+            None
+        );
+
+        // Only the trading term has enough data points
+        let atr = FundManager::first_available_atr(&terms, |term| match term {
+            SampleTerm::TradingTerm => Decimal::new(15, 1),
+            _ => Decimal::ZERO,
+        });
+        assert_eq!(atr, Some((2, Decimal::new(15, 1))));
+
+        // The configured term is used once it is warmed up
+        let atr = FundManager::first_available_atr(&terms, |_| Decimal::ONE);
+        assert_eq!(atr, Some((0, Decimal::ONE)));
+    }
+
+    #[tokio::test]
+    async fn test_take_profit_ladder() {
+        let dex_connector = emulated_venue(Decimal::ONE).await;
+        let mut fund_manager = test_fund_manager(
+            "a",
+            0,
+            warmed_up_market_data(),
+            Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+            dex_connector.clone(),
+            ContractType::Linear,
+        )
+        .await;
+        // Half of the rest at +1%, and half of that at +2%, below the take profit at +5%
+        fund_manager.config.take_profit_ratio = Some(Decimal::new(5, 2));
+        fund_manager.config.take_profit_ladder = vec![
+            (Decimal::new(1, 2), Decimal::new(5, 1)),
+            (Decimal::new(2, 2), Decimal::new(5, 1)),
+        ];
+        dex_connector
+            .get_ticker("BTC", Some(Decimal::new(100, 0)))
+            .await
+            .unwrap();
+        open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+        process_fills(&mut fund_manager, &dex_connector).await;
+        let position_id = fund_manager.get_open_position().unwrap().id();
+
+        // The rungs close a part of the position each, and only once
+        let steps = [
+            (100, Decimal::new(2, 0)),
+            (101, Decimal::ONE),
+            (101, Decimal::ONE),
+            (102, Decimal::new(5, 1)),
+        ];
+        for (i, (price, expected_amount)) in steps.into_iter().enumerate() {
+            let price = Decimal::new(price, 0);
+            dex_connector.get_ticker("BTC", Some(price)).await.unwrap();
+            let mut trace = DecisionTrace::new(false, "a", i as u64 + 1, price);
+            fund_manager
+                .find_close_chances(price, &mut trace)
+                .await
+                .unwrap();
+            process_fills(&mut fund_manager, &dex_connector).await;
+
+            let position = fund_manager.get_open_position().unwrap();
+            assert_eq!(position.amount(), expected_amount, "at {}", price);
+            assert_eq!(position.state(), State::Open, "at {}", price);
+        }
+        // The stop of the rest has moved to breakeven
+        assert_eq!(
+            fund_manager.state.trailing_stop_prices.get(&position_id),
+            Some(&Decimal::new(100, 0))
+        );
+
+        // So the rest is closed at the open price
+        let price = Decimal::new(100, 0);
+        dex_connector.get_ticker("BTC", Some(price)).await.unwrap();
+        let mut trace = DecisionTrace::new(false, "a", 5, price);
+        fund_manager
+            .find_close_chances(price, &mut trace)
+            .await
+            .unwrap();
+        process_fills(&mut fund_manager, &dex_connector).await;
+        assert!(fund_manager.get_open_position().is_none());
+        assert_eq!(fund_manager.statistics.trailing_stop_count, 1);
+        // 1 at 101 and 0.5 at 102
+        assert_eq!(fund_manager.statistics.pnl, Decimal::new(2, 0));
+    }
+
+    #[test]
+    fn test_stoch_rsi_crossed() {
+        let stochastics = |values: &[i64]| -> VecDeque<Decimal> {
+            values.iter().map(|v| Decimal::new(*v, 0)).collect()
+        };
+
+        // Not enough samples yet
+        assert!(!FundManager::is_stoch_rsi_crossed(
+            PositionType::Long,
+            &stochastics(&[85])
+        ));
+
+        assert!(FundManager::is_stoch_rsi_crossed(
+            PositionType::Long,
+            &stochastics(&[85, 75])
+        ));
+        assert!(!FundManager::is_stoch_rsi_crossed(
+            PositionType::Long,
+            &stochastics(&[85, 90])
+        ));
+        assert!(!FundManager::is_stoch_rsi_crossed(
+            PositionType::Long,
+            &stochastics(&[70, 60])
+        ));
+
+        assert!(FundManager::is_stoch_rsi_crossed(
+            PositionType::Short,
+            &stochastics(&[15, 25])
+        ));
+        assert!(!FundManager::is_stoch_rsi_crossed(
+            PositionType::Short,
+            &stochastics(&[15, 10])
+        ));
+        // A Short is not closed on the overbought cross
+        assert!(!FundManager::is_stoch_rsi_crossed(
+            PositionType::Short,
+            &stochastics(&[85, 75])
+        ));
+    }
+
+    #[test]
+    fn test_price_zscore() {
+        // mean = 100, std = 2
+        let history: VecDeque<Decimal> = [98, 102, 98, 102]
+            .iter()
+            .map(|p| Decimal::new(*p, 0))
+            .collect();
+        let zscore = FundManager::price_zscore(&history, Decimal::new(96, 0));
+        assert_eq!(zscore, Decimal::new(-2, 0));
+        assert!(zscore.abs() >= Decimal::new(15, 1));
+        assert!(
+            FundManager::price_zscore(&history, Decimal::new(101, 0)).abs() < Decimal::new(15, 1)
+        );
+
+        // A flat series never lets an entry through
+        let flat: VecDeque<Decimal> = vec![Decimal::new(100, 0); 4].into();
+        assert_eq!(
+            FundManager::price_zscore(&flat, Decimal::new(120, 0)),
+            Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_daily_loss_limit() {
+        let limit = Decimal::new(100, 0);
+        let day = 86400;
+        let mut statistics = FundManagerStatics::default();
+
+        statistics.add_daily_pnl(Decimal::new(-60, 0), 3 * day + 10);
+        assert!(!FundManager::is_daily_loss_breached(
+            statistics.daily_pnl(3 * day + 20),
+            limit
+        ));
+
+        // Opens are blocked once the losses pass the limit
+        statistics.add_daily_pnl(Decimal::new(-50, 0), 3 * day + 30);
+        assert!(FundManager::is_daily_loss_breached(
+            statistics.daily_pnl(3 * day + 40),
+            limit
+        ));
+        // Closes still go through, and their PnL is accumulated
+        statistics.add_daily_pnl(Decimal::new(20, 0), 3 * day + 50);
+        assert_eq!(statistics.daily_pnl(3 * day + 60), Decimal::new(-90, 0));
+
+        // Reset at UTC midnight
+        assert_eq!(statistics.daily_pnl(4 * day), Decimal::ZERO);
+        statistics.add_daily_pnl(Decimal::new(-10, 0), 4 * day + 1);
+        assert_eq!(statistics.daily_pnl(4 * day + 2), Decimal::new(-10, 0));
+    }
+
+    #[test]
+    fn test_max_open_positions() {
+        let cap = Some(2);
+        let open_positions = FundManager::count_open_positions(vec![State::Open].into_iter());
+        assert!(FundManager::is_below_cap(open_positions, cap));
+
+        // Opening up to the cap
+        let open_positions =
+            FundManager::count_open_positions(vec![State::Open, State::Opening].into_iter());
+        assert_eq!(open_positions, 2);
+        assert!(!FundManager::is_below_cap(open_positions, cap));
+
+        // No cap
+        assert!(FundManager::is_below_cap(open_positions, None));
+    }
+
+    #[test]
+    fn test_filter_confident_opens() {
+        let open = |is_buy: bool, confidence: i64| {
+            let detail = TradeDetail::new(None, None, Decimal::new(confidence, 1), None);
+            if is_buy {
+                TradeAction::BuyOpen(detail)
+            } else {
+                TradeAction::SellOpen(detail)
+            }
+        };
+        let actions = vec![open(true, 3), open(false, 6), open(true, 7), open(false, 9)];
+
+        let (confident_actions, below_confidence) =
+            FundManager::filter_confident_opens(&actions, Decimal::new(6, 1));
+        assert_eq!(below_confidence, 1);
+        let confidences: Vec<Option<Decimal>> = confident_actions
+            .iter()
+            .map(|action| action.confidence())
+            .collect();
+        assert_eq!(
+            confidences,
+            vec![
+                Some(Decimal::new(6, 1)),
+                Some(Decimal::new(7, 1)),
+                Some(Decimal::new(9, 1)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_close_signals_below_min_close_confidence_are_ignored() {
+        let close_signal = |trim: bool, confidence: i64| {
+            let detail = TradeDetail::new(None, None, Decimal::new(confidence, 1), None);
+            if trim {
+                TradeAction::SellTrim(detail)
+            } else {
+                TradeAction::SellClose(detail)
+            }
+        };
+
+        for trim in [true, false] {
+            let dex_connector = emulated_venue(Decimal::ONE).await;
+            let mut fund_manager = test_fund_manager(
+                "a",
+                0,
+                warmed_up_market_data(),
+                Arc::new(Mutex::new(DBHandler::offline(true, None).await)),
+                dex_connector.clone(),
+                ContractType::Linear,
+            )
+            .await;
+            fund_manager.config.min_close_confidence = Decimal::new(6, 1);
+            assert!(!fund_manager.is_close_confident(&close_signal(trim, 5)));
+            assert!(fund_manager.is_close_confident(&close_signal(trim, 6)));
+
+            open(&mut fund_manager, TradeAction::BuyOpen(open_detail(2))).await;
+            process_fills(&mut fund_manager, &dex_connector).await;
+
+            // Neither the take-profit nor the cut-loss is hit at 100
+            let price = Decimal::new(100, 0);
+            for (confidence, closing) in [(5, false), (6, true)] {
+                let position = fund_manager.get_open_position().unwrap();
+                let mut trace = DecisionTrace::new(false, "a", 1, price);
+                fund_manager
+                    .handle_close_chances(
+                        price,
+                        position.id(),
+                        &position,
+                        Some(&close_signal(trim, confidence)),
+                        &mut trace,
+                    )
+                    .await
+                    .unwrap();
+                let state = fund_manager.get_open_position().unwrap().state();
+                assert_eq!(matches!(state, State::Closing(_)), closing, "{:?}", state);
+            }
+        }
+    }
+
+    #[test]
+    fn test_would_cross() {
+        let current_price = Decimal::new(100, 0);
+        assert!(FundManager::would_cross(
+            &OrderSide::Long,
+            Decimal::new(101, 0),
+            current_price
+        ));
+        assert!(!FundManager::would_cross(
+            &OrderSide::Long,
+            Decimal::new(99, 0),
+            current_price
+        ));
+        assert!(FundManager::would_cross(
+            &OrderSide::Short,
+            Decimal::new(99, 0),
+            current_price
+        ));
+        assert!(!FundManager::would_cross(
+            &OrderSide::Short,
+            Decimal::new(101, 0),
+            current_price
+        ));
+        // Unknown price
+        assert!(!FundManager::would_cross(
+            &OrderSide::Long,
+            Decimal::new(101, 0),
+            Decimal::ZERO
+        ));
+    }
+
+    #[test]
+    fn test_edge_price_for_slippage() {
+        let book = OrderBook {
+            bids: vec![(Decimal::new(99, 0), Decimal::new(5, 0))],
+            asks: vec![
+                (Decimal::new(100, 0), Decimal::new(1, 0)),
+                (Decimal::new(110, 0), Decimal::new(1, 0)),
+            ],
+        };
+        let max_slippage = Decimal::new(1, 2);
+
+        // Filled at the best ask
+        assert_eq!(
+            FundManager::edge_price_for_slippage(
+                &book,
+                &OrderSide::Long,
+                Decimal::new(1, 0),
+                max_slippage
+            ),
+            None
+        );
+        // Averages 105, 5% away from the best ask
+        assert_eq!(
+            FundManager::edge_price_for_slippage(
+                &book,
+                &OrderSide::Long,
+                Decimal::new(2, 0),
+                max_slippage
+            ),
+            Some(Decimal::new(100, 0))
+        );
+        // Deeper than the book
+        assert_eq!(
+            FundManager::edge_price_for_slippage(
+                &book,
+                &OrderSide::Long,
+                Decimal::new(3, 0),
+                max_slippage
+            ),
+            Some(Decimal::new(100, 0))
+        );
+        assert_eq!(
+            FundManager::edge_price_for_slippage(
+                &book,
+                &OrderSide::Short,
+                Decimal::new(3, 0),
+                max_slippage
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_funding_rate_guard() {
+        let max_rate = Some(Decimal::new(1, 4));
+
+        // Long pays a positive funding rate
+        assert!(FundManager::is_funding_rate_acceptable(
+            true,
+            Decimal::new(1, 4),
+            max_rate,
+            max_rate
+        ));
+        assert!(!FundManager::is_funding_rate_acceptable(
+            true,
+            Decimal::new(2, 4),
+            max_rate,
+            max_rate
+        ));
+        assert!(FundManager::is_funding_rate_acceptable(
+            true,
+            Decimal::new(2, 4),
+            None,
+            max_rate
+        ));
+
+        // Short pays a negative funding rate
+        assert!(FundManager::is_funding_rate_acceptable(
+            false,
+            Decimal::new(2, 4),
+            max_rate,
+            max_rate
+        ));
+        assert!(!FundManager::is_funding_rate_acceptable(
+            false,
+            Decimal::new(-2, 4),
+            max_rate,
+            max_rate
+        ));
+        assert!(FundManager::is_funding_rate_acceptable(
+            false,
+            Decimal::new(-2, 4),
+            max_rate,
+            None
+        ));
+    }
+
+    #[test]
+    fn test_slippage_ratio() {
+        let ordered_price = Decimal::new(100, 0);
+        assert_eq!(
+            FundManager::slippage_ratio(&OrderSide::Long, ordered_price, Decimal::new(101, 0)),
+            Decimal::new(1, 2)
+        );
+        assert_eq!(
+            FundManager::slippage_ratio(&OrderSide::Short, ordered_price, Decimal::new(99, 0)),
+            Decimal::new(1, 2)
+        );
+        // Price improvement is negative slippage
+        assert!(
+            FundManager::slippage_ratio(&OrderSide::Long, ordered_price, Decimal::new(99, 0))
+                < Decimal::ZERO
+        );
+    }
+
+    #[test]
+    fn test_fund_params_from_document() {
+        let document = bson::doc! {
+            "fund_name": "test-fund",
+            "take_profit_ratio": "0.015",
+            "atr_spread": 0.5,
+            "close_order_tick_count_max": 10,
+        };
+        let params: FundParams = bson::from_document(document).unwrap();
+        assert_eq!(params.take_profit_ratio, Some(Decimal::new(15, 3)));
+        assert_eq!(params.atr_spread, Some(Decimal::new(5, 1)));
+        assert_eq!(params.close_order_tick_count_max, Some(10));
+        assert_eq!(params.risk_reward, None);
+        assert_eq!(params.open_tick_count_max, None);
+    }
+
+    #[test]
+    fn test_trailing_stop_ratchets_with_price() {
+        let ratio = Decimal::new(1, 2);
+        let open_price = Decimal::new(100, 0);
+        let prices = [100, 101, 105, 103, 110, 104];
+        let expected = [
+            None,
+            None,
+            Some(Decimal::new(10395, 2)),
+            Some(Decimal::new(10395, 2)),
+            Some(Decimal::new(1089, 1)),
+            Some(Decimal::new(1089, 1)),
+        ];
+
+        let mut stop = None;
+        for (price, expected) in prices.iter().zip(expected.iter()) {
+            stop = FundManager::trailing_stop_price(
+                PositionType::Long,
+                open_price,
+                stop,
+                Decimal::from(*price),
+                ratio,
+            );
+            assert_eq!(stop, *expected);
+        }
+    }
+
+    #[test]
+    fn test_trailing_stop_never_moves_against_short() {
+        let ratio = Decimal::new(1, 2);
+        let open_price = Decimal::new(100, 0);
+        let mut stop = None;
+        for price in [95, 90, 93, 97] {
+            stop = FundManager::trailing_stop_price(
+                PositionType::Short,
+                open_price,
+                stop,
+                Decimal::from(price),
+                ratio,
+            );
+        }
+        assert_eq!(stop, Some(Decimal::new(909, 1)));
+    }
+}
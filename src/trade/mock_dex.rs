@@ -0,0 +1,306 @@
+// mock_dex.rs
+
+use async_trait::async_trait;
+use dex_connector::{
+    BalanceResponse, CreateOrderResponse, DexConnector, DexError, FilledOrdersResponse, OrderSide,
+    TickerResponse,
+};
+use rust_decimal::Decimal;
+use std::sync::{Arc, Mutex};
+
+use super::dex_connector_box::{DexConnectorExt, MarginInfo, OrderBook, TimeInForce};
+
+// The venue calls a test needs. Each test implements the few it looks at, the others
+// answer as a quiet venue would.
+#[async_trait]
+pub trait MockDex: Send + Sync {
+    async fn set_leverage(&self, _symbol: &str, _leverage: u32) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn get_ticker(&self, _symbol: &str) -> Result<TickerResponse, DexError> {
+        Ok(TickerResponse {
+            price: Decimal::new(100, 0),
+            min_tick: Some(Decimal::new(1, 2)),
+            ..Default::default()
+        })
+    }
+
+    async fn get_filled_orders(&self, _symbol: &str) -> Result<FilledOrdersResponse, DexError> {
+        Ok(FilledOrdersResponse { orders: vec![] })
+    }
+
+    async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+        Ok(BalanceResponse::default())
+    }
+
+    async fn create_order(
+        &self,
+        _symbol: &str,
+        size: Decimal,
+        _side: OrderSide,
+        price: Option<Decimal>,
+        _time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        Ok(CreateOrderResponse {
+            order_id: "1".to_owned(),
+            ordered_price: price.unwrap_or(Decimal::new(100, 0)),
+            ordered_size: size,
+        })
+    }
+
+    async fn cancel_order(&self, _symbol: &str, _order_id: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    // None falls back to one cancel_order per order
+    async fn cancel_orders(
+        &self,
+        _symbol: &str,
+        _order_ids: &[String],
+    ) -> Option<Result<usize, DexError>> {
+        None
+    }
+
+    async fn close_all_positions(&self, _symbol: Option<String>) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn get_orderbook(&self, _symbol: &str, _depth: usize) -> Result<OrderBook, DexError> {
+        Err(DexError::Other("no orderbook".to_owned()))
+    }
+
+    async fn get_open_order_symbols(&self) -> Result<Vec<String>, DexError> {
+        Ok(vec![])
+    }
+
+    async fn get_open_order_ids(&self, _symbol: &str) -> Result<Vec<String>, DexError> {
+        Ok(vec![])
+    }
+
+    async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+        Ok(vec![])
+    }
+
+    async fn get_margin_info(&self) -> Result<MarginInfo, DexError> {
+        Err(DexError::Other("no margin info".to_owned()))
+    }
+}
+
+// A MockDex as a connector
+pub struct Mock<T: MockDex>(pub T);
+
+#[async_trait]
+impl<T: MockDex> DexConnector for Mock<T> {
+    async fn start(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn restart(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn set_leverage(&self, symbol: &str, leverage: u32) -> Result<(), DexError> {
+        self.0.set_leverage(symbol, leverage).await
+    }
+
+    async fn get_ticker(
+        &self,
+        symbol: &str,
+        _test_price: Option<Decimal>,
+    ) -> Result<TickerResponse, DexError> {
+        self.0.get_ticker(symbol).await
+    }
+
+    async fn get_filled_orders(&self, symbol: &str) -> Result<FilledOrdersResponse, DexError> {
+        self.0.get_filled_orders(symbol).await
+    }
+
+    async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+        self.0.get_balance().await
+    }
+
+    async fn clear_filled_order(&self, _symbol: &str, _trade_id: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn clear_all_filled_order(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn create_order(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        _spread: Option<i64>,
+    ) -> Result<CreateOrderResponse, DexError> {
+        self.0
+            .create_order(symbol, size, side, price, TimeInForce::Gtc)
+            .await
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: &str) -> Result<(), DexError> {
+        self.0.cancel_order(symbol, order_id).await
+    }
+
+    async fn cancel_all_orders(&self, _symbol: Option<String>) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn close_all_positions(&self, symbol: Option<String>) -> Result<(), DexError> {
+        self.0.close_all_positions(symbol).await
+    }
+
+    async fn clear_last_trades(&self, _symbol: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: MockDex> DexConnectorExt for Mock<T> {
+    async fn cancel_orders(&self, symbol: &str, order_ids: &[String]) -> Result<usize, DexError> {
+        if let Some(res) = self.0.cancel_orders(symbol, order_ids).await {
+            return res;
+        }
+        for order_id in order_ids {
+            self.0.cancel_order(symbol, order_id).await?;
+        }
+        Ok(order_ids.len())
+    }
+
+    async fn create_order_with_tif(
+        &self,
+        symbol: &str,
+        size: Decimal,
+        side: OrderSide,
+        price: Option<Decimal>,
+        _spread: Option<i64>,
+        time_in_force: TimeInForce,
+    ) -> Result<CreateOrderResponse, DexError> {
+        self.0
+            .create_order(symbol, size, side, price, time_in_force)
+            .await
+    }
+
+    async fn get_orderbook(&self, symbol: &str, depth: usize) -> Result<OrderBook, DexError> {
+        self.0.get_orderbook(symbol, depth).await
+    }
+
+    async fn get_open_order_symbols(&self) -> Result<Vec<String>, DexError> {
+        self.0.get_open_order_symbols().await
+    }
+
+    async fn get_open_order_ids(&self, symbol: &str) -> Result<Vec<String>, DexError> {
+        self.0.get_open_order_ids(symbol).await
+    }
+
+    async fn get_positions(&self) -> Result<Vec<(String, Decimal)>, DexError> {
+        self.0.get_positions().await
+    }
+
+    async fn get_margin_info(&self) -> Result<MarginInfo, DexError> {
+        self.0.get_margin_info().await
+    }
+}
+
+// A Hyperliquid exchange endpoint that keeps the actions it receives. The cancels succeed,
+// and the orders are filled with the ids from 1. Its meta lists ETH and BTC, in that order.
+pub fn start_hyperliquid_exchange() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    let actions = Arc::new(Mutex::new(vec![]));
+    let received = actions.clone();
+    let make_service = make_service_fn(move |_conn| {
+        let received = received.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let received = received.clone();
+                async move {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    if payload["type"] == "meta" {
+                        let meta = serde_json::json!({ "universe": [
+                            { "name": "ETH", "szDecimals": 4 },
+                            { "name": "BTC", "szDecimals": 5 },
+                        ]});
+                        return Ok::<_, Infallible>(Response::new(Body::from(meta.to_string())));
+                    }
+                    let action = payload["action"].clone();
+                    let response_type = action["type"].as_str().unwrap_or_default().to_owned();
+                    let statuses: Vec<serde_json::Value> = match response_type.as_str() {
+                        "cancel" => action["cancels"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .map(|_| serde_json::json!("success"))
+                            .collect(),
+                        _ => action["orders"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .enumerate()
+                            .map(|(i, order)| {
+                                serde_json::json!({ "filled": {
+                                    "totalSz": order["s"],
+                                    "avgPx": order["p"],
+                                    "oid": i + 1,
+                                }})
+                            })
+                            .collect(),
+                    };
+                    received.lock().unwrap().push(action);
+                    let res = serde_json::json!({
+                        "status": "ok",
+                        "response": { "type": response_type, "data": { "statuses": statuses } }
+                    });
+                    Ok::<_, Infallible>(Response::new(Body::from(res.to_string())))
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (url, actions)
+}
+
+// A Hyperliquid info endpoint of an account with no open order nor position. It keeps the
+// requests it receives.
+pub fn start_hyperliquid_info() -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    let requests = Arc::new(Mutex::new(vec![]));
+    let received = requests.clone();
+    let make_service = make_service_fn(move |_conn| {
+        let received = received.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let received = received.clone();
+                async move {
+                    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                    let payload: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    let res = match payload["type"].as_str() {
+                        Some("clearinghouseState") => serde_json::json!({ "assetPositions": [] }),
+                        _ => serde_json::json!([]),
+                    };
+                    received.lock().unwrap().push(payload);
+                    Ok::<_, Infallible>(Response::new(Body::from(res.to_string())))
+                }
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    (url, requests)
+}
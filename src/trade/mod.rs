@@ -1,14 +1,20 @@
 // mod.rs
 
+pub mod bybit_connector;
+pub mod candle;
 pub mod db_handler;
+pub mod decision_trace;
 pub mod derivative_trader;
 pub mod dex_connector_box;
 pub mod dex_emulator;
 pub mod fund_config;
 pub mod fund_manager;
+#[cfg(test)]
+pub mod mock_dex;
+pub mod replay_venue;
 pub mod trader_config;
+pub mod trading_calendar;
 
 pub use db_handler::DBHandler;
 pub use derivative_trader::DerivativeTrader;
-pub use fund_config::TOKEN_LIST_SIZE;
 pub use fund_manager::FundManager;
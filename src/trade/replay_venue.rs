@@ -0,0 +1,89 @@
+// replay_venue.rs
+
+use async_trait::async_trait;
+use dex_connector::{
+    BalanceResponse, CreateOrderResponse, DexConnector, DexError, FilledOrdersResponse, OrderSide,
+    TickerResponse,
+};
+use rust_decimal::Decimal;
+
+// The saved prices are replayed as they are, so they are not rounded to a coarser tick
+const REPLAY_MIN_TICK: Decimal = Decimal::from_parts(1, 0, 0, false, 8);
+
+// The venue under the emulator of a replay. The prices come from the file and the orders
+// are filled by the emulator, so nothing is sent anywhere. There is no account, so the
+// balance is zero and no draw down is measured.
+pub struct ReplayVenue;
+
+#[async_trait]
+impl DexConnector for ReplayVenue {
+    async fn start(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn stop(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn restart(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn set_leverage(&self, _symbol: &str, _leverage: u32) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn get_ticker(
+        &self,
+        _symbol: &str,
+        _test_price: Option<Decimal>,
+    ) -> Result<TickerResponse, DexError> {
+        Ok(TickerResponse {
+            min_tick: Some(REPLAY_MIN_TICK),
+            ..Default::default()
+        })
+    }
+
+    async fn get_filled_orders(&self, _symbol: &str) -> Result<FilledOrdersResponse, DexError> {
+        Ok(FilledOrdersResponse { orders: vec![] })
+    }
+
+    async fn get_balance(&self) -> Result<BalanceResponse, DexError> {
+        Ok(BalanceResponse::default())
+    }
+
+    async fn clear_filled_order(&self, _symbol: &str, _trade_id: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn clear_all_filled_order(&self) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn create_order(
+        &self,
+        _symbol: &str,
+        _size: Decimal,
+        _side: OrderSide,
+        _price: Option<Decimal>,
+        _spread: Option<i64>,
+    ) -> Result<CreateOrderResponse, DexError> {
+        Err(DexError::Other("A replay takes no order".to_owned()))
+    }
+
+    async fn cancel_order(&self, _symbol: &str, _order_id: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn cancel_all_orders(&self, _symbol: Option<String>) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn close_all_positions(&self, _symbol: Option<String>) -> Result<(), DexError> {
+        Ok(())
+    }
+
+    async fn clear_last_trades(&self, _symbol: &str) -> Result<(), DexError> {
+        Ok(())
+    }
+}
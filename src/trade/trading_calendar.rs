@@ -0,0 +1,120 @@
+// trading_calendar.rs
+
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Utc, Weekday};
+use lazy_static::lazy_static;
+use std::env;
+
+lazy_static! {
+    pub static ref TRADING_CALENDAR: TradingCalendar = TradingCalendar::from_env();
+}
+
+// Days on which no new position is opened, in the local time of TIMEZONE_OFFSET
+#[derive(Debug, Clone)]
+pub struct TradingCalendar {
+    offset: FixedOffset,
+    closed_weekdays: Vec<Weekday>,
+    closed_dates: Vec<NaiveDate>,
+}
+
+impl TradingCalendar {
+    pub fn new(
+        offset: FixedOffset,
+        closed_weekdays: Vec<Weekday>,
+        closed_dates: Vec<NaiveDate>,
+    ) -> Self {
+        Self {
+            offset,
+            closed_weekdays,
+            closed_dates,
+        }
+    }
+
+    // CLOSED_WEEKDAYS defaults to Sunday, e.g. "Sat,Sun"
+    // CLOSED_DATES is empty by default, e.g. "2024-12-25,2025-01-01"
+    pub fn from_env() -> Self {
+        let offset_seconds = env::var("TIMEZONE_OFFSET")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse::<i32>()
+            .expect("Invalid TIMEZONE_OFFSET");
+        let offset = FixedOffset::east_opt(offset_seconds).expect("Invalid offset");
+
+        let closed_weekdays = env::var("CLOSED_WEEKDAYS")
+            .unwrap_or_else(|_| "Sun".to_string())
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<Weekday>()
+                    .unwrap_or_else(|_| panic!("Invalid CLOSED_WEEKDAYS: {}", s))
+            })
+            .collect();
+
+        let closed_dates = env::var("CLOSED_DATES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                    .unwrap_or_else(|_| panic!("Invalid CLOSED_DATES: {}", s))
+            })
+            .collect();
+
+        Self::new(offset, closed_weekdays, closed_dates)
+    }
+
+    pub fn is_trading_allowed(&self, now: DateTime<Utc>) -> bool {
+        let local_date = now.with_timezone(&self.offset).date_naive();
+        !self.closed_weekdays.contains(&local_date.weekday())
+            && !self.closed_dates.contains(&local_date)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, 0).unwrap()
+    }
+
+    #[test]
+    fn test_closed_weekdays_and_dates() {
+        let calendar = TradingCalendar::new(
+            FixedOffset::east_opt(0).unwrap(),
+            vec![Weekday::Sat, Weekday::Sun],
+            vec![NaiveDate::from_ymd_opt(2024, 12, 25).unwrap()],
+        );
+
+        // 2024-12-20 is a Friday
+        assert!(calendar.is_trading_allowed(utc(2024, 12, 20, 12, 0)));
+        assert!(!calendar.is_trading_allowed(utc(2024, 12, 21, 12, 0)));
+        assert!(!calendar.is_trading_allowed(utc(2024, 12, 22, 12, 0)));
+        assert!(calendar.is_trading_allowed(utc(2024, 12, 24, 12, 0)));
+        assert!(!calendar.is_trading_allowed(utc(2024, 12, 25, 12, 0)));
+    }
+
+    #[test]
+    fn test_timezone_boundary() {
+        // Sunday starts at 23:00 UTC on Saturday in UTC+1
+        let calendar = TradingCalendar::new(
+            FixedOffset::east_opt(3600).unwrap(),
+            vec![Weekday::Sun],
+            vec![],
+        );
+        assert!(calendar.is_trading_allowed(utc(2024, 12, 21, 22, 59)));
+        assert!(!calendar.is_trading_allowed(utc(2024, 12, 21, 23, 0)));
+        assert!(!calendar.is_trading_allowed(utc(2024, 12, 22, 22, 59)));
+        assert!(calendar.is_trading_allowed(utc(2024, 12, 22, 23, 0)));
+
+        // And ends at 05:00 UTC on Monday in UTC-5
+        let calendar = TradingCalendar::new(
+            FixedOffset::west_opt(5 * 3600).unwrap(),
+            vec![Weekday::Sun],
+            vec![],
+        );
+        assert!(!calendar.is_trading_allowed(utc(2024, 12, 23, 4, 59)));
+        assert!(calendar.is_trading_allowed(utc(2024, 12, 23, 5, 0)));
+    }
+}
@@ -0,0 +1,428 @@
+// walk_forward.rs
+
+use crate::backtest::{TrainingData, WalkForwardFold};
+use crate::checkpoint::TrainingCheckpoint;
+use debot_db::{ModelParams, SerializableModel};
+use smartcore::ensemble::random_forest_classifier::{
+    RandomForestClassifier, RandomForestClassifierParameters,
+};
+use smartcore::ensemble::random_forest_regressor::{
+    RandomForestRegressor, RandomForestRegressorParameters,
+};
+use smartcore::linalg::basic::arrays::Array2;
+use smartcore::linalg::basic::matrix::DenseMatrix;
+use smartcore::metrics::{accuracy, mean_squared_error};
+use smartcore::tree::decision_tree_classifier::SplitCriterion;
+
+// The grid search of debot_ml cross-validates on shuffled samples, so its scores come from
+// models that have seen the future. With the walk-forward folds, each candidate is scored on
+// the validation windows that follow its training data, and the best one is fit on all the
+// samples and saved under the same keys. Each candidate's score is checkpointed as soon as
+// it is known, so a resumed search only evaluates the candidates left.
+
+pub fn classifier_candidates() -> Vec<RandomForestClassifierParameters> {
+    let candidate = |criterion, max_depth, min_samples_leaf, min_samples_split, n_trees, m| {
+        RandomForestClassifierParameters {
+            criterion,
+            max_depth,
+            min_samples_leaf,
+            min_samples_split,
+            n_trees,
+            m: Some(m),
+            keep_samples: false,
+            seed: 42,
+        }
+    };
+    vec![
+        candidate(SplitCriterion::Gini, None, 1, 2, 100, 10),
+        candidate(SplitCriterion::Entropy, Some(15), 1, 3, 200, 12),
+        candidate(SplitCriterion::Gini, Some(20), 2, 4, 150, 9),
+        candidate(SplitCriterion::Entropy, Some(25), 2, 5, 250, 13),
+    ]
+}
+
+pub fn regressor_candidates() -> Vec<RandomForestRegressorParameters> {
+    let candidate = |max_depth, min_samples_leaf, min_samples_split, n_trees, m| {
+        RandomForestRegressorParameters {
+            max_depth,
+            min_samples_leaf,
+            min_samples_split,
+            n_trees,
+            m: Some(m),
+            keep_samples: false,
+            seed: 42,
+        }
+    };
+    vec![
+        candidate(None, 1, 2, 100, 6),
+        candidate(Some(10), 2, 4, 150, 5),
+        candidate(Some(15), 1, 3, 200, 7),
+        candidate(Some(20), 3, 6, 250, 6),
+    ]
+}
+
+pub async fn train_classifier_on_folds(
+    key: &str,
+    model_params: &ModelParams,
+    folds: &[WalkForwardFold],
+    x: DenseMatrix<f64>,
+    y: Vec<i32>,
+    checkpoint: &mut TrainingCheckpoint,
+) {
+    let params = match select_classifier(folds, classifier_candidates(), checkpoint) {
+        Some(params) => params,
+        None => {
+            log::error!("{}: no classifier could be validated on the folds", key);
+            return;
+        }
+    };
+    let model = match RandomForestClassifier::fit(&x, &y, params) {
+        Ok(model) => model,
+        Err(e) => {
+            log::error!("{}: failed to fit the classifier: {}", key, e);
+            return;
+        }
+    };
+    save_model(key, 0, model_params, bincode::serialize(&model).unwrap()).await;
+}
+
+// A regressor of the models, saved under the suffix of its output in the samples
+pub struct RegressorTarget {
+    pub suffix: usize,
+    pub num_features: usize,
+    // The samples with this output are left out
+    pub exclude_value: Option<f64>,
+}
+
+impl RegressorTarget {
+    fn regression_data(&self, data: &TrainingData) -> Option<RegressionData> {
+        let (x, _, y_1, y_2) = data;
+        let y = if self.suffix == 1 { y_1 } else { y_2 };
+        regression_data(x, y, self.num_features, self.exclude_value)
+    }
+}
+
+pub async fn train_regressor_on_folds(
+    key: &str,
+    model_params: &ModelParams,
+    folds: &[WalkForwardFold],
+    x: DenseMatrix<f64>,
+    y: Vec<f64>,
+    target: &RegressorTarget,
+    checkpoint: &mut TrainingCheckpoint,
+) {
+    let suffix = target.suffix;
+    let folds: Vec<(RegressionData, RegressionData)> = folds
+        .iter()
+        .filter_map(|fold| {
+            Some((
+                target.regression_data(&fold.train)?,
+                target.regression_data(&fold.validation)?,
+            ))
+        })
+        .collect();
+
+    let params = match select_regressor(&folds, regressor_candidates(), suffix, checkpoint) {
+        Some(params) => params,
+        None => {
+            log::error!(
+                "{}_{}: no regressor could be validated on the folds",
+                key,
+                suffix
+            );
+            return;
+        }
+    };
+    let (x, y) = match regression_data(&x, &y, target.num_features, target.exclude_value) {
+        Some(data) => data,
+        None => {
+            log::error!("{}_{}: no sample to fit the regressor", key, suffix);
+            return;
+        }
+    };
+    let model = match RandomForestRegressor::fit(&x, &y, params) {
+        Ok(model) => model,
+        Err(e) => {
+            log::error!("{}_{}: failed to fit the regressor: {}", key, suffix, e);
+            return;
+        }
+    };
+    save_model(
+        key,
+        suffix,
+        model_params,
+        bincode::serialize(&model).unwrap(),
+    )
+    .await;
+}
+
+async fn save_model(key: &str, suffix: usize, model_params: &ModelParams, model: Vec<u8>) {
+    model_params
+        .save_model(&format!("{}_{}", key, suffix), &SerializableModel { model })
+        .await
+        .unwrap_or_else(|e| panic!("Failed to save model {}: {:?}", suffix, e));
+}
+
+// The candidate with the best mean accuracy over the validation windows
+fn select_classifier(
+    folds: &[WalkForwardFold],
+    candidates: Vec<RandomForestClassifierParameters>,
+    checkpoint: &mut TrainingCheckpoint,
+) -> Option<RandomForestClassifierParameters> {
+    let evaluate = |params: &RandomForestClassifierParameters| {
+        let scores: Vec<f64> = folds
+            .iter()
+            .filter_map(|fold| {
+                let (x, y, _, _) = &fold.train;
+                let (x_valid, y_valid, _, _) = &fold.validation;
+                let model = RandomForestClassifier::fit(x, y, params.clone()).ok()?;
+                Some(accuracy(y_valid, &model.predict(x_valid).ok()?))
+            })
+            .collect();
+        log::info!("{:?}: validation accuracies = {:.3?}", params, scores);
+        mean(&scores)
+    };
+    select_checkpointed(candidates, checkpoint, "classifier", evaluate, |a, b| a > b)
+}
+
+type RegressionData = (DenseMatrix<f64>, Vec<f64>);
+
+// The candidate with the lowest mean squared error over the validation windows
+fn select_regressor(
+    folds: &[(RegressionData, RegressionData)],
+    candidates: Vec<RandomForestRegressorParameters>,
+    suffix: usize,
+    checkpoint: &mut TrainingCheckpoint,
+) -> Option<RandomForestRegressorParameters> {
+    let evaluate = |params: &RandomForestRegressorParameters| {
+        let scores: Vec<f64> = folds
+            .iter()
+            .filter_map(|((x, y), (x_valid, y_valid))| {
+                let model = RandomForestRegressor::fit(x, y, params.clone()).ok()?;
+                Some(mean_squared_error(y_valid, &model.predict(x_valid).ok()?))
+            })
+            .collect();
+        log::info!("{:?}: validation MSEs = {:.8?}", params, scores);
+        mean(&scores)
+    };
+    let stage = format!("regressor_{}", suffix);
+    select_checkpointed(candidates, checkpoint, &stage, evaluate, |a, b| a < b)
+}
+
+// The best of the candidates over the scores checkpointed by an earlier run and the ones
+// evaluated now. A candidate is known by its parameters.
+fn select_checkpointed<T: std::fmt::Debug>(
+    candidates: Vec<T>,
+    checkpoint: &mut TrainingCheckpoint,
+    stage: &str,
+    mut evaluate: impl FnMut(&T) -> Option<f64>,
+    is_better: impl Fn(f64, f64) -> bool,
+) -> Option<T> {
+    let scored = candidates.into_iter().filter_map(|params| {
+        let combo = format!("{:?}", params);
+        if let Some(score) = checkpoint.score(stage, &combo) {
+            log::info!("{:?}: already scored {:.8}", params, score);
+            return Some((params, score));
+        }
+        let score = evaluate(&params)?;
+        if let Err(e) = checkpoint.record_score(stage, &combo, score) {
+            log::warn!("the score of {:?} is not checkpointed: {:?}", params, e);
+        }
+        Some((params, score))
+    });
+    best(scored, is_better)
+}
+
+fn best<T: std::fmt::Debug>(
+    scored: impl Iterator<Item = (T, f64)>,
+    is_better: impl Fn(f64, f64) -> bool,
+) -> Option<T> {
+    let (params, score) = scored.reduce(|best, candidate| {
+        if is_better(candidate.1, best.1) {
+            candidate
+        } else {
+            best
+        }
+    })?;
+    log::info!("best: {:?}, mean validation score = {:.8}", params, score);
+    Some(params)
+}
+
+fn mean(scores: &[f64]) -> Option<f64> {
+    if scores.is_empty() {
+        return None;
+    }
+    Some(scores.iter().sum::<f64>() / scores.len() as f64)
+}
+
+// The samples whose target is not excluded, with their first num_features inputs
+fn regression_data(
+    x: &DenseMatrix<f64>,
+    y: &[f64],
+    num_features: usize,
+    exclude_value: Option<f64>,
+) -> Option<RegressionData> {
+    let (rows, targets): (Vec<Vec<f64>>, Vec<f64>) = y
+        .iter()
+        .enumerate()
+        .filter(|(_, &target)| exclude_value != Some(target))
+        .map(|(i, &target)| {
+            let row = x
+                .get_row(i)
+                .iterator(0)
+                .take(num_features)
+                .copied()
+                .collect();
+            (row, target)
+        })
+        .unzip();
+    if rows.is_empty() {
+        return None;
+    }
+    Some((DenseMatrix::from_2d_vec(&rows), targets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A checkpoint of its own per test, in the temp dir
+    fn temp_checkpoint(name: &str, resume: bool) -> TrainingCheckpoint {
+        let file_key = format!("debot_walk_forward_{}_{}", name, std::process::id());
+        TrainingCheckpoint::load(&std::env::temp_dir(), &file_key, resume)
+    }
+
+    // The label is 1 above 0.5, and both regressor outputs are the input
+    fn fold(train: &[f64], validation: &[f64]) -> WalkForwardFold {
+        let data = |xs: &[f64]| -> TrainingData {
+            let rows: Vec<Vec<f64>> = xs.iter().map(|&x| vec![x, 1.0 - x]).collect();
+            let y: Vec<i32> = xs.iter().map(|&x| (x > 0.5) as i32).collect();
+            (DenseMatrix::from_2d_vec(&rows), y, xs.to_vec(), xs.to_vec())
+        };
+        WalkForwardFold {
+            train: data(train),
+            validation: data(validation),
+        }
+    }
+
+    fn grid(x: std::ops::Range<i32>) -> Vec<f64> {
+        x.map(|i| i as f64 / 20.0).collect()
+    }
+
+    #[test]
+    fn test_select_classifier_on_the_validation_folds() {
+        let folds = vec![
+            fold(&grid(0..20), &grid(0..20)),
+            fold(&grid(0..20), &grid(1..20)),
+        ];
+        let candidate = |min_samples_leaf| RandomForestClassifierParameters {
+            criterion: SplitCriterion::Gini,
+            max_depth: None,
+            min_samples_leaf,
+            min_samples_split: 2,
+            n_trees: 10,
+            m: Some(2),
+            keep_samples: false,
+            seed: 42,
+        };
+
+        // A leaf of all the samples can only predict the majority, and loses on validation
+        let mut checkpoint = temp_checkpoint("classifier", false);
+        let params =
+            select_classifier(&folds, vec![candidate(20), candidate(1)], &mut checkpoint).unwrap();
+        assert_eq!(params.min_samples_leaf, 1);
+        let params =
+            select_classifier(&folds, vec![candidate(1), candidate(20)], &mut checkpoint).unwrap();
+        assert_eq!(params.min_samples_leaf, 1);
+        checkpoint.clear();
+
+        let mut checkpoint = temp_checkpoint("classifier_without_folds", false);
+        assert!(select_classifier(&[], vec![candidate(1)], &mut checkpoint).is_none());
+        checkpoint.clear();
+    }
+
+    #[test]
+    fn test_select_regressor_on_the_validation_folds() {
+        let target = RegressorTarget {
+            suffix: 1,
+            num_features: 2,
+            exclude_value: None,
+        };
+        let fold = fold(&grid(0..20), &grid(0..20));
+        let folds = vec![(
+            target.regression_data(&fold.train).unwrap(),
+            target.regression_data(&fold.validation).unwrap(),
+        )];
+        let candidate = |max_depth| RandomForestRegressorParameters {
+            max_depth,
+            min_samples_leaf: 1,
+            min_samples_split: 2,
+            n_trees: 10,
+            m: Some(2),
+            keep_samples: false,
+            seed: 42,
+        };
+
+        // A stump can't follow the target
+        let mut checkpoint = temp_checkpoint("regressor", false);
+        let params = select_regressor(
+            &folds,
+            vec![Some(1), None].into_iter().map(candidate).collect(),
+            1,
+            &mut checkpoint,
+        )
+        .unwrap();
+        assert_eq!(params.max_depth, None);
+        checkpoint.clear();
+    }
+
+    #[test]
+    fn test_resumed_search_skips_the_scored_candidates() {
+        let candidates = vec![3, 9, 1, 5];
+        let search = |candidates: &[i32], checkpoint: &mut TrainingCheckpoint| {
+            let mut evaluated = vec![];
+            let best = select_checkpointed(
+                candidates.to_vec(),
+                checkpoint,
+                "classifier",
+                |&candidate| {
+                    evaluated.push(candidate);
+                    Some(candidate as f64)
+                },
+                |a, b| a > b,
+            );
+            (best, evaluated)
+        };
+
+        // Interrupted after the first two candidates, the best of which is 9
+        let mut interrupted = temp_checkpoint("interrupted", false);
+        assert_eq!(
+            search(&candidates[..2], &mut interrupted),
+            (Some(9), vec![3, 9])
+        );
+        drop(interrupted);
+
+        // The resumed search evaluates the other two only, and still picks 9
+        let mut resumed = temp_checkpoint("interrupted", true);
+        assert_eq!(search(&candidates, &mut resumed), (Some(9), vec![1, 5]));
+        resumed.clear();
+
+        // As a search that ran through does
+        let mut uninterrupted = temp_checkpoint("uninterrupted", false);
+        assert_eq!(
+            search(&candidates, &mut uninterrupted),
+            (Some(9), candidates.clone())
+        );
+        uninterrupted.clear();
+    }
+
+    #[test]
+    fn test_regression_data() {
+        let x = DenseMatrix::from_2d_vec(&vec![vec![1.0, 2.0, 3.0], vec![4.0, 5.0, 6.0]]);
+        let (x, y) = regression_data(&x, &[0.0, 0.5], 2, Some(0.0)).unwrap();
+        assert_eq!(x, DenseMatrix::from_2d_vec(&vec![vec![4.0, 5.0]]));
+        assert_eq!(y, vec![0.5]);
+
+        assert!(regression_data(&x, &[0.0], 2, Some(0.0)).is_none());
+    }
+}
@@ -0,0 +1,85 @@
+// replay.rs
+
+use std::process::{Command, Output};
+
+// Replays a saved price file with neither the DB nor a venue.
+// MONGODB_URI and the endpoints are read by the config, but never connected to.
+fn replay(prices: &str, envs: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_debot"))
+        .args(["replay", prices])
+        .env_clear()
+        .env("MONGODB_URI", "mongodb://unreachable.invalid")
+        .env("DB_R_NAME", "unused")
+        .env("DB_W_NAME", "unused")
+        .env("REST_ENDPOINT", "http://unreachable.invalid")
+        .env("WEB_SOCKET_ENDPOINT", "ws://unreachable.invalid")
+        .env("DEX_NAME", "hyperliquid")
+        .env("TRADING_STRATEGY", "randomwalk")
+        .env("RUST_LOG", "info")
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+// The "realized pnl = .., fill count = .." line logged at the end of a back test
+fn result_line(log: &str) -> &str {
+    let line = log
+        .lines()
+        .find(|line| line.contains("realized pnl = "))
+        .unwrap_or_else(|| panic!("{}", log));
+    &line[line.find("realized pnl = ").unwrap()..]
+}
+
+fn fill_count(result_line: &str) -> u64 {
+    result_line
+        .rsplit("fill count = ")
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_replay_runs_offline_to_the_end() {
+    let output = replay(
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/replay_prices.json"
+        ),
+        &[("RANDOM_SEED", "1")],
+    );
+
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", log);
+    // The back test ends with an InvalidData error once the prices run out
+    assert!(log.contains("Back test is finished"), "{}", log);
+    assert!(log.contains("realized pnl = "), "{}", log);
+}
+
+// The hourly prices warm up the ATR of every term, after which the RandomWalk funds open
+// and the emulator fills them. Both draw from RANDOM_SEED, so a second run trades the same.
+#[test]
+fn test_seeded_back_test_is_reproducible() {
+    let run = |seed| {
+        let output = replay(
+            concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/replay_hourly_prices.json"
+            ),
+            &[
+                ("RANDOM_SEED", seed),
+                ("INITIAL_FUND_AMOUNT", "100000"),
+                ("INTERVAL_SECS", "3600"),
+                ("MAX_PRICE_SIZE_HOURS", "48"),
+            ],
+        );
+        let log = String::from_utf8_lossy(&output.stderr).into_owned();
+        assert!(output.status.success(), "{}", log);
+        result_line(&log).to_owned()
+    };
+
+    let first = run("1");
+    assert!(fill_count(&first) > 0, "{}", first);
+    assert_eq!(first, run("1"));
+    assert_ne!(first, run("2"));
+}